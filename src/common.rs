@@ -1,29 +1,58 @@
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Int64(i64),
     Int32(i32),
-    String(String),
+    String(Vec<u8>),
     Vector(Vec<Value>),
-    VectorString(Vec<String>),
+    VectorString(Vec<Vec<u8>>),
+    /// Null reply: `$-1` in RESP2, `_` in RESP3.
     Empty,
+
+    // RESP3 types. They are downgraded to their closest RESP2 counterpart
+    // when the connection hasn't negotiated protocol 3 via HELLO.
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(Vec<u8>),
+    /// Verbatim string with its three letter format, e.g. `txt`.
+    Verbatim(String, Vec<u8>),
+    /// Out-of-band message, e.g. a pub/sub delivery.
+    Push(Vec<Value>),
 }
 
 impl Value {
-    fn simple_to_str(&self) -> String {
+    /// Raw bytes of a scalar value, as they travel over the wire.
+    pub fn to_bytes(&self) -> Vec<u8> {
         return match self {
             Value::String(s) => s.to_owned(),
+            _ => self.simple_to_str().into_bytes(),
+        };
+    }
+
+    fn simple_to_str(&self) -> String {
+        return match self {
+            Value::String(s) => String::from_utf8_lossy(s).into_owned(),
             Value::Int64(i) => i.to_string(),
             Value::Int32(i) => i.to_string(),
-            Value::VectorString(vs) => vs.join(", "),
+            Value::VectorString(vs) => vs
+                .iter()
+                .map(|s| String::from_utf8_lossy(s))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Value::Double(d) => format_double(*d),
+            Value::Boolean(b) => b.to_string(),
+            Value::BigNumber(n) => String::from_utf8_lossy(n).into_owned(),
+            Value::Verbatim(_, s) => String::from_utf8_lossy(s).into_owned(),
             _ => "".to_string(),
         };
     }
 
     fn custom_to_string(&self) -> String {
         return match self {
-            Value::Vector(vec) => vec
+            Value::Vector(vec) | Value::Set(vec) | Value::Push(vec) => vec
                 .iter()
                 .map(|v| v.simple_to_str())
                 .collect::<Vec<String>>()
@@ -39,3 +68,184 @@ impl fmt::Display for Value {
         return Ok(());
     }
 }
+
+/// Parses a byte string as a base-10 signed 64-bit integer, the way Redis
+/// does for numeric arguments.
+pub fn parse_i64(bytes: &[u8]) -> Option<i64> {
+    return std::str::from_utf8(bytes).ok()?.parse::<i64>().ok();
+}
+
+/// Parses a byte string as a double, rejecting NaN. `inf` and `-inf` are
+/// accepted like Redis does.
+pub fn parse_f64(bytes: &[u8]) -> Option<f64> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    // Rust also accepts things like "infinity" or "+nan"
+    if s.is_empty() || s.starts_with(char::is_whitespace) || s.ends_with(char::is_whitespace) {
+        return None;
+    }
+
+    return s.parse::<f64>().ok().filter(|d| !d.is_nan());
+}
+
+/// Glob-style matching the way Redis does it for KEYS, SCAN and friends:
+/// `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape.
+pub fn string_match(pattern: &[u8], s: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| a == b || (nocase && a.eq_ignore_ascii_case(&b));
+    let (mut p, mut i) = (0, 0);
+    // Where to resume after the last `*` if what follows it doesn't match
+    let mut backtrack = None;
+
+    while i < s.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, i));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p + 1, s[i], nocase),
+            Some(b'\\') if p + 1 < pattern.len() => eq(pattern[p + 1], s[i]).then_some(p + 2),
+            Some(&c) => eq(c, s[i]).then_some(p + 1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                i += 1;
+            }
+            (None, Some((star_p, star_i))) => {
+                // Let the star swallow one more byte
+                backtrack = Some((star_p, star_i + 1));
+                p = star_p;
+                i = star_i + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    return pattern[p..].iter().all(|&c| c == b'*');
+}
+
+/// Matches `c` against the class starting at `p`, right after the `[`.
+/// Returns the position after the closing `]` on a match.
+fn match_class(pattern: &[u8], mut p: usize, c: u8, nocase: bool) -> Option<usize> {
+    let fold = |b: u8| if nocase { b.to_ascii_lowercase() } else { b };
+    let c = fold(c);
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    // An unterminated class runs to the end of the pattern
+    while p < pattern.len() && pattern[p] != b']' {
+        if pattern[p] == b'\\' && p + 1 < pattern.len() {
+            matched |= fold(pattern[p + 1]) == c;
+            p += 2;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == b'-' && pattern[p + 2] != b']' {
+            let (mut lo, mut hi) = (fold(pattern[p]), fold(pattern[p + 2]));
+            if lo > hi {
+                std::mem::swap(&mut lo, &mut hi);
+            }
+            matched |= (lo..=hi).contains(&c);
+            p += 3;
+        } else {
+            matched |= fold(pattern[p]) == c;
+            p += 1;
+        }
+    }
+
+    if matched == negate {
+        return None;
+    }
+    return Some((p + 1).min(pattern.len()));
+}
+
+/// Formats a double the way it is sent to clients: shortest representation
+/// that round-trips, with `inf`, `-inf` and `nan` spelled like RESP3 does.
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        return "nan".to_string();
+    }
+    if d.is_infinite() {
+        return if d > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    return d.to_string();
+}
+
+thread_local! {
+    static RNG_STATE: std::cell::Cell<u64> = std::cell::Cell::new({
+        use std::hash::{BuildHasher, Hasher};
+        // RandomState is seeded from the OS, good enough to seed from.
+        std::collections::hash_map::RandomState::new().build_hasher().finish() | 1
+    });
+}
+
+/// Fast non-cryptographic random number (xorshift64*), for sampling keys.
+pub fn random_u64() -> u64 {
+    return RNG_STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545f4914f6cdd1d)
+    });
+}
+
+/// Random number in `[0, 1)`.
+pub fn random_f64() -> f64 {
+    return (random_u64() >> 11) as f64 / (1_u64 << 53) as f64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_match() {
+        assert!(string_match(b"*", b"", false));
+        assert!(string_match(b"h?llo", b"hello", false));
+        assert!(string_match(b"h*llo", b"heeeello", false));
+        assert!(string_match(b"h[ae]llo", b"hallo", false));
+        assert!(!string_match(b"h[^e]llo", b"hello", false));
+        assert!(string_match(b"h[a-b]llo", b"hbllo", false));
+        assert!(string_match(b"*o*o", b"foo:bar:boo", false));
+        assert!(!string_match(b"*o*o", b"foo:bar", false));
+        assert!(string_match(b"h\\*llo", b"h*llo", false));
+        assert!(!string_match(b"h\\*llo", b"hello", false));
+        assert!(string_match(b"HEL*", b"hello", true));
+        assert!(!string_match(b"HEL*", b"hello", false));
+    }
+
+    #[test]
+    fn test_string_match_classes_and_escapes() {
+        // Reversed ranges, escapes and a trailing `-` inside a class
+        assert!(string_match(b"h[z-a]llo", b"hbllo", false));
+        assert!(string_match(b"[\\]]", b"]", false));
+        assert!(string_match(b"[a\\-z]", b"-", false));
+        assert!(!string_match(b"[a\\-z]", b"b", false));
+        assert!(string_match(b"[a-]", b"-", false));
+
+        assert!(string_match(b"[^a-c]x", b"dx", false));
+        assert!(!string_match(b"[^a-c]x", b"bx", false));
+        assert!(!string_match(b"[^abc]", b"", false));
+        assert!(string_match(b"[A-C]", b"b", true));
+        assert!(!string_match(b"[A-C]", b"b", false));
+        assert!(!string_match(b"[^A-C]", b"b", true));
+
+        // An unterminated class runs to the end of the pattern
+        assert!(string_match(b"[abc", b"b", false));
+        assert!(!string_match(b"[abc", b"bc", false));
+
+        assert!(string_match(b"\\[a]", b"[a]", false));
+        assert!(!string_match(b"\\[a]", b"a", false));
+        assert!(string_match(b"a\\", b"a\\", false));
+        assert!(string_match(b"*[0-9]", b"key12", false));
+        assert!(!string_match(b"*[0-9]", b"key", false));
+        assert!(!string_match(b"?", b"", false));
+        assert!(string_match(b"\xff*", b"\xff\x00", false));
+    }
+}
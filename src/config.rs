@@ -1,4 +1,102 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+
+/// When the append-only file is flushed to disk with fsync.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppendFsync {
+    /// After every write, before replying to the client
+    Always,
+    /// At most once per second, in the background
+    Everysec,
+    /// Leave it to the operating system
+    No,
+}
+
+/// Which keys to evict once the dataset is over its limit, named after the
+/// Redis `maxmemory-policy` values.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionStrategy {
+    /// Evict nothing, refuse commands that would grow the dataset
+    Noeviction,
+    /// Least recently used among all keys
+    AllkeysLru,
+    /// Least frequently used among all keys
+    AllkeysLfu,
+    /// Any key
+    AllkeysRandom,
+    /// Least recently used among keys with a TTL
+    VolatileLru,
+    /// Least frequently used among keys with a TTL
+    VolatileLfu,
+    /// Any key with a TTL
+    VolatileRandom,
+    /// The key with a TTL closest to expiring
+    VolatileTtl,
+}
+
+impl EvictionStrategy {
+    /// Name as given on the command line, e.g. `allkeys-lru`.
+    pub fn name(self) -> String {
+        return self.to_possible_value().unwrap().get_name().to_string();
+    }
+
+    pub fn is_lfu(self) -> bool {
+        return matches!(self, EvictionStrategy::AllkeysLfu | EvictionStrategy::VolatileLfu);
+    }
+
+    /// Only keys with a TTL may be evicted.
+    pub fn is_volatile(self) -> bool {
+        return matches!(
+            self,
+            EvictionStrategy::VolatileLru
+                | EvictionStrategy::VolatileLfu
+                | EvictionStrategy::VolatileRandom
+                | EvictionStrategy::VolatileTtl
+        );
+    }
+}
+
+/// Automatic snapshot rules: save after `seconds` if at least `changes`
+/// writes happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveParams(pub Vec<(u64, u64)>);
+
+/// Parses `"<seconds> <changes> [<seconds> <changes> ...]"`, an empty string
+/// disables automatic snapshots.
+fn parse_save_params(s: &str) -> Result<SaveParams, String> {
+    let tokens = s
+        .split_whitespace()
+        .map(|t| t.parse::<u64>().map_err(|_| format!("invalid number '{}'", t)))
+        .collect::<Result<Vec<u64>, String>>()?;
+    if tokens.len() % 2 != 0 {
+        return Err("expected pairs of <seconds> <changes>".to_string());
+    }
+
+    return Ok(SaveParams(tokens.chunks(2).map(|c| (c[0], c[1])).collect()));
+}
+
+/// Parses a memory size the way Redis does: a number of bytes optionally
+/// followed by a unit, k/m/g are powers of 1000 and kb/mb/gb powers of 1024.
+fn parse_memory(s: &str) -> Result<u64, String> {
+    let lower = s.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1_000,
+        "kb" => 1 << 10,
+        "m" => 1_000_000,
+        "mb" => 1 << 20,
+        "g" => 1_000_000_000,
+        "gb" => 1 << 30,
+        unit => return Err(format!("unknown unit '{}'", unit)),
+    };
+    let n = digits
+        .parse::<u64>()
+        .map_err(|_| format!("invalid memory size '{}'", s))?;
+
+    return n
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("memory size '{}' is too large", s));
+}
 
 /// Program to simulate Redis functionalities
 #[derive(Parser, Debug, Clone)]
@@ -12,12 +110,79 @@ pub struct Config {
     #[arg(long, default_value_t = 7379)]
     pub port: u16,
 
-    #[arg(long, default_value_t = 5)]
-    pub keys_limit: i32,
+    /// Number of databases, numbered from 0 and picked with SELECT
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    pub databases: u32,
 
-    #[arg(long, default_value = "simple-first")]
-    pub eviction_strategy: String,
+    /// Memory limit in bytes, e.g. 100mb, past which keys are evicted. 0
+    /// means no limit
+    #[arg(long, default_value = "0", value_parser = parse_memory)]
+    pub maxmemory: u64,
+
+    /// Deprecated in favor of maxmemory: number of keys past which keys are
+    /// evicted
+    #[arg(long, hide = true)]
+    pub keys_limit: Option<usize>,
+
+    /// Which keys to evict once used memory is over maxmemory
+    #[arg(long, value_enum, default_value_t = EvictionStrategy::Noeviction)]
+    pub eviction_strategy: EvictionStrategy,
+
+    /// Keys sampled per eviction by the LRU, LFU and TTL strategies, more is
+    /// more accurate but slower
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub maxmemory_samples: u32,
+
+    /// Largest list kept as a single listpack, and largest quicklist node:
+    /// a number of entries, or -1 to -5 for 4KB to 64KB
+    #[arg(long, default_value_t = -2, value_parser = clap::value_parser!(i64).range(-5..))]
+    pub list_max_listpack_size: i64,
+
+    /// Largest number of fields of a hash kept as a listpack
+    #[arg(long, default_value_t = 128)]
+    pub hash_max_listpack_entries: usize,
+
+    /// Largest field or value of a hash kept as a listpack, in bytes
+    #[arg(long, default_value_t = 64)]
+    pub hash_max_listpack_value: usize,
+
+    /// Largest number of members of a set kept as an intset
+    #[arg(long, default_value_t = 512)]
+    pub set_max_intset_entries: usize,
+
+    /// Largest number of members of a sorted set kept as a listpack
+    #[arg(long, default_value_t = 128)]
+    pub zset_max_listpack_entries: usize,
+
+    /// Largest member of a sorted set kept as a listpack, in bytes
+    #[arg(long, default_value_t = 64)]
+    pub zset_max_listpack_value: usize,
 
     #[arg(long, default_value = "./redrust-master.aof")]
     pub aof_file: String,
+
+    /// Log every write command to the append-only file
+    #[arg(long, default_value_t = false)]
+    pub appendonly: bool,
+
+    /// Load an append-only file whose last command was cut short, truncating
+    /// it, instead of refusing to start
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub aof_load_truncated: bool,
+
+    /// fsync policy of the append-only file
+    #[arg(long, value_enum, default_value_t = AppendFsync::Everysec)]
+    pub appendfsync: AppendFsync,
+
+    /// Refuse every write command, the way a read-only replica does
+    #[arg(long, default_value_t = false)]
+    pub read_only: bool,
+
+    /// File the snapshot is saved to and loaded from when the AOF is off
+    #[arg(long, default_value = "./dump.rdb")]
+    pub dbfilename: String,
+
+    /// Snapshot rules as "<seconds> <changes>" pairs, "" disables them
+    #[arg(long, default_value = "3600 1 300 100 60 10000", value_parser = parse_save_params)]
+    pub save: SaveParams,
 }
@@ -0,0 +1,223 @@
+use chrono::Utc;
+
+use crate::core::{client::Client, cmd::Command, eval};
+use crate::data::store::Store;
+
+/// What a client blocked by BLPOP and friends is waiting for.
+pub struct BlockedState {
+    /// Command to run again once one of `keys` is ready.
+    pub cmd: Command,
+    pub keys: Vec<Vec<u8>>,
+    /// Unix time in ms after which the client gives up, 0 to wait forever.
+    pub timeout_at: i64,
+    /// Reply sent when the timeout expires.
+    pub timeout_reply: Vec<u8>,
+}
+
+/// Parses the timeout of a blocking command, in seconds with decimals, into
+/// an absolute time in ms. 0 means no timeout.
+pub fn parse_timeout(arg: &[u8]) -> anyhow::Result<i64> {
+    let timeout = std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|t| t.is_finite())
+        .ok_or_else(|| anyhow::anyhow!("ERR timeout is not a float or out of range"))?;
+    if timeout < 0.0 {
+        return Err(anyhow::anyhow!("ERR timeout is negative"));
+    }
+    if timeout == 0.0 {
+        return Ok(0);
+    }
+
+    let ms = (timeout * 1000.0).ceil();
+    if ms > (i64::MAX / 2) as f64 {
+        return Err(anyhow::anyhow!("ERR timeout is out of range"));
+    }
+
+    return Ok(Utc::now().timestamp_millis() + ms as i64);
+}
+
+/// Parks `client` until one of `keys` is created as a `key_type` or
+/// `timeout_at` passes, and returns the reply of the command: nothing until
+/// then. A transaction can't wait, so inside one it times out right away.
+/// A command run again by `reprocess` keeps its original deadline, rather
+/// than one computed from the time it was woken up.
+pub fn block_for_keys(
+    client: &mut Client,
+    store: &mut Store,
+    cmd: Command,
+    keys: Vec<Vec<u8>>,
+    key_type: u8,
+    timeout_at: i64,
+    timeout_reply: Vec<u8>,
+) -> Vec<u8> {
+    if client.multi.is_some() {
+        return timeout_reply;
+    }
+
+    store.block_on_keys(client.id, &keys, key_type);
+    client.blocked = Some(BlockedState {
+        cmd,
+        keys,
+        timeout_at: client.reprocessing_timeout_at.unwrap_or(timeout_at),
+        timeout_reply,
+    });
+    return vec![];
+}
+
+/// Takes `client` out of the blocked state, e.g. when it disconnects.
+pub fn unblock(client: &mut Client, store: &mut Store) -> Option<BlockedState> {
+    let state = client.blocked.take()?;
+    store.unblock_from_keys(client.id, client.db, &state.keys);
+    return Some(state);
+}
+
+/// Runs the blocked command of `client` again now that one of its keys is
+/// ready, followed by the commands it sent meanwhile. Returns the replies.
+pub fn reprocess(client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(state) = unblock(client, store) else {
+        return vec![];
+    };
+
+    client.reprocessing_timeout_at = Some(state.timeout_at);
+    let mut reply = eval::call(state.cmd, client, store);
+    client.reprocessing_timeout_at = None;
+    reply.extend(eval::process_pending(client, store));
+    return reply;
+}
+
+/// Unblocks `client` if its timeout expired at `now`, returns the replies
+/// it is owed.
+pub fn expire_timeout(client: &mut Client, store: &mut Store, now: i64) -> Option<Vec<u8>> {
+    let state = client.blocked.as_ref()?;
+    if state.timeout_at == 0 || state.timeout_at > now {
+        return None;
+    }
+
+    let state = unblock(client, store)?;
+    let mut reply = state.timeout_reply;
+    reply.extend(eval::process_pending(client, store));
+    return Some(reply);
+}
+
+/// Serves the clients blocked on keys that were created since the last
+/// call. Clients blocked on the same key are served in the order they
+/// blocked, for as long as the key exists: once the first ones consumed it,
+/// the others keep waiting. Clients waiting for another type of key are
+/// skipped. `serve` is given the id of each client to `reprocess`.
+pub fn handle_clients_blocked_on_keys(store: &mut Store, mut serve: impl FnMut(u64, &mut Store)) {
+    loop {
+        // Serving clients may create keys, e.g. BLMOVE's destination
+        let keys = store.take_ready_keys();
+        if keys.is_empty() {
+            return;
+        }
+
+        for (db, key) in keys {
+            // Clients only block on keys of the database they selected, which
+            // serving them selects again
+            store.select(db);
+            for (id, key_type) in store.clients_blocked_on(&key) {
+                if !store.exists(&key) {
+                    break;
+                }
+                if store.get_ref(&key).is_some_and(|obj| obj.get_type() == key_type) {
+                    serve(id, store);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{config::Config, data::store::TYPE_LIST};
+
+    fn command(args: &[&str]) -> Command {
+        return Command {
+            cmd: args[0].to_uppercase(),
+            args: args[1..].iter().map(|a| a.as_bytes().to_vec()).collect(),
+        };
+    }
+
+    #[test]
+    fn test_clients_are_served_first_come_first() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut first = Client::new();
+        let mut second = Client::new();
+        let mut pusher = Client::new();
+
+        for client in [&mut first, &mut second] {
+            assert!(eval::call(command(&["blpop", "q", "0"]), client, &mut store).is_empty());
+            client.pending.push_back(command(&["ping"]));
+        }
+        assert!(first.blocked.is_some() && second.blocked.is_some());
+
+        eval::call(command(&["rpush", "q", "a"]), &mut pusher, &mut store);
+        let mut served = Vec::new();
+        handle_clients_blocked_on_keys(&mut store, |id, store| {
+            let client = if id == first.id {
+                &mut first
+            } else {
+                &mut second
+            };
+            served.push((id, reprocess(client, store)));
+        });
+
+        assert_eq!(
+            served,
+            vec![(first.id, b"*2\r\n$1\r\nq\r\n$1\r\na\r\n+PONG\r\n".to_vec())]
+        );
+        assert!(first.blocked.is_none());
+        assert!(second.blocked.is_some());
+
+        let now = Utc::now().timestamp_millis();
+        assert_eq!(expire_timeout(&mut second, &mut store, now), None);
+        second.blocked.as_mut().unwrap().timeout_at = now;
+        assert_eq!(
+            expire_timeout(&mut second, &mut store, now),
+            Some(b"*-1\r\n+PONG\r\n".to_vec())
+        );
+        assert!(store.clients_blocked_on(b"q").is_empty());
+    }
+
+    #[test]
+    fn test_clients_are_only_served_by_their_type() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut list_client = Client::new();
+        let mut zset_client = Client::new();
+        let mut writer = Client::new();
+
+        eval::call(command(&["blpop", "k", "0"]), &mut list_client, &mut store);
+        eval::call(command(&["bzpopmin", "k", "0"]), &mut zset_client, &mut store);
+        eval::call(command(&["zadd", "k", "1", "a"]), &mut writer, &mut store);
+
+        let mut served = Vec::new();
+        handle_clients_blocked_on_keys(&mut store, |id, store| {
+            assert_eq!(id, zset_client.id);
+            served.push(reprocess(&mut zset_client, store));
+        });
+
+        assert_eq!(served, vec![b"*3\r\n$1\r\nk\r\n$1\r\na\r\n$1\r\n1\r\n".to_vec()]);
+        assert!(list_client.blocked.is_some());
+        assert_eq!(store.clients_blocked_on(b"k"), vec![(list_client.id, TYPE_LIST)]);
+    }
+
+    #[test]
+    fn test_clients_blocked_again_keep_their_timeout() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut client = Client::new();
+
+        eval::call(command(&["blpop", "k", "100"]), &mut client, &mut store);
+        let timeout_at = client.blocked.as_ref().unwrap().timeout_at - 50_000;
+        client.blocked.as_mut().unwrap().timeout_at = timeout_at;
+
+        // Woken up for nothing, e.g. another client took the element first
+        assert!(reprocess(&mut client, &mut store).is_empty());
+        assert_eq!(client.blocked.as_ref().unwrap().timeout_at, timeout_at);
+        assert_eq!(client.reprocessing_timeout_at, None);
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use super::{
+    blocked::BlockedState,
+    cmd::Command,
+    multi::MultiState,
+    resp::{RequestParser, RESP2},
+};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State the server keeps for each connection between reads.
+pub struct Client {
+    /// Unique, monotonically increasing connection id.
+    pub id: u64,
+
+    /// Bytes received from the socket that don't form a complete command
+    /// yet. Frames split across reads accumulate here until they can be
+    /// decoded.
+    pub query_buf: Vec<u8>,
+
+    /// How far the request at the start of `query_buf` was parsed.
+    pub request: RequestParser,
+
+    /// RESP version negotiated with HELLO, 2 until the client asks otherwise.
+    pub protover: u8,
+
+    /// Name given with HELLO SETNAME.
+    pub name: Option<Vec<u8>>,
+
+    /// Database picked with SELECT, 0 until the client asks otherwise.
+    pub db: usize,
+
+    /// Set while the client waits on a blocking command like BLPOP.
+    pub blocked: Option<BlockedState>,
+
+    /// Deadline of the blocked command `reprocess` is running again, which
+    /// it keeps if it has to block once more.
+    pub reprocessing_timeout_at: Option<i64>,
+
+    /// Commands received while blocked, they run once it is unblocked.
+    pub pending: VecDeque<Command>,
+
+    /// Set between MULTI and EXEC or DISCARD.
+    pub multi: Option<MultiState>,
+
+    /// Keys watched with WATCH, along with their database.
+    pub watched_keys: Vec<(usize, Vec<u8>)>,
+
+    /// Replies the socket didn't take yet, written once it is writable
+    /// again. `reply_sent` bytes of them already were. No command is read
+    /// from the client meanwhile.
+    pub reply_buf: Vec<u8>,
+    pub reply_sent: usize,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        return Client {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            query_buf: Vec::new(),
+            request: RequestParser::default(),
+            protover: RESP2,
+            name: None,
+            db: 0,
+            blocked: None,
+            reprocessing_timeout_at: None,
+            pending: VecDeque::new(),
+            multi: None,
+            watched_keys: Vec::new(),
+            reply_buf: Vec::new(),
+            reply_sent: 0,
+        };
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        return Client::new();
+    }
+}
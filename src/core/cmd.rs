@@ -1,6 +1,474 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::anyhow;
+
+use crate::{
+    common::{parse_i64, Value},
+    data::store::Store,
+};
+
+use super::{
+    client::Client,
+    eval::{hash, keyspace, list, server, set, stream, string, transaction, zset},
+};
+
 pub struct Command {
     pub cmd: String,
-    pub args: Vec<String>,
+    pub args: Vec<Vec<u8>>,
 }
 
 pub type Commands = Vec<Command>;
+
+impl TryFrom<Value> for Command {
+    type Error = anyhow::Error;
+
+    /// Builds a command out of a decoded RESP array of bulk strings.
+    fn try_from(value: Value) -> anyhow::Result<Command> {
+        let Value::Vector(values) = value else {
+            return Err(anyhow!("Value is not a Vec type"));
+        };
+        if values.is_empty() {
+            return Err(anyhow!("Empty command"));
+        }
+
+        return Ok(Command::from(values.iter().map(Value::to_bytes).collect::<Vec<_>>()));
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Command {
+    /// Builds a command out of its name followed by its arguments.
+    fn from(argv: Vec<Vec<u8>>) -> Command {
+        let mut tokens = argv.into_iter();
+        let cmd = String::from_utf8_lossy(&tokens.next().unwrap_or_default()).to_uppercase();
+
+        return Command {
+            cmd,
+            args: tokens.collect(),
+        };
+    }
+}
+
+/// Signature shared by every command implementation. `args` excludes the
+/// command name itself.
+pub type CommandFn = fn(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8>;
+
+/// Finds the positions of the keys in `args`, which excludes the command
+/// name, for commands whose keys can't be told by their first/last/step, like
+/// Redis' getkeys procs.
+pub type GetKeysFn = fn(args: &[Vec<u8>]) -> Vec<usize>;
+
+/// The command may modify the dataset.
+pub const CMD_WRITE: u32 = 1 << 0;
+/// The command only reads from the dataset.
+pub const CMD_READONLY: u32 = 1 << 1;
+/// The command may grow memory usage and is refused when over the limit.
+pub const CMD_DENYOOM: u32 = 1 << 2;
+/// Administrative command, not meant for regular clients.
+pub const CMD_ADMIN: u32 = 1 << 3;
+/// The command runs in O(1) or O(log(N)).
+pub const CMD_FAST: u32 = 1 << 4;
+
+const FLAG_NAMES: &[(u32, &str)] = &[
+    (CMD_WRITE, "write"),
+    (CMD_READONLY, "readonly"),
+    (CMD_DENYOOM, "denyoom"),
+    (CMD_ADMIN, "admin"),
+    (CMD_FAST, "fast"),
+];
+
+/// Static description of a command, the same metadata Redis exposes through
+/// `COMMAND INFO`.
+pub struct CommandSpec {
+    /// Lowercase command name.
+    pub name: &'static str,
+    /// Number of arguments including the command name. A negative value `-N`
+    /// means "at least N".
+    pub arity: i32,
+    pub flags: u32,
+    /// Position of the first key in the argument vector (the command name is
+    /// at position 0), or 0 if the command takes no keys.
+    pub first_key: i32,
+    /// Position of the last key, negative values count from the end.
+    pub last_key: i32,
+    /// Distance between two consecutive keys.
+    pub step: i32,
+    /// Set when the keys depend on the arguments, e.g. a number of keys,
+    /// rather than only on first/last/step.
+    pub getkeys: Option<GetKeysFn>,
+    pub handler: CommandFn,
+}
+
+impl CommandSpec {
+    pub fn is_write(&self) -> bool {
+        return self.flags & CMD_WRITE != 0;
+    }
+
+    /// Checks the number of arguments, `argc` includes the command name.
+    pub fn check_arity(&self, argc: usize) -> bool {
+        let argc = argc as i32;
+
+        return if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        };
+    }
+
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = FLAG_NAMES
+            .iter()
+            .filter(|(flag, _)| self.flags & flag != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        if self.getkeys.is_some() {
+            names.push("movablekeys");
+        }
+
+        return names;
+    }
+
+    /// Positions of the keys in `args`, which excludes the command name.
+    pub fn key_positions(&self, args: &[Vec<u8>]) -> Vec<usize> {
+        if let Some(getkeys) = self.getkeys {
+            return getkeys(args);
+        }
+        if self.first_key == 0 {
+            return vec![];
+        }
+
+        let argc = args.len() as i32 + 1;
+        let last = if self.last_key < 0 {
+            argc + self.last_key
+        } else {
+            self.last_key.min(argc - 1)
+        };
+
+        let mut positions = Vec::new();
+        let mut pos = self.first_key;
+        while pos <= last {
+            positions.push((pos - 1) as usize);
+            pos += self.step;
+        }
+
+        return positions;
+    }
+}
+
+/// Positions of the keys of a command taking a number of keys at `at`, then
+/// the keys. Empty if the number isn't valid.
+fn numkeys_keys(args: &[Vec<u8>], at: usize) -> Vec<usize> {
+    let numkeys = args
+        .get(at)
+        .and_then(|arg| parse_i64(arg))
+        .filter(|&n| n > 0 && (n as usize) < args.len() - at);
+
+    return match numkeys {
+        Some(n) => (at + 1..=at + n as usize).collect(),
+        None => vec![],
+    };
+}
+
+/// SINTERCARD: the number of keys first.
+fn sintercard_keys(args: &[Vec<u8>]) -> Vec<usize> {
+    return numkeys_keys(args, 0);
+}
+
+/// ZUNIONSTORE and ZINTERSTORE: the destination, then the number of keys.
+fn zstore_keys(args: &[Vec<u8>]) -> Vec<usize> {
+    let mut keys = vec![0];
+    keys.extend(numkeys_keys(args, 1));
+    return keys;
+}
+
+/// XREAD and XREADGROUP: the first half of the arguments after STREAMS, the
+/// other half are ids.
+fn xread_keys(args: &[Vec<u8>]) -> Vec<usize> {
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"COUNT" | b"BLOCK" => i += 1,
+            b"GROUP" => i += 2,
+            b"STREAMS" => {
+                let streams = args.len() - i - 1;
+                if !streams.is_multiple_of(2) {
+                    return vec![];
+                }
+                return (i + 1..i + 1 + streams / 2).collect();
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    return vec![];
+}
+
+macro_rules! command {
+    ($name: expr, $handler: expr, $arity: expr, $flags: expr, $first: expr, $last: expr, $step: expr) => {
+        command!($name, $handler, $arity, $flags, $first, $last, $step, None)
+    };
+    ($name: expr, $handler: expr, $arity: expr, $flags: expr, $first: expr, $last: expr, $step: expr, $getkeys: expr) => {
+        CommandSpec {
+            name: $name,
+            arity: $arity,
+            flags: $flags,
+            first_key: $first,
+            last_key: $last,
+            step: $step,
+            getkeys: $getkeys,
+            handler: $handler,
+        }
+    };
+}
+
+pub static COMMAND_TABLE: &[CommandSpec] = &[
+    // Server
+    command!("ping", server::ping, -1, CMD_FAST, 0, 0, 0),
+    command!("hello", server::hello, -1, CMD_FAST, 0, 0, 0),
+    command!("command", server::command, -1, 0, 0, 0, 0),
+    command!("bgrewriteaof", server::bg_rewrite_aof, 1, CMD_ADMIN, 0, 0, 0),
+    command!("save", server::save, 1, CMD_ADMIN, 0, 0, 0),
+    command!("bgsave", server::bgsave, -1, CMD_ADMIN, 0, 0, 0),
+    command!("lastsave", server::lastsave, 1, CMD_FAST, 0, 0, 0),
+    command!("info", server::info, -1, 0, 0, 0, 0),
+    command!("memory", server::memory, -2, CMD_READONLY, 0, 0, 0),
+    // Transactions
+    command!("multi", transaction::multi, 1, CMD_FAST, 0, 0, 0),
+    command!("exec", transaction::exec, 1, 0, 0, 0, 0),
+    command!("discard", transaction::discard, 1, CMD_FAST, 0, 0, 0),
+    command!("watch", transaction::watch, -2, CMD_FAST, 1, -1, 1),
+    command!("unwatch", transaction::unwatch, 1, CMD_FAST, 0, 0, 0),
+    // Strings
+    command!("get", string::get, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("set", string::set, -3, CMD_WRITE | CMD_DENYOOM, 1, 1, 1),
+    command!("setnx", string::setnx, 3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("setex", string::setex, 4, CMD_WRITE | CMD_DENYOOM, 1, 1, 1),
+    command!("psetex", string::psetex, 4, CMD_WRITE | CMD_DENYOOM, 1, 1, 1),
+    command!("mget", string::mget, -2, CMD_READONLY | CMD_FAST, 1, -1, 1),
+    command!("mset", string::mset, -3, CMD_WRITE | CMD_DENYOOM, 1, -1, 2),
+    command!("msetnx", string::msetnx, -3, CMD_WRITE | CMD_DENYOOM, 1, -1, 2),
+    command!("getset", string::getset, 3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("getdel", string::getdel, 2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("getex", string::getex, -2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("append", string::append, 3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("strlen", string::strlen, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("getrange", string::getrange, 4, CMD_READONLY, 1, 1, 1),
+    command!("setrange", string::setrange, 4, CMD_WRITE | CMD_DENYOOM, 1, 1, 1),
+    command!("incr", string::incr, 2, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("incrby", string::incrby, 3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("decr", string::decr, 2, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("decrby", string::decrby, 3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("incrbyfloat", string::incrbyfloat, 3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("lcs", string::lcs, -3, CMD_READONLY, 1, 2, 1),
+    // Keyspace
+    command!("keys", keyspace::keys, 2, CMD_READONLY, 0, 0, 0),
+    command!("scan", keyspace::scan, -2, CMD_READONLY, 0, 0, 0),
+    command!("del", keyspace::del, -2, CMD_WRITE, 1, -1, 1),
+    command!("unlink", keyspace::unlink, -2, CMD_WRITE | CMD_FAST, 1, -1, 1),
+    command!("exists", keyspace::exists, -2, CMD_READONLY | CMD_FAST, 1, -1, 1),
+    command!("touch", keyspace::touch, -2, CMD_READONLY | CMD_FAST, 1, -1, 1),
+    command!("type", keyspace::type_of, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("rename", keyspace::rename, 3, CMD_WRITE, 1, 2, 1),
+    command!("renamenx", keyspace::renamenx, 3, CMD_WRITE | CMD_FAST, 1, 2, 1),
+    command!("copy", keyspace::copy, -3, CMD_WRITE | CMD_DENYOOM, 1, 2, 1),
+    command!("move", keyspace::move_key, 3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("select", keyspace::select, 2, CMD_FAST, 0, 0, 0),
+    command!("swapdb", keyspace::swapdb, 3, CMD_WRITE | CMD_FAST, 0, 0, 0),
+    command!("persist", keyspace::persist, 2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("randomkey", keyspace::randomkey, 1, CMD_READONLY, 0, 0, 0),
+    command!("dbsize", keyspace::dbsize, 1, CMD_READONLY | CMD_FAST, 0, 0, 0),
+    command!("flushdb", keyspace::flushdb, -1, CMD_WRITE, 0, 0, 0),
+    command!("flushall", keyspace::flushall, -1, CMD_WRITE, 0, 0, 0),
+    command!("expire", keyspace::expire, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("pexpire", keyspace::pexpire, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("expireat", keyspace::expireat, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("pexpireat", keyspace::pexpireat, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("ttl", keyspace::ttl, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("pttl", keyspace::pttl, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("expiretime", keyspace::expiretime, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("pexpiretime", keyspace::pexpiretime, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("object", keyspace::object, -2, CMD_READONLY, 2, 2, 1),
+    // Lists
+    command!("lpush", list::lpush, -3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("rpush", list::rpush, -3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("lpushx", list::lpushx, -3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("rpushx", list::rpushx, -3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("lpop", list::lpop, -2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("rpop", list::rpop, -2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("llen", list::llen, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("lrange", list::lrange, 4, CMD_READONLY, 1, 1, 1),
+    command!("lindex", list::lindex, 3, CMD_READONLY, 1, 1, 1),
+    command!("lset", list::lset, 4, CMD_WRITE | CMD_DENYOOM, 1, 1, 1),
+    command!("ltrim", list::ltrim, 4, CMD_WRITE, 1, 1, 1),
+    command!("lrem", list::lrem, 4, CMD_WRITE, 1, 1, 1),
+    command!("linsert", list::linsert, 5, CMD_WRITE | CMD_DENYOOM, 1, 1, 1),
+    command!("lpos", list::lpos, -3, CMD_READONLY, 1, 1, 1),
+    command!("lmove", list::lmove, 5, CMD_WRITE | CMD_DENYOOM, 1, 2, 1),
+    command!("rpoplpush", list::rpoplpush, 3, CMD_WRITE | CMD_DENYOOM, 1, 2, 1),
+    command!("blpop", list::blpop, -3, CMD_WRITE, 1, -2, 1),
+    command!("brpop", list::brpop, -3, CMD_WRITE, 1, -2, 1),
+    command!("blmove", list::blmove, 6, CMD_WRITE | CMD_DENYOOM, 1, 2, 1),
+    command!("brpoplpush", list::brpoplpush, 4, CMD_WRITE | CMD_DENYOOM, 1, 2, 1),
+    // Hashes
+    command!("hset", hash::hset, -4, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("hmset", hash::hmset, -4, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("hsetnx", hash::hsetnx, 4, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("hget", hash::hget, 3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hmget", hash::hmget, -3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hgetall", hash::hgetall, 2, CMD_READONLY, 1, 1, 1),
+    command!("hkeys", hash::hkeys, 2, CMD_READONLY, 1, 1, 1),
+    command!("hvals", hash::hvals, 2, CMD_READONLY, 1, 1, 1),
+    command!("hdel", hash::hdel, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("hexists", hash::hexists, 3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hlen", hash::hlen, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hstrlen", hash::hstrlen, 3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hincrby", hash::hincrby, 4, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("hincrbyfloat", hash::hincrbyfloat, 4, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("hrandfield", hash::hrandfield, -2, CMD_READONLY, 1, 1, 1),
+    command!("hscan", hash::hscan, -3, CMD_READONLY, 1, 1, 1),
+    command!("hexpire", hash::hexpire, -6, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("hpexpire", hash::hpexpire, -6, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("hexpireat", hash::hexpireat, -6, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("hpexpireat", hash::hpexpireat, -6, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("httl", hash::httl, -5, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hpttl", hash::hpttl, -5, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hexpiretime", hash::hexpiretime, -5, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hpexpiretime", hash::hpexpiretime, -5, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("hpersist", hash::hpersist, -5, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    // Sets
+    command!("sadd", set::sadd, -3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("srem", set::srem, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("sismember", set::sismember, 3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("smismember", set::smismember, -3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("smembers", set::smembers, 2, CMD_READONLY, 1, 1, 1),
+    command!("scard", set::scard, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("spop", set::spop, -2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("srandmember", set::srandmember, -2, CMD_READONLY, 1, 1, 1),
+    command!("smove", set::smove, 4, CMD_WRITE | CMD_FAST, 1, 2, 1),
+    command!("sinter", set::sinter, -2, CMD_READONLY, 1, -1, 1),
+    command!("sunion", set::sunion, -2, CMD_READONLY, 1, -1, 1),
+    command!("sdiff", set::sdiff, -2, CMD_READONLY, 1, -1, 1),
+    command!("sinterstore", set::sinterstore, -3, CMD_WRITE | CMD_DENYOOM, 1, -1, 1),
+    command!("sunionstore", set::sunionstore, -3, CMD_WRITE | CMD_DENYOOM, 1, -1, 1),
+    command!("sdiffstore", set::sdiffstore, -3, CMD_WRITE | CMD_DENYOOM, 1, -1, 1),
+    command!("sintercard", set::sintercard, -3, CMD_READONLY, 0, 0, 0, Some(sintercard_keys)),
+    command!("sscan", set::sscan, -3, CMD_READONLY, 1, 1, 1),
+    // Sorted sets
+    command!("zadd", zset::zadd, -4, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("zincrby", zset::zincrby, 4, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("zrem", zset::zrem, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("zcard", zset::zcard, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("zscore", zset::zscore, 3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("zcount", zset::zcount, 4, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("zlexcount", zset::zlexcount, 4, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("zrange", zset::zrange, -4, CMD_READONLY, 1, 1, 1),
+    command!("zrank", zset::zrank, -3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("zrevrank", zset::zrevrank, -3, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("zremrangebyrank", zset::zremrangebyrank, 4, CMD_WRITE, 1, 1, 1),
+    command!("zremrangebyscore", zset::zremrangebyscore, 4, CMD_WRITE, 1, 1, 1),
+    command!("zremrangebylex", zset::zremrangebylex, 4, CMD_WRITE, 1, 1, 1),
+    command!("zpopmin", zset::zpopmin, -2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("zpopmax", zset::zpopmax, -2, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("bzpopmin", zset::bzpopmin, -3, CMD_WRITE | CMD_FAST, 1, -2, 1),
+    command!("bzpopmax", zset::bzpopmax, -3, CMD_WRITE | CMD_FAST, 1, -2, 1),
+    command!("zunionstore", zset::zunionstore, -4, CMD_WRITE | CMD_DENYOOM, 1, 1, 1, Some(zstore_keys)),
+    command!("zinterstore", zset::zinterstore, -4, CMD_WRITE | CMD_DENYOOM, 1, 1, 1, Some(zstore_keys)),
+    command!("zscan", zset::zscan, -3, CMD_READONLY, 1, 1, 1),
+    // Streams
+    command!("xadd", stream::xadd, -5, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("xtrim", stream::xtrim, -4, CMD_WRITE, 1, 1, 1),
+    command!("xdel", stream::xdel, -3, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("xlen", stream::xlen, 2, CMD_READONLY | CMD_FAST, 1, 1, 1),
+    command!("xrange", stream::xrange, -4, CMD_READONLY, 1, 1, 1),
+    command!("xrevrange", stream::xrevrange, -4, CMD_READONLY, 1, 1, 1),
+    command!("xread", stream::xread, -4, CMD_READONLY, 0, 0, 0, Some(xread_keys)),
+    command!("xreadgroup", stream::xreadgroup, -7, CMD_WRITE, 0, 0, 0, Some(xread_keys)),
+    command!("xgroup", stream::xgroup, -2, CMD_WRITE | CMD_DENYOOM, 2, 2, 1),
+    command!("xack", stream::xack, -4, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("xpending", stream::xpending, -3, CMD_READONLY, 1, 1, 1),
+    command!("xclaim", stream::xclaim, -6, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("xautoclaim", stream::xautoclaim, -6, CMD_WRITE | CMD_FAST, 1, 1, 1),
+    command!("xsetid", stream::xsetid, -3, CMD_WRITE | CMD_DENYOOM | CMD_FAST, 1, 1, 1),
+    command!("xinfo", stream::xinfo, -2, CMD_READONLY, 2, 2, 1),
+];
+
+/// Finds a command by name, case-insensitively.
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    static INDEX: OnceLock<HashMap<&'static str, &'static CommandSpec>> = OnceLock::new();
+
+    let index = INDEX.get_or_init(|| COMMAND_TABLE.iter().map(|c| (c.name, c)).collect());
+
+    return index.get(name.to_ascii_lowercase().as_str()).copied();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(tokens: &[&str]) -> Vec<Vec<u8>> {
+        return tokens.iter().map(|t| t.as_bytes().to_vec()).collect();
+    }
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        assert_eq!(lookup("GET").map(|c| c.name), Some("get"));
+        assert_eq!(lookup("gEt").map(|c| c.name), Some("get"));
+        assert!(lookup("gte").is_none());
+    }
+
+    #[test]
+    fn test_arity() {
+        let get = lookup("get").unwrap();
+        assert!(get.check_arity(2));
+        assert!(!get.check_arity(3));
+
+        let set = lookup("set").unwrap();
+        assert!(!set.check_arity(2));
+        assert!(set.check_arity(3));
+        assert!(set.check_arity(5));
+    }
+
+    #[test]
+    fn test_key_positions() {
+        let del = lookup("del").unwrap();
+        assert_eq!(del.key_positions(&args(&["a", "b", "c"])), vec![0, 1, 2]);
+
+        let set = lookup("set").unwrap();
+        assert_eq!(set.key_positions(&args(&["k", "v", "EX", "10"])), vec![0]);
+
+        let ping = lookup("ping").unwrap();
+        assert!(ping.key_positions(&args(&["hello"])).is_empty());
+    }
+
+    #[test]
+    fn test_movable_key_positions() {
+        let sintercard = lookup("sintercard").unwrap();
+        assert_eq!(
+            sintercard.key_positions(&args(&["2", "a", "b", "LIMIT", "1"])),
+            vec![1, 2]
+        );
+        assert!(sintercard.key_positions(&args(&["0", "a"])).is_empty());
+
+        let zunionstore = lookup("zunionstore").unwrap();
+        assert_eq!(
+            zunionstore.key_positions(&args(&["d", "2", "a", "b", "WEIGHTS", "1", "2"])),
+            vec![0, 2, 3]
+        );
+        assert!(zunionstore.flag_names().contains(&"movablekeys"));
+        // Only the destination is sure to be a key
+        assert_eq!(zunionstore.key_positions(&args(&["d", "3", "a", "b"])), vec![0]);
+        assert_eq!(zunionstore.key_positions(&args(&["d", "x", "a"])), vec![0]);
+
+        let xread = lookup("xread").unwrap();
+        assert_eq!(
+            xread.key_positions(&args(&["COUNT", "2", "STREAMS", "a", "b", "0", "$"])),
+            vec![3, 4]
+        );
+        assert!(xread.key_positions(&args(&["STREAMS", "a", "b", "0"])).is_empty());
+        let xreadgroup = lookup("xreadgroup").unwrap();
+        assert_eq!(
+            xreadgroup.key_positions(&args(&["GROUP", "g", "c", "NOACK", "STREAMS", "s", ">"])),
+            vec![5]
+        );
+    }
+}
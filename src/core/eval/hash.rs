@@ -0,0 +1,730 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use chrono::Utc;
+
+use crate::common::{format_double, parse_f64, parse_i64, random_u64, Value};
+use crate::core::{
+    client::Client,
+    resp::{encode, encode_error, encode_proto, nil, RESP3, RESP_OK, RESP_ONE, RESP_ZERO},
+};
+use crate::data::{
+    hash::{Hash, HashLimits},
+    store::{ObjectValue, Store, StoreObject, ENCODING_LISTPACK, TYPE_HASH},
+};
+
+use super::keyspace::{parse_scan_args, scan_buckets, scan_reply, ExpireCondition};
+
+/// Latest field expiry accepted, in milliseconds, same as Redis.
+const FIELD_EXPIRE_MAX_MS: i64 = (1 << 48) - 1;
+
+/// The hash at `key`, `None` if there is no such key.
+fn get_hash<'a>(store: &'a mut Store, key: &[u8]) -> anyhow::Result<Option<&'a Hash>> {
+    // Gets rid of the expired fields first
+    if with_hash(store, key, |_, _| ())?.is_none() {
+        return Ok(None);
+    }
+
+    return match store.peek(key).map(|obj| &obj.value) {
+        Some(ObjectValue::Hash(hash)) => Ok(Some(hash)),
+        _ => Ok(None),
+    };
+}
+
+/// Runs `f` on the hash at `key`, `None` if there is no such key. Fields
+/// that expired are removed before `f` sees the hash, except while loading
+/// like for keys, and like every aggregate, a hash that ends up empty is
+/// deleted.
+fn with_hash<T>(
+    store: &mut Store,
+    key: &[u8],
+    f: impl FnOnce(&mut Hash, HashLimits) -> T,
+) -> anyhow::Result<Option<T>> {
+    let limits = store.hash_limits();
+    let loading = store.is_loading();
+    let Some(obj) = store.get_mut(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_HASH)?;
+
+    let ObjectValue::Hash(hash) = &mut obj.value else {
+        unreachable!("hash typed object doesn't hold a hash");
+    };
+    if !loading && hash.remove_expired(Utc::now().timestamp_millis()) > 0 && hash.is_empty() {
+        store.del(key);
+        return Ok(None);
+    }
+
+    let result = f(hash, limits);
+    let empty = hash.is_empty();
+    obj.refresh_encoding();
+
+    if empty {
+        store.del(key);
+    } else {
+        store.track_field_expiries(key);
+    }
+
+    return Ok(Some(result));
+}
+
+/// Makes sure there is a hash at `key` to set fields in.
+fn create_hash_if_missing(store: &mut Store, key: &[u8]) -> anyhow::Result<()> {
+    // A hash whose fields all expired is as good as missing
+    if with_hash(store, key, |_, _| ())?.is_some() {
+        return Ok(());
+    }
+
+    let hash = ObjectValue::Hash(Hash::new());
+    store.put(
+        key.to_vec(),
+        StoreObject::new(hash, -1, TYPE_HASH, ENCODING_LISTPACK),
+    );
+
+    return Ok(());
+}
+
+/// Sets the field/value pairs of `args[1..]`, returns the number of new
+/// fields.
+fn set_fields(args: &[Vec<u8>], store: &mut Store, name: &str) -> anyhow::Result<usize> {
+    let key = &args[0];
+    let pairs = &args[1..];
+    if !pairs.len().is_multiple_of(2) {
+        return Err(anyhow!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ));
+    }
+    create_hash_if_missing(store, key)?;
+
+    let added = with_hash(store, key, |hash, limits| {
+        pairs
+            .chunks(2)
+            .filter(|pair| hash.set(&pair[0], &pair[1], limits))
+            .count()
+    })?;
+    store.add_dirty((pairs.len() / 2) as u64);
+
+    return Ok(added.unwrap_or(0));
+}
+
+pub fn hset(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match set_fields(&args, store, "hset") {
+        Ok(added) => encode(Value::Int64(added as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hmset(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match set_fields(&args, store, "hmset") {
+        Ok(_) => RESP_OK.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hsetnx(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, field) = (&args[0], &args[1]);
+    if let Err(err) = create_hash_if_missing(store, key) {
+        return encode_error(err);
+    }
+
+    let added = with_hash(store, key, |hash, limits| {
+        !hash.contains(field) && hash.set(field, &args[2], limits)
+    });
+
+    return match added {
+        Ok(Some(true)) => {
+            store.add_dirty(1);
+            RESP_ONE.to_vec()
+        }
+        Ok(_) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hget(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_hash(store, &args[0]) {
+        Ok(Some(hash)) => match hash.get(&args[1]) {
+            Some(v) => encode(Value::String(v.to_vec()), false),
+            None => nil(client.protover),
+        },
+        Ok(None) => nil(client.protover),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hmget(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let hash = match get_hash(store, &args[0]) {
+        Ok(hash) => hash,
+        Err(err) => return encode_error(err),
+    };
+
+    let values = args[1..]
+        .iter()
+        .map(|field| match hash.and_then(|h| h.get(field)) {
+            Some(v) => Value::String(v.to_vec()),
+            None => Value::Empty,
+        })
+        .collect();
+
+    return encode_proto(Value::Vector(values), false, client.protover);
+}
+
+pub fn hgetall(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let pairs = match get_hash(store, &args[0]) {
+        Ok(Some(hash)) => hash
+            .iter()
+            .map(|(f, v)| (Value::String(f.to_vec()), Value::String(v.to_vec())))
+            .collect(),
+        Ok(None) => vec![],
+        Err(err) => return encode_error(err),
+    };
+
+    return encode_proto(Value::Map(pairs), false, client.protover);
+}
+
+pub fn hkeys(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_hash(store, &args[0]) {
+        Ok(hash) => {
+            let fields = hash.map_or(vec![], |h| h.iter().map(|(f, _)| f.to_vec()).collect());
+            encode(Value::VectorString(fields), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hvals(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_hash(store, &args[0]) {
+        Ok(hash) => {
+            let values = hash.map_or(vec![], |h| h.iter().map(|(_, v)| v.to_vec()).collect());
+            encode(Value::VectorString(values), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hdel(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let fields = &args[1..];
+    let deleted = with_hash(store, &args[0], |hash, _| {
+        fields.iter().filter(|field| hash.remove(field)).count()
+    });
+
+    return match deleted {
+        Ok(deleted) => {
+            let deleted = deleted.unwrap_or(0);
+            store.add_dirty(deleted as u64);
+            encode(Value::Int64(deleted as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hexists(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_hash(store, &args[0]) {
+        Ok(Some(hash)) if hash.contains(&args[1]) => RESP_ONE.to_vec(),
+        Ok(_) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hlen(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_hash(store, &args[0]) {
+        Ok(hash) => encode(Value::Int64(hash.map_or(0, |h| h.len()) as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hstrlen(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_hash(store, &args[0]) {
+        Ok(hash) => {
+            let len = hash.and_then(|h| h.get(&args[1])).map_or(0, |v| v.len());
+            encode(Value::Int64(len as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hincrby(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, field) = (&args[0], &args[1]);
+    let Some(incr) = parse_i64(&args[2]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+    if let Err(err) = create_hash_if_missing(store, key) {
+        return encode_error(err);
+    }
+
+    let result = with_hash(store, key, |hash, limits| {
+        let current = match hash.get(field) {
+            Some(v) => parse_i64(v).ok_or_else(|| anyhow!("ERR hash value is not an integer"))?,
+            None => 0,
+        };
+        let new = current
+            .checked_add(incr)
+            .ok_or_else(|| anyhow!("ERR increment or decrement would overflow"))?;
+        hash.set_keep_ttl(field, new.to_string().as_bytes(), limits);
+        Ok(new)
+    });
+
+    return match result.and_then(|r| r.transpose()) {
+        Ok(new) => {
+            store.add_dirty(1);
+            encode(Value::Int64(new.unwrap_or(0)), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn hincrbyfloat(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, field) = (&args[0], &args[1]);
+    let Some(incr) = parse_f64(&args[2]) else {
+        return encode_error(anyhow!("ERR value is not a valid float"));
+    };
+    if !incr.is_finite() {
+        return encode_error(anyhow!("ERR increment would produce NaN or Infinity"));
+    }
+    if let Err(err) = create_hash_if_missing(store, key) {
+        return encode_error(err);
+    }
+
+    let result = with_hash(store, key, |hash, limits| {
+        let current = match hash.get(field) {
+            Some(v) => parse_f64(v).ok_or_else(|| anyhow!("ERR hash value is not a float"))?,
+            None => 0.0,
+        };
+        let new = current + incr;
+        if !new.is_finite() {
+            return Err(anyhow!("ERR increment would produce NaN or Infinity"));
+        }
+        let new = format_double(new).into_bytes();
+        hash.set_keep_ttl(field, &new, limits);
+        Ok((new, hash.expires_at(field).unwrap_or(-1)))
+    });
+
+    return match result.and_then(|r| r.transpose()) {
+        Ok(Some((new, expires_at))) => {
+            store.add_dirty(1);
+            // Replaying the increment could round differently, log the
+            // result instead. HSET drops the TTL of the field, put it back.
+            store.rewrite_propagation(vec![
+                b"HSET".to_vec(),
+                key.clone(),
+                field.clone(),
+                new.clone(),
+            ]);
+            if expires_at != -1 {
+                store.rewrite_propagation(vec![
+                    b"HPEXPIREAT".to_vec(),
+                    key.clone(),
+                    expires_at.to_string().into_bytes(),
+                    b"FIELDS".to_vec(),
+                    b"1".to_vec(),
+                    field.clone(),
+                ]);
+            }
+            encode(Value::String(new), false)
+        }
+        Ok(None) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+/// Picks `count` distinct entries of `hash`, which is bigger than that.
+fn distinct_random_entries(hash: &Hash, count: usize) -> Vec<(&[u8], &[u8])> {
+    // Close to the whole hash: drop random entries rather than picking
+    // random ones, which would mostly find already picked fields.
+    if count * 3 > hash.len() {
+        let mut entries: Vec<_> = hash.iter().collect();
+        while entries.len() > count {
+            entries.swap_remove(random_u64() as usize % entries.len());
+        }
+        return entries;
+    }
+
+    let mut seen = HashSet::new();
+    let mut entries = Vec::with_capacity(count);
+    while entries.len() < count {
+        if let Some((field, value)) = hash.random_entry() {
+            if seen.insert(field) {
+                entries.push((field, value));
+            }
+        }
+    }
+
+    return entries;
+}
+
+pub fn hrandfield(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(count) = args.get(1) else {
+        return match get_hash(store, &args[0]) {
+            Ok(Some(hash)) => match hash.random_entry() {
+                Some((field, _)) => encode(Value::String(field.to_vec()), false),
+                None => nil(client.protover),
+            },
+            Ok(None) => nil(client.protover),
+            Err(err) => encode_error(err),
+        };
+    };
+
+    let Some(count) = parse_i64(count) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+    let with_values = match args.get(2) {
+        None => false,
+        Some(arg) if args.len() == 3 && arg.eq_ignore_ascii_case(b"WITHVALUES") => true,
+        Some(_) => return encode_error(anyhow!("ERR syntax error")),
+    };
+    // The reply would be twice as long
+    if with_values && count.unsigned_abs() > i64::MAX as u64 / 2 {
+        return encode_error(anyhow!("ERR value is out of range"));
+    }
+
+    let hash = match get_hash(store, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return encode(Value::Vector(vec![]), false),
+        Err(err) => return encode_error(err),
+    };
+
+    let wanted = count.unsigned_abs() as usize;
+    let entries: Vec<(&[u8], &[u8])> = if count < 0 {
+        // Repetitions are allowed
+        (0..wanted).filter_map(|_| hash.random_entry()).collect()
+    } else if wanted >= hash.len() {
+        hash.iter().collect()
+    } else {
+        distinct_random_entries(hash, wanted)
+    };
+
+    if !with_values {
+        let fields = entries.iter().map(|(f, _)| f.to_vec()).collect();
+        return encode(Value::VectorString(fields), false);
+    }
+    if client.protover >= RESP3 {
+        let pairs = entries
+            .iter()
+            .map(|(f, v)| Value::VectorString(vec![f.to_vec(), v.to_vec()]))
+            .collect();
+        return encode_proto(Value::Vector(pairs), false, client.protover);
+    }
+
+    let flat = entries
+        .iter()
+        .flat_map(|(f, v)| [f.to_vec(), v.to_vec()])
+        .collect();
+    return encode(Value::VectorString(flat), false);
+}
+
+pub fn hscan(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (cursor, opts) = match parse_scan_args(&args[1..], true, false) {
+        Ok(parsed) => parsed,
+        Err(err) => return encode_error(err),
+    };
+
+    let hash = match get_hash(store, &args[0]) {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return scan_reply(0, vec![]),
+        Err(err) => return encode_error(err),
+    };
+
+    let mut items = Vec::new();
+    let cursor = scan_buckets(cursor, opts.count, |cursor| {
+        let next = hash.scan(cursor, |field, value| {
+            if opts.matches(field) {
+                items.push(field.to_vec());
+                if !opts.no_values {
+                    items.push(value.to_vec());
+                }
+            }
+        });
+        let found = if opts.no_values {
+            items.len()
+        } else {
+            items.len() / 2
+        };
+        (next, found)
+    });
+
+    return scan_reply(cursor, items);
+}
+
+/// The fields of `FIELDS numfields field [field ...]`, which starts at
+/// `args[at]` and runs to the end.
+fn parse_fields(args: &[Vec<u8>], at: usize) -> anyhow::Result<&[Vec<u8>]> {
+    if at + 1 >= args.len() || !args[at].eq_ignore_ascii_case(b"FIELDS") {
+        return Err(anyhow!(
+            "ERR Mandatory argument FIELDS is missing or not at the right position"
+        ));
+    }
+
+    let fields = &args[(at + 2)..];
+    match parse_i64(&args[at + 1]) {
+        Some(n) if n > 0 && n as usize == fields.len() => return Ok(fields),
+        Some(n) if n > 0 => {
+            return Err(anyhow!(
+                "ERR The `numfields` parameter must match the number of arguments"
+            ))
+        }
+        _ => {
+            return Err(anyhow!(
+                "ERR Parameter `numFields` should be greater than 0"
+            ))
+        }
+    }
+}
+
+fn integers(replies: Vec<i64>) -> Vec<u8> {
+    return encode(
+        Value::Vector(replies.into_iter().map(Value::Int64).collect()),
+        false,
+    );
+}
+
+/// HEXPIRE and friends, `unit_ms` is the unit of the time argument and
+/// `absolute` whether it is a unix time rather than a TTL.
+fn field_expire_generic(
+    args: Vec<Vec<u8>>,
+    store: &mut Store,
+    unit_ms: i64,
+    absolute: bool,
+    name: &str,
+) -> Vec<u8> {
+    let key = &args[0];
+    let Some(time) = parse_i64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+    if time < 0 {
+        return encode_error(anyhow!("ERR invalid expire time, must be >= 0"));
+    }
+
+    let now = Utc::now().timestamp_millis();
+    let base = if absolute { 0 } else { now };
+    let expires_at = match time.checked_mul(unit_ms).and_then(|t| t.checked_add(base)) {
+        Some(at) if at <= FIELD_EXPIRE_MAX_MS => at,
+        _ => return encode_error(anyhow!("ERR invalid expire time in '{}' command", name)),
+    };
+
+    let condition = args.get(2).and_then(|arg| ExpireCondition::parse(arg));
+    let fields_at = if condition.is_some() { 3 } else { 2 };
+    let fields = match parse_fields(&args, fields_at) {
+        Ok(fields) => fields,
+        Err(err) => return encode_error(err),
+    };
+
+    let loading = store.is_loading();
+    let replies = with_hash(store, key, |hash, _| {
+        fields
+            .iter()
+            .map(|field| {
+                let Some(current) = hash.expires_at(field) else {
+                    return -2; // No such field
+                };
+                if !ExpireCondition::allows(condition, current, expires_at) {
+                    return 0;
+                }
+                // Already in the past: the field is deleted right away, but
+                // not while loading, like for keys
+                if expires_at <= now && !loading {
+                    hash.remove(field);
+                    return 2;
+                }
+
+                hash.set_expiry(field, expires_at);
+                1
+            })
+            .collect::<Vec<_>>()
+    });
+    let replies = match replies {
+        Ok(Some(replies)) => replies,
+        Ok(None) => vec![-2; fields.len()],
+        Err(err) => return encode_error(err),
+    };
+
+    let with_reply = |wanted: i64| {
+        fields
+            .iter()
+            .zip(replies.iter())
+            .filter(|(_, reply)| **reply == wanted)
+            .map(|(field, _)| field.clone())
+            .collect::<Vec<_>>()
+    };
+    let (updated, deleted) = (with_reply(1), with_reply(2));
+    store.add_dirty((updated.len() + deleted.len()) as u64);
+
+    // Log absolute expiries and deletions, which replay the same way.
+    if !updated.is_empty() {
+        let mut argv = vec![
+            b"HPEXPIREAT".to_vec(),
+            key.clone(),
+            expires_at.to_string().into_bytes(),
+            b"FIELDS".to_vec(),
+            updated.len().to_string().into_bytes(),
+        ];
+        argv.extend(updated);
+        store.rewrite_propagation(argv);
+    }
+    if !deleted.is_empty() {
+        let mut argv = vec![b"HDEL".to_vec(), key.clone()];
+        argv.extend(deleted);
+        store.rewrite_propagation(argv);
+    }
+
+    return integers(replies);
+}
+
+pub fn hexpire(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_expire_generic(args, store, 1000, false, "hexpire");
+}
+
+pub fn hpexpire(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_expire_generic(args, store, 1, false, "hpexpire");
+}
+
+pub fn hexpireat(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_expire_generic(args, store, 1000, true, "hexpireat");
+}
+
+pub fn hpexpireat(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_expire_generic(args, store, 1, true, "hpexpireat");
+}
+
+/// HTTL and friends, replying in `unit_ms`, and with unix times rather than
+/// TTLs when `absolute`.
+fn field_ttl_generic(
+    args: Vec<Vec<u8>>,
+    store: &mut Store,
+    unit_ms: i64,
+    absolute: bool,
+) -> Vec<u8> {
+    let fields = match parse_fields(&args, 1) {
+        Ok(fields) => fields,
+        Err(err) => return encode_error(err),
+    };
+    let hash = match get_hash(store, &args[0]) {
+        Ok(hash) => hash,
+        Err(err) => return encode_error(err),
+    };
+
+    let base = if absolute {
+        0
+    } else {
+        Utc::now().timestamp_millis()
+    };
+    let replies = fields
+        .iter()
+        .map(|field| match hash.and_then(|h| h.expires_at(field)) {
+            None => -2,
+            Some(-1) => -1,
+            Some(at) if absolute => at / unit_ms,
+            // Rounded up, a field doesn't live past its TTL
+            Some(at) => (at - base + unit_ms - 1) / unit_ms,
+        })
+        .collect();
+
+    return integers(replies);
+}
+
+pub fn httl(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_ttl_generic(args, store, 1000, false);
+}
+
+pub fn hpttl(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_ttl_generic(args, store, 1, false);
+}
+
+pub fn hexpiretime(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_ttl_generic(args, store, 1000, true);
+}
+
+pub fn hpexpiretime(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return field_ttl_generic(args, store, 1, true);
+}
+
+pub fn hpersist(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let fields = match parse_fields(&args, 1) {
+        Ok(fields) => fields,
+        Err(err) => return encode_error(err),
+    };
+
+    let replies = with_hash(store, &args[0], |hash, _| {
+        fields
+            .iter()
+            .map(|field| match hash.expires_at(field) {
+                None => -2,
+                Some(-1) => -1,
+                Some(_) => {
+                    hash.set_expiry(field, -1);
+                    1
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+    let replies = match replies {
+        Ok(Some(replies)) => replies,
+        Ok(None) => vec![-2; fields.len()],
+        Err(err) => return encode_error(err),
+    };
+    store.add_dirty(replies.iter().filter(|r| **r == 1).count() as u64);
+
+    return integers(replies);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::eval::test_helpers::{run, scan_with_huge_count, store, store_from_aof};
+
+    #[test]
+    fn test_replay_doesnt_expire_fields() {
+        let past = (Utc::now().timestamp_millis() - 1000).to_string();
+        let mut store = store_from_aof(
+            "replay-doesnt-expire-fields",
+            &[
+                &["HSET", "h", "a", "5", "b", "1"],
+                &["HPEXPIREAT", "h", &past, "FIELDS", "1", "a"],
+                &["HINCRBY", "h", "a", "1"],
+            ],
+        );
+        let mut client = Client::new();
+
+        // HINCRBY ran on the field as it was, which then expired
+        assert_eq!(
+            run(&mut store, &mut client, &["HGETALL", "h"]),
+            b"*2\r\n$1\r\nb\r\n$1\r\n1\r\n"
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["HTTL", "h", "FIELDS", "1", "a"]),
+            b"*1\r\n:-2\r\n"
+        );
+    }
+
+    #[test]
+    fn test_expire_times_are_rounded_down() {
+        let mut store = store();
+        let mut client = Client::new();
+        let at = (Utc::now().timestamp_millis() / 1000 + 60) * 1000 + 500;
+
+        run(&mut store, &mut client, &["HSET", "h", "f", "v"]);
+        run(
+            &mut store,
+            &mut client,
+            &["HPEXPIREAT", "h", &at.to_string(), "FIELDS", "1", "f"],
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["HEXPIRETIME", "h", "FIELDS", "1", "f"]),
+            format!("*1\r\n:{}\r\n", at / 1000).into_bytes()
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["HPEXPIRETIME", "h", "FIELDS", "1", "f"]),
+            format!("*1\r\n:{}\r\n", at).into_bytes()
+        );
+    }
+
+    #[test]
+    fn test_hscan_with_a_huge_count() {
+        let mut fill = vec!["HSET".to_string(), "h".to_string()];
+        for i in 0..200 {
+            fill.extend([format!("f{}", i), "v".to_string()]);
+        }
+
+        assert_eq!(scan_with_huge_count(&fill, &["HSCAN", "h", "0"]), (b"0".to_vec(), 400));
+    }
+}
@@ -0,0 +1,872 @@
+use anyhow::anyhow;
+use chrono::Utc;
+
+use crate::common::{parse_i64, string_match, Value};
+use crate::core::{
+    client::Client,
+    resp::{
+        encode, encode_error, encode_help, nil, RESP_MINUS_ONE, RESP_MINUS_TWO, RESP_OK,
+        RESP_ONE, RESP_ZERO,
+    },
+};
+use crate::data::store::{encoding_name, type_by_name, type_name, Store};
+
+/// NX, XX, GT or LT option of the EXPIRE family.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum ExpireCondition {
+    /// Only when there is no expiry yet.
+    Nx,
+    /// Only when there is an expiry already.
+    Xx,
+    /// Only when the new expiry is later, no expiry counts as infinite.
+    Gt,
+    /// Only when the new expiry is sooner, no expiry counts as infinite.
+    Lt,
+}
+
+impl ExpireCondition {
+    pub fn parse(arg: &[u8]) -> Option<ExpireCondition> {
+        return match arg.to_ascii_uppercase().as_slice() {
+            b"NX" => Some(ExpireCondition::Nx),
+            b"XX" => Some(ExpireCondition::Xx),
+            b"GT" => Some(ExpireCondition::Gt),
+            b"LT" => Some(ExpireCondition::Lt),
+            _ => None,
+        };
+    }
+
+    /// Whether an expiry may go from `current`, -1 for none, to `new`.
+    pub fn allows(condition: Option<ExpireCondition>, current: i64, new: i64) -> bool {
+        return match condition {
+            None => true,
+            Some(ExpireCondition::Nx) => current == -1,
+            Some(ExpireCondition::Xx) => current != -1,
+            Some(ExpireCondition::Gt) => current != -1 && new > current,
+            Some(ExpireCondition::Lt) => current == -1 || new < current,
+        };
+    }
+}
+
+/// Options shared by the SCAN family.
+pub(super) struct ScanOptions {
+    pub pattern: Option<Vec<u8>>,
+    pub count: usize,
+    /// Only return fields, for HSCAN.
+    pub no_values: bool,
+    /// Only return keys of this type, for SCAN.
+    pub key_type: Option<u8>,
+}
+
+impl ScanOptions {
+    pub fn matches(&self, item: &[u8]) -> bool {
+        return match &self.pattern {
+            Some(pattern) => string_match(pattern, item, false),
+            None => true,
+        };
+    }
+}
+
+/// Parses `cursor [MATCH pattern] [COUNT count]`, plus `NOVALUES` if
+/// `allow_no_values` and `TYPE type` if `allow_type`.
+pub(super) fn parse_scan_args(
+    args: &[Vec<u8>],
+    allow_no_values: bool,
+    allow_type: bool,
+) -> anyhow::Result<(u64, ScanOptions)> {
+    let cursor = std::str::from_utf8(&args[0])
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("ERR invalid cursor"))?;
+    let mut opts = ScanOptions {
+        pattern: None,
+        count: 10,
+        no_values: false,
+        key_type: None,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match (args[i].to_ascii_uppercase().as_slice(), value) {
+            (b"MATCH", Some(pattern)) => {
+                // Matching everything is the same as not matching at all
+                opts.pattern = (pattern.as_slice() != b"*").then(|| pattern.clone());
+            }
+            (b"COUNT", Some(count)) => {
+                opts.count = match parse_i64(count) {
+                    Some(n) if n >= 1 => n as usize,
+                    Some(_) => return Err(anyhow!("ERR syntax error")),
+                    None => return Err(anyhow!("ERR value is not an integer or out of range")),
+                };
+            }
+            (b"TYPE", Some(name)) if allow_type => {
+                opts.key_type = Some(type_by_name(name).ok_or_else(|| {
+                    anyhow!("ERR unknown type name '{}'", String::from_utf8_lossy(name))
+                })?);
+            }
+            (b"NOVALUES", _) if allow_no_values => {
+                opts.no_values = true;
+                i += 1;
+                continue;
+            }
+            _ => return Err(anyhow!("ERR syntax error")),
+        }
+        i += 2;
+    }
+
+    return Ok((cursor, opts));
+}
+
+/// `[cursor, [items...]]`, the cursor being sent as a string.
+pub(super) fn scan_reply(cursor: u64, items: Vec<Vec<u8>>) -> Vec<u8> {
+    return encode(
+        Value::Vector(vec![
+            Value::String(cursor.to_string().into_bytes()),
+            Value::VectorString(items),
+        ]),
+        false,
+    );
+}
+
+/// Walks a table from `cursor` with `step`, which scans one bucket and
+/// returns the next cursor along with how many items were found so far,
+/// until the walk is over or about `count` items were found. Returns the
+/// cursor to resume from.
+pub(super) fn scan_buckets(
+    mut cursor: u64,
+    count: usize,
+    mut step: impl FnMut(u64) -> (u64, usize),
+) -> u64 {
+    // Bound the work done on a sparse table
+    let mut iterations = count.saturating_mul(10);
+    loop {
+        let (next, found) = step(cursor);
+        cursor = next;
+        iterations -= 1;
+
+        if cursor == 0 || iterations == 0 || found >= count {
+            return cursor;
+        }
+    }
+}
+
+pub fn keys(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let pattern = &args[0];
+    let all = pattern.as_slice() == b"*";
+    let keys = store
+        .iter()
+        .filter(|(key, _)| all || string_match(pattern, key, false))
+        .map(|(key, _)| key.to_vec())
+        .collect();
+
+    return encode(Value::VectorString(keys), false);
+}
+
+pub fn scan(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (cursor, opts) = match parse_scan_args(&args, false, true) {
+        Ok(parsed) => parsed,
+        Err(err) => return encode_error(err),
+    };
+
+    let mut keys = Vec::new();
+    let cursor = scan_buckets(cursor, opts.count, |cursor| {
+        let next = store.scan(cursor, |key, obj| {
+            if opts.matches(key) && opts.key_type.is_none_or(|t| obj.get_type() == t) {
+                keys.push(key.to_vec());
+            }
+        });
+        (next, keys.len())
+    });
+
+    return scan_reply(cursor, keys);
+}
+
+pub fn object(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let subcommand = args[0].to_ascii_uppercase();
+    match (subcommand.as_slice(), args.len()) {
+        (b"ENCODING" | b"FREQ" | b"IDLETIME" | b"REFCOUNT", 2) => {}
+        (b"HELP", 1) => {
+            return encode_help(&[
+                "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "ENCODING <key>",
+                "    Return the kind of internal representation used in order to store the value",
+                "    associated with a <key>.",
+                "FREQ <key>",
+                "    Return the access frequency index of the <key>. The returned integer is",
+                "    proportional to the logarithm of the recent access frequency of the key.",
+                "IDLETIME <key>",
+                "    Return the idle time of the <key>, that is the approximated number of",
+                "    seconds elapsed since the last access to the key.",
+                "REFCOUNT <key>",
+                "    Return the number of references of the value associated with the specified",
+                "    <key>.",
+                "HELP",
+                "    Print this help.",
+            ])
+        }
+        _ => {
+            return encode_error(anyhow!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try OBJECT HELP.",
+                String::from_utf8_lossy(&args[0])
+            ))
+        }
+    }
+
+    let lfu = store.config().eviction_strategy.is_lfu();
+    // Looking at the object must not count as an access
+    let Some(obj) = store.peek(&args[1]) else {
+        return nil(client.protover);
+    };
+
+    return match subcommand.as_slice() {
+        b"ENCODING" => encode(
+            Value::String(encoding_name(obj.get_encoding()).as_bytes().to_vec()),
+            false,
+        ),
+        b"FREQ" if !lfu => encode_error(anyhow!(
+            "ERR An LFU maxmemory policy is not selected, access frequency not tracked. \
+             Please note that when switching between policies at runtime LRU and LFU data \
+             will take some time to adjust."
+        )),
+        b"FREQ" => encode(Value::Int64(obj.lfu_freq() as i64), false),
+        b"IDLETIME" if lfu => encode_error(anyhow!(
+            "ERR An LFU maxmemory policy is selected, idle time not tracked. Please note \
+             that when switching between policies at runtime LRU and LFU data will take \
+             some time to adjust."
+        )),
+        b"IDLETIME" => encode(Value::Int64(obj.idle_time() as i64), false),
+        // Values are never shared
+        _ => RESP_ONE.to_vec(),
+    };
+}
+
+pub fn del(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let mut count_deleted = 0;
+
+    for key in args {
+        if store.del(&key) {
+            count_deleted += 1;
+        }
+    }
+
+    return encode(Value::Int32(count_deleted), false);
+}
+
+pub fn unlink(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    // There is no memory to reclaim in the background, it's a DEL
+    return del(args, client, store);
+}
+
+pub fn exists(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    // A key given several times is counted as many times
+    let count = args.iter().filter(|key| store.exists(key)).count();
+
+    return encode(Value::Int64(count as i64), false);
+}
+
+pub fn touch(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let count = args.iter().filter(|key| store.get(key).is_some()).count();
+
+    return encode(Value::Int64(count as i64), false);
+}
+
+pub fn type_of(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let name = match store.peek(&args[0]) {
+        Some(obj) => type_name(obj.get_type()),
+        None => "none",
+    };
+
+    return encode(Value::String(name.as_bytes().to_vec()), true);
+}
+
+/// Moves the object at `from` to `to`, TTL and encoding included, in a
+/// single step. Returns false if `to` exists and `nx` is set.
+fn rename_key(store: &mut Store, from: &[u8], to: &[u8], nx: bool) -> anyhow::Result<bool> {
+    if !store.exists(from) {
+        return Err(anyhow!("ERR no such key"));
+    }
+    if from == to {
+        return Ok(!nx);
+    }
+    if nx && store.exists(to) {
+        return Ok(false);
+    }
+
+    let obj = store.take(from).expect("key was just found");
+    store.put(to.to_vec(), obj);
+    return Ok(true);
+}
+
+pub fn rename(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match rename_key(store, &args[0], &args[1], false) {
+        Ok(_) => RESP_OK.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn renamenx(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match rename_key(store, &args[0], &args[1], true) {
+        Ok(true) => RESP_ONE.to_vec(),
+        Ok(false) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+/// Parses a database index given to SELECT and friends, `not_integer` being
+/// the error for something that isn't one.
+fn parse_db_index(arg: &[u8], store: &Store, not_integer: &str) -> anyhow::Result<usize> {
+    let Some(db) = parse_i64(arg) else {
+        return Err(anyhow!("{}", not_integer));
+    };
+    if !(0..store.databases() as i64).contains(&db) {
+        return Err(anyhow!("ERR DB index is out of range"));
+    }
+
+    return Ok(db as usize);
+}
+
+pub fn copy(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (source, destination) = (&args[0], &args[1]);
+    let mut replace = false;
+    let mut db = client.db;
+
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"REPLACE" => replace = true,
+            b"DB" if i + 1 < args.len() => {
+                i += 1;
+                db = match parse_db_index(&args[i], store, "ERR value is not an integer or out of range") {
+                    Ok(db) => db,
+                    Err(err) => return encode_error(err),
+                };
+            }
+            _ => return encode_error(anyhow!("ERR syntax error")),
+        }
+        i += 1;
+    }
+    if source == destination && db == client.db {
+        return encode_error(anyhow!("ERR source and destination objects are the same"));
+    }
+
+    // The copy keeps the TTL and encoding of the source
+    let Some(obj) = store.get(source).cloned() else {
+        return RESP_ZERO.to_vec();
+    };
+    store.select(db);
+    let copied = replace || !store.exists(destination);
+    if copied {
+        store.put(destination.clone(), obj);
+    }
+    store.select(client.db);
+
+    return if copied { RESP_ONE } else { RESP_ZERO }.to_vec();
+}
+
+pub fn move_key(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let db = match parse_db_index(&args[1], store, "ERR value is not an integer or out of range") {
+        Ok(db) => db,
+        Err(err) => return encode_error(err),
+    };
+    if db == client.db {
+        return encode_error(anyhow!("ERR source and destination objects are the same"));
+    }
+
+    return if store.move_key(&args[0], db) {
+        RESP_ONE
+    } else {
+        RESP_ZERO
+    }
+    .to_vec();
+}
+
+pub fn select(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match parse_db_index(&args[0], store, "ERR value is not an integer or out of range") {
+        Ok(db) => {
+            client.db = db;
+            RESP_OK.to_vec()
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn swapdb(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let a = match parse_db_index(&args[0], store, "ERR invalid first DB index") {
+        Ok(db) => db,
+        Err(err) => return encode_error(err),
+    };
+    let b = match parse_db_index(&args[1], store, "ERR invalid second DB index") {
+        Ok(db) => db,
+        Err(err) => return encode_error(err),
+    };
+
+    store.swap_dbs(a, b);
+    return RESP_OK.to_vec();
+}
+
+pub fn persist(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    if store.peek(key).is_none_or(|obj| obj.expires_at == -1) {
+        return RESP_ZERO.to_vec();
+    }
+
+    store.set_expiry(key, -1);
+    store.add_dirty(1);
+    return RESP_ONE.to_vec();
+}
+
+pub fn randomkey(_args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match store.random_key() {
+        Some(key) => encode(Value::String(key), false),
+        None => nil(client.protover),
+    };
+}
+
+pub fn dbsize(_args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return encode(Value::Int64(store.len() as i64), false);
+}
+
+/// Whether FLUSHDB or FLUSHALL was given ASYNC rather than SYNC or nothing.
+fn parse_flush_mode(args: &[Vec<u8>]) -> anyhow::Result<bool> {
+    return match args {
+        [] => Ok(false),
+        [mode] => match mode.to_ascii_uppercase().as_slice() {
+            b"ASYNC" => Ok(true),
+            b"SYNC" => Ok(false),
+            _ => Err(anyhow!("ERR syntax error")),
+        },
+        _ => Err(anyhow!("ERR syntax error")),
+    };
+}
+
+pub fn flushdb(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let lazy = match parse_flush_mode(&args) {
+        Ok(lazy) => lazy,
+        Err(err) => return encode_error(err),
+    };
+
+    store.flush(lazy);
+    // Logged even when there was nothing to remove, like Redis does
+    store.add_dirty(1);
+    return RESP_OK.to_vec();
+}
+
+pub fn flushall(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let lazy = match parse_flush_mode(&args) {
+        Ok(lazy) => lazy,
+        Err(err) => return encode_error(err),
+    };
+
+    store.flush_all(lazy);
+    store.add_dirty(1);
+    return RESP_OK.to_vec();
+}
+
+/// Parses the NX, XX, GT and LT options of the EXPIRE family, which may be
+/// combined as long as they don't contradict each other.
+fn parse_expire_conditions(args: &[Vec<u8>]) -> anyhow::Result<Vec<ExpireCondition>> {
+    let mut conditions = Vec::new();
+    for arg in args {
+        let Some(condition) = ExpireCondition::parse(arg) else {
+            return Err(anyhow!("ERR Unsupported option {}", String::from_utf8_lossy(arg)));
+        };
+        conditions.push(condition);
+    }
+
+    let has = |c| conditions.contains(&c);
+    if has(ExpireCondition::Nx)
+        && (has(ExpireCondition::Xx) || has(ExpireCondition::Gt) || has(ExpireCondition::Lt))
+    {
+        return Err(anyhow!(
+            "ERR NX and XX, GT or LT options at the same time are not compatible"
+        ));
+    }
+    if has(ExpireCondition::Gt) && has(ExpireCondition::Lt) {
+        return Err(anyhow!("ERR GT and LT options at the same time are not compatible"));
+    }
+
+    return Ok(conditions);
+}
+
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT: `unit_ms` is the unit of the
+/// time given, relative to now unless `absolute`.
+fn expire_generic(
+    args: &[Vec<u8>],
+    store: &mut Store,
+    name: &str,
+    unit_ms: i64,
+    absolute: bool,
+) -> Vec<u8> {
+    let key = &args[0];
+    let conditions = match parse_expire_conditions(&args[2..]) {
+        Ok(conditions) => conditions,
+        Err(err) => return encode_error(err),
+    };
+    let Some(when) = parse_i64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+
+    let now = Utc::now().timestamp_millis();
+    let expires_at = when
+        .checked_mul(unit_ms)
+        .and_then(|ms| if absolute { Some(ms) } else { ms.checked_add(now) });
+    let Some(expires_at) = expires_at else {
+        return encode_error(anyhow!("ERR invalid expire time in '{}' command", name));
+    };
+
+    let Some(obj) = store.peek(key) else {
+        return RESP_ZERO.to_vec();
+    };
+    let current = obj.expires_at;
+    if !conditions
+        .iter()
+        .all(|&c| ExpireCondition::allows(Some(c), current, expires_at))
+    {
+        return RESP_ZERO.to_vec();
+    }
+
+    // Already in the past: the key is deleted right away. Not while loading
+    // though, nothing expires until the whole file is replayed, see
+    // `Store::is_expired`.
+    if expires_at <= now && !store.is_loading() {
+        store.del(key);
+        store.rewrite_propagation(vec![b"DEL".to_vec(), key.clone()]);
+        return RESP_ONE.to_vec();
+    }
+
+    store.set_expiry(key, expires_at);
+    store.add_dirty(1);
+    // A relative timeout would be measured from the time of the replay.
+    store.rewrite_propagation(vec![
+        b"PEXPIREAT".to_vec(),
+        key.clone(),
+        expires_at.to_string().into_bytes(),
+    ]);
+
+    return RESP_ONE.to_vec();
+}
+
+pub fn expire(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return expire_generic(&args, store, "expire", 1_000, false);
+}
+
+pub fn pexpire(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return expire_generic(&args, store, "pexpire", 1, false);
+}
+
+pub fn expireat(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return expire_generic(&args, store, "expireat", 1_000, true);
+}
+
+pub fn pexpireat(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return expire_generic(&args, store, "pexpireat", 1, true);
+}
+
+/// TTL, PTTL, EXPIRETIME and PEXPIRETIME: -2 for no such key, -1 for no
+/// expiry, otherwise the time left, or the unix time of the expiry if
+/// `absolute`, in milliseconds if `ms`.
+fn ttl_generic(args: &[Vec<u8>], store: &mut Store, ms: bool, absolute: bool) -> Vec<u8> {
+    // Looking at the TTL must not count as an access
+    let Some(obj) = store.peek(&args[0]) else {
+        return RESP_MINUS_TWO.to_vec();
+    };
+    if obj.expires_at == -1 {
+        return RESP_MINUS_ONE.to_vec();
+    }
+
+    let t = if absolute {
+        obj.expires_at
+    } else {
+        (obj.expires_at - Utc::now().timestamp_millis()).max(0)
+    };
+    // Rounded to the nearest second, like Redis
+    let t = if ms { t } else { (t + 500) / 1_000 };
+
+    return encode(Value::Int64(t), false);
+}
+
+pub fn ttl(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return ttl_generic(&args, store, false, false);
+}
+
+pub fn pttl(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return ttl_generic(&args, store, true, false);
+}
+
+pub fn expiretime(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return ttl_generic(&args, store, false, true);
+}
+
+pub fn pexpiretime(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return ttl_generic(&args, store, true, true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{
+        eval::test_helpers::{
+            logged, run, scan_with_huge_count, store, store_from_aof, store_with_aof,
+        },
+        resp::decode,
+    };
+
+    #[test]
+    fn test_expire_in_the_past_deletes() {
+        let mut store = store_with_aof("expire-in-the-past");
+        let mut client = Client::new();
+        let past = (Utc::now().timestamp_millis() - 1000).to_string();
+
+        for expire in [
+            &["EXPIRE", "k", "-1"][..],
+            &["PEXPIRE", "k", "0"],
+            &["EXPIREAT", "k", "0"],
+            &["PEXPIREAT", "k", &past],
+        ] {
+            run(&mut store, &mut client, &["SET", "k", "v"]);
+            assert_eq!(run(&mut store, &mut client, expire), b":1\r\n");
+            assert_eq!(run(&mut store, &mut client, &["EXISTS", "k"]), b":0\r\n");
+        }
+
+        let set = vec!["SET".to_string(), "k".to_string(), "v".to_string()];
+        let del = vec!["DEL".to_string(), "k".to_string()];
+        let mut expected = vec![vec!["SELECT".to_string(), "0".to_string()]];
+        for _ in 0..4 {
+            expected.extend([set.clone(), del.clone()]);
+        }
+        assert_eq!(logged(&mut store), expected);
+    }
+
+    #[test]
+    fn test_scan_with_a_huge_count() {
+        let mut fill = vec!["MSET".to_string()];
+        for i in 0..100 {
+            fill.extend([format!("k{}", i), "v".to_string()]);
+        }
+
+        assert_eq!(scan_with_huge_count(&fill, &["SCAN", "0"]), (b"0".to_vec(), 100));
+    }
+
+    #[test]
+    fn test_rename_and_copy_keep_ttl_and_encoding() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        run(&mut store, &mut client, &["SADD", "s", "1", "2"]);
+        run(&mut store, &mut client, &["EXPIRE", "s", "100"]);
+        run(&mut store, &mut client, &["SET", "n", "12"]);
+
+        assert_eq!(run(&mut store, &mut client, &["RENAME", "s", "t"]), RESP_OK);
+        assert_eq!(run(&mut store, &mut client, &["COPY", "t", "u"]), RESP_ONE);
+        assert_eq!(run(&mut store, &mut client, &["COPY", "n", "m"]), RESP_ONE);
+        for (key, ttl, encoding) in [("t", 100, "intset"), ("u", 100, "intset"), ("m", -1, "int")] {
+            assert_eq!(
+                run(&mut store, &mut client, &["TTL", key]),
+                format!(":{}\r\n", ttl).into_bytes()
+            );
+            assert_eq!(
+                run(&mut store, &mut client, &["OBJECT", "ENCODING", key]),
+                format!("${}\r\n{}\r\n", encoding.len(), encoding).into_bytes()
+            );
+        }
+        assert_eq!(run(&mut store, &mut client, &["EXISTS", "s"]), b":0\r\n");
+
+        // The copy is a copy, changing it leaves the source alone
+        run(&mut store, &mut client, &["SADD", "u", "3"]);
+        assert_eq!(run(&mut store, &mut client, &["SCARD", "t"]), b":2\r\n");
+        assert_eq!(run(&mut store, &mut client, &["COPY", "t", "u"]), RESP_ZERO);
+        assert_eq!(run(&mut store, &mut client, &["COPY", "t", "u", "REPLACE"]), RESP_ONE);
+        assert_eq!(run(&mut store, &mut client, &["SCARD", "u"]), b":2\r\n");
+
+        assert_eq!(
+            run(&mut store, &mut client, &["RENAME", "missing", "x"]),
+            b"-ERR no such key\r\n"
+        );
+    }
+
+    #[test]
+    fn test_async_flushes() {
+        let mut store = store_with_aof("async-flushes");
+        let mut client = Client::new();
+
+        run(&mut store, &mut client, &["MSET", "a", "1", "b", "2"]);
+        assert_eq!(run(&mut store, &mut client, &["FLUSHDB", "ASYNC"]), RESP_OK);
+        assert_eq!(run(&mut store, &mut client, &["DBSIZE"]), b":0\r\n");
+
+        run(&mut store, &mut client, &["SET", "a", "1"]);
+        run(&mut store, &mut client, &["SELECT", "1"]);
+        run(&mut store, &mut client, &["SET", "b", "2"]);
+        assert_eq!(run(&mut store, &mut client, &["FLUSHALL", "async"]), RESP_OK);
+        assert_eq!(run(&mut store, &mut client, &["DBSIZE"]), b":0\r\n");
+        run(&mut store, &mut client, &["SELECT", "0"]);
+        assert_eq!(run(&mut store, &mut client, &["DBSIZE"]), b":0\r\n");
+
+        for flush in ["FLUSHDB", "FLUSHALL"] {
+            assert_eq!(
+                run(&mut store, &mut client, &[flush, "LAZY"]),
+                b"-ERR syntax error\r\n"
+            );
+            assert_eq!(
+                run(&mut store, &mut client, &[flush, "ASYNC", "SYNC"]),
+                b"-ERR syntax error\r\n"
+            );
+        }
+
+        let logged = logged(&mut store);
+        assert_eq!(logged[2], ["FLUSHDB", "ASYNC"]);
+        assert_eq!(logged.last().unwrap(), &["FLUSHALL", "async"]);
+    }
+
+    #[test]
+    fn test_scan_by_type() {
+        let mut store = store();
+        let mut client = Client::new();
+        run(&mut store, &mut client, &["MSET", "s1", "v", "s2", "v"]);
+        run(&mut store, &mut client, &["RPUSH", "l1", "a"]);
+        run(&mut store, &mut client, &["RPUSH", "l2", "a"]);
+        run(&mut store, &mut client, &["HSET", "h1", "f", "v"]);
+
+        let mut scan = |args: &[&str]| {
+            let scan = [&["SCAN", "0", "COUNT", "100"], args].concat();
+            let reply = run(&mut store, &mut client, &scan);
+            let Value::Vector(reply) = &decode(&reply).unwrap()[0] else {
+                panic!("not a scan reply");
+            };
+            let Value::Vector(keys) = &reply[1] else {
+                panic!("not a scan reply");
+            };
+            let mut keys = keys.iter().map(Value::to_string).collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(scan(&["TYPE", "list"]), ["l1", "l2"]);
+        assert_eq!(scan(&["TYPE", "STRING", "MATCH", "*2"]), ["s2"]);
+        assert_eq!(scan(&["TYPE", "zset"]), Vec::<String>::new());
+
+        assert_eq!(
+            run(&mut store, &mut client, &["SCAN", "0", "TYPE", "foo"]),
+            b"-ERR unknown type name 'foo'\r\n"
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        assert_eq!(run(&mut store, &mut client, &["SELECT", "1"]), RESP_OK);
+        run(&mut store, &mut client, &["SET", "k", "v"]);
+        assert_eq!(run(&mut store, &mut client, &["DBSIZE"]), b":1\r\n");
+        run(&mut store, &mut client, &["SELECT", "0"]);
+        assert_eq!(run(&mut store, &mut client, &["EXISTS", "k"]), b":0\r\n");
+
+        // Other clients start from database 0
+        let mut other = Client::new();
+        assert_eq!(run(&mut store, &mut other, &["EXISTS", "k"]), b":0\r\n");
+
+        assert_eq!(
+            run(&mut store, &mut client, &["SELECT", "16"]),
+            b"-ERR DB index is out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["SELECT", "x"]),
+            b"-ERR value is not an integer or out of range\r\n"
+        );
+        assert_eq!(client.db, 0);
+    }
+
+    #[test]
+    fn test_move() {
+        let mut store = store();
+        let mut client = Client::new();
+        run(&mut store, &mut client, &["SET", "k", "v", "EX", "100"]);
+
+        assert_eq!(run(&mut store, &mut client, &["MOVE", "k", "1"]), RESP_ONE);
+        assert_eq!(run(&mut store, &mut client, &["EXISTS", "k"]), b":0\r\n");
+        assert_eq!(run(&mut store, &mut client, &["MOVE", "k", "1"]), RESP_ZERO);
+
+        // Nothing is overwritten in the destination
+        run(&mut store, &mut client, &["SET", "k", "w"]);
+        assert_eq!(run(&mut store, &mut client, &["MOVE", "k", "1"]), RESP_ZERO);
+        assert_eq!(run(&mut store, &mut client, &["GET", "k"]), b"$1\r\nw\r\n");
+
+        run(&mut store, &mut client, &["SELECT", "1"]);
+        assert_eq!(run(&mut store, &mut client, &["GET", "k"]), b"$1\r\nv\r\n");
+        assert_eq!(run(&mut store, &mut client, &["TTL", "k"]), b":100\r\n");
+        assert_eq!(
+            run(&mut store, &mut client, &["MOVE", "k", "1"]),
+            b"-ERR source and destination objects are the same\r\n"
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["MOVE", "k", "16"]),
+            b"-ERR DB index is out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_swapdb() {
+        let mut store = store();
+        let mut client = Client::new();
+        run(&mut store, &mut client, &["SET", "a", "0"]);
+        run(&mut store, &mut client, &["SELECT", "1"]);
+        run(&mut store, &mut client, &["MSET", "b", "1", "c", "1"]);
+
+        assert_eq!(run(&mut store, &mut client, &["SWAPDB", "0", "1"]), RESP_OK);
+        // Clients stay on their database index and see the other's keys
+        assert_eq!(run(&mut store, &mut client, &["KEYS", "*"]), b"*1\r\n$1\r\na\r\n");
+        run(&mut store, &mut client, &["SELECT", "0"]);
+        assert_eq!(run(&mut store, &mut client, &["DBSIZE"]), b":2\r\n");
+
+        assert_eq!(run(&mut store, &mut client, &["SWAPDB", "2", "2"]), RESP_OK);
+        assert_eq!(
+            run(&mut store, &mut client, &["SWAPDB", "x", "1"]),
+            b"-ERR invalid first DB index\r\n"
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["SWAPDB", "0", "x"]),
+            b"-ERR invalid second DB index\r\n"
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["SWAPDB", "0", "16"]),
+            b"-ERR DB index is out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_writes_to_several_databases_replay() {
+        let mut store = store_with_aof("several-databases");
+        let mut client = Client::new();
+        run(&mut store, &mut client, &["SET", "a", "0"]);
+        run(&mut store, &mut client, &["SELECT", "2"]);
+        run(&mut store, &mut client, &["SET", "a", "2"]);
+        run(&mut store, &mut client, &["RPUSH", "l", "x"]);
+        run(&mut store, &mut client, &["MOVE", "l", "3"]);
+        run(&mut store, &mut client, &["SELECT", "0"]);
+        run(&mut store, &mut client, &["INCR", "a"]);
+
+        let logged = logged(&mut store);
+        let select = |db: &str| vec!["SELECT".to_string(), db.to_string()];
+        assert_eq!(logged[0], select("0"));
+        assert_eq!(logged[2], select("2"));
+        assert_eq!(logged[6], select("0"));
+
+        let cmds = logged
+            .iter()
+            .map(|argv| argv.iter().map(String::as_str).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let cmds = cmds.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let mut replayed = store_from_aof("several-databases-replay", &cmds);
+        for (db, cmd, expected) in [
+            ("0", &["GET", "a"][..], &b"$1\r\n1\r\n"[..]),
+            ("2", &["GET", "a"], b"$1\r\n2\r\n"),
+            ("2", &["EXISTS", "l"], b":0\r\n"),
+            ("3", &["LINDEX", "l", "0"], b"$1\r\nx\r\n"),
+        ] {
+            run(&mut replayed, &mut client, &["SELECT", db]);
+            assert_eq!(run(&mut replayed, &mut client, cmd), expected);
+        }
+        run(&mut replayed, &mut client, &["SELECT", "1"]);
+        assert_eq!(run(&mut replayed, &mut client, &["DBSIZE"]), b":0\r\n");
+    }
+}
@@ -0,0 +1,594 @@
+use anyhow::anyhow;
+
+use crate::common::{parse_i64, Value};
+use crate::core::{
+    blocked::{block_for_keys, parse_timeout},
+    client::Client,
+    cmd::Command,
+    resp::{encode, encode_error, nil, nil_array, RESP_MINUS_ONE, RESP_OK, RESP_ZERO},
+};
+use crate::data::{
+    list::{List, ListEnd, ListpackLimit},
+    store::{ObjectValue, Store, StoreObject, ENCODING_LISTPACK, TYPE_LIST},
+};
+
+/// The list at `key`, `None` if there is no such key.
+fn get_list<'a>(store: &'a mut Store, key: &[u8]) -> anyhow::Result<Option<&'a List>> {
+    let Some(obj) = store.get(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_LIST)?;
+
+    return match &obj.value {
+        ObjectValue::List(list) => Ok(Some(list)),
+        _ => unreachable!("list typed object doesn't hold a list"),
+    };
+}
+
+/// Runs `f` on the list at `key`, `None` if there is no such key. Like every
+/// aggregate, a list that ends up empty is deleted.
+pub(super) fn with_list<T>(
+    store: &mut Store,
+    key: &[u8],
+    f: impl FnOnce(&mut List, ListpackLimit) -> T,
+) -> anyhow::Result<Option<T>> {
+    let limit = store.list_limit();
+    let Some(obj) = store.get_mut(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_LIST)?;
+
+    let ObjectValue::List(list) = &mut obj.value else {
+        unreachable!("list typed object doesn't hold a list");
+    };
+    let result = f(list, limit);
+    let empty = list.is_empty();
+    obj.refresh_encoding();
+
+    if empty {
+        store.del(key);
+    }
+
+    return Ok(Some(result));
+}
+
+/// Makes sure there is a list at `key` to push to.
+fn create_list_if_missing(store: &mut Store, key: &[u8]) -> anyhow::Result<()> {
+    match store.get(key) {
+        Some(obj) => obj.assert_type(TYPE_LIST)?,
+        None => {
+            let list = ObjectValue::List(List::new());
+            store.put(
+                key.to_vec(),
+                StoreObject::new(list, -1, TYPE_LIST, ENCODING_LISTPACK),
+            );
+        }
+    }
+
+    return Ok(());
+}
+
+fn parse_end(arg: &[u8]) -> Option<ListEnd> {
+    if arg.eq_ignore_ascii_case(b"LEFT") {
+        return Some(ListEnd::Head);
+    }
+    if arg.eq_ignore_ascii_case(b"RIGHT") {
+        return Some(ListEnd::Tail);
+    }
+
+    return None;
+}
+
+/// Turns a possibly negative index into an offset from the head.
+fn normalize_index(i: i64, len: usize) -> Option<usize> {
+    let i = if i < 0 { i + len as i64 } else { i };
+    if i < 0 || i >= len as i64 {
+        return None;
+    }
+
+    return Some(i as usize);
+}
+
+/// Turns a `start`/`stop` pair, possibly negative, into an inclusive range
+/// of offsets from the head. `None` if the range is empty.
+fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return None;
+    }
+
+    return Some((start as usize, stop as usize));
+}
+
+fn push_generic(
+    args: Vec<Vec<u8>>,
+    store: &mut Store,
+    end: ListEnd,
+    only_existing: bool,
+) -> Vec<u8> {
+    let key = &args[0];
+    if only_existing {
+        match get_list(store, key) {
+            Ok(Some(_)) => {}
+            Ok(None) => return RESP_ZERO.to_vec(),
+            Err(err) => return encode_error(err),
+        }
+    } else if let Err(err) = create_list_if_missing(store, key) {
+        return encode_error(err);
+    }
+
+    let values = &args[1..];
+    let len = with_list(store, key, |list, limit| {
+        for v in values {
+            list.push(v, end, limit);
+        }
+        list.len()
+    });
+    store.add_dirty(values.len() as u64);
+
+    return match len {
+        Ok(len) => encode(Value::Int64(len.unwrap_or(0) as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn lpush(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return push_generic(args, store, ListEnd::Head, false);
+}
+
+pub fn rpush(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return push_generic(args, store, ListEnd::Tail, false);
+}
+
+pub fn lpushx(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return push_generic(args, store, ListEnd::Head, true);
+}
+
+pub fn rpushx(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return push_generic(args, store, ListEnd::Tail, true);
+}
+
+fn pop_generic(args: Vec<Vec<u8>>, client: &Client, store: &mut Store, end: ListEnd) -> Vec<u8> {
+    let key = &args[0];
+    let count = match args.get(1) {
+        None => None,
+        Some(arg) => match parse_i64(arg) {
+            Some(n) if n >= 0 => Some(n as usize),
+            _ => return encode_error(anyhow!("ERR value is out of range, must be positive")),
+        },
+    };
+
+    let popped = with_list(store, key, |list, limit| {
+        let mut popped = Vec::new();
+        for _ in 0..count.unwrap_or(1) {
+            match list.pop(end, limit) {
+                Some(v) => popped.push(v),
+                None => break,
+            }
+        }
+        popped
+    });
+
+    let mut popped = match popped {
+        Ok(Some(popped)) => popped,
+        Ok(None) if count.is_some() => return nil_array(client.protover),
+        Ok(None) => return nil(client.protover),
+        Err(err) => return encode_error(err),
+    };
+    store.add_dirty(popped.len() as u64);
+
+    if count.is_some() {
+        return encode(Value::VectorString(popped), false);
+    }
+    return encode(Value::String(popped.remove(0)), false);
+}
+
+pub fn lpop(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return pop_generic(args, client, store, ListEnd::Head);
+}
+
+pub fn rpop(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return pop_generic(args, client, store, ListEnd::Tail);
+}
+
+pub fn llen(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_list(store, &args[0]) {
+        Ok(list) => encode(Value::Int64(list.map_or(0, |l| l.len()) as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn lrange(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (Some(start), Some(stop)) = (parse_i64(&args[1]), parse_i64(&args[2])) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+
+    let list = match get_list(store, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return encode(Value::VectorString(vec![]), false),
+        Err(err) => return encode_error(err),
+    };
+
+    let items = match normalize_range(start, stop, list.len()) {
+        Some((start, stop)) => list
+            .iter()
+            .skip(start)
+            .take(stop - start + 1)
+            .map(|v| v.to_vec())
+            .collect(),
+        None => vec![],
+    };
+
+    return encode(Value::VectorString(items), false);
+}
+
+pub fn lindex(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(index) = parse_i64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+
+    let list = match get_list(store, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) => return nil(client.protover),
+        Err(err) => return encode_error(err),
+    };
+
+    return match normalize_index(index, list.len()).and_then(|i| list.get(i)) {
+        Some(v) => encode(Value::String(v.to_vec()), false),
+        None => nil(client.protover),
+    };
+}
+
+pub fn lset(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(index) = parse_i64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+
+    let updated = with_list(store, &args[0], |list, limit| {
+        normalize_index(index, list.len()).is_some_and(|i| list.set(i, &args[2], limit))
+    });
+
+    return match updated {
+        Ok(Some(true)) => {
+            store.add_dirty(1);
+            RESP_OK.to_vec()
+        }
+        Ok(Some(false)) => encode_error(anyhow!("ERR index out of range")),
+        Ok(None) => encode_error(anyhow!("ERR no such key")),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn ltrim(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (Some(start), Some(stop)) = (parse_i64(&args[1]), parse_i64(&args[2])) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+
+    let removed = with_list(store, &args[0], |list, limit| {
+        let len = list.len();
+        match normalize_range(start, stop, len) {
+            Some((start, stop)) => list.retain(|i, _| (start..=stop).contains(&i), limit),
+            None => list.retain(|_, _| false, limit),
+        }
+        len - list.len()
+    });
+
+    return match removed {
+        Ok(removed) => {
+            store.add_dirty(removed.unwrap_or(0) as u64);
+            RESP_OK.to_vec()
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn lrem(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(count) = parse_i64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+    let element = &args[2];
+
+    let removed = with_list(store, &args[0], |list, limit| {
+        let max = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let matches = list
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| *v == element.as_slice());
+        let mut doomed: Vec<usize> = if count < 0 {
+            matches.rev().take(max).map(|(i, _)| i).collect()
+        } else {
+            matches.take(max).map(|(i, _)| i).collect()
+        };
+        doomed.sort_unstable();
+
+        if !doomed.is_empty() {
+            list.retain(|i, _| doomed.binary_search(&i).is_err(), limit);
+        }
+        doomed.len()
+    });
+
+    return match removed {
+        Ok(removed) => {
+            let removed = removed.unwrap_or(0);
+            store.add_dirty(removed as u64);
+            encode(Value::Int64(removed as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn linsert(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let after = match args[1].to_ascii_uppercase().as_slice() {
+        b"BEFORE" => false,
+        b"AFTER" => true,
+        _ => return encode_error(anyhow!("ERR syntax error")),
+    };
+    let (pivot, element) = (&args[2], &args[3]);
+
+    let len = with_list(store, &args[0], |list, limit| {
+        let pos = list.iter().position(|v| v == pivot.as_slice())?;
+        list.insert(pos + after as usize, element, limit);
+        Some(list.len())
+    });
+
+    return match len {
+        Ok(Some(Some(len))) => {
+            store.add_dirty(1);
+            encode(Value::Int64(len as i64), false)
+        }
+        Ok(Some(None)) => RESP_MINUS_ONE.to_vec(),
+        Ok(None) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn lpos(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let element = &args[1];
+    let mut rank = 1_i64;
+    let mut count = None;
+    let mut maxlen = 0_usize;
+
+    let mut i = 2;
+    while i < args.len() {
+        if i + 1 == args.len() {
+            return encode_error(anyhow!("ERR syntax error"));
+        }
+        let Some(n) = parse_i64(&args[i + 1]) else {
+            return encode_error(anyhow!("ERR value is not an integer or out of range"));
+        };
+
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"RANK" if n == 0 || n == i64::MIN => {
+                return encode_error(anyhow!(
+                    "ERR RANK can't be zero: use 1 to start from the first match, 2 from the \
+                     second ... or use negative to start from the end of the list"
+                ))
+            }
+            b"RANK" => rank = n,
+            b"COUNT" if n < 0 => return encode_error(anyhow!("ERR COUNT can't be negative")),
+            b"COUNT" => count = Some(n as usize),
+            b"MAXLEN" if n < 0 => return encode_error(anyhow!("ERR MAXLEN can't be negative")),
+            b"MAXLEN" => maxlen = n as usize,
+            _ => return encode_error(anyhow!("ERR syntax error")),
+        }
+        i += 2;
+    }
+
+    let list = match get_list(store, &args[0]) {
+        Ok(Some(list)) => list,
+        Ok(None) if count.is_some() => return encode(Value::Vector(vec![]), false),
+        Ok(None) => return nil(client.protover),
+        Err(err) => return encode_error(err),
+    };
+
+    let len = list.len();
+    let scanned = if maxlen == 0 { len } else { maxlen.min(len) };
+    let wanted = match count {
+        Some(0) => usize::MAX,
+        Some(n) => n,
+        None => 1,
+    };
+    let skip = rank.unsigned_abs() as usize - 1;
+    let is_match = |(_, v): &(usize, &[u8])| *v == element.as_slice();
+
+    let positions: Vec<Value> = if rank > 0 {
+        list.iter()
+            .enumerate()
+            .take(scanned)
+            .filter(is_match)
+            .skip(skip)
+            .take(wanted)
+            .map(|(i, _)| Value::Int64(i as i64))
+            .collect()
+    } else {
+        list.iter()
+            .enumerate()
+            .rev()
+            .take(scanned)
+            .filter(is_match)
+            .skip(skip)
+            .take(wanted)
+            .map(|(i, _)| Value::Int64(i as i64))
+            .collect()
+    };
+
+    if count.is_some() {
+        return encode(Value::Vector(positions), false);
+    }
+    return match positions.into_iter().next() {
+        Some(pos) => encode(pos, false),
+        None => nil(client.protover),
+    };
+}
+
+/// Pops from `src` and pushes to `dst`, `None` if `src` doesn't exist.
+/// Shared with BLMOVE.
+pub(super) fn lmove_generic(
+    store: &mut Store,
+    src: &[u8],
+    dst: &[u8],
+    from: ListEnd,
+    to: ListEnd,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if get_list(store, src)?.is_none() {
+        return Ok(None);
+    }
+    // Fail before popping anything
+    get_list(store, dst)?;
+
+    let Some(Some(v)) = with_list(store, src, |list, limit| list.pop(from, limit))? else {
+        return Ok(None);
+    };
+    create_list_if_missing(store, dst)?;
+    with_list(store, dst, |list, limit| list.push(&v, to, limit))?;
+    store.add_dirty(2);
+
+    return Ok(Some(v));
+}
+
+pub fn lmove(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (Some(from), Some(to)) = (parse_end(&args[2]), parse_end(&args[3])) else {
+        return encode_error(anyhow!("ERR syntax error"));
+    };
+
+    return match lmove_generic(store, &args[0], &args[1], from, to) {
+        Ok(Some(v)) => encode(Value::String(v), false),
+        Ok(None) => nil(client.protover),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn rpoplpush(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match lmove_generic(store, &args[0], &args[1], ListEnd::Tail, ListEnd::Head) {
+        Ok(Some(v)) => encode(Value::String(v), false),
+        Ok(None) => nil(client.protover),
+        Err(err) => encode_error(err),
+    };
+}
+
+fn blocking_pop(
+    args: Vec<Vec<u8>>,
+    client: &mut Client,
+    store: &mut Store,
+    end: ListEnd,
+) -> Vec<u8> {
+    let (timeout, keys) = args.split_last().unwrap();
+    let timeout_at = match parse_timeout(timeout) {
+        Ok(t) => t,
+        Err(err) => return encode_error(err),
+    };
+
+    for key in keys {
+        match with_list(store, key, |list, limit| list.pop(end, limit)) {
+            Ok(Some(Some(v))) => {
+                store.add_dirty(1);
+                // Replaying must never block
+                let pop: &[u8] = if end == ListEnd::Head {
+                    b"LPOP"
+                } else {
+                    b"RPOP"
+                };
+                store.rewrite_propagation(vec![pop.to_vec(), key.clone()]);
+                return encode(Value::VectorString(vec![key.clone(), v]), false);
+            }
+            Ok(_) => continue,
+            Err(err) => return encode_error(err),
+        }
+    }
+
+    let name = if end == ListEnd::Head {
+        "BLPOP"
+    } else {
+        "BRPOP"
+    };
+    let keys = keys.to_vec();
+    let timeout_reply = nil_array(client.protover);
+    let cmd = Command {
+        cmd: name.to_string(),
+        args,
+    };
+    return block_for_keys(client, store, cmd, keys, TYPE_LIST, timeout_at, timeout_reply);
+}
+
+pub fn blpop(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return blocking_pop(args, client, store, ListEnd::Head);
+}
+
+pub fn brpop(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return blocking_pop(args, client, store, ListEnd::Tail);
+}
+
+fn blocking_move(
+    cmd: Command,
+    client: &mut Client,
+    store: &mut Store,
+    from: ListEnd,
+    to: ListEnd,
+) -> Vec<u8> {
+    let args = &cmd.args;
+    let timeout_at = match parse_timeout(&args[args.len() - 1]) {
+        Ok(t) => t,
+        Err(err) => return encode_error(err),
+    };
+
+    match lmove_generic(store, &args[0], &args[1], from, to) {
+        Ok(Some(v)) => {
+            let end = |end: ListEnd| -> &[u8] {
+                if end == ListEnd::Head {
+                    b"LEFT"
+                } else {
+                    b"RIGHT"
+                }
+            };
+            // Replaying must never block
+            store.rewrite_propagation(vec![
+                b"LMOVE".to_vec(),
+                args[0].clone(),
+                args[1].clone(),
+                end(from).to_vec(),
+                end(to).to_vec(),
+            ]);
+            return encode(Value::String(v), false);
+        }
+        Ok(None) => {}
+        Err(err) => return encode_error(err),
+    }
+
+    let keys = vec![args[0].clone()];
+    let timeout_reply = nil(client.protover);
+    return block_for_keys(client, store, cmd, keys, TYPE_LIST, timeout_at, timeout_reply);
+}
+
+pub fn blmove(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (Some(from), Some(to)) = (parse_end(&args[2]), parse_end(&args[3])) else {
+        return encode_error(anyhow!("ERR syntax error"));
+    };
+
+    let cmd = Command {
+        cmd: "BLMOVE".to_string(),
+        args,
+    };
+    return blocking_move(cmd, client, store, from, to);
+}
+
+pub fn brpoplpush(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let cmd = Command {
+        cmd: "BRPOPLPUSH".to_string(),
+        args,
+    };
+    return blocking_move(cmd, client, store, ListEnd::Tail, ListEnd::Head);
+}
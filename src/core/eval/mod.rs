@@ -0,0 +1,262 @@
+use std::io::{self, Write};
+
+use anyhow::anyhow;
+
+use crate::common::Value;
+use crate::core::{
+    client::Client,
+    cmd::{lookup, Command, CommandSpec, Commands, CMD_DENYOOM},
+    multi::{flag_transaction, is_queued},
+    resp::{encode, encode_error},
+};
+use crate::data::store::Store;
+
+pub mod hash;
+pub mod keyspace;
+pub mod list;
+pub mod server;
+pub mod set;
+pub mod stream;
+pub mod string;
+pub mod transaction;
+pub mod zset;
+
+/// Looks the command up in the command table, validates it against its
+/// descriptor and runs it, or queues it if the client is in a transaction.
+/// A command refused while queuing makes the whole transaction fail.
+pub fn call(cmd: Command, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(spec) = lookup(&cmd.cmd) else {
+        flag_transaction(client);
+        let args = cmd
+            .args
+            .iter()
+            .take(20)
+            .map(|a| format!("'{}' ", String::from_utf8_lossy(a)))
+            .collect::<String>();
+        return encode_error(anyhow!(
+            "ERR unknown command '{}', with args beginning with: {}",
+            cmd.cmd,
+            args
+        ));
+    };
+
+    if !spec.check_arity(cmd.args.len() + 1) {
+        flag_transaction(client);
+        return encode_error(anyhow!(
+            "ERR wrong number of arguments for '{}' command",
+            spec.name
+        ));
+    }
+
+    if spec.is_write() && store.config().read_only && !store.is_loading() {
+        flag_transaction(client);
+        return encode_error(anyhow!("READONLY You can't write against a read only replica."));
+    }
+
+    // Like Redis, refuse writes that can't be persisted rather than lose
+    // them, and PING so that monitoring notices
+    if let Some(err) = store.aof_write_error() {
+        if spec.is_write() || spec.name == "ping" {
+            flag_transaction(client);
+            return encode_error(anyhow!("MISCONF Errors writing to the AOF file: {}", err));
+        }
+    }
+
+    // Make room before running anything, like Redis does, and refuse
+    // commands that may grow the dataset when that isn't possible.
+    if !store.is_loading() && !store.perform_evictions() && spec.flags & CMD_DENYOOM != 0 {
+        flag_transaction(client);
+        return encode_error(anyhow!("OOM command not allowed when used memory > 'maxmemory'."));
+    }
+
+    if let Some(multi) = client.multi.as_mut() {
+        if is_queued(spec.name) {
+            multi.cmds.push(cmd);
+            return encode(Value::String(b"QUEUED".to_vec()), true);
+        }
+    }
+
+    return execute(spec, cmd.args, client, store);
+}
+
+/// Runs a command that passed the checks of `call` against the database of
+/// `client`, and propagates it if it changed the dataset.
+fn execute(
+    spec: &CommandSpec,
+    args: Vec<Vec<u8>>,
+    client: &mut Client,
+    store: &mut Store,
+) -> Vec<u8> {
+    store.select(client.db);
+
+    if !spec.is_write() {
+        let reply = (spec.handler)(args, client, store);
+        // Reads go through `get_mut` too, e.g. to drop expired hash fields
+        store.discard_propagation();
+        return reply;
+    }
+
+    let mut argv = Vec::with_capacity(args.len() + 1);
+    argv.push(spec.name.to_ascii_uppercase().into_bytes());
+    argv.extend(args.iter().cloned());
+
+    let dirty = store.dirty();
+    let reply = (spec.handler)(args, client, store);
+
+    // Only writes that actually changed the dataset reach the AOF, so a
+    // failed or no-op command is never replayed.
+    if store.dirty() != dirty {
+        store.propagate(argv);
+    } else {
+        store.discard_propagation();
+    }
+
+    return reply;
+}
+
+/// Runs `cmds` in order and returns their replies. A command that blocks
+/// the client stops the batch, the rest is queued in `client.pending` until
+/// it is unblocked.
+fn process_commands(
+    cmds: impl IntoIterator<Item = Command>,
+    client: &mut Client,
+    store: &mut Store,
+) -> Vec<u8> {
+    let mut replies = Vec::new();
+    let mut cmds = cmds.into_iter();
+
+    while client.blocked.is_none() {
+        let Some(cmd) = cmds.next() else {
+            break;
+        };
+        replies.extend(call(cmd, client, store));
+    }
+    client.pending.extend(cmds);
+
+    return replies;
+}
+
+/// Runs the commands `client` sent while it was blocked.
+pub fn process_pending(client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let pending = std::mem::take(&mut client.pending);
+    return process_commands(pending, client, store);
+}
+
+/// Runs `cmds` and returns the replies to send to `client`.
+pub fn replies(cmds: Commands, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let replies = process_commands(cmds, client, store);
+
+    // Writes must be in the AOF before the client hears about them.
+    store.flush_aof();
+
+    return replies;
+}
+
+pub fn respond(
+    cmds: Commands,
+    client: &mut Client,
+    store: &mut Store,
+    stream: &mut impl Write,
+) -> io::Result<()> {
+    stream.write_all(&replies(cmds, client, store))?;
+    return stream.flush();
+}
+
+/// Helpers shared by the tests of the command implementations.
+#[cfg(test)]
+mod test_helpers {
+    use clap::Parser;
+
+    use crate::{
+        common::Value,
+        config::Config,
+        core::{
+            client::Client,
+            cmd::Command,
+            resp::{decode, encode},
+        },
+        data::store::Store,
+    };
+
+    pub(super) fn store() -> Store {
+        return Store::new(Config::parse_from(["redrust"]));
+    }
+
+    /// Config of a store with an AOF in the temporary directory, named after
+    /// `test`.
+    fn aof_config(test: &str) -> Config {
+        let path = std::env::temp_dir().join(format!("redrust-{}-{}.aof", test, std::process::id()));
+        return Config::parse_from(["redrust", "--appendonly", "--aof-file", path.to_str().unwrap()]);
+    }
+
+    /// A store logging to a new AOF, named after `test`.
+    pub(super) fn store_with_aof(test: &str) -> Store {
+        let config = aof_config(test);
+        let _ = std::fs::remove_file(&config.aof_file);
+
+        let mut store = Store::new(config);
+        store.open_aof().unwrap();
+        return store;
+    }
+
+    /// A store that loaded an AOF made of `cmds`, named after `test`.
+    pub(super) fn store_from_aof(test: &str, cmds: &[&[&str]]) -> Store {
+        let config = aof_config(test);
+        let mut data = Vec::new();
+        for argv in cmds {
+            let argv = argv.iter().map(|a| a.as_bytes().to_vec()).collect();
+            data.extend(encode(Value::VectorString(argv), false));
+        }
+        std::fs::write(&config.aof_file, data).unwrap();
+
+        let mut store = Store::new(config);
+        store.load_aof().unwrap();
+        return store;
+    }
+
+    /// The commands `store` logged to its AOF so far.
+    pub(super) fn logged(store: &mut Store) -> Vec<Vec<String>> {
+        store.flush_aof();
+        let data = std::fs::read(&store.config().aof_file).unwrap();
+
+        return decode(&data)
+            .unwrap()
+            .into_iter()
+            .map(|cmd| {
+                let Value::Vector(argv) = cmd else {
+                    panic!("not a command");
+                };
+                argv.iter().map(Value::to_string).collect()
+            })
+            .collect();
+    }
+
+    /// Runs `args`, a command name followed by its arguments, the way the
+    /// server does when `client` sends it.
+    pub(super) fn run(store: &mut Store, client: &mut Client, args: &[&str]) -> Vec<u8> {
+        let argv = args.iter().map(|a| a.as_bytes().to_vec()).collect::<Vec<_>>();
+        return super::call(Command::from(argv), client, store);
+    }
+
+    /// Runs `fill` against an empty store, then `scan`, a command of the SCAN
+    /// family without its options, with a COUNT of `i64::MAX`. Returns the
+    /// cursor of the reply and how many items it holds.
+    pub(super) fn scan_with_huge_count(fill: &[String], scan: &[&str]) -> (Vec<u8>, usize) {
+        let mut store = store();
+        let mut client = Client::new();
+        let fill = fill.iter().map(String::as_str).collect::<Vec<_>>();
+        run(&mut store, &mut client, &fill);
+
+        let count = i64::MAX.to_string();
+        let scan = [scan, &["COUNT", &count]].concat();
+        let reply = run(&mut store, &mut client, &scan);
+
+        let Value::Vector(reply) = &decode(&reply).unwrap()[0] else {
+            panic!("not a scan reply");
+        };
+        let Value::Vector(items) = &reply[1] else {
+            panic!("not a scan reply");
+        };
+        return (reply[0].to_bytes(), items.len());
+    }
+}
@@ -0,0 +1,337 @@
+use std::fmt::Display;
+
+use anyhow::anyhow;
+
+use crate::common::{parse_i64, Value};
+use crate::core::{
+    client::Client,
+    cmd::{lookup, CommandSpec, COMMAND_TABLE},
+    resp::{encode, encode_error, encode_help, encode_proto, nil, RESP2, RESP3, RESP_OK},
+};
+use crate::data::store::{Store, MEMORY_USAGE_SAMPLES};
+
+/// Redis version reported to clients, they use it to decide which features
+/// are available.
+const REDIS_VERSION: &str = "7.4.0";
+
+pub fn ping(args: Vec<Vec<u8>>, _client: &mut Client, _store: &mut Store) -> Vec<u8> {
+    if args.len() >= 2 {
+        return encode_error(anyhow!("ERR wrong number of arguments for 'ping' command"));
+    }
+
+    return if args.is_empty() {
+        encode(Value::String(b"PONG".to_vec()), true)
+    } else {
+        encode(Value::String(args[0].clone()), false)
+    };
+}
+
+pub fn hello(args: Vec<Vec<u8>>, client: &mut Client, _store: &mut Store) -> Vec<u8> {
+    let mut protover = client.protover;
+    let mut i = 0;
+
+    if !args.is_empty() {
+        protover = match parse_i64(&args[0]) {
+            Some(v) if v == RESP2 as i64 || v == RESP3 as i64 => v as u8,
+            Some(_) => return encode_error(anyhow!("NOPROTO unsupported protocol version")),
+            None => {
+                return encode_error(anyhow!(
+                    "ERR Protocol version is not an integer or out of range"
+                ))
+            }
+        };
+        i = 1;
+    }
+
+    let mut name = None;
+    while i < args.len() {
+        let more_args = args.len() - i - 1;
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"AUTH" if more_args >= 2 => {
+                // There is no ACL, so only the implicit `default` user with
+                // no password exists and any password is accepted for it.
+                if args[i + 1] != b"default" {
+                    return encode_error(anyhow!(
+                        "WRONGPASS invalid username-password pair or user is disabled."
+                    ));
+                }
+                i += 3;
+            }
+            b"SETNAME" if more_args >= 1 => {
+                if args[i + 1].iter().any(|&b| b <= b' ' || b > b'~') {
+                    return encode_error(anyhow!(
+                        "ERR Client names cannot contain spaces, newlines or special characters."
+                    ));
+                }
+                name = Some(args[i + 1].clone());
+                i += 2;
+            }
+            _ => {
+                return encode_error(anyhow!(
+                    "ERR Syntax error in HELLO option '{}'",
+                    String::from_utf8_lossy(&args[i])
+                ))
+            }
+        }
+    }
+
+    client.protover = protover;
+    if name.is_some() {
+        client.name = name;
+    }
+
+    let info = Value::Map(vec![
+        (Value::String(b"server".to_vec()), Value::String(b"redis".to_vec())),
+        (
+            Value::String(b"version".to_vec()),
+            Value::String(REDIS_VERSION.as_bytes().to_vec()),
+        ),
+        (Value::String(b"proto".to_vec()), Value::Int64(protover as i64)),
+        (Value::String(b"id".to_vec()), Value::Int64(client.id as i64)),
+        (Value::String(b"mode".to_vec()), Value::String(b"standalone".to_vec())),
+        (Value::String(b"role".to_vec()), Value::String(b"master".to_vec())),
+        (Value::String(b"modules".to_vec()), Value::Vector(vec![])),
+    ]);
+
+    // The reply already uses the protocol that was just negotiated.
+    return encode_proto(info, false, client.protover);
+}
+
+pub fn bg_rewrite_aof(_args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    if store.aof_rewrite_in_progress() {
+        return encode_error(anyhow!(
+            "ERR Background append only file rewriting already in progress"
+        ));
+    }
+    if store.child_in_progress() {
+        store.schedule_aof_rewrite();
+        return encode(
+            Value::String(b"Background append only file rewriting scheduled".to_vec()),
+            true,
+        );
+    }
+
+    if let Err(err) = store.bg_rewrite_aof() {
+        return encode_error(anyhow!("ERR {}", err));
+    }
+
+    return encode(
+        Value::String(b"Background append only file rewriting started".to_vec()),
+        true,
+    );
+}
+
+pub fn save(_args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    if let Err(err) = store.rdb_save() {
+        return encode_error(anyhow!("ERR {}", err));
+    }
+
+    return RESP_OK.to_vec();
+}
+
+pub fn bgsave(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let schedule = match args.first() {
+        None => false,
+        Some(arg) if arg.eq_ignore_ascii_case(b"SCHEDULE") => true,
+        Some(_) => return encode_error(anyhow!("ERR syntax error")),
+    };
+
+    if store.aof_rewrite_in_progress() {
+        if !schedule {
+            return encode_error(anyhow!(
+                "ERR Another child process is active (AOF?): can't BGSAVE right now. \
+                 Use BGSAVE SCHEDULE in order to schedule a BGSAVE whenever possible."
+            ));
+        }
+
+        store.schedule_bgsave();
+        return encode(Value::String(b"Background saving scheduled".to_vec()), true);
+    }
+
+    if let Err(err) = store.rdb_bgsave() {
+        return encode_error(anyhow!("ERR {}", err));
+    }
+
+    return encode(Value::String(b"Background saving started".to_vec()), true);
+}
+
+pub fn memory(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let subcommand = args[0].to_ascii_uppercase();
+    match (subcommand.as_slice(), args.len()) {
+        (b"USAGE", 2 | 4) => {
+            let mut samples = MEMORY_USAGE_SAMPLES;
+            if args.len() == 4 {
+                if !args[2].eq_ignore_ascii_case(b"SAMPLES") {
+                    return encode_error(anyhow!("ERR syntax error"));
+                }
+                samples = match parse_i64(&args[3]) {
+                    Some(n) if n >= 0 => n as usize,
+                    _ => {
+                        return encode_error(anyhow!(
+                            "ERR value is out of range, must be positive"
+                        ))
+                    }
+                };
+            }
+
+            return match store.memory_usage(&args[1], samples) {
+                Some(n) => encode(Value::Int64(n as i64), false),
+                None => nil(client.protover),
+            };
+        }
+        (b"HELP", 1) => {
+            let help = [
+                "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+                "USAGE <key> [SAMPLES <count>]",
+                "    Return memory in bytes used by <key> and its value. Nested values are",
+                "    sampled up to <count> times (default: 5, 0 means sample all).",
+                "HELP",
+                "    Print this help.",
+            ];
+            return encode_help(&help);
+        }
+        _ => {
+            return encode_error(anyhow!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try MEMORY HELP.",
+                String::from_utf8_lossy(&args[0])
+            ))
+        }
+    }
+}
+
+pub fn lastsave(_args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return encode(Value::Int64(store.last_save()), false);
+}
+
+fn info_section(title: &str, fields: Vec<(impl Display, String)>) -> String {
+    let mut section = format!("# {}\r\n", title);
+    for (name, value) in fields {
+        section.push_str(&format!("{}:{}\r\n", name, value));
+    }
+
+    return section;
+}
+
+pub fn info(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let wanted: Vec<String> = args
+        .iter()
+        .map(|a| String::from_utf8_lossy(a).to_lowercase())
+        .collect();
+    let all = wanted.is_empty() || wanted.iter().any(|w| w == "all" || w == "everything");
+    let include = |name: &str| all || wanted.iter().any(|w| w == name || w == "default");
+
+    let mut sections = Vec::new();
+    if include("server") {
+        sections.push(info_section(
+            "Server",
+            vec![
+                ("redis_version", REDIS_VERSION.to_string()),
+                ("redrust_version", env!("CARGO_PKG_VERSION").to_string()),
+                ("process_id", std::process::id().to_string()),
+                ("tcp_port", store.config().port.to_string()),
+            ],
+        ));
+    }
+    if include("memory") {
+        sections.push(info_section("Memory", store.memory_info()));
+    }
+    if include("persistence") {
+        let mut fields = vec![("loading", (store.is_loading() as u8).to_string())];
+        fields.extend(store.rdb_info());
+        fields.extend(store.child_info());
+        fields.extend(store.aof_info());
+        sections.push(info_section("Persistence", fields));
+    }
+
+    if include("stats") {
+        sections.push(info_section("Stats", store.stats_info()));
+    }
+    if include("keyspace") {
+        sections.push(info_section("Keyspace", store.keyspace_info()));
+    }
+
+    let text = sections.join("\r\n").into_bytes();
+    return encode_proto(Value::Verbatim("txt".to_owned(), text), false, client.protover);
+}
+
+fn command_info(spec: &CommandSpec) -> Value {
+    return Value::Vector(vec![
+        Value::String(spec.name.as_bytes().to_vec()),
+        Value::Int64(spec.arity as i64),
+        Value::Set(
+            spec.flag_names()
+                .into_iter()
+                .map(|f| Value::String(f.as_bytes().to_vec()))
+                .collect(),
+        ),
+        Value::Int64(spec.first_key as i64),
+        Value::Int64(spec.last_key as i64),
+        Value::Int64(spec.step as i64),
+        // ACL categories, tips, key specifications and subcommands
+        Value::Set(vec![]),
+        Value::Set(vec![]),
+        Value::Vector(vec![]),
+        Value::Vector(vec![]),
+    ]);
+}
+
+fn command_getkeys(args: &[Vec<u8>]) -> Vec<u8> {
+    let Some(spec) = lookup(&String::from_utf8_lossy(&args[0])) else {
+        return encode_error(anyhow!("ERR Invalid command specified"));
+    };
+    if !spec.check_arity(args.len()) {
+        return encode_error(anyhow!(
+            "ERR Invalid number of arguments specified for command"
+        ));
+    }
+
+    let cmd_args = &args[1..];
+    let keys: Vec<Vec<u8>> = spec
+        .key_positions(cmd_args)
+        .into_iter()
+        .map(|pos| cmd_args[pos].clone())
+        .collect();
+    if keys.is_empty() {
+        return encode_error(anyhow!("ERR The command has no key arguments"));
+    }
+
+    return encode(Value::VectorString(keys), false);
+}
+
+pub fn command(args: Vec<Vec<u8>>, client: &mut Client, _store: &mut Store) -> Vec<u8> {
+    if args.is_empty() {
+        let infos = COMMAND_TABLE.iter().map(command_info).collect();
+        return encode_proto(Value::Vector(infos), false, client.protover);
+    }
+
+    let subcommand = args[0].to_ascii_uppercase();
+    let reply = match (subcommand.as_slice(), args.len()) {
+        (b"COUNT", 1) => Value::Int64(COMMAND_TABLE.len() as i64),
+        (b"LIST", 1) => Value::VectorString(
+            COMMAND_TABLE
+                .iter()
+                .map(|c| c.name.as_bytes().to_vec())
+                .collect(),
+        ),
+        (b"INFO", 1) => Value::Vector(COMMAND_TABLE.iter().map(command_info).collect()),
+        (b"INFO", _) => Value::Vector(
+            args[1..]
+                .iter()
+                .map(|name| match lookup(&String::from_utf8_lossy(name)) {
+                    Some(spec) => command_info(spec),
+                    None => Value::Empty,
+                })
+                .collect(),
+        ),
+        (b"DOCS", _) => Value::Map(vec![]),
+        (b"GETKEYS", n) if n >= 2 => return command_getkeys(&args[1..]),
+        _ => {
+            return encode_error(anyhow!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try COMMAND HELP.",
+                String::from_utf8_lossy(&args[0])
+            ))
+        }
+    };
+
+    return encode_proto(reply, false, client.protover);
+}
@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+
+use crate::common::{parse_i64, random_u64, Value};
+use crate::core::{
+    client::Client,
+    resp::{encode, encode_error, encode_proto, nil, RESP_ONE, RESP_ZERO},
+};
+use crate::data::{
+    set::Set,
+    store::{ObjectValue, Store, StoreObject, ENCODING_INTSET, TYPE_SET},
+};
+
+use super::keyspace::{parse_scan_args, scan_buckets, scan_reply};
+
+/// The set at `key`, `None` if there is no such key.
+fn get_set<'a>(store: &'a mut Store, key: &[u8]) -> anyhow::Result<Option<&'a Set>> {
+    let Some(obj) = store.get(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_SET)?;
+
+    return match &obj.value {
+        ObjectValue::Set(set) => Ok(Some(set)),
+        _ => unreachable!("set typed object doesn't hold a set"),
+    };
+}
+
+/// Runs `f` on the set at `key`, `None` if there is no such key. Like every
+/// aggregate, a set that ends up empty is deleted.
+fn with_set<T>(
+    store: &mut Store,
+    key: &[u8],
+    f: impl FnOnce(&mut Set, usize) -> T,
+) -> anyhow::Result<Option<T>> {
+    let max_intset_entries = store.set_max_intset_entries();
+    let Some(obj) = store.get_mut(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_SET)?;
+
+    let ObjectValue::Set(set) = &mut obj.value else {
+        unreachable!("set typed object doesn't hold a set");
+    };
+    let result = f(set, max_intset_entries);
+    let empty = set.is_empty();
+    obj.refresh_encoding();
+
+    if empty {
+        store.del(key);
+    }
+
+    return Ok(Some(result));
+}
+
+/// Replaces whatever is at `key` with a set made of `members`, or deletes
+/// it if there are none. Returns the size of the set.
+fn store_set(store: &mut Store, key: &[u8], members: Vec<Vec<u8>>) -> usize {
+    store.del(key);
+    if members.is_empty() {
+        return 0;
+    }
+
+    let set = Set::from_members(members, store.set_max_intset_entries());
+    let len = set.len();
+    let mut obj = StoreObject::new(ObjectValue::Set(set), -1, TYPE_SET, ENCODING_INTSET);
+    obj.refresh_encoding();
+    store.put(key.to_vec(), obj);
+
+    return len;
+}
+
+/// Set reply: a set in RESP3, an array in RESP2.
+fn encode_members(members: Vec<Vec<u8>>, protover: u8) -> Vec<u8> {
+    let members = members.into_iter().map(Value::String).collect();
+    return encode_proto(Value::Set(members), false, protover);
+}
+
+pub fn sadd(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    match get_set(store, key) {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let set = ObjectValue::Set(Set::new());
+            store.put(
+                key.to_vec(),
+                StoreObject::new(set, -1, TYPE_SET, ENCODING_INTSET),
+            );
+        }
+        Err(err) => return encode_error(err),
+    }
+
+    let members = &args[1..];
+    let added = with_set(store, key, |set, max_intset_entries| {
+        members
+            .iter()
+            .filter(|member| set.insert(member, max_intset_entries))
+            .count()
+    });
+
+    return match added {
+        Ok(added) => {
+            let added = added.unwrap_or(0);
+            store.add_dirty(added as u64);
+            encode(Value::Int64(added as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn srem(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let members = &args[1..];
+    let removed = with_set(store, &args[0], |set, _| {
+        members.iter().filter(|member| set.remove(member)).count()
+    });
+
+    return match removed {
+        Ok(removed) => {
+            let removed = removed.unwrap_or(0);
+            store.add_dirty(removed as u64);
+            encode(Value::Int64(removed as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn sismember(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_set(store, &args[0]) {
+        Ok(Some(set)) if set.contains(&args[1]) => RESP_ONE.to_vec(),
+        Ok(_) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn smismember(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let set = match get_set(store, &args[0]) {
+        Ok(set) => set,
+        Err(err) => return encode_error(err),
+    };
+
+    let replies = args[1..]
+        .iter()
+        .map(|member| Value::Int64(set.is_some_and(|s| s.contains(member)) as i64))
+        .collect();
+    return encode(Value::Vector(replies), false);
+}
+
+pub fn smembers(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_set(store, &args[0]) {
+        Ok(set) => {
+            let members = set.map_or(vec![], |s| s.iter().collect());
+            encode_members(members, client.protover)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn scard(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_set(store, &args[0]) {
+        Ok(set) => encode(Value::Int64(set.map_or(0, |s| s.len()) as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn spop(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    let count = match args.get(1) {
+        None => None,
+        Some(arg) => match parse_i64(arg) {
+            Some(n) if n >= 0 => Some(n as usize),
+            _ => return encode_error(anyhow!("ERR value is out of range, must be positive")),
+        },
+    };
+    if args.len() > 2 {
+        return encode_error(anyhow!("ERR syntax error"));
+    }
+
+    let popped = with_set(store, key, |set, _| {
+        (0..count.unwrap_or(1))
+            .map_while(|_| set.pop())
+            .collect::<Vec<_>>()
+    });
+    let mut popped = match popped {
+        Ok(Some(popped)) => popped,
+        Ok(None) if count.is_some() => return encode_members(vec![], client.protover),
+        Ok(None) => return nil(client.protover),
+        Err(err) => return encode_error(err),
+    };
+
+    if !popped.is_empty() {
+        store.add_dirty(popped.len() as u64);
+        // Which members get popped is random, log which ones did.
+        let mut argv = vec![b"SREM".to_vec(), key.clone()];
+        argv.extend(popped.iter().cloned());
+        store.rewrite_propagation(argv);
+    }
+
+    if count.is_some() {
+        return encode_members(popped, client.protover);
+    }
+    return encode(Value::String(popped.remove(0)), false);
+}
+
+/// Picks `count` distinct members of `set`, which is bigger than that.
+fn distinct_random_members(set: &Set, count: usize) -> Vec<Vec<u8>> {
+    // Close to the whole set: drop random members rather than picking
+    // random ones, which would mostly find already picked members.
+    if count * 3 > set.len() {
+        let mut members: Vec<_> = set.iter().collect();
+        while members.len() > count {
+            members.swap_remove(random_u64() as usize % members.len());
+        }
+        return members;
+    }
+
+    let mut picked = HashSet::new();
+    while picked.len() < count {
+        if let Some(member) = set.random_member() {
+            picked.insert(member);
+        }
+    }
+
+    return picked.into_iter().collect();
+}
+
+pub fn srandmember(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(count) = args.get(1) else {
+        return match get_set(store, &args[0]) {
+            Ok(set) => match set.and_then(|s| s.random_member()) {
+                Some(member) => encode(Value::String(member), false),
+                None => nil(client.protover),
+            },
+            Err(err) => encode_error(err),
+        };
+    };
+
+    let Some(count) = parse_i64(count) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+    if args.len() > 2 {
+        return encode_error(anyhow!("ERR syntax error"));
+    }
+
+    let set = match get_set(store, &args[0]) {
+        Ok(Some(set)) => set,
+        Ok(None) => return encode(Value::VectorString(vec![]), false),
+        Err(err) => return encode_error(err),
+    };
+
+    let wanted = count.unsigned_abs() as usize;
+    let members = if count < 0 {
+        // Repetitions are allowed
+        (0..wanted).filter_map(|_| set.random_member()).collect()
+    } else if wanted >= set.len() {
+        set.iter().collect()
+    } else {
+        distinct_random_members(set, wanted)
+    };
+
+    return encode(Value::VectorString(members), false);
+}
+
+pub fn smove(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (src, dst, member) = (&args[0], &args[1], &args[2]);
+
+    // Both types are checked before anything moves
+    let in_src = match get_set(store, src) {
+        Ok(set) => set.is_some_and(|s| s.contains(member)),
+        Err(err) => return encode_error(err),
+    };
+    if let Err(err) = get_set(store, dst) {
+        return encode_error(err);
+    }
+    if !in_src {
+        return RESP_ZERO.to_vec();
+    }
+    if src == dst {
+        return RESP_ONE.to_vec();
+    }
+
+    let _ = with_set(store, src, |set, _| set.remove(member));
+    if !store.exists(dst) {
+        store_set(store, dst, vec![member.clone()]);
+    } else {
+        let _ = with_set(store, dst, |set, max_intset_entries| {
+            set.insert(member, max_intset_entries)
+        });
+    }
+    store.add_dirty(1);
+
+    return RESP_ONE.to_vec();
+}
+
+/// The sets at `keys`, `None` for missing keys.
+fn read_sets<'a>(store: &'a mut Store, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Option<&'a Set>>> {
+    // Check the types, and get rid of expired keys
+    for key in keys {
+        get_set(store, key)?;
+    }
+
+    let store = &*store;
+    return Ok(keys
+        .iter()
+        .map(|key| match store.get_ref(key).map(|obj| &obj.value) {
+            Some(ObjectValue::Set(set)) => Some(set),
+            _ => None,
+        })
+        .collect());
+}
+
+/// Members of every set, stopping once `limit` were found if not 0.
+fn inter(sets: &[Option<&Set>], limit: usize) -> Vec<Vec<u8>> {
+    let Some(mut sets) = sets.iter().copied().collect::<Option<Vec<&Set>>>() else {
+        return vec![]; // A missing key is an empty set
+    };
+    // Smallest first, it bounds the result
+    sets.sort_by_key(|set| set.len());
+
+    let (first, others) = sets.split_first().unwrap();
+    let members = first
+        .iter()
+        .filter(|member| others.iter().all(|set| set.contains(member)));
+    if limit > 0 {
+        return members.take(limit).collect();
+    }
+    return members.collect();
+}
+
+fn union(sets: &[Option<&Set>]) -> Vec<Vec<u8>> {
+    let mut seen = HashSet::new();
+    return sets
+        .iter()
+        .flatten()
+        .flat_map(|set| set.iter())
+        .filter(|member| seen.insert(member.clone()))
+        .collect();
+}
+
+/// Members of the first set that are in none of the others.
+fn diff(sets: &[Option<&Set>]) -> Vec<Vec<u8>> {
+    let Some(first) = sets[0] else {
+        return vec![];
+    };
+
+    return first
+        .iter()
+        .filter(|member| sets[1..].iter().flatten().all(|set| !set.contains(member)))
+        .collect();
+}
+
+fn algebra(
+    keys: &[Vec<u8>],
+    store: &mut Store,
+    op: fn(&[Option<&Set>]) -> Vec<Vec<u8>>,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let sets = read_sets(store, keys)?;
+    return Ok(op(&sets));
+}
+
+fn algebra_command(
+    args: Vec<Vec<u8>>,
+    client: &Client,
+    store: &mut Store,
+    op: fn(&[Option<&Set>]) -> Vec<Vec<u8>>,
+) -> Vec<u8> {
+    return match algebra(&args, store, op) {
+        Ok(members) => encode_members(members, client.protover),
+        Err(err) => encode_error(err),
+    };
+}
+
+fn algebra_store_command(
+    args: Vec<Vec<u8>>,
+    store: &mut Store,
+    op: fn(&[Option<&Set>]) -> Vec<Vec<u8>>,
+) -> Vec<u8> {
+    return match algebra(&args[1..], store, op) {
+        Ok(members) => {
+            let len = store_set(store, &args[0], members);
+            encode(Value::Int64(len as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn sinter(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return algebra_command(args, client, store, |sets| inter(sets, 0));
+}
+
+pub fn sunion(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return algebra_command(args, client, store, union);
+}
+
+pub fn sdiff(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return algebra_command(args, client, store, diff);
+}
+
+pub fn sinterstore(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return algebra_store_command(args, store, |sets| inter(sets, 0));
+}
+
+pub fn sunionstore(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return algebra_store_command(args, store, union);
+}
+
+pub fn sdiffstore(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return algebra_store_command(args, store, diff);
+}
+
+pub fn sintercard(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let numkeys = match parse_i64(&args[0]) {
+        Some(n) if n > 0 => n as usize,
+        Some(_) => return encode_error(anyhow!("ERR numkeys should be greater than 0")),
+        None => return encode_error(anyhow!("ERR value is not an integer or out of range")),
+    };
+    if numkeys > args.len() - 1 {
+        return encode_error(anyhow!(
+            "ERR Number of keys can't be greater than number of args"
+        ));
+    }
+
+    let keys = &args[1..=numkeys];
+    let mut limit = 0;
+    let mut i = numkeys + 1;
+    while i < args.len() {
+        if !args[i].eq_ignore_ascii_case(b"LIMIT") || i + 1 == args.len() {
+            return encode_error(anyhow!("ERR syntax error"));
+        }
+        limit = match parse_i64(&args[i + 1]) {
+            Some(n) if n >= 0 => n as usize,
+            Some(_) => return encode_error(anyhow!("ERR LIMIT can't be negative")),
+            None => return encode_error(anyhow!("ERR value is not an integer or out of range")),
+        };
+        i += 2;
+    }
+
+    return match read_sets(store, keys) {
+        Ok(sets) => encode(Value::Int64(inter(&sets, limit).len() as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn sscan(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (cursor, opts) = match parse_scan_args(&args[1..], false, false) {
+        Ok(parsed) => parsed,
+        Err(err) => return encode_error(err),
+    };
+
+    let set = match get_set(store, &args[0]) {
+        Ok(Some(set)) => set,
+        Ok(None) => return scan_reply(0, vec![]),
+        Err(err) => return encode_error(err),
+    };
+
+    let mut members = Vec::new();
+    let cursor = scan_buckets(cursor, opts.count, |cursor| {
+        let next = set.scan(cursor, |member| {
+            if opts.matches(member) {
+                members.push(member.to_vec());
+            }
+        });
+        (next, members.len())
+    });
+
+    return scan_reply(cursor, members);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::eval::test_helpers::scan_with_huge_count;
+
+    #[test]
+    fn test_sscan_with_a_huge_count() {
+        let mut fill = vec!["SADD".to_string(), "s".to_string()];
+        fill.extend((0..200).map(|i| format!("m{}", i)));
+
+        assert_eq!(scan_with_huge_count(&fill, &["SSCAN", "s", "0"]), (b"0".to_vec(), 200));
+    }
+}
@@ -0,0 +1,1820 @@
+use anyhow::anyhow;
+use chrono::Utc;
+
+use crate::common::{parse_i64, Value};
+use crate::core::{
+    blocked::block_for_keys,
+    client::Client,
+    cmd::Command,
+    resp::{
+        encode, encode_error, encode_help, encode_proto, nil, nil_array, RESP3, RESP_OK, RESP_ZERO,
+    },
+};
+use crate::data::{
+    store::{ObjectValue, Store, StoreObject, ENCODING_STREAM, TYPE_STREAM},
+    stream::{ConsumerGroup, Fields, Stream, StreamId, Trim},
+};
+
+/// Entries removed at most by a `~` trim without LIMIT, Redis' default of
+/// 100 times `stream-node-max-entries`.
+const APPROX_TRIM_LIMIT: usize = 10_000;
+
+/// Entries XAUTOCLAIM claims when not given a COUNT.
+const AUTOCLAIM_DEFAULT_COUNT: usize = 100;
+
+/// Entries XINFO STREAM FULL lists when not given a COUNT.
+const XINFO_FULL_DEFAULT_COUNT: usize = 10;
+
+/// Unix time in ms, the clock `StoreObject::new` uses too. Stream ids and
+/// delivery times are based on it.
+fn now_ms() -> i64 {
+    return Utc::now().timestamp_millis();
+}
+
+fn arg(s: &str) -> Vec<u8> {
+    return s.as_bytes().to_vec();
+}
+
+fn invalid_id() -> anyhow::Error {
+    return anyhow!("ERR Invalid stream ID specified as stream command argument");
+}
+
+fn no_group(key: &[u8], group: &[u8]) -> anyhow::Error {
+    return anyhow!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group)
+    );
+}
+
+fn no_group_for_key(key: &[u8], group: &[u8]) -> anyhow::Error {
+    return anyhow!(
+        "NOGROUP No such consumer group '{}' for key name '{}'",
+        String::from_utf8_lossy(group),
+        String::from_utf8_lossy(key)
+    );
+}
+
+fn parse_integer(arg: &[u8]) -> anyhow::Result<i64> {
+    return parse_i64(arg).ok_or_else(|| anyhow!("ERR value is not an integer or out of range"));
+}
+
+/// Parses an id where `-` and `+` also stand for the smallest and largest
+/// ones. A missing sequence number gets `missing_seq`.
+fn parse_id(arg: &[u8], missing_seq: u64) -> anyhow::Result<StreamId> {
+    return StreamId::parse(arg, missing_seq).ok_or_else(invalid_id);
+}
+
+/// Parses an id that must be spelled out, as in XADD or XACK.
+fn parse_strict_id(arg: &[u8]) -> anyhow::Result<StreamId> {
+    if arg == b"-" || arg == b"+" {
+        return Err(invalid_id());
+    }
+    return parse_id(arg, 0);
+}
+
+/// Parses the start of an interval, exclusive when prefixed by `(`. `None`
+/// when nothing can come after an exclusive start.
+fn parse_range_start(arg: &[u8]) -> anyhow::Result<Option<StreamId>> {
+    return match arg.strip_prefix(b"(") {
+        Some(id) => Ok(parse_strict_id(id)?.next()),
+        None => Ok(Some(parse_id(arg, 0)?)),
+    };
+}
+
+/// Parses the end of an interval, exclusive when prefixed by `(`. A missing
+/// sequence number includes the whole millisecond.
+fn parse_range_end(arg: &[u8]) -> anyhow::Result<Option<StreamId>> {
+    return match arg.strip_prefix(b"(") {
+        Some(id) => Ok(parse_strict_id(id)?.prev()),
+        None => Ok(Some(parse_id(arg, u64::MAX)?)),
+    };
+}
+
+/// The stream at `key`, `None` if there is no such key.
+fn get_stream<'a>(store: &'a mut Store, key: &[u8]) -> anyhow::Result<Option<&'a Stream>> {
+    let Some(obj) = store.get(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_STREAM)?;
+
+    return match &obj.value {
+        ObjectValue::Stream(stream) => Ok(Some(stream)),
+        _ => unreachable!("stream typed object doesn't hold a stream"),
+    };
+}
+
+/// Runs `f` on the stream at `key`, `None` if there is no such key. Unlike
+/// the other aggregates, an empty stream stays around along with its ids
+/// and groups.
+fn with_stream<T>(
+    store: &mut Store,
+    key: &[u8],
+    f: impl FnOnce(&mut Stream) -> T,
+) -> anyhow::Result<Option<T>> {
+    let Some(obj) = store.get_mut(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_STREAM)?;
+
+    let ObjectValue::Stream(stream) = &mut obj.value else {
+        unreachable!("stream typed object doesn't hold a stream");
+    };
+    return Ok(Some(f(stream)));
+}
+
+fn create_stream(store: &mut Store, key: &[u8]) {
+    let stream = ObjectValue::Stream(Stream::new());
+    store.put(
+        key.to_vec(),
+        StoreObject::new(stream, -1, TYPE_STREAM, ENCODING_STREAM),
+    );
+}
+
+fn entry_value(id: StreamId, fields: Option<Fields>) -> Value {
+    let fields = match fields {
+        Some(fields) => Value::VectorString(fields),
+        None => Value::Empty,
+    };
+    return Value::Vector(vec![Value::String(id.to_bytes()), fields]);
+}
+
+fn entries_value(entries: Vec<(StreamId, Fields)>) -> Value {
+    return Value::Vector(
+        entries
+            .into_iter()
+            .map(|(id, fields)| entry_value(id, Some(fields)))
+            .collect(),
+    );
+}
+
+/// MAXLEN or MINID option of XADD and XTRIM, along with its LIMIT.
+struct TrimArgs {
+    trim: Trim,
+    /// Most entries to remove, 0 for no limit.
+    limit: usize,
+}
+
+/// Options of XADD and XTRIM, parsed from `args[*i]` on. XADD's stop at
+/// the first argument that isn't one, its id.
+struct AddArgs {
+    nomkstream: bool,
+    trim: Option<TrimArgs>,
+}
+
+fn parse_add_args(args: &[Vec<u8>], i: &mut usize, xadd: bool) -> anyhow::Result<AddArgs> {
+    let mut nomkstream = false;
+    let mut trim = None;
+    let mut approx = false;
+    let mut limit = None;
+
+    while *i < args.len() {
+        let more = args.len() - *i - 1;
+        match args[*i].to_ascii_uppercase().as_slice() {
+            b"*" if xadd => break,
+            opt @ (b"MAXLEN" | b"MINID") if more > 0 => {
+                if trim.is_some() {
+                    return Err(anyhow!(
+                        "ERR syntax error, MAXLEN and MINID options at the same time are not compatible"
+                    ));
+                }
+                approx = false;
+                if more >= 2 && (args[*i + 1] == b"~" || args[*i + 1] == b"=") {
+                    approx = args[*i + 1] == b"~";
+                    *i += 1;
+                }
+                *i += 1;
+                trim = Some(if opt == b"MAXLEN" {
+                    let max = parse_integer(&args[*i])?;
+                    if max < 0 {
+                        return Err(anyhow!("ERR The MAXLEN argument must be >= 0."));
+                    }
+                    Trim::MaxLen(max as u64)
+                } else {
+                    Trim::MinId(parse_strict_id(&args[*i])?)
+                });
+            }
+            b"LIMIT" if more > 0 => {
+                *i += 1;
+                let n = parse_integer(&args[*i])?;
+                if n < 0 {
+                    return Err(anyhow!("ERR The LIMIT argument must be >= 0."));
+                }
+                limit = Some(n as usize);
+            }
+            b"NOMKSTREAM" if xadd => nomkstream = true,
+            _ if xadd => break,
+            _ => return Err(anyhow!("ERR syntax error")),
+        }
+        *i += 1;
+    }
+
+    if limit.is_some() && trim.is_none() {
+        return Err(anyhow!(
+            "ERR syntax error, LIMIT cannot be used without specifying a trimming strategy"
+        ));
+    }
+    if !xadd && trim.is_none() {
+        return Err(anyhow!(
+            "ERR syntax error, XTRIM must be called with a trimming strategy"
+        ));
+    }
+    if limit.is_some() && !approx {
+        return Err(anyhow!(
+            "ERR syntax error, LIMIT cannot be used without the special ~ option"
+        ));
+    }
+
+    // Without the node structure of Redis, approximate trimming is exact
+    // trimming, up to the limit
+    let limit = match (limit, approx) {
+        (Some(limit), _) => limit,
+        (None, true) => APPROX_TRIM_LIMIT,
+        (None, false) => 0,
+    };
+    return Ok(AddArgs {
+        nomkstream,
+        trim: trim.map(|trim| TrimArgs { trim, limit }),
+    });
+}
+
+/// Id given to XADD: `*`, `<ms>-*` or a full id.
+enum NewId {
+    Auto,
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl NewId {
+    fn parse(arg: &[u8]) -> anyhow::Result<NewId> {
+        if arg == b"*" {
+            return Ok(NewId::Auto);
+        }
+        if let Some(ms) = arg.strip_suffix(b"-*") {
+            let ms = StreamId::parse(ms, 0).filter(|_| ms != b"-" && ms != b"+");
+            return ms.map(|id| NewId::AutoSeq(id.ms)).ok_or_else(invalid_id);
+        }
+
+        let id = parse_strict_id(arg)?;
+        if id == StreamId::MIN {
+            return Err(anyhow!(
+                "ERR The ID specified in XADD must be greater than 0-0"
+            ));
+        }
+        return Ok(NewId::Explicit(id));
+    }
+
+    /// The id of the entry to add to `stream`.
+    fn resolve(&self, stream: &Stream) -> anyhow::Result<StreamId> {
+        if stream.last_id == StreamId::MAX {
+            return Err(anyhow!(
+                "ERR The stream has exhausted the last possible ID, unable to add more items"
+            ));
+        }
+
+        let too_small = || {
+            anyhow!(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            )
+        };
+        return match *self {
+            NewId::Auto => Ok(stream.next_id(now_ms() as u64).unwrap()),
+            NewId::AutoSeq(ms) if ms > stream.last_id.ms => Ok(StreamId { ms, seq: 0 }),
+            NewId::AutoSeq(ms) if ms == stream.last_id.ms && stream.last_id.seq < u64::MAX => {
+                Ok(StreamId {
+                    ms,
+                    seq: stream.last_id.seq + 1,
+                })
+            }
+            NewId::AutoSeq(_) => Err(too_small()),
+            NewId::Explicit(id) if id > stream.last_id => Ok(id),
+            NewId::Explicit(_) => Err(too_small()),
+        };
+    }
+}
+
+pub fn xadd(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    let mut i = 1;
+    let opts = match parse_add_args(&args, &mut i, true) {
+        Ok(opts) => opts,
+        Err(err) => return encode_error(err),
+    };
+
+    let fields = args.get(i + 1..).unwrap_or_default();
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return encode_error(anyhow!("ERR wrong number of arguments for 'xadd' command"));
+    }
+    let new_id = match NewId::parse(&args[i]) {
+        Ok(new_id) => new_id,
+        Err(err) => return encode_error(err),
+    };
+
+    let id = match get_stream(store, key) {
+        Ok(Some(stream)) => new_id.resolve(stream),
+        Ok(None) if opts.nomkstream => return nil(client.protover),
+        Ok(None) => new_id.resolve(&Stream::new()),
+        Err(err) => return encode_error(err),
+    };
+    let id = match id {
+        Ok(id) => id,
+        Err(err) => return encode_error(err),
+    };
+
+    if get_stream(store, key).is_ok_and(|s| s.is_none()) {
+        create_stream(store, key);
+    }
+    let trimmed = with_stream(store, key, |stream| {
+        stream.add(id, fields.to_vec());
+        return opts.trim.map_or(0, |t| stream.trim(t.trim, t.limit));
+    });
+    store.add_dirty(1 + trimmed.unwrap().unwrap() as u64);
+    store.signal_key_as_ready(key);
+
+    // Replaying must add the entry under the same id
+    let mut argv = vec![arg("XADD")];
+    argv.extend(args.iter().cloned());
+    argv[i + 1] = id.to_bytes();
+    store.rewrite_propagation(argv);
+
+    return encode(Value::String(id.to_bytes()), false);
+}
+
+pub fn xtrim(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let mut i = 1;
+    let trim = match parse_add_args(&args, &mut i, false) {
+        Ok(opts) => opts.trim.unwrap(),
+        Err(err) => return encode_error(err),
+    };
+
+    return match with_stream(store, &args[0], |stream| stream.trim(trim.trim, trim.limit)) {
+        Ok(Some(removed)) => {
+            store.add_dirty(removed as u64);
+            encode(Value::Int64(removed as i64), false)
+        }
+        Ok(None) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn xdel(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let ids = match args[1..]
+        .iter()
+        .map(|a| parse_strict_id(a))
+        .collect::<anyhow::Result<Vec<_>>>()
+    {
+        Ok(ids) => ids,
+        Err(err) => return encode_error(err),
+    };
+
+    let deleted = with_stream(store, &args[0], |stream| {
+        return ids.into_iter().filter(|&id| stream.delete(id)).count();
+    });
+    return match deleted {
+        Ok(Some(deleted)) => {
+            store.add_dirty(deleted as u64);
+            encode(Value::Int64(deleted as i64), false)
+        }
+        Ok(None) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn xlen(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_stream(store, &args[0]) {
+        Ok(Some(stream)) => encode(Value::Int64(stream.len() as i64), false),
+        Ok(None) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+fn range_generic(
+    args: Vec<Vec<u8>>,
+    client: &mut Client,
+    store: &mut Store,
+    reverse: bool,
+) -> Vec<u8> {
+    let (start, end) = if reverse {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+    let bounds = parse_range_start(start).and_then(|s| Ok((s, parse_range_end(end)?)));
+    let (start, end) = match bounds {
+        Ok(bounds) => bounds,
+        Err(err) => return encode_error(err),
+    };
+
+    let mut count = usize::MAX;
+    match &args[3..] {
+        [] => {}
+        [opt, n] if opt.eq_ignore_ascii_case(b"COUNT") => match parse_integer(n) {
+            Ok(n) => count = n.max(0) as usize,
+            Err(err) => return encode_error(err),
+        },
+        _ => return encode_error(anyhow!("ERR syntax error")),
+    }
+
+    let entries = match get_stream(store, &args[0]) {
+        Ok(Some(stream)) => match (start, end) {
+            (Some(start), Some(end)) => stream.range(start, end, count, reverse),
+            _ => vec![],
+        },
+        Ok(None) => vec![],
+        Err(err) => return encode_error(err),
+    };
+    return encode_proto(entries_value(entries), false, client.protover);
+}
+
+pub fn xrange(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return range_generic(args, client, store, false);
+}
+
+pub fn xrevrange(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return range_generic(args, client, store, true);
+}
+
+/// Where XREAD and XREADGROUP start reading a stream.
+#[derive(Clone, Copy)]
+enum ReadFrom {
+    /// Entries after this id.
+    After(StreamId),
+    /// `$`: entries added from now on.
+    New,
+    /// `>`: entries never delivered to the group.
+    Undelivered,
+}
+
+struct ReadArgs {
+    count: usize,
+    /// Timeout of BLOCK as a unix time in ms, 0 to wait forever.
+    block: Option<i64>,
+    group: Option<(Vec<u8>, Vec<u8>)>,
+    noack: bool,
+    /// Index of the first key in the arguments.
+    streams: usize,
+    reads: Vec<(Vec<u8>, ReadFrom)>,
+}
+
+fn parse_read_args(args: &[Vec<u8>], xreadgroup: bool) -> anyhow::Result<ReadArgs> {
+    let name = if xreadgroup { "xreadgroup" } else { "xread" };
+    let mut count = usize::MAX;
+    let mut block = None;
+    let mut group = None;
+    let mut noack = false;
+    let mut streams = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let more = args.len() - i - 1;
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"COUNT" if more > 0 => {
+                i += 1;
+                let n = parse_integer(&args[i])?;
+                count = if n <= 0 { usize::MAX } else { n as usize };
+            }
+            b"BLOCK" if more > 0 => {
+                i += 1;
+                let ms = parse_i64(&args[i])
+                    .ok_or_else(|| anyhow!("ERR timeout is not an integer or out of range"))?;
+                if ms < 0 {
+                    return Err(anyhow!("ERR timeout is negative"));
+                }
+                block = Some(if ms == 0 {
+                    0
+                } else {
+                    now_ms().saturating_add(ms)
+                });
+            }
+            b"STREAMS" if more > 0 => {
+                streams = Some(i + 1);
+                break;
+            }
+            b"GROUP" if more > 1 => {
+                if !xreadgroup {
+                    return Err(anyhow!(
+                        "ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead."
+                    ));
+                }
+                group = Some((args[i + 1].clone(), args[i + 2].clone()));
+                i += 2;
+            }
+            b"NOACK" if xreadgroup => noack = true,
+            _ => return Err(anyhow!("ERR syntax error")),
+        }
+        i += 1;
+    }
+
+    let Some(streams) = streams else {
+        return Err(anyhow!("ERR syntax error"));
+    };
+    if !(args.len() - streams).is_multiple_of(2) {
+        return Err(anyhow!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            name
+        ));
+    }
+    if xreadgroup && group.is_none() {
+        return Err(anyhow!("ERR Missing GROUP option for XREADGROUP"));
+    }
+
+    let n = (args.len() - streams) / 2;
+    let mut reads = Vec::with_capacity(n);
+    for (key, id) in args[streams..streams + n]
+        .iter()
+        .zip(args[streams + n..].iter())
+    {
+        let from = match id.as_slice() {
+            b"$" if xreadgroup => {
+                return Err(anyhow!(
+                    "ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."
+                ))
+            }
+            b"$" => ReadFrom::New,
+            b">" if xreadgroup => ReadFrom::Undelivered,
+            b">" => {
+                return Err(anyhow!(
+                    "ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option."
+                ))
+            }
+            id => ReadFrom::After(parse_id(id, 0)?),
+        };
+        reads.push((key.clone(), from));
+    }
+
+    return Ok(ReadArgs {
+        count,
+        block,
+        group,
+        noack,
+        streams,
+        reads,
+    });
+}
+
+/// Reply of XREAD and XREADGROUP: entries per stream, as a map in RESP3.
+fn read_reply(replies: Vec<(Vec<u8>, Value)>, protover: u8) -> Vec<u8> {
+    if replies.is_empty() {
+        return nil_array(protover);
+    }
+
+    let pairs = replies
+        .into_iter()
+        .map(|(key, entries)| (Value::String(key), entries));
+    if protover >= RESP3 {
+        return encode_proto(Value::Map(pairs.collect()), false, protover);
+    }
+    let items = pairs.map(|(k, v)| Value::Vector(vec![k, v])).collect();
+    return encode_proto(Value::Vector(items), false, protover);
+}
+
+/// Parks the client of a XREAD or XREADGROUP that found nothing to read,
+/// see `block_for_keys`.
+/// `$` is pinned to the current last id, so that it only means the entries
+/// added from now on when the command runs again.
+fn block_read(
+    name: &str,
+    mut args: Vec<Vec<u8>>,
+    opts: ReadArgs,
+    client: &mut Client,
+    store: &mut Store,
+) -> Vec<u8> {
+    let n = opts.reads.len();
+    for (j, (key, from)) in opts.reads.iter().enumerate() {
+        if let ReadFrom::New = from {
+            let last_id = get_stream(store, key)
+                .ok()
+                .flatten()
+                .map_or(StreamId::MIN, |s| s.last_id);
+            args[opts.streams + n + j] = last_id.to_bytes();
+        }
+    }
+
+    let keys = opts.reads.into_iter().map(|(key, _)| key).collect();
+    let timeout_reply = nil_array(client.protover);
+    let cmd = Command {
+        cmd: name.to_string(),
+        args,
+    };
+    return block_for_keys(
+        client,
+        store,
+        cmd,
+        keys,
+        TYPE_STREAM,
+        opts.block.unwrap(),
+        timeout_reply,
+    );
+}
+
+pub fn xread(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let opts = match parse_read_args(&args, false) {
+        Ok(opts) => opts,
+        Err(err) => return encode_error(err),
+    };
+
+    let mut replies = Vec::new();
+    for (key, from) in opts.reads.iter() {
+        let stream = match get_stream(store, key) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => continue,
+            Err(err) => return encode_error(err),
+        };
+        let ReadFrom::After(after) = *from else {
+            continue;
+        };
+        let Some(start) = after.next() else {
+            continue;
+        };
+
+        let entries = stream.range(start, StreamId::MAX, opts.count, false);
+        if !entries.is_empty() {
+            replies.push((key.clone(), entries_value(entries)));
+        }
+    }
+
+    if replies.is_empty() && opts.block.is_some() {
+        return block_read("XREAD", args, opts, client, store);
+    }
+    return read_reply(replies, client.protover);
+}
+
+/// `XCLAIM` that forces the pending entry `id` of `group` into the state it
+/// has on this side, which is how group reads and claims are replayed.
+fn claim_propagation(key: &[u8], name: &[u8], group: &ConsumerGroup, id: StreamId) -> Vec<Vec<u8>> {
+    let (consumer, time, count) = match group.pel.get(&id) {
+        Some(entry) => (
+            entry.consumer.clone(),
+            entry.delivery_time,
+            entry.delivery_count,
+        ),
+        // Acknowledged by now, replaying makes it go away as well
+        None => (vec![], 0, 0),
+    };
+    return vec![
+        arg("XCLAIM"),
+        key.to_vec(),
+        name.to_vec(),
+        consumer,
+        arg("0"),
+        id.to_bytes(),
+        arg("TIME"),
+        time.to_string().into_bytes(),
+        arg("RETRYCOUNT"),
+        count.to_string().into_bytes(),
+        arg("FORCE"),
+        arg("JUSTID"),
+        arg("LASTID"),
+        group.last_id.to_bytes(),
+    ];
+}
+
+/// `XGROUP SETID` restoring the last id and entries read of `group`.
+fn setid_propagation(key: &[u8], name: &[u8], group: &ConsumerGroup) -> Vec<Vec<u8>> {
+    let entries_read = group.entries_read.map_or(-1, |n| n as i64);
+    return vec![
+        arg("XGROUP"),
+        arg("SETID"),
+        key.to_vec(),
+        name.to_vec(),
+        group.last_id.to_bytes(),
+        arg("ENTRIESREAD"),
+        entries_read.to_string().into_bytes(),
+    ];
+}
+
+pub fn xreadgroup(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let opts = match parse_read_args(&args, true) {
+        Ok(opts) => opts,
+        Err(err) => return encode_error(err),
+    };
+    let (name, consumer) = opts.group.clone().unwrap();
+
+    // Every group must exist before anything is read
+    for (key, _) in opts.reads.iter() {
+        match get_stream(store, key) {
+            Ok(Some(stream)) if stream.groups.contains_key(&name) => {}
+            Ok(_) => {
+                return encode_error(anyhow!(
+                "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                String::from_utf8_lossy(key),
+                String::from_utf8_lossy(&name)
+            ))
+            }
+            Err(err) => return encode_error(err),
+        }
+    }
+
+    let now = now_ms();
+    let mut replies = Vec::new();
+    for (key, from) in opts.reads.iter() {
+        let mut propagate = Vec::new();
+        let reply = with_stream(store, key, |stream| {
+            let group = stream.groups.get_mut(&name).unwrap();
+            if !group.consumers.contains_key(&consumer) {
+                propagate.push(vec![
+                    arg("XGROUP"),
+                    arg("CREATECONSUMER"),
+                    key.clone(),
+                    name.clone(),
+                    consumer.clone(),
+                ]);
+            }
+            group.consumer(&consumer, now);
+
+            let ReadFrom::After(after) = *from else {
+                let entries = stream.deliver(&name, &consumer, opts.count, opts.noack, now);
+                if entries.is_empty() {
+                    return None;
+                }
+
+                let group = &stream.groups[&name];
+                if !opts.noack {
+                    for (id, _) in entries.iter() {
+                        propagate.push(claim_propagation(key, &name, group, *id));
+                    }
+                }
+                propagate.push(setid_propagation(key, &name, group));
+                return Some(entries_value(entries));
+            };
+
+            // Reading the history of the consumer always replies, even
+            // with no entries
+            let entries = stream.redeliver(&name, &consumer, after, opts.count, now);
+            return Some(Value::Vector(
+                entries
+                    .into_iter()
+                    .map(|(id, fields)| entry_value(id, fields))
+                    .collect(),
+            ));
+        });
+
+        store.add_dirty(propagate.len() as u64);
+        for argv in propagate {
+            store.rewrite_propagation(argv);
+        }
+        if let Ok(Some(Some(entries))) = reply {
+            replies.push((key.clone(), entries));
+        }
+    }
+
+    if replies.is_empty() && opts.block.is_some() {
+        return block_read("XREADGROUP", args, opts, client, store);
+    }
+    return read_reply(replies, client.protover);
+}
+
+pub fn xack(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let ids = match args[2..]
+        .iter()
+        .map(|a| parse_strict_id(a))
+        .collect::<anyhow::Result<Vec<_>>>()
+    {
+        Ok(ids) => ids,
+        Err(err) => return encode_error(err),
+    };
+
+    let acked = with_stream(store, &args[0], |stream| {
+        return stream.groups.get_mut(&args[1]).map_or(0, |group| {
+            ids.into_iter().filter(|&id| group.ack(id)).count()
+        });
+    });
+    return match acked {
+        Ok(Some(acked)) => {
+            store.add_dirty(acked as u64);
+            encode(Value::Int64(acked as i64), false)
+        }
+        Ok(None) => RESP_ZERO.to_vec(),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn xpending(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, name) = (&args[0], &args[1]);
+
+    // Extended form: [IDLE min-idle-time] start end count [consumer]
+    let mut rest = &args[2..];
+    let mut min_idle = None;
+    if rest.len() >= 2 && rest[0].eq_ignore_ascii_case(b"IDLE") {
+        match parse_integer(&rest[1]) {
+            Ok(idle) => min_idle = Some(idle),
+            Err(err) => return encode_error(err),
+        }
+        rest = &rest[2..];
+    }
+    let extended = match rest {
+        [] if min_idle.is_none() => None,
+        [start, end, count] | [start, end, count, _] => {
+            let parsed = (|| {
+                let range = (parse_range_start(start)?, parse_range_end(end)?);
+                return anyhow::Ok((range, parse_integer(count)?));
+            })();
+            match parsed {
+                Ok(parsed) => Some((parsed, rest.get(3))),
+                Err(err) => return encode_error(err),
+            }
+        }
+        _ => return encode_error(anyhow!("ERR syntax error")),
+    };
+
+    let stream = match get_stream(store, key) {
+        Ok(stream) => stream,
+        Err(err) => return encode_error(err),
+    };
+    let Some(group) = stream.and_then(|s| s.groups.get(name)) else {
+        return encode_error(no_group(key, name));
+    };
+
+    let Some(((range, count), consumer)) = extended else {
+        let (Some(first), Some(last)) = (group.pel.keys().next(), group.pel.keys().next_back())
+        else {
+            let reply = vec![Value::Int64(0), Value::Empty, Value::Empty, Value::Empty];
+            return encode_proto(Value::Vector(reply), false, client.protover);
+        };
+        let consumers = group
+            .consumers
+            .iter()
+            .filter(|(_, c)| !c.pending.is_empty())
+            .map(|(name, c)| {
+                Value::VectorString(vec![name.clone(), c.pending.len().to_string().into_bytes()])
+            })
+            .collect();
+        let reply = vec![
+            Value::Int64(group.pel.len() as i64),
+            Value::String(first.to_bytes()),
+            Value::String(last.to_bytes()),
+            Value::Vector(consumers),
+        ];
+        return encode_proto(Value::Vector(reply), false, client.protover);
+    };
+
+    let (Some(start), Some(end)) = range else {
+        return encode(Value::Vector(vec![]), false);
+    };
+    if start > end || count <= 0 {
+        return encode(Value::Vector(vec![]), false);
+    }
+
+    let now = now_ms();
+    let entries = group
+        .pel
+        .range(start..=end)
+        .filter(|(_, e)| consumer.is_none_or(|c| &e.consumer == c))
+        .filter(|(_, e)| min_idle.is_none_or(|idle| now - e.delivery_time >= idle))
+        .take(count as usize)
+        .map(|(id, e)| {
+            Value::Vector(vec![
+                Value::String(id.to_bytes()),
+                Value::String(e.consumer.clone()),
+                Value::Int64(now - e.delivery_time),
+                Value::Int64(e.delivery_count as i64),
+            ])
+        })
+        .collect();
+    return encode(Value::Vector(entries), false);
+}
+
+/// Gives the pending entry `id` of `group` to `consumer`, like XCLAIM and
+/// XAUTOCLAIM do. `retry_count` replaces the delivery count, which otherwise
+/// goes up unless `justid`.
+fn claim(
+    group: &mut ConsumerGroup,
+    id: StreamId,
+    consumer: &[u8],
+    delivery_time: i64,
+    retry_count: Option<u64>,
+    justid: bool,
+    now: i64,
+) {
+    let count = group.pel[&id].delivery_count;
+    let count = match retry_count {
+        Some(n) => n,
+        None if justid => count,
+        None => count + 1,
+    };
+    group.consumer(consumer, now).active_time = now;
+    group.assign(id, consumer, delivery_time, count);
+}
+
+/// Options of XCLAIM after the ids.
+struct ClaimArgs {
+    delivery_time: Option<i64>,
+    retry_count: Option<u64>,
+    force: bool,
+    justid: bool,
+    last_id: Option<StreamId>,
+}
+
+fn parse_claim_args(args: &[Vec<u8>]) -> anyhow::Result<ClaimArgs> {
+    let mut opts = ClaimArgs {
+        delivery_time: None,
+        retry_count: None,
+        force: false,
+        justid: false,
+        last_id: None,
+    };
+    let now = now_ms();
+
+    let mut i = 0;
+    while i < args.len() {
+        let more = i + 1 < args.len();
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"FORCE" => opts.force = true,
+            b"JUSTID" => opts.justid = true,
+            b"IDLE" if more => {
+                i += 1;
+                let idle = parse_i64(&args[i])
+                    .ok_or_else(|| anyhow!("ERR Invalid IDLE option argument for XCLAIM"))?;
+                opts.delivery_time = Some(now - idle);
+            }
+            b"TIME" if more => {
+                i += 1;
+                let time = parse_i64(&args[i])
+                    .ok_or_else(|| anyhow!("ERR Invalid TIME option argument for XCLAIM"))?;
+                opts.delivery_time = Some(time);
+            }
+            b"RETRYCOUNT" if more => {
+                i += 1;
+                let count = parse_i64(&args[i])
+                    .filter(|&n| n >= 0)
+                    .ok_or_else(|| anyhow!("ERR Invalid RETRYCOUNT option argument for XCLAIM"))?;
+                opts.retry_count = Some(count as u64);
+            }
+            b"LASTID" if more => {
+                i += 1;
+                opts.last_id = Some(parse_strict_id(&args[i])?);
+            }
+            _ => {
+                return Err(anyhow!(
+                    "ERR Unrecognized XCLAIM option '{}'",
+                    String::from_utf8_lossy(&args[i])
+                ))
+            }
+        }
+        i += 1;
+    }
+
+    // Delivery times in the future or before the epoch make no sense
+    opts.delivery_time = opts
+        .delivery_time
+        .map(|t| if t < 0 || t > now { now } else { t });
+    return Ok(opts);
+}
+
+pub fn xclaim(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, name, consumer) = (&args[0], &args[1], &args[2]);
+    let min_idle = match parse_i64(&args[3]) {
+        Some(idle) => idle.max(0),
+        None => return encode_error(anyhow!("ERR Invalid min-idle-time argument for XCLAIM")),
+    };
+
+    let ids_end = args[4..]
+        .iter()
+        .position(|a| parse_strict_id(a).is_err())
+        .map_or(args.len(), |n| n + 4);
+    let ids: Vec<_> = args[4..ids_end]
+        .iter()
+        .map(|a| parse_strict_id(a).unwrap())
+        .collect();
+    let opts = match parse_claim_args(&args[ids_end..]) {
+        Ok(opts) => opts,
+        Err(err) => return encode_error(err),
+    };
+
+    match get_stream(store, key) {
+        Ok(Some(stream)) if stream.groups.contains_key(name) => {}
+        Ok(_) => return encode_error(no_group(key, name)),
+        Err(err) => return encode_error(err),
+    }
+
+    let now = now_ms();
+    let mut propagate = Vec::new();
+    let claimed = with_stream(store, key, |stream| {
+        let group = stream.groups.get_mut(name).unwrap();
+        let mut last_id_moved = false;
+        if let Some(last_id) = opts.last_id.filter(|&id| id > group.last_id) {
+            group.last_id = last_id;
+            last_id_moved = true;
+        }
+
+        let mut claimed = Vec::new();
+        for id in ids {
+            let exists = stream.entries.contains_key(&id);
+            if !group.pel.contains_key(&id) {
+                if !opts.force || !exists {
+                    continue;
+                }
+                group.consumer(consumer, now);
+                group.assign(id, consumer, now, 1);
+            } else if min_idle > 0 && now - group.pel[&id].delivery_time < min_idle {
+                continue;
+            }
+
+            if !exists {
+                // The entry was deleted, so is its pending entry
+                propagate.push(claim_propagation(key, name, group, id));
+                group.ack(id);
+                continue;
+            }
+
+            let delivery_time = opts.delivery_time.unwrap_or(now);
+            claim(
+                group,
+                id,
+                consumer,
+                delivery_time,
+                opts.retry_count,
+                opts.justid,
+                now,
+            );
+            propagate.push(claim_propagation(key, name, group, id));
+            claimed.push(id);
+        }
+        if last_id_moved && propagate.is_empty() {
+            propagate.push(setid_propagation(key, name, group));
+        }
+
+        if opts.justid {
+            return Value::Vector(
+                claimed
+                    .into_iter()
+                    .map(|id| Value::String(id.to_bytes()))
+                    .collect(),
+            );
+        }
+        return Value::Vector(
+            claimed
+                .into_iter()
+                .map(|id| entry_value(id, stream.entries.get(&id).cloned()))
+                .collect(),
+        );
+    });
+
+    store.add_dirty(propagate.len() as u64);
+    for argv in propagate {
+        store.rewrite_propagation(argv);
+    }
+    return encode_proto(claimed.unwrap().unwrap(), false, client.protover);
+}
+
+pub fn xautoclaim(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, name, consumer) = (&args[0], &args[1], &args[2]);
+    let min_idle = match parse_i64(&args[3]) {
+        Some(idle) => idle.max(0),
+        None => return encode_error(anyhow!("ERR Invalid min-idle-time argument for XAUTOCLAIM")),
+    };
+    let start = match parse_range_start(&args[4]) {
+        Ok(start) => start,
+        Err(err) => return encode_error(err),
+    };
+
+    let mut count = AUTOCLAIM_DEFAULT_COUNT;
+    let mut justid = false;
+    let mut i = 5;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"COUNT" if i + 1 < args.len() => {
+                i += 1;
+                match parse_i64(&args[i]) {
+                    // Keep the number of attempts below from overflowing
+                    Some(n) if (1..=i64::MAX / 10).contains(&n) => count = n as usize,
+                    _ => return encode_error(anyhow!("ERR COUNT must be > 0")),
+                }
+            }
+            b"JUSTID" => justid = true,
+            _ => return encode_error(anyhow!("ERR syntax error")),
+        }
+        i += 1;
+    }
+
+    match get_stream(store, key) {
+        Ok(Some(stream)) if stream.groups.contains_key(name) => {}
+        Ok(_) => return encode_error(no_group(key, name)),
+        Err(err) => return encode_error(err),
+    }
+
+    let now = now_ms();
+    let mut propagate = Vec::new();
+    let reply = with_stream(store, key, |stream| {
+        let group = stream.groups.get_mut(name).unwrap();
+        let ids: Vec<StreamId> = match start {
+            Some(start) => group.pel.range(start..).map(|(id, _)| *id).collect(),
+            None => vec![],
+        };
+
+        // Like Redis, look at no more than 10 entries per entry to claim
+        let mut attempts = count * 10;
+        let mut claimed = Vec::new();
+        let mut deleted = Vec::new();
+        let mut ids = ids.into_iter().peekable();
+        while attempts > 0 && claimed.len() < count {
+            let Some(id) = ids.next() else {
+                break;
+            };
+            attempts -= 1;
+
+            if min_idle > 0 && now - group.pel[&id].delivery_time < min_idle {
+                continue;
+            }
+            if !stream.entries.contains_key(&id) {
+                propagate.push(claim_propagation(key, name, group, id));
+                group.ack(id);
+                deleted.push(id);
+                continue;
+            }
+
+            claim(group, id, consumer, now, None, justid, now);
+            propagate.push(claim_propagation(key, name, group, id));
+            claimed.push(id);
+        }
+        let cursor = ids.peek().copied().unwrap_or(StreamId::MIN);
+
+        let claimed = if justid {
+            claimed
+                .into_iter()
+                .map(|id| Value::String(id.to_bytes()))
+                .collect()
+        } else {
+            claimed
+                .into_iter()
+                .map(|id| entry_value(id, stream.entries.get(&id).cloned()))
+                .collect()
+        };
+        return Value::Vector(vec![
+            Value::String(cursor.to_bytes()),
+            Value::Vector(claimed),
+            Value::VectorString(deleted.into_iter().map(|id| id.to_bytes()).collect()),
+        ]);
+    });
+
+    store.add_dirty(propagate.len() as u64);
+    for argv in propagate {
+        store.rewrite_propagation(argv);
+    }
+    return encode_proto(reply.unwrap().unwrap(), false, client.protover);
+}
+
+/// Parses the ENTRIESREAD option of XGROUP CREATE and SETID, -1 meaning
+/// unknown.
+fn parse_entries_read(arg: &[u8]) -> anyhow::Result<Option<u64>> {
+    let n = parse_integer(arg)?;
+    if n < -1 {
+        return Err(anyhow!("ERR value for ENTRIESREAD must be positive or -1"));
+    }
+    return Ok(u64::try_from(n).ok());
+}
+
+pub fn xgroup(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let subcommand = args[0].to_ascii_uppercase();
+    let syntax_error = || {
+        encode_error(anyhow!(
+            "ERR unknown subcommand or wrong number of arguments for '{}'. Try XGROUP HELP.",
+            String::from_utf8_lossy(&args[0])
+        ))
+    };
+
+    if subcommand == b"HELP" && args.len() == 1 {
+        return encode_help(&[
+            "XGROUP <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CREATE <key> <groupname> <id|$> [option]",
+            "    Create a new consumer group. Options are:",
+            "    * MKSTREAM",
+            "      Create the empty stream if it does not exist.",
+            "    * ENTRIESREAD entries_read",
+            "      Set the group's entries_read counter (internal use).",
+            "CREATECONSUMER <key> <groupname> <consumer>",
+            "    Create a new consumer in the specified group.",
+            "DELCONSUMER <key> <groupname> <consumer>",
+            "    Remove the specified consumer.",
+            "DESTROY <key> <groupname>",
+            "    Remove the specified group.",
+            "SETID <key> <groupname> <id|$> [ENTRIESREAD entries_read]",
+            "    Set the current group ID and entries_read counter.",
+            "HELP",
+            "    Print this help.",
+        ]);
+    }
+    if args.len() < 3 {
+        return syntax_error();
+    }
+    let (key, name) = (&args[1], &args[2]);
+
+    let mut mkstream = false;
+    let mut entries_read = None;
+    if subcommand == b"CREATE" || subcommand == b"SETID" {
+        let mut i = 4;
+        while i < args.len() {
+            match args[i].to_ascii_uppercase().as_slice() {
+                b"MKSTREAM" if subcommand == b"CREATE" => mkstream = true,
+                b"ENTRIESREAD" if i + 1 < args.len() => {
+                    i += 1;
+                    match parse_entries_read(&args[i]) {
+                        Ok(n) => entries_read = n,
+                        Err(err) => return encode_error(err),
+                    }
+                }
+                _ => return syntax_error(),
+            }
+            i += 1;
+        }
+    }
+
+    let (last_id, has_group) = match get_stream(store, key) {
+        Ok(Some(stream)) => (stream.last_id, stream.groups.contains_key(name)),
+        Ok(None) if subcommand == b"CREATE" && mkstream => (StreamId::MIN, false),
+        Ok(None) => {
+            return encode_error(anyhow!(
+                "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+            ))
+        }
+        Err(err) => return encode_error(err),
+    };
+    let needs_group = [&b"SETID"[..], b"CREATECONSUMER", b"DELCONSUMER"];
+    if !has_group && needs_group.contains(&subcommand.as_slice()) {
+        return encode_error(no_group_for_key(key, name));
+    }
+
+    match (subcommand.as_slice(), args.len()) {
+        (b"CREATE", 4..=7) => {
+            let id = match args[3].as_slice() {
+                b"$" => last_id,
+                id => match parse_strict_id(id) {
+                    Ok(id) => id,
+                    Err(err) => return encode_error(err),
+                },
+            };
+            if has_group {
+                return encode_error(anyhow!("BUSYGROUP Consumer Group name already exists"));
+            }
+
+            if get_stream(store, key).is_ok_and(|s| s.is_none()) {
+                create_stream(store, key);
+            }
+            with_stream(store, key, |stream| {
+                stream
+                    .groups
+                    .insert(name.clone(), ConsumerGroup::new(id, entries_read));
+            })
+            .unwrap();
+            store.add_dirty(1);
+            return RESP_OK.to_vec();
+        }
+        (b"SETID", 4 | 6) => {
+            let id = match args[3].as_slice() {
+                b"$" => last_id,
+                id => match parse_id(id, 0) {
+                    Ok(id) => id,
+                    Err(err) => return encode_error(err),
+                },
+            };
+            with_stream(store, key, |stream| {
+                let group = stream.groups.get_mut(name).unwrap();
+                group.last_id = id;
+                group.entries_read = entries_read;
+            })
+            .unwrap();
+            store.add_dirty(1);
+            return RESP_OK.to_vec();
+        }
+        (b"DESTROY", 3) => {
+            if !has_group {
+                return RESP_ZERO.to_vec();
+            }
+            with_stream(store, key, |stream| stream.groups.remove(name)).unwrap();
+            store.add_dirty(1);
+            // Clients blocked in XREADGROUP on that group get an error
+            store.signal_key_as_ready(key);
+            return encode(Value::Int64(1), false);
+        }
+        (b"CREATECONSUMER", 4) => {
+            let created = with_stream(store, key, |stream| {
+                let group = stream.groups.get_mut(name).unwrap();
+                if group.consumers.contains_key(&args[3]) {
+                    return false;
+                }
+                group.consumer(&args[3], now_ms());
+                return true;
+            });
+            if !created.unwrap().unwrap() {
+                return RESP_ZERO.to_vec();
+            }
+            store.add_dirty(1);
+            return encode(Value::Int64(1), false);
+        }
+        (b"DELCONSUMER", 4) => {
+            let pending = with_stream(store, key, |stream| {
+                stream
+                    .groups
+                    .get_mut(name)
+                    .unwrap()
+                    .remove_consumer(&args[3])
+            });
+            let Some(pending) = pending.unwrap().unwrap() else {
+                return RESP_ZERO.to_vec();
+            };
+            store.add_dirty(1);
+            return encode(Value::Int64(pending as i64), false);
+        }
+        _ => return syntax_error(),
+    }
+}
+
+pub fn xsetid(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let parsed = (|| {
+        let id = parse_strict_id(&args[1])?;
+        let mut entries_added = None;
+        let mut max_deleted_id = StreamId::MIN;
+        let mut i = 2;
+        while i < args.len() {
+            match args[i].to_ascii_uppercase().as_slice() {
+                b"ENTRIESADDED" if i + 1 < args.len() => {
+                    let n = parse_integer(&args[i + 1])?;
+                    if n < 0 {
+                        return Err(anyhow!("ERR entries_added must be positive"));
+                    }
+                    entries_added = Some(n as u64);
+                }
+                b"MAXDELETEDID" if i + 1 < args.len() => {
+                    max_deleted_id = parse_strict_id(&args[i + 1])?;
+                    if id < max_deleted_id {
+                        return Err(anyhow!(
+                            "ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id"
+                        ));
+                    }
+                }
+                _ => return Err(anyhow!("ERR syntax error")),
+            }
+            i += 2;
+        }
+        return Ok((id, entries_added, max_deleted_id));
+    })();
+    let (id, entries_added, max_deleted_id) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => return encode_error(err),
+    };
+
+    let set = with_stream(store, &args[0], |stream| {
+        if let Some(&top) = stream.entries.keys().next_back() {
+            if id < top {
+                return Err(anyhow!(
+                    "ERR The ID specified in XSETID is smaller than the target stream top item"
+                ));
+            }
+            if entries_added.is_some_and(|n| n < stream.len() as u64) {
+                return Err(anyhow!(
+                    "ERR The entries_added specified in XSETID is smaller than the target stream length"
+                ));
+            }
+        }
+
+        stream.last_id = id;
+        if let Some(n) = entries_added {
+            stream.entries_added = n;
+        }
+        if max_deleted_id != StreamId::MIN {
+            stream.max_deleted_id = max_deleted_id;
+        }
+        return Ok(());
+    });
+    return match set {
+        Ok(Some(Ok(()))) => {
+            store.add_dirty(1);
+            RESP_OK.to_vec()
+        }
+        Ok(Some(Err(err))) | Err(err) => encode_error(err),
+        Ok(None) => encode_error(anyhow!("ERR no such key")),
+    };
+}
+
+fn map(pairs: Vec<(&str, Value)>) -> Value {
+    return Value::Map(
+        pairs
+            .into_iter()
+            .map(|(k, v)| (Value::String(k.as_bytes().to_vec()), v))
+            .collect(),
+    );
+}
+
+fn optional_int(n: Option<u64>) -> Value {
+    return n.map_or(Value::Empty, |n| Value::Int64(n as i64));
+}
+
+fn xinfo_stream(stream: &Stream, full: Option<usize>) -> Value {
+    let mut fields = vec![
+        ("length", Value::Int64(stream.len() as i64)),
+        (
+            "last-generated-id",
+            Value::String(stream.last_id.to_bytes()),
+        ),
+        (
+            "max-deleted-entry-id",
+            Value::String(stream.max_deleted_id.to_bytes()),
+        ),
+        ("entries-added", Value::Int64(stream.entries_added as i64)),
+        (
+            "recorded-first-entry-id",
+            Value::String(stream.first_id().to_bytes()),
+        ),
+    ];
+
+    let Some(count) = full else {
+        let entry = |e: Option<(&StreamId, &Fields)>| {
+            e.map_or(Value::Empty, |(id, fields)| {
+                entry_value(*id, Some(fields.clone()))
+            })
+        };
+        fields.push(("groups", Value::Int64(stream.groups.len() as i64)));
+        fields.push(("first-entry", entry(stream.entries.iter().next())));
+        fields.push(("last-entry", entry(stream.entries.iter().next_back())));
+        return map(fields);
+    };
+
+    let entries = stream.range(StreamId::MIN, StreamId::MAX, count, false);
+    fields.push(("entries", entries_value(entries)));
+    let groups = stream
+        .groups
+        .iter()
+        .map(|(name, group)| {
+            let pending = group
+                .pel
+                .iter()
+                .take(count)
+                .map(|(id, e)| {
+                    Value::Vector(vec![
+                        Value::String(id.to_bytes()),
+                        Value::String(e.consumer.clone()),
+                        Value::Int64(e.delivery_time),
+                        Value::Int64(e.delivery_count as i64),
+                    ])
+                })
+                .collect();
+            let consumers = group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| {
+                    let pending = consumer
+                        .pending
+                        .iter()
+                        .take(count)
+                        .map(|id| {
+                            let e = &group.pel[id];
+                            Value::Vector(vec![
+                                Value::String(id.to_bytes()),
+                                Value::Int64(e.delivery_time),
+                                Value::Int64(e.delivery_count as i64),
+                            ])
+                        })
+                        .collect();
+                    map(vec![
+                        ("name", Value::String(name.clone())),
+                        ("seen-time", Value::Int64(consumer.seen_time)),
+                        ("active-time", Value::Int64(consumer.active_time)),
+                        ("pel-count", Value::Int64(consumer.pending.len() as i64)),
+                        ("pending", Value::Vector(pending)),
+                    ])
+                })
+                .collect();
+            map(vec![
+                ("name", Value::String(name.clone())),
+                ("last-delivered-id", Value::String(group.last_id.to_bytes())),
+                ("entries-read", optional_int(group.entries_read)),
+                ("lag", optional_int(stream.lag(group))),
+                ("pel-count", Value::Int64(group.pel.len() as i64)),
+                ("pending", Value::Vector(pending)),
+                ("consumers", Value::Vector(consumers)),
+            ])
+        })
+        .collect();
+    fields.push(("groups", Value::Vector(groups)));
+
+    return map(fields);
+}
+
+fn xinfo_groups(stream: &Stream) -> Value {
+    return Value::Vector(
+        stream
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                map(vec![
+                    ("name", Value::String(name.clone())),
+                    ("consumers", Value::Int64(group.consumers.len() as i64)),
+                    ("pending", Value::Int64(group.pel.len() as i64)),
+                    ("last-delivered-id", Value::String(group.last_id.to_bytes())),
+                    ("entries-read", optional_int(group.entries_read)),
+                    ("lag", optional_int(stream.lag(group))),
+                ])
+            })
+            .collect(),
+    );
+}
+
+fn xinfo_consumers(group: &ConsumerGroup, now: i64) -> Value {
+    return Value::Vector(
+        group
+            .consumers
+            .iter()
+            .map(|(name, consumer)| {
+                let inactive = if consumer.active_time == -1 {
+                    -1
+                } else {
+                    now - consumer.active_time
+                };
+                map(vec![
+                    ("name", Value::String(name.clone())),
+                    ("pending", Value::Int64(consumer.pending.len() as i64)),
+                    ("idle", Value::Int64(now - consumer.seen_time)),
+                    ("inactive", Value::Int64(inactive)),
+                ])
+            })
+            .collect(),
+    );
+}
+
+pub fn xinfo(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let subcommand = args[0].to_ascii_uppercase();
+    if subcommand == b"HELP" && args.len() == 1 {
+        return encode_help(&[
+            "XINFO <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CONSUMERS <key> <groupname>",
+            "    Show consumers of <groupname>.",
+            "GROUPS <key>",
+            "    Show the stream consumer groups.",
+            "STREAM <key> [FULL [COUNT <count>]",
+            "    Show information about the stream.",
+            "HELP",
+            "    Print this help.",
+        ]);
+    }
+
+    let full = match (subcommand.as_slice(), &args[1..]) {
+        (b"STREAM", [_]) | (b"GROUPS", [_]) | (b"CONSUMERS", [_, _]) => None,
+        (b"STREAM", [_, opt]) if opt.eq_ignore_ascii_case(b"FULL") => {
+            Some(XINFO_FULL_DEFAULT_COUNT)
+        }
+        (b"STREAM", [_, opt, count_opt, n])
+            if opt.eq_ignore_ascii_case(b"FULL") && count_opt.eq_ignore_ascii_case(b"COUNT") =>
+        {
+            match parse_integer(n) {
+                Ok(n) if n > 0 => Some(n as usize),
+                Ok(_) => Some(usize::MAX),
+                Err(err) => return encode_error(err),
+            }
+        }
+        (b"STREAM", _) => return encode_error(anyhow!("ERR syntax error")),
+        _ => {
+            return encode_error(anyhow!(
+                "ERR unknown subcommand or wrong number of arguments for '{}'. Try XINFO HELP.",
+                String::from_utf8_lossy(&args[0])
+            ))
+        }
+    };
+
+    let key = &args[1];
+    let stream = match get_stream(store, key) {
+        Ok(Some(stream)) => stream,
+        Ok(None) => return encode_error(anyhow!("ERR no such key")),
+        Err(err) => return encode_error(err),
+    };
+    let reply = match subcommand.as_slice() {
+        b"STREAM" => xinfo_stream(stream, full),
+        b"GROUPS" => xinfo_groups(stream),
+        _ => match stream.groups.get(&args[2]) {
+            Some(group) => xinfo_consumers(group, now_ms()),
+            None => return encode_error(no_group_for_key(key, &args[2])),
+        },
+    };
+    return encode_proto(reply, false, client.protover);
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        core::{blocked, eval},
+    };
+
+    fn call(store: &mut Store, client: &mut Client, args: &[&str]) -> Vec<u8> {
+        let cmd = Command {
+            cmd: args[0].to_uppercase(),
+            args: args[1..].iter().map(|a| a.as_bytes().to_vec()).collect(),
+        };
+        return eval::call(cmd, client, store);
+    }
+
+    #[test]
+    fn test_add_and_range() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut client = Client::new();
+
+        assert_eq!(
+            call(&mut store, &mut client, &["xadd", "s", "1-1", "a", "1"]),
+            b"$3\r\n1-1\r\n"
+        );
+        assert_eq!(
+            call(&mut store, &mut client, &["xadd", "s", "1-*", "b", "2"]),
+            b"$3\r\n1-2\r\n"
+        );
+        assert_eq!(
+            call(&mut store, &mut client, &["xadd", "s", "3", "c", "3"]),
+            b"$3\r\n3-0\r\n"
+        );
+        assert_eq!(
+            call(&mut store, &mut client, &["xadd", "s", "2-5", "d", "4"]),
+            b"-ERR The ID specified in XADD is equal or smaller than the target stream top item\r\n"
+        );
+        assert_eq!(
+            call(&mut store, &mut client, &["xadd", "s", "0-0", "d", "4"]),
+            b"-ERR The ID specified in XADD must be greater than 0-0\r\n"
+        );
+        assert_eq!(
+            call(&mut store, &mut client, &["xadd", "s", "4", "odd"]),
+            b"-ERR wrong number of arguments for 'xadd' command\r\n"
+        );
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xadd", "new", "NOMKSTREAM", "*", "a", "1"]
+            ),
+            b"$-1\r\n"
+        );
+
+        assert_eq!(
+            call(&mut store, &mut client, &["xrange", "s", "(1-1", "1"]),
+            b"*1\r\n*2\r\n$3\r\n1-2\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n"
+        );
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xrevrange", "s", "+", "-", "COUNT", "1"]
+            ),
+            b"*1\r\n*2\r\n$3\r\n3-0\r\n*2\r\n$1\r\nc\r\n$1\r\n3\r\n"
+        );
+
+        // Trimming and deleting
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xadd", "s", "MAXLEN", "2", "4", "e", "5"]
+            ),
+            b"$3\r\n4-0\r\n"
+        );
+        assert_eq!(call(&mut store, &mut client, &["xlen", "s"]), b":2\r\n");
+        assert_eq!(
+            call(&mut store, &mut client, &["xtrim", "s", "MINID", "=", "4"]),
+            b":1\r\n"
+        );
+        assert_eq!(
+            call(&mut store, &mut client, &["xdel", "s", "4-0", "9-9"]),
+            b":1\r\n"
+        );
+        assert_eq!(call(&mut store, &mut client, &["xlen", "s"]), b":0\r\n");
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xtrim", "s", "MAXLEN", "1", "LIMIT", "5"]
+            ),
+            b"-ERR syntax error, LIMIT cannot be used without the special ~ option\r\n"
+        );
+    }
+
+    #[test]
+    fn test_consumer_groups() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut client = Client::new();
+
+        call(&mut store, &mut client, &["xadd", "s", "1", "a", "1"]);
+        call(&mut store, &mut client, &["xadd", "s", "2", "b", "2"]);
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xgroup", "create", "s", "g", "0"]
+            ),
+            RESP_OK
+        );
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xgroup", "create", "s", "g", "$"]
+            ),
+            b"-BUSYGROUP Consumer Group name already exists\r\n"
+        );
+
+        let reply = call(
+            &mut store,
+            &mut client,
+            &[
+                "xreadgroup",
+                "group",
+                "g",
+                "alice",
+                "count",
+                "1",
+                "streams",
+                "s",
+                ">",
+            ],
+        );
+        assert_eq!(
+            reply,
+            b"*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\na\r\n$1\r\n1\r\n"
+        );
+        call(
+            &mut store,
+            &mut client,
+            &["xreadgroup", "group", "g", "bob", "streams", "s", ">"],
+        );
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xreadgroup", "group", "g", "bob", "streams", "s", ">"]
+            ),
+            b"*-1\r\n"
+        );
+
+        let summary = call(&mut store, &mut client, &["xpending", "s", "g"]);
+        assert_eq!(
+            summary,
+            b"*4\r\n:2\r\n$3\r\n1-0\r\n$3\r\n2-0\r\n*2\r\n*2\r\n$5\r\nalice\r\n$1\r\n1\r\n*2\r\n$3\r\nbob\r\n$1\r\n1\r\n"
+        );
+
+        // Bob takes over the entry of Alice
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xclaim", "s", "g", "bob", "0", "1-0", "JUSTID"]
+            ),
+            b"*1\r\n$3\r\n1-0\r\n"
+        );
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xreadgroup", "group", "g", "alice", "streams", "s", "0"]
+            ),
+            b"*1\r\n*2\r\n$1\r\ns\r\n*0\r\n"
+        );
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xack", "s", "g", "1-0", "2-0", "3-0"]
+            ),
+            b":2\r\n"
+        );
+        assert_eq!(
+            call(
+                &mut store,
+                &mut client,
+                &["xgroup", "delconsumer", "s", "g", "bob"]
+            ),
+            b":0\r\n"
+        );
+        assert_eq!(
+            call(&mut store, &mut client, &["xpending", "s", "nope"]),
+            b"-NOGROUP No such key 's' or consumer group 'nope'\r\n"
+        );
+    }
+
+    #[test]
+    fn test_blocked_read_wakes_up_on_new_entries() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut reader = Client::new();
+        let mut writer = Client::new();
+
+        call(&mut store, &mut writer, &["xadd", "s", "1", "a", "1"]);
+        let reply = call(
+            &mut store,
+            &mut reader,
+            &["xread", "block", "0", "streams", "s", "$"],
+        );
+        assert!(reply.is_empty() && reader.blocked.is_some());
+        // `$` was pinned to the last id when blocking
+        assert_eq!(reader.blocked.as_ref().unwrap().cmd.args[4], b"1-0");
+
+        call(&mut store, &mut writer, &["xadd", "s", "2", "b", "2"]);
+        let mut served = Vec::new();
+        blocked::handle_clients_blocked_on_keys(&mut store, |_, store| {
+            served.push(blocked::reprocess(&mut reader, store));
+        });
+        assert_eq!(
+            served,
+            vec![
+                b"*1\r\n*2\r\n$1\r\ns\r\n*1\r\n*2\r\n$3\r\n2-0\r\n*2\r\n$1\r\nb\r\n$1\r\n2\r\n"
+                    .to_vec()
+            ]
+        );
+        assert!(reader.blocked.is_none());
+    }
+}
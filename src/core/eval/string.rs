@@ -0,0 +1,983 @@
+use anyhow::anyhow;
+use chrono::Utc;
+
+use crate::common::{format_double, parse_f64, parse_i64, Value};
+use crate::core::{
+    client::Client,
+    resp::{
+        encode, encode_error, encode_proto, nil, PROTO_MAX_BULK_LEN, RESP_ONE, RESP_OK, RESP_ZERO,
+    },
+};
+use crate::data::store::{
+    deduce_type_encoding, ObjectValue, Store, StoreObject, ENCODING_EMBSTR, ENCODING_INT,
+    TYPE_STRING,
+};
+
+pub fn get(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+
+    return match store.get(key) {
+        Some(s) => {
+            if s.expires_at != -1 && s.expires_at <= Utc::now().timestamp_millis() {
+                return nil(client.protover);
+            }
+
+            match &s.value {
+                ObjectValue::String(v) => encode(Value::String(v.clone()), false),
+                _ => encode_error(s.assert_type(TYPE_STRING).unwrap_err()),
+            }
+        }
+        None => nil(client.protover),
+    };
+}
+
+/// EX, PX, EXAT or PXAT option, as taken by SET and GETEX.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ExpireOption {
+    Ex,
+    Px,
+    ExAt,
+    PxAt,
+}
+
+impl ExpireOption {
+    fn parse(arg: &[u8]) -> Option<ExpireOption> {
+        return match arg.to_ascii_uppercase().as_slice() {
+            b"EX" => Some(ExpireOption::Ex),
+            b"PX" => Some(ExpireOption::Px),
+            b"EXAT" => Some(ExpireOption::ExAt),
+            b"PXAT" => Some(ExpireOption::PxAt),
+            _ => None,
+        };
+    }
+
+    /// Turns the value of the option into a unix time in ms. `name` is the
+    /// command, for the error message.
+    fn expires_at(self, arg: &[u8], name: &str) -> anyhow::Result<i64> {
+        let n = parse_i64(arg)
+            .ok_or_else(|| anyhow!("ERR value is not an integer or out of range"))?;
+        let invalid = || anyhow!("ERR invalid expire time in '{}' command", name);
+        if n <= 0 {
+            return Err(invalid());
+        }
+
+        let (unit_ms, absolute) = match self {
+            ExpireOption::Ex => (1_000, false),
+            ExpireOption::Px => (1, false),
+            ExpireOption::ExAt => (1_000, true),
+            ExpireOption::PxAt => (1, true),
+        };
+        let ms = n.checked_mul(unit_ms).ok_or_else(invalid)?;
+        if absolute {
+            return Ok(ms);
+        }
+        return ms.checked_add(Utc::now().timestamp_millis()).ok_or_else(invalid);
+    }
+}
+
+/// The string value at `key`, an error if it holds another type.
+fn get_string(store: &mut Store, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some(obj) = store.get(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_STRING)?;
+
+    return match &obj.value {
+        ObjectValue::String(v) => Ok(Some(v.clone())),
+        _ => unreachable!("string typed object doesn't hold a string"),
+    };
+}
+
+pub fn set(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    let mut nx = false;
+    let mut xx = false;
+    let mut get = false;
+    let mut keep_ttl = false;
+    let mut expire = None;
+
+    // Like Redis, an option may be repeated but not combined with one it
+    // conflicts with
+    let mut i = 2;
+    while i < args.len() {
+        let more = i + 1 < args.len();
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"NX" if !xx => nx = true,
+            b"XX" if !nx => xx = true,
+            b"GET" => get = true,
+            b"KEEPTTL" if expire.is_none() => keep_ttl = true,
+            opt => match ExpireOption::parse(opt) {
+                Some(option)
+                    if more && !keep_ttl && expire.is_none_or(|(prev, _)| prev == option) =>
+                {
+                    i += 1;
+                    expire = Some((option, &args[i]));
+                }
+                _ => return encode_error(anyhow!("ERR syntax error")),
+            },
+        }
+        i += 1;
+    }
+
+    let expires_at = match expire.map(|(option, arg)| option.expires_at(arg, "set")) {
+        Some(Ok(at)) => Some(at),
+        Some(Err(err)) => return encode_error(err),
+        None => None,
+    };
+    let old = if get {
+        match get_string(store, key) {
+            Ok(old) => old,
+            Err(err) => return encode_error(err),
+        }
+    } else {
+        None
+    };
+
+    let exists = store.exists(key);
+    if (nx && exists) || (xx && !exists) {
+        return string_reply(old, client.protover);
+    }
+
+    let expires_at = match (expires_at, keep_ttl) {
+        (Some(at), _) => {
+            propagate_set_pxat(store, key, &args[1], at);
+            at
+        }
+        (None, true) => store.peek(key).map_or(-1, |old| old.expires_at),
+        (None, false) => -1,
+    };
+    set_string(store, key, args[1].clone(), expires_at);
+
+    if get {
+        return string_reply(old, client.protover);
+    }
+    return RESP_OK.to_vec();
+}
+
+fn string_reply(value: Option<Vec<u8>>, protover: u8) -> Vec<u8> {
+    return match value {
+        Some(v) => encode(Value::String(v), false),
+        None => nil(protover),
+    };
+}
+
+/// Replaces whatever is at `key` with a string expiring at `expires_at`, -1
+/// for never.
+fn set_string(store: &mut Store, key: &[u8], value: Vec<u8>, expires_at: i64) {
+    let (obj_type, obj_encoding) = deduce_type_encoding(&value);
+    let mut obj = StoreObject::new(ObjectValue::String(value), -1, obj_type, obj_encoding);
+    obj.expires_at = expires_at;
+    store.put(key.to_vec(), obj);
+}
+
+/// Logs a write that sets a TTL as `SET key value PXAT at`, a relative
+/// timeout would be measured from the time of the replay.
+fn propagate_set_pxat(store: &mut Store, key: &[u8], value: &[u8], at: i64) {
+    store.rewrite_propagation(vec![
+        b"SET".to_vec(),
+        key.to_vec(),
+        value.to_vec(),
+        b"PXAT".to_vec(),
+        at.to_string().into_bytes(),
+    ]);
+}
+
+pub fn setnx(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    if store.exists(&args[0]) {
+        return RESP_ZERO.to_vec();
+    }
+
+    set_string(store, &args[0], args[1].clone(), -1);
+    return RESP_ONE.to_vec();
+}
+
+pub fn setex(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return set_expiring(args, store, ExpireOption::Ex, "setex");
+}
+
+pub fn psetex(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return set_expiring(args, store, ExpireOption::Px, "psetex");
+}
+
+fn set_expiring(
+    args: Vec<Vec<u8>>,
+    store: &mut Store,
+    option: ExpireOption,
+    name: &str,
+) -> Vec<u8> {
+    let (key, value) = (&args[0], &args[2]);
+    let at = match option.expires_at(&args[1], name) {
+        Ok(at) => at,
+        Err(err) => return encode_error(err),
+    };
+
+    set_string(store, key, value.clone(), at);
+    propagate_set_pxat(store, key, value, at);
+    return RESP_OK.to_vec();
+}
+
+pub fn mset(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    if !args.len().is_multiple_of(2) {
+        return encode_error(anyhow!("ERR wrong number of arguments for 'mset' command"));
+    }
+
+    for pair in args.chunks(2) {
+        set_string(store, &pair[0], pair[1].clone(), -1);
+    }
+    return RESP_OK.to_vec();
+}
+
+pub fn msetnx(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    if !args.len().is_multiple_of(2) {
+        return encode_error(anyhow!("ERR wrong number of arguments for 'msetnx' command"));
+    }
+    if args.chunks(2).any(|pair| store.exists(&pair[0])) {
+        return RESP_ZERO.to_vec();
+    }
+
+    for pair in args.chunks(2) {
+        set_string(store, &pair[0], pair[1].clone(), -1);
+    }
+    return RESP_ONE.to_vec();
+}
+
+pub fn mget(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    // Keys of another type are reported as missing, not as an error
+    let values = args
+        .iter()
+        .map(|key| match store.get(key).map(|obj| &obj.value) {
+            Some(ObjectValue::String(v)) => Value::String(v.clone()),
+            _ => Value::Empty,
+        })
+        .collect();
+
+    return encode_proto(Value::Vector(values), false, client.protover);
+}
+
+pub fn getset(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let old = match get_string(store, &args[0]) {
+        Ok(old) => old,
+        Err(err) => return encode_error(err),
+    };
+
+    set_string(store, &args[0], args[1].clone(), -1);
+    return string_reply(old, client.protover);
+}
+
+pub fn getdel(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+
+    return match get_string(store, key) {
+        Ok(Some(v)) => {
+            store.del(key);
+            encode(Value::String(v), false)
+        }
+        Ok(None) => nil(client.protover),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn getex(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    let mut persist = false;
+    let mut expire = None;
+
+    let mut i = 1;
+    while i < args.len() {
+        let opt = args[i].to_ascii_uppercase();
+        match ExpireOption::parse(&opt) {
+            Some(option)
+                if i + 1 < args.len()
+                    && !persist
+                    && expire.is_none_or(|(prev, _)| prev == option) =>
+            {
+                i += 1;
+                expire = Some((option, &args[i]));
+            }
+            None if opt == b"PERSIST" && expire.is_none() => persist = true,
+            _ => return encode_error(anyhow!("ERR syntax error")),
+        }
+        i += 1;
+    }
+
+    let expires_at = match expire.map(|(option, arg)| option.expires_at(arg, "getex")) {
+        Some(Ok(at)) => Some(at),
+        Some(Err(err)) => return encode_error(err),
+        None => None,
+    };
+    let value = match get_string(store, key) {
+        Ok(Some(v)) => v,
+        Ok(None) => return nil(client.protover),
+        Err(err) => return encode_error(err),
+    };
+
+    match expires_at {
+        // An absolute time in the past deletes the key right away
+        Some(at) if at <= Utc::now().timestamp_millis() => {
+            store.del(key);
+            store.rewrite_propagation(vec![b"DEL".to_vec(), key.clone()]);
+        }
+        Some(at) => {
+            store.set_expiry(key, at);
+            store.add_dirty(1);
+            store.rewrite_propagation(vec![
+                b"GETEX".to_vec(),
+                key.clone(),
+                b"PXAT".to_vec(),
+                at.to_string().into_bytes(),
+            ]);
+        }
+        None => {
+            if persist && store.peek(key).is_some_and(|obj| obj.expires_at != -1) {
+                store.set_expiry(key, -1);
+                store.add_dirty(1);
+            }
+        }
+    }
+
+    return encode(Value::String(value), false);
+}
+
+pub fn strlen(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_string(store, &args[0]) {
+        Ok(v) => encode(Value::Int64(v.map_or(0, |v| v.len() as i64)), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+/// Changes the string at `key` in place with `f`, keeping its TTL, or
+/// creates it out of an empty string. `new_len` gives the length the string
+/// ends up with out of its current one, so that it can be checked against
+/// the size limit before anything is done. Returns the new length.
+fn update_string(
+    store: &mut Store,
+    key: &[u8],
+    new_len: impl FnOnce(usize) -> usize,
+    f: impl FnOnce(&mut Vec<u8>),
+) -> anyhow::Result<usize> {
+    let len = get_string(store, key)?.map_or(0, |v| v.len());
+    if new_len(len) > PROTO_MAX_BULK_LEN as usize {
+        return Err(anyhow!("ERR string exceeds maximum allowed size (proto-max-bulk-len)"));
+    }
+
+    let obj = store.get_or_insert(
+        key,
+        StoreObject::new(ObjectValue::String(Vec::new()), -1, TYPE_STRING, ENCODING_EMBSTR),
+    );
+    let ObjectValue::String(value) = &mut obj.value else {
+        unreachable!("string typed object doesn't hold a string");
+    };
+    f(value);
+
+    let len = value.len();
+    let (obj_type, obj_encoding) = deduce_type_encoding(value);
+    obj.type_encoding = obj_type | obj_encoding;
+    store.add_dirty(1);
+
+    return Ok(len);
+}
+
+pub fn append(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, suffix) = (&args[0], &args[1]);
+
+    return match update_string(store, key, |len| len + suffix.len(), |v| v.extend(suffix)) {
+        Ok(len) => encode(Value::Int64(len as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn setrange(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, value) = (&args[0], &args[2]);
+    let Some(offset) = parse_i64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+    if offset < 0 {
+        return encode_error(anyhow!("ERR offset is out of range"));
+    }
+
+    // Nothing to write, not even the padding, and no key to create
+    if value.is_empty() {
+        return strlen(vec![key.clone()], client, store);
+    }
+
+    let (start, end) = (offset as usize, offset as usize + value.len());
+    let result = update_string(
+        store,
+        key,
+        |len| len.max(end),
+        |v| {
+            if v.len() < end {
+                v.resize(end, 0);
+            }
+            v[start..end].copy_from_slice(value);
+        },
+    );
+
+    return match result {
+        Ok(len) => encode(Value::Int64(len as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+/// The bytes of `s` from `start` to `end` included, negative indexes
+/// counting from the end, the way GETRANGE clamps them.
+fn substring(s: &[u8], start: i64, end: i64) -> &[u8] {
+    let len = s.len() as i64;
+    if len == 0 || (start < 0 && end < 0 && start > end) {
+        return &[];
+    }
+
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+    if start > end {
+        return &[];
+    }
+    return &s[start as usize..=end as usize];
+}
+
+pub fn getrange(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (Some(start), Some(end)) = (parse_i64(&args[1]), parse_i64(&args[2])) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+
+    return match get_string(store, &args[0]) {
+        Ok(v) => {
+            let v = v.unwrap_or_default();
+            encode(Value::String(substring(&v, start, end).to_vec()), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+/// Adds `by` to the integer at `key`, which starts from 0 if missing.
+fn incr_by(store: &mut Store, key: &[u8], by: i64) -> Vec<u8> {
+    let obj = store.get_or_insert(
+        key,
+        StoreObject::new(ObjectValue::String(b"0".to_vec()), -1, TYPE_STRING, ENCODING_INT),
+    );
+
+    if let Err(err) = obj.assert_type(TYPE_STRING) {
+        return encode_error(err);
+    }
+    // Writes of strings pick the INT encoding for anything that is an
+    // integer, others don't need to be parsed
+    if let Err(err) = obj.assert_encoding(ENCODING_INT) {
+        return encode_error(err);
+    }
+
+    let ObjectValue::String(s) = &obj.value else {
+        unreachable!("string typed object doesn't hold a string");
+    };
+    let current = parse_i64(s).expect("int encoded string is not an integer");
+    let Some(i) = current.checked_add(by) else {
+        return encode_error(anyhow!("ERR increment or decrement would overflow"));
+    };
+
+    obj.value = ObjectValue::String(i.to_string().into_bytes());
+    store.add_dirty(1);
+
+    return encode(Value::Int64(i), false);
+}
+
+pub fn incr(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return incr_by(store, &args[0], 1);
+}
+
+pub fn decr(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return incr_by(store, &args[0], -1);
+}
+
+pub fn incrby(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match parse_i64(&args[1]) {
+        Some(by) => incr_by(store, &args[0], by),
+        None => encode_error(anyhow!("ERR value is not an integer or out of range")),
+    };
+}
+
+pub fn decrby(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(by) = parse_i64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not an integer or out of range"));
+    };
+    let Some(by) = by.checked_neg() else {
+        return encode_error(anyhow!("ERR decrement would overflow"));
+    };
+
+    return incr_by(store, &args[0], by);
+}
+
+pub fn incrbyfloat(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    let Some(incr) = parse_f64(&args[1]) else {
+        return encode_error(anyhow!("ERR value is not a valid float"));
+    };
+    let current = match get_string(store, key) {
+        Ok(Some(v)) => match parse_f64(&v) {
+            Some(current) => current,
+            None => return encode_error(anyhow!("ERR value is not a valid float")),
+        },
+        Ok(None) => 0.0,
+        Err(err) => return encode_error(err),
+    };
+
+    let new = current + incr;
+    if !new.is_finite() {
+        return encode_error(anyhow!("ERR increment would produce NaN or Infinity"));
+    }
+    let new = format_double(new).into_bytes();
+    let expires_at = store.peek(key).map_or(-1, |obj| obj.expires_at);
+    set_string(store, key, new.clone(), expires_at);
+
+    // Replaying the increment could round differently, log the result instead
+    store.rewrite_propagation(vec![
+        b"SET".to_vec(),
+        key.clone(),
+        new.clone(),
+        b"KEEPTTL".to_vec(),
+    ]);
+    return encode(Value::String(new), false);
+}
+
+/// Ranges of the two strings that are part of their longest common
+/// subsequence, as `(start, end)` with the end included.
+type LcsMatch = ((usize, usize), (usize, usize));
+
+/// Longest common subsequence of `a` and `b` with the ranges of each that
+/// it is made of, last ones first, found the same way Redis does.
+fn longest_common_subsequence(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<LcsMatch>) {
+    // table[i][j] is the length of the LCS of a[..i] and b[..j]
+    let width = b.len() + 1;
+    let mut table = vec![0_u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = if a[i - 1] == b[j - 1] {
+                table[(i - 1) * width + j - 1] + 1
+            } else {
+                table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    // Walk back from the end, gathering contiguous matches into ranges
+    let mut len = table[a.len() * width + b.len()] as usize;
+    let mut common = vec![0; len];
+    let mut matches = Vec::new();
+    let mut range: Option<LcsMatch> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            common[len - 1] = a[i - 1];
+            match &mut range {
+                Some((ra, rb)) if ra.0 == i && rb.0 == j => {
+                    ra.0 -= 1;
+                    rb.0 -= 1;
+                }
+                Some(_) => emit = true,
+                None => range = Some(((i - 1, i - 1), (j - 1, j - 1))),
+            }
+            // Nothing left to extend it with on one side
+            if range.is_some_and(|(ra, rb)| ra.0 == 0 || rb.0 == 0) {
+                emit = true;
+            }
+            len -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[(i - 1) * width + j] > table[i * width + j - 1] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = range.is_some();
+        }
+
+        if emit {
+            matches.extend(range.take());
+        }
+    }
+
+    return (common, matches);
+}
+
+pub fn lcs(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let mut len_only = false;
+    let mut idx = false;
+    let mut min_match_len = 0;
+    let mut with_match_len = false;
+
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"LEN" => len_only = true,
+            b"IDX" => idx = true,
+            b"WITHMATCHLEN" => with_match_len = true,
+            b"MINMATCHLEN" if i + 1 < args.len() => {
+                i += 1;
+                let Some(n) = parse_i64(&args[i]) else {
+                    return encode_error(anyhow!("ERR value is not an integer or out of range"));
+                };
+                min_match_len = n.max(0) as usize;
+            }
+            _ => return encode_error(anyhow!("ERR syntax error")),
+        }
+        i += 1;
+    }
+    if len_only && idx {
+        return encode_error(anyhow!(
+            "ERR If you want both the length and indexes, please just use IDX."
+        ));
+    }
+
+    let mut strings = Vec::with_capacity(2);
+    for key in &args[..2] {
+        match store.get(key).map(|obj| &obj.value) {
+            Some(ObjectValue::String(v)) => strings.push(v.clone()),
+            Some(_) => {
+                return encode_error(anyhow!("ERR The specified keys must contain string values"))
+            }
+            None => strings.push(Vec::new()),
+        }
+    }
+    let (a, b) = (&strings[0], &strings[1]);
+    // Same limit as Redis on the table of lengths
+    let table_size = (a.len() + 1)
+        .checked_mul(b.len() + 1)
+        .and_then(|cells| cells.checked_mul(std::mem::size_of::<u32>()));
+    if table_size.is_none_or(|size| size > PROTO_MAX_BULK_LEN as usize) {
+        return encode_error(anyhow!(
+            "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+        ));
+    }
+
+    let (common, matches) = longest_common_subsequence(a, b);
+    if len_only {
+        return encode(Value::Int64(common.len() as i64), false);
+    }
+    if !idx {
+        return encode(Value::String(common), false);
+    }
+
+    let matches = matches
+        .into_iter()
+        .filter(|((start, end), _)| end - start + 1 >= min_match_len)
+        .map(|((a_start, a_end), (b_start, b_end))| {
+            let mut reply = vec![
+                Value::Vector(vec![Value::Int64(a_start as i64), Value::Int64(a_end as i64)]),
+                Value::Vector(vec![Value::Int64(b_start as i64), Value::Int64(b_end as i64)]),
+            ];
+            if with_match_len {
+                reply.push(Value::Int64((a_end - a_start + 1) as i64));
+            }
+            Value::Vector(reply)
+        })
+        .collect();
+    let reply = Value::Map(vec![
+        (Value::String(b"matches".to_vec()), Value::Vector(matches)),
+        (Value::String(b"len".to_vec()), Value::Int64(common.len() as i64)),
+    ]);
+
+    return encode_proto(reply, false, client.protover);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::eval::test_helpers::{logged, run, store, store_with_aof};
+
+    #[test]
+    fn test_set_rejects_conflicting_options() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        for args in [
+            &["SET", "k", "v", "NX", "XX"][..],
+            &["SET", "k", "v", "XX", "NX"],
+            &["SET", "k", "v", "EX", "10", "KEEPTTL"],
+            &["SET", "k", "v", "KEEPTTL", "PX", "10"],
+            &["SET", "k", "v", "EX", "10", "PX", "10"],
+            &["SET", "k", "v", "EX"],
+        ] {
+            assert_eq!(run(&mut store, &mut client, args), b"-ERR syntax error\r\n");
+        }
+        assert_eq!(run(&mut store, &mut client, &["EXISTS", "k"]), b":0\r\n");
+
+        // Repeating an option is fine
+        assert_eq!(run(&mut store, &mut client, &["SET", "k", "v", "NX", "NX"]), RESP_OK);
+        assert_eq!(run(&mut store, &mut client, &["SET", "k", "w", "NX"]), b"$-1\r\n");
+        assert_eq!(run(&mut store, &mut client, &["SET", "k", "w", "XX", "GET"]), b"$1\r\nv\r\n");
+        assert_eq!(run(&mut store, &mut client, &["SET", "j", "w", "XX", "GET"]), b"$-1\r\n");
+        assert_eq!(run(&mut store, &mut client, &["EXISTS", "j"]), b":0\r\n");
+    }
+
+    #[test]
+    fn test_set_keepttl() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        run(&mut store, &mut client, &["SET", "k", "v", "EX", "100"]);
+        run(&mut store, &mut client, &["SET", "k", "w", "KEEPTTL"]);
+        assert_eq!(run(&mut store, &mut client, &["TTL", "k"]), b":100\r\n");
+
+        run(&mut store, &mut client, &["SET", "k", "x"]);
+        assert_eq!(run(&mut store, &mut client, &["TTL", "k"]), b":-1\r\n");
+    }
+
+    #[test]
+    fn test_set_get_on_the_wrong_type() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        run(&mut store, &mut client, &["RPUSH", "l", "a"]);
+        assert_eq!(
+            run(&mut store, &mut client, &["SET", "l", "v", "GET"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(run(&mut store, &mut client, &["TYPE", "l"]), b"+list\r\n");
+
+        // Without GET the type doesn't matter
+        assert_eq!(run(&mut store, &mut client, &["SET", "l", "v"]), RESP_OK);
+        assert_eq!(run(&mut store, &mut client, &["GET", "l"]), b"$1\r\nv\r\n");
+    }
+
+    #[test]
+    fn test_set_with_relative_expiry_is_logged_with_pxat() {
+        let mut store = store_with_aof("set-pxat");
+        let mut client = Client::new();
+
+        let before = Utc::now().timestamp_millis();
+        run(&mut store, &mut client, &["SET", "k", "v", "EX", "100"]);
+        let after = Utc::now().timestamp_millis();
+        run(&mut store, &mut client, &["SET", "j", "v", "NX", "GET"]);
+        run(&mut store, &mut client, &["SET", "j", "w", "NX"]);
+
+        let logged = logged(&mut store);
+        let [set_k, set_j] = &logged[logged.len() - 2..] else {
+            unreachable!();
+        };
+        assert_eq!(set_k[..4], ["SET", "k", "v", "PXAT"]);
+        let at = set_k[4].parse::<i64>().unwrap();
+        assert!(before + 100_000 <= at && at <= after + 100_000);
+        // Without an expiry SET is logged as is, and not at all when NX or
+        // XX kept it from happening
+        assert_eq!(set_j, &["SET", "j", "v", "NX", "GET"]);
+    }
+
+    #[test]
+    fn test_getex() {
+        let mut store = store_with_aof("getex");
+        let mut client = Client::new();
+        run(&mut store, &mut client, &["SET", "k", "v"]);
+
+        assert_eq!(
+            run(&mut store, &mut client, &["GETEX", "k", "EX", "10", "PERSIST"]),
+            b"-ERR syntax error\r\n"
+        );
+        assert_eq!(run(&mut store, &mut client, &["GETEX", "k", "EX", "100"]), b"$1\r\nv\r\n");
+        assert_eq!(run(&mut store, &mut client, &["TTL", "k"]), b":100\r\n");
+        assert_eq!(run(&mut store, &mut client, &["GETEX", "k", "PERSIST"]), b"$1\r\nv\r\n");
+        assert_eq!(run(&mut store, &mut client, &["TTL", "k"]), b":-1\r\n");
+        assert_eq!(run(&mut store, &mut client, &["GETEX", "j", "EX", "100"]), b"$-1\r\n");
+
+        // A time in the past deletes the key
+        assert_eq!(
+            run(&mut store, &mut client, &["GETEX", "k", "PXAT", "1"]),
+            b"$1\r\nv\r\n"
+        );
+        assert_eq!(run(&mut store, &mut client, &["EXISTS", "k"]), b":0\r\n");
+
+        let logged = logged(&mut store);
+        assert_eq!(logged[2][..3], ["GETEX", "k", "PXAT"]);
+        assert_eq!(logged[3], ["GETEX", "k", "PERSIST"]);
+        assert_eq!(logged[4], ["DEL", "k"]);
+    }
+
+    #[test]
+    fn test_setrange() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        assert_eq!(run(&mut store, &mut client, &["SETRANGE", "k", "0", ""]), b":0\r\n");
+        assert_eq!(run(&mut store, &mut client, &["EXISTS", "k"]), b":0\r\n");
+        assert_eq!(run(&mut store, &mut client, &["SETRANGE", "k", "3", "x"]), b":4\r\n");
+        assert_eq!(run(&mut store, &mut client, &["GET", "k"]), b"$4\r\n\0\0\0x\r\n");
+        assert_eq!(
+            run(&mut store, &mut client, &["SETRANGE", "k", "-1", "x"]),
+            b"-ERR offset is out of range\r\n"
+        );
+        assert_eq!(
+            run(&mut store, &mut client, &["SETRANGE", "k", &PROTO_MAX_BULK_LEN.to_string(), "x"]),
+            b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n"
+        );
+
+        // Overwrites in place, keeping the TTL and updating the encoding
+        run(&mut store, &mut client, &["SET", "n", "12", "EX", "100"]);
+        assert_eq!(run(&mut store, &mut client, &["SETRANGE", "n", "1", "3"]), b":2\r\n");
+        assert_eq!(run(&mut store, &mut client, &["GET", "n"]), b"$2\r\n13\r\n");
+        assert_eq!(run(&mut store, &mut client, &["OBJECT", "ENCODING", "n"]), b"$3\r\nint\r\n");
+        assert_eq!(run(&mut store, &mut client, &["TTL", "n"]), b":100\r\n");
+        run(&mut store, &mut client, &["SETRANGE", "n", "1", "x"]);
+        assert_eq!(
+            run(&mut store, &mut client, &["OBJECT", "ENCODING", "n"]),
+            b"$6\r\nembstr\r\n"
+        );
+
+        run(&mut store, &mut client, &["RPUSH", "l", "a"]);
+        assert_eq!(
+            run(&mut store, &mut client, &["SETRANGE", "l", "0", "x"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_getrange() {
+        let mut store = store();
+        let mut client = Client::new();
+        run(&mut store, &mut client, &["SET", "k", "Hello World"]);
+
+        for (start, end, expected) in [
+            ("0", "4", "Hello"),
+            ("-5", "-1", "World"),
+            ("0", "100", "Hello World"),
+            ("-100", "1", "He"),
+            ("5", "3", ""),
+            ("-1", "-5", ""),
+            ("20", "30", ""),
+        ] {
+            assert_eq!(
+                run(&mut store, &mut client, &["GETRANGE", "k", start, end]),
+                format!("${}\r\n{}\r\n", expected.len(), expected).into_bytes()
+            );
+        }
+        assert_eq!(run(&mut store, &mut client, &["GETRANGE", "j", "0", "-1"]), b"$0\r\n\r\n");
+        assert_eq!(
+            run(&mut store, &mut client, &["GETRANGE", "k", "a", "1"]),
+            b"-ERR value is not an integer or out of range\r\n"
+        );
+    }
+
+    #[test]
+    fn test_append() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        assert_eq!(run(&mut store, &mut client, &["APPEND", "k", "1"]), b":1\r\n");
+        assert_eq!(run(&mut store, &mut client, &["OBJECT", "ENCODING", "k"]), b"$3\r\nint\r\n");
+        run(&mut store, &mut client, &["EXPIRE", "k", "100"]);
+        assert_eq!(run(&mut store, &mut client, &["APPEND", "k", "x"]), b":2\r\n");
+        assert_eq!(run(&mut store, &mut client, &["GET", "k"]), b"$2\r\n1x\r\n");
+        assert_eq!(run(&mut store, &mut client, &["TTL", "k"]), b":100\r\n");
+    }
+
+    #[test]
+    fn test_incr_and_decr() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        assert_eq!(run(&mut store, &mut client, &["INCR", "n"]), b":1\r\n");
+        assert_eq!(run(&mut store, &mut client, &["DECRBY", "n", "11"]), b":-10\r\n");
+        assert_eq!(
+            run(&mut store, &mut client, &["DECRBY", "n", &i64::MIN.to_string()]),
+            b"-ERR decrement would overflow\r\n"
+        );
+        run(&mut store, &mut client, &["SET", "n", &i64::MAX.to_string()]);
+        assert_eq!(
+            run(&mut store, &mut client, &["INCR", "n"]),
+            b"-ERR increment or decrement would overflow\r\n"
+        );
+
+        // Only integers that print back the same are integers
+        for value in ["007", "+1", "1.5", " 1", "x"] {
+            run(&mut store, &mut client, &["SET", "n", value]);
+            assert_eq!(
+                run(&mut store, &mut client, &["INCR", "n"]),
+                b"-ERR value is not an integer or out of range\r\n"
+            );
+        }
+
+        run(&mut store, &mut client, &["RPUSH", "l", "a"]);
+        assert_eq!(
+            run(&mut store, &mut client, &["INCR", "l"]),
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_incrbyfloat() {
+        let mut store = store_with_aof("incrbyfloat");
+        let mut client = Client::new();
+
+        run(&mut store, &mut client, &["SET", "f", "10.50", "EX", "100"]);
+        assert_eq!(run(&mut store, &mut client, &["INCRBYFLOAT", "f", "0.1"]), b"$4\r\n10.6\r\n");
+        assert_eq!(
+            run(&mut store, &mut client, &["INCRBYFLOAT", "f", "5.0e3"]),
+            b"$6\r\n5010.6\r\n"
+        );
+        assert_eq!(run(&mut store, &mut client, &["TTL", "f"]), b":100\r\n");
+        assert_eq!(run(&mut store, &mut client, &["INCRBYFLOAT", "g", "3"]), b"$1\r\n3\r\n");
+
+        assert_eq!(
+            run(&mut store, &mut client, &["INCRBYFLOAT", "f", "x"]),
+            b"-ERR value is not a valid float\r\n"
+        );
+        run(&mut store, &mut client, &["SET", "f", "1.7e308"]);
+        assert_eq!(
+            run(&mut store, &mut client, &["INCRBYFLOAT", "f", "1.7e308"]),
+            b"-ERR increment would produce NaN or Infinity\r\n"
+        );
+
+        // Logged as the result, which replays the same whatever the rounding
+        let logged = logged(&mut store);
+        assert_eq!(logged[2], ["SET", "f", "10.6", "KEEPTTL"]);
+        assert_eq!(logged[3], ["SET", "f", "5010.6", "KEEPTTL"]);
+    }
+
+    #[test]
+    fn test_lcs() {
+        let mut store = store();
+        let mut client = Client::new();
+        run(&mut store, &mut client, &["MSET", "a", "ohmytext", "b", "mynewtext"]);
+
+        assert_eq!(run(&mut store, &mut client, &["LCS", "a", "b"]), b"$6\r\nmytext\r\n");
+        assert_eq!(run(&mut store, &mut client, &["LCS", "a", "b", "LEN"]), b":6\r\n");
+        assert_eq!(
+            run(&mut store, &mut client, &["LCS", "a", "b", "IDX"]),
+            b"*4\r\n$7\r\nmatches\r\n*2\r\n*2\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n\
+              *2\r\n*2\r\n:2\r\n:3\r\n*2\r\n:0\r\n:1\r\n$3\r\nlen\r\n:6\r\n"
+        );
+        assert_eq!(
+            run(
+                &mut store,
+                &mut client,
+                &["LCS", "a", "b", "IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"]
+            ),
+            b"*4\r\n$7\r\nmatches\r\n*1\r\n*3\r\n*2\r\n:4\r\n:7\r\n*2\r\n:5\r\n:8\r\n:4\r\n\
+              $3\r\nlen\r\n:6\r\n"
+        );
+        assert_eq!(run(&mut store, &mut client, &["LCS", "a", "missing"]), b"$0\r\n\r\n");
+
+        assert_eq!(
+            run(&mut store, &mut client, &["LCS", "a", "b", "LEN", "IDX"]),
+            b"-ERR If you want both the length and indexes, please just use IDX.\r\n"
+        );
+        run(&mut store, &mut client, &["RPUSH", "l", "x"]);
+        assert_eq!(
+            run(&mut store, &mut client, &["LCS", "a", "l"]),
+            b"-ERR The specified keys must contain string values\r\n"
+        );
+    }
+
+    #[test]
+    fn test_getset_and_getdel() {
+        let mut store = store();
+        let mut client = Client::new();
+
+        assert_eq!(run(&mut store, &mut client, &["GETSET", "k", "v"]), b"$-1\r\n");
+        run(&mut store, &mut client, &["EXPIRE", "k", "100"]);
+        assert_eq!(run(&mut store, &mut client, &["GETSET", "k", "w"]), b"$1\r\nv\r\n");
+        // Unlike SET KEEPTTL, the TTL is gone
+        assert_eq!(run(&mut store, &mut client, &["TTL", "k"]), b":-1\r\n");
+
+        assert_eq!(run(&mut store, &mut client, &["GETDEL", "k"]), b"$1\r\nw\r\n");
+        assert_eq!(run(&mut store, &mut client, &["GETDEL", "k"]), b"$-1\r\n");
+    }
+}
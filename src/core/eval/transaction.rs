@@ -0,0 +1,76 @@
+use anyhow::anyhow;
+
+use crate::core::{
+    client::Client,
+    cmd::lookup,
+    multi::{discard_transaction, unwatch_all, watch_key, MultiState},
+    resp::{array_header, encode_error, nil_array, RESP_OK},
+};
+use crate::data::store::Store;
+
+pub fn multi(_args: Vec<Vec<u8>>, client: &mut Client, _store: &mut Store) -> Vec<u8> {
+    if client.multi.is_some() {
+        return encode_error(anyhow!("ERR MULTI calls can not be nested"));
+    }
+
+    client.multi = Some(MultiState::default());
+    return RESP_OK.to_vec();
+}
+
+pub fn exec(_args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let Some(multi) = client.multi.as_mut() else {
+        return encode_error(anyhow!("ERR EXEC without MULTI"));
+    };
+    if multi.dirty_exec {
+        discard_transaction(client, store);
+        return encode_error(anyhow!(
+            "EXECABORT Transaction discarded because of previous errors."
+        ));
+    }
+    let cmds = std::mem::take(&mut multi.cmds);
+
+    let touched = store.watched_keys_touched(client.id, &client.watched_keys);
+    unwatch_all(client, store);
+    if touched {
+        client.multi = None;
+        return nil_array(client.protover);
+    }
+
+    // The client stays in the transaction while it runs, which keeps
+    // blocking commands from blocking
+    let mut reply = array_header(cmds.len());
+    store.begin_exec();
+    for cmd in cmds {
+        let spec = lookup(&cmd.cmd).expect("queued commands exist");
+        reply.extend(super::execute(spec, cmd.args, client, store));
+    }
+    store.end_exec();
+    client.multi = None;
+
+    return reply;
+}
+
+pub fn discard(_args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    if client.multi.is_none() {
+        return encode_error(anyhow!("ERR DISCARD without MULTI"));
+    }
+
+    discard_transaction(client, store);
+    return RESP_OK.to_vec();
+}
+
+pub fn watch(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    if client.multi.is_some() {
+        return encode_error(anyhow!("ERR WATCH inside MULTI is not allowed"));
+    }
+
+    for key in args.iter() {
+        watch_key(client, store, key);
+    }
+    return RESP_OK.to_vec();
+}
+
+pub fn unwatch(_args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    unwatch_all(client, store);
+    return RESP_OK.to_vec();
+}
@@ -0,0 +1,848 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+
+use crate::common::{format_double, parse_f64, parse_i64, Value};
+use crate::core::{
+    blocked::{block_for_keys, parse_timeout},
+    client::Client,
+    cmd::Command,
+    resp::{encode, encode_error, encode_proto, nil, nil_array, RESP3},
+};
+use crate::data::{
+    store::{ObjectValue, Store, StoreObject, ENCODING_LISTPACK, TYPE_ZSET},
+    zset::{Zset, ZsetLimits},
+};
+
+use super::keyspace::{parse_scan_args, scan_buckets, scan_reply};
+
+/// Members along with their scores.
+type Entries = Vec<(Vec<u8>, f64)>;
+
+/// The sorted set at `key`, `None` if there is no such key.
+fn get_zset<'a>(store: &'a mut Store, key: &[u8]) -> anyhow::Result<Option<&'a Zset>> {
+    let Some(obj) = store.get(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_ZSET)?;
+
+    return match &obj.value {
+        ObjectValue::Zset(zset) => Ok(Some(zset)),
+        _ => unreachable!("zset typed object doesn't hold a sorted set"),
+    };
+}
+
+/// Runs `f` on the sorted set at `key`, `None` if there is no such key.
+/// Like every aggregate, a sorted set that ends up empty is deleted.
+fn with_zset<T>(
+    store: &mut Store,
+    key: &[u8],
+    f: impl FnOnce(&mut Zset, ZsetLimits) -> T,
+) -> anyhow::Result<Option<T>> {
+    let limits = store.zset_limits();
+    let Some(obj) = store.get_mut(key) else {
+        return Ok(None);
+    };
+    obj.assert_type(TYPE_ZSET)?;
+
+    let ObjectValue::Zset(zset) = &mut obj.value else {
+        unreachable!("zset typed object doesn't hold a sorted set");
+    };
+    let result = f(zset, limits);
+    let empty = zset.is_empty();
+    obj.refresh_encoding();
+
+    if empty {
+        store.del(key);
+    }
+
+    return Ok(Some(result));
+}
+
+/// Makes sure there is a sorted set at `key` to add members to.
+fn create_zset_if_missing(store: &mut Store, key: &[u8]) -> anyhow::Result<()> {
+    if get_zset(store, key)?.is_some() {
+        return Ok(());
+    }
+
+    let zset = ObjectValue::Zset(Zset::new());
+    store.put(
+        key.to_vec(),
+        StoreObject::new(zset, -1, TYPE_ZSET, ENCODING_LISTPACK),
+    );
+    return Ok(());
+}
+
+/// Replaces whatever is at `key` with a sorted set made of `entries`, or
+/// deletes it if there are none. Returns the size of the sorted set.
+fn store_zset(store: &mut Store, key: &[u8], entries: Entries) -> usize {
+    store.del(key);
+    if entries.is_empty() {
+        return 0;
+    }
+
+    let zset = Zset::from_entries(entries, store.zset_limits());
+    let len = zset.len();
+    let mut obj = StoreObject::new(ObjectValue::Zset(zset), -1, TYPE_ZSET, ENCODING_LISTPACK);
+    obj.refresh_encoding();
+    store.put(key.to_vec(), obj);
+
+    return len;
+}
+
+fn parse_score(arg: &[u8]) -> anyhow::Result<f64> {
+    return parse_f64(arg).ok_or_else(|| anyhow!("ERR value is not a valid float"));
+}
+
+/// Members, with their scores if `with_scores`: as pairs in RESP3, one
+/// after the other in RESP2.
+fn encode_entries(entries: Entries, with_scores: bool, protover: u8) -> Vec<u8> {
+    if !with_scores {
+        let members = entries.into_iter().map(|(m, _)| m).collect();
+        return encode(Value::VectorString(members), false);
+    }
+
+    let items = if protover >= RESP3 {
+        entries
+            .into_iter()
+            .map(|(m, s)| Value::Vector(vec![Value::String(m), Value::Double(s)]))
+            .collect()
+    } else {
+        entries
+            .into_iter()
+            .flat_map(|(m, s)| [Value::String(m), Value::Double(s)])
+            .collect()
+    };
+    return encode_proto(Value::Vector(items), false, protover);
+}
+
+/// End of a lex range, as in `[a`, `(a`, `-` or `+`.
+enum LexBound {
+    Min,
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+impl LexBound {
+    fn parse(arg: &[u8]) -> anyhow::Result<LexBound> {
+        return match arg.first() {
+            Some(b'-') if arg.len() == 1 => Ok(LexBound::Min),
+            Some(b'+') if arg.len() == 1 => Ok(LexBound::Max),
+            Some(b'[') => Ok(LexBound::Inclusive(arg[1..].to_vec())),
+            Some(b'(') => Ok(LexBound::Exclusive(arg[1..].to_vec())),
+            _ => Err(anyhow!("ERR min or max not valid string range item")),
+        };
+    }
+
+    /// Whether `member` sorts before the range starting at this bound.
+    fn is_below(&self, member: &[u8]) -> bool {
+        return match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(v) => member < v.as_slice(),
+            LexBound::Exclusive(v) => member <= v.as_slice(),
+        };
+    }
+
+    /// Whether `member` isn't past the range ending at this bound.
+    fn is_not_above(&self, member: &[u8]) -> bool {
+        return match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(v) => member <= v.as_slice(),
+            LexBound::Exclusive(v) => member < v.as_slice(),
+        };
+    }
+}
+
+/// Which elements a range command works on.
+enum Range {
+    /// Start and stop indexes, inclusive, negative from the end.
+    Rank(i64, i64),
+    /// Min and max scores, along with whether they are excluded.
+    Score((f64, bool), (f64, bool)),
+    Lex(LexBound, LexBound),
+}
+
+impl Range {
+    fn parse_rank(start: &[u8], stop: &[u8]) -> anyhow::Result<Range> {
+        let (Some(start), Some(stop)) = (parse_i64(start), parse_i64(stop)) else {
+            return Err(anyhow!("ERR value is not an integer or out of range"));
+        };
+        return Ok(Range::Rank(start, stop));
+    }
+
+    fn parse_score(min: &[u8], max: &[u8]) -> anyhow::Result<Range> {
+        let bound = |arg: &[u8]| match arg.strip_prefix(b"(") {
+            Some(score) => parse_f64(score).map(|s| (s, true)),
+            None => parse_f64(arg).map(|s| (s, false)),
+        };
+
+        let (Some(min), Some(max)) = (bound(min), bound(max)) else {
+            return Err(anyhow!("ERR min or max is not a float"));
+        };
+        return Ok(Range::Score(min, max));
+    }
+
+    fn parse_lex(min: &[u8], max: &[u8]) -> anyhow::Result<Range> {
+        return Ok(Range::Lex(LexBound::parse(min)?, LexBound::parse(max)?));
+    }
+
+    /// Ranks `start..end` of the elements in the range. Rank ranges count
+    /// from the end if `reverse`.
+    fn ranks(&self, zset: &Zset, reverse: bool) -> (usize, usize) {
+        let (start, end) = match self {
+            Range::Rank(start, stop) => {
+                let len = zset.len() as i64;
+                let start = (if *start < 0 { start + len } else { *start }).max(0);
+                let stop = (if *stop < 0 { stop + len } else { *stop }).min(len - 1);
+                if start > stop {
+                    return (0, 0);
+                }
+
+                if reverse {
+                    ((len - 1 - stop) as usize, (len - start) as usize)
+                } else {
+                    (start as usize, stop as usize + 1)
+                }
+            }
+            Range::Score((min, min_ex), (max, max_ex)) => (
+                zset.count_while(|_, s| s < *min || (*min_ex && s == *min)),
+                zset.count_while(|_, s| s < *max || (!*max_ex && s == *max)),
+            ),
+            Range::Lex(min, max) => (
+                zset.count_while(|m, _| min.is_below(m)),
+                zset.count_while(|m, _| max.is_not_above(m)),
+            ),
+        };
+
+        return (start, end.max(start));
+    }
+}
+
+pub fn zadd(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let key = &args[0];
+    let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+        (false, false, false, false, false, false);
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"GT" => gt = true,
+            b"LT" => lt = true,
+            b"CH" => ch = true,
+            b"INCR" => incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    let pairs = &args[i..];
+    if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+        return encode_error(anyhow!("ERR syntax error"));
+    }
+    if nx && xx {
+        return encode_error(anyhow!(
+            "ERR XX and NX options at the same time are not compatible"
+        ));
+    }
+    if (gt && lt) || (nx && (gt || lt)) {
+        return encode_error(anyhow!(
+            "ERR GT, LT, and/or NX options at the same time are not compatible"
+        ));
+    }
+    if incr && pairs.len() > 2 {
+        return encode_error(anyhow!(
+            "ERR INCR option supports a single increment-element pair"
+        ));
+    }
+
+    let mut elements = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.chunks(2) {
+        match parse_score(&pair[0]) {
+            Ok(score) => elements.push((score, &pair[1])),
+            Err(err) => return encode_error(err),
+        }
+    }
+
+    let skipped = |client: &Client| {
+        if incr {
+            return nil(client.protover);
+        }
+        return encode(Value::Int64(0), false);
+    };
+    match get_zset(store, key) {
+        Ok(None) if xx => return skipped(client),
+        Ok(_) => {}
+        Err(err) => return encode_error(err),
+    }
+    if let Err(err) = create_zset_if_missing(store, key) {
+        return encode_error(err);
+    }
+
+    let result = with_zset(store, key, |zset, limits| {
+        let (mut added, mut changed) = (0, 0);
+        let mut last_score = None;
+        for (score, member) in elements {
+            let new = match zset.score(member) {
+                None if xx => continue,
+                None => score,
+                Some(_) if nx => continue,
+                Some(current) => {
+                    let new = if incr { current + score } else { score };
+                    if new.is_nan() {
+                        return Err(anyhow!("ERR resulting score is not a number (NaN)"));
+                    }
+                    if (gt && new <= current) || (lt && new >= current) {
+                        continue;
+                    }
+                    new
+                }
+            };
+
+            match zset.insert(member, new, limits) {
+                None => added += 1,
+                Some(old) if old != new => changed += 1,
+                Some(_) => {}
+            }
+            last_score = Some(new);
+        }
+
+        return Ok((added, changed, last_score));
+    });
+
+    let (added, changed, last_score) = match result {
+        Ok(Some(Ok(counts))) => counts,
+        Ok(Some(Err(err))) | Err(err) => return encode_error(err),
+        Ok(None) => (0, 0, None),
+    };
+    store.add_dirty(added + changed);
+
+    if incr {
+        return match last_score {
+            Some(score) => encode_proto(Value::Double(score), false, client.protover),
+            None => skipped(client),
+        };
+    }
+    let count = if ch { added + changed } else { added };
+    return encode(Value::Int64(count as i64), false);
+}
+
+pub fn zincrby(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (key, member) = (&args[0], &args[2]);
+    let incr = match parse_score(&args[1]) {
+        Ok(incr) => incr,
+        Err(err) => return encode_error(err),
+    };
+
+    if let Err(err) = create_zset_if_missing(store, key) {
+        return encode_error(err);
+    }
+    let result = with_zset(store, key, |zset, limits| {
+        let score = zset.score(member).unwrap_or(0.0) + incr;
+        if score.is_nan() {
+            return Err(anyhow!("ERR resulting score is not a number (NaN)"));
+        }
+        zset.insert(member, score, limits);
+        return Ok(score);
+    });
+
+    return match result {
+        Ok(Some(Ok(score))) => {
+            store.add_dirty(1);
+            encode_proto(Value::Double(score), false, client.protover)
+        }
+        Ok(Some(Err(err))) | Err(err) => encode_error(err),
+        Ok(None) => unreachable!("sorted set created above"),
+    };
+}
+
+pub fn zrem(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let members = &args[1..];
+    let removed = with_zset(store, &args[0], |zset, _| {
+        members.iter().filter(|member| zset.remove(member)).count()
+    });
+
+    return match removed {
+        Ok(removed) => {
+            let removed = removed.unwrap_or(0);
+            store.add_dirty(removed as u64);
+            encode(Value::Int64(removed as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn zcard(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_zset(store, &args[0]) {
+        Ok(zset) => encode(Value::Int64(zset.map_or(0, |z| z.len()) as i64), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn zscore(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return match get_zset(store, &args[0]) {
+        Ok(zset) => match zset.and_then(|z| z.score(&args[1])) {
+            Some(score) => encode_proto(Value::Double(score), false, client.protover),
+            None => nil(client.protover),
+        },
+        Err(err) => encode_error(err),
+    };
+}
+
+fn count_generic(args: Vec<Vec<u8>>, store: &mut Store, range: anyhow::Result<Range>) -> Vec<u8> {
+    let range = match range {
+        Ok(range) => range,
+        Err(err) => return encode_error(err),
+    };
+
+    return match get_zset(store, &args[0]) {
+        Ok(Some(zset)) => {
+            let (start, end) = range.ranks(zset, false);
+            encode(Value::Int64((end - start) as i64), false)
+        }
+        Ok(None) => encode(Value::Int64(0), false),
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn zcount(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let range = Range::parse_score(&args[1], &args[2]);
+    return count_generic(args, store, range);
+}
+
+pub fn zlexcount(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let range = Range::parse_lex(&args[1], &args[2]);
+    return count_generic(args, store, range);
+}
+
+pub fn zrange(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (mut by_score, mut by_lex, mut reverse, mut with_scores) = (false, false, false, false);
+    let mut limit = None;
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"BYSCORE" => by_score = true,
+            b"BYLEX" => by_lex = true,
+            b"REV" => reverse = true,
+            b"WITHSCORES" => with_scores = true,
+            b"LIMIT" if i + 2 < args.len() => {
+                let (Some(offset), Some(count)) =
+                    (parse_i64(&args[i + 1]), parse_i64(&args[i + 2]))
+                else {
+                    return encode_error(anyhow!("ERR value is not an integer or out of range"));
+                };
+                limit = Some((offset, count));
+                i += 2;
+            }
+            _ => return encode_error(anyhow!("ERR syntax error")),
+        }
+        i += 1;
+    }
+
+    if by_score && by_lex {
+        return encode_error(anyhow!("ERR syntax error"));
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return encode_error(anyhow!(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+        ));
+    }
+    if with_scores && by_lex {
+        return encode_error(anyhow!(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX"
+        ));
+    }
+
+    // Score and lex ranges are given highest first with REV
+    let (min, max) = if reverse && (by_score || by_lex) {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+    let range = if by_score {
+        Range::parse_score(min, max)
+    } else if by_lex {
+        Range::parse_lex(min, max)
+    } else {
+        Range::parse_rank(min, max)
+    };
+    let range = match range {
+        Ok(range) => range,
+        Err(err) => return encode_error(err),
+    };
+
+    let zset = match get_zset(store, &args[0]) {
+        Ok(Some(zset)) => zset,
+        Ok(None) => return encode_entries(vec![], false, client.protover),
+        Err(err) => return encode_error(err),
+    };
+
+    let (mut start, mut end) = range.ranks(zset, reverse);
+    if let Some((offset, count)) = limit {
+        let offset = if offset < 0 {
+            usize::MAX
+        } else {
+            offset as usize
+        };
+        let count = if count < 0 {
+            usize::MAX
+        } else {
+            count as usize
+        };
+        let taken = (end - start).saturating_sub(offset).min(count);
+        // The offset is counted from the end that is walked first
+        if reverse {
+            end = end.saturating_sub(offset);
+            start = end - taken;
+        } else {
+            start = start.saturating_add(offset).min(end);
+            end = start + taken;
+        }
+    }
+
+    let entries = zset.range(start, end, reverse);
+    return encode_entries(entries, with_scores, client.protover);
+}
+
+fn rank_generic(args: Vec<Vec<u8>>, client: &Client, store: &mut Store, reverse: bool) -> Vec<u8> {
+    let with_score = match args.get(2) {
+        None => false,
+        Some(arg) if args.len() == 3 && arg.eq_ignore_ascii_case(b"WITHSCORE") => true,
+        Some(_) => return encode_error(anyhow!("ERR syntax error")),
+    };
+
+    let zset = match get_zset(store, &args[0]) {
+        Ok(zset) => zset,
+        Err(err) => return encode_error(err),
+    };
+    let Some((zset, rank)) = zset.and_then(|z| Some((z, z.rank(&args[1])?))) else {
+        if with_score {
+            return nil_array(client.protover);
+        }
+        return nil(client.protover);
+    };
+
+    let rank = (if reverse { zset.len() - 1 - rank } else { rank }) as i64;
+    if with_score {
+        let score = zset.score(&args[1]).unwrap();
+        let reply = Value::Vector(vec![Value::Int64(rank), Value::Double(score)]);
+        return encode_proto(reply, false, client.protover);
+    }
+    return encode(Value::Int64(rank), false);
+}
+
+pub fn zrank(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return rank_generic(args, client, store, false);
+}
+
+pub fn zrevrank(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return rank_generic(args, client, store, true);
+}
+
+fn remove_range_generic(
+    args: Vec<Vec<u8>>,
+    store: &mut Store,
+    range: anyhow::Result<Range>,
+) -> Vec<u8> {
+    let range = match range {
+        Ok(range) => range,
+        Err(err) => return encode_error(err),
+    };
+
+    let removed = with_zset(store, &args[0], |zset, _| {
+        let (start, end) = range.ranks(zset, false);
+        zset.remove_range(start, end)
+    });
+
+    return match removed {
+        Ok(removed) => {
+            let removed = removed.unwrap_or(0);
+            store.add_dirty(removed as u64);
+            encode(Value::Int64(removed as i64), false)
+        }
+        Err(err) => encode_error(err),
+    };
+}
+
+pub fn zremrangebyrank(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let range = Range::parse_rank(&args[1], &args[2]);
+    return remove_range_generic(args, store, range);
+}
+
+pub fn zremrangebyscore(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let range = Range::parse_score(&args[1], &args[2]);
+    return remove_range_generic(args, store, range);
+}
+
+pub fn zremrangebylex(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let range = Range::parse_lex(&args[1], &args[2]);
+    return remove_range_generic(args, store, range);
+}
+
+fn pop_generic(args: Vec<Vec<u8>>, client: &Client, store: &mut Store, max: bool) -> Vec<u8> {
+    let count = match args.get(1) {
+        None => None,
+        Some(arg) => match parse_i64(arg) {
+            Some(n) if n >= 0 => Some(n as usize),
+            _ => return encode_error(anyhow!("ERR value is out of range, must be positive")),
+        },
+    };
+    if args.len() > 2 {
+        return encode_error(anyhow!("ERR syntax error"));
+    }
+
+    let popped = with_zset(store, &args[0], |zset, _| {
+        (0..count.unwrap_or(1))
+            .map_while(|_| zset.pop(max))
+            .collect::<Vec<_>>()
+    });
+    let popped = match popped {
+        Ok(popped) => popped.unwrap_or_default(),
+        Err(err) => return encode_error(err),
+    };
+    store.add_dirty(popped.len() as u64);
+
+    // Without a count, the member and its score are flat even in RESP3
+    if count.is_none() {
+        let items = popped
+            .into_iter()
+            .flat_map(|(m, s)| [Value::String(m), Value::Double(s)])
+            .collect();
+        return encode_proto(Value::Vector(items), false, client.protover);
+    }
+    return encode_entries(popped, true, client.protover);
+}
+
+pub fn zpopmin(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return pop_generic(args, client, store, false);
+}
+
+pub fn zpopmax(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return pop_generic(args, client, store, true);
+}
+
+fn blocking_pop(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store, max: bool) -> Vec<u8> {
+    let (timeout, keys) = args.split_last().unwrap();
+    let timeout_at = match parse_timeout(timeout) {
+        Ok(t) => t,
+        Err(err) => return encode_error(err),
+    };
+
+    for key in keys {
+        match with_zset(store, key, |zset, _| zset.pop(max)) {
+            Ok(Some(Some((member, score)))) => {
+                store.add_dirty(1);
+                // Replaying must never block
+                let pop: &[u8] = if max { b"ZPOPMAX" } else { b"ZPOPMIN" };
+                store.rewrite_propagation(vec![pop.to_vec(), key.clone()]);
+                let reply = vec![
+                    Value::String(key.clone()),
+                    Value::String(member),
+                    Value::Double(score),
+                ];
+                return encode_proto(Value::Vector(reply), false, client.protover);
+            }
+            Ok(_) => continue,
+            Err(err) => return encode_error(err),
+        }
+    }
+
+    let name = if max { "BZPOPMAX" } else { "BZPOPMIN" };
+    let keys = keys.to_vec();
+    let timeout_reply = nil_array(client.protover);
+    let cmd = Command {
+        cmd: name.to_string(),
+        args,
+    };
+    return block_for_keys(
+        client,
+        store,
+        cmd,
+        keys,
+        TYPE_ZSET,
+        timeout_at,
+        timeout_reply,
+    );
+}
+
+pub fn bzpopmin(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return blocking_pop(args, client, store, false);
+}
+
+pub fn bzpopmax(args: Vec<Vec<u8>>, client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return blocking_pop(args, client, store, true);
+}
+
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        return match self {
+            // inf + -inf is taken as 0, like in Redis
+            Aggregate::Sum if (a + b).is_nan() => 0.0,
+            Aggregate::Sum => a + b,
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        };
+    }
+}
+
+/// Members and scores of the sorted set or set at each of `keys`, sets
+/// counting as sorted sets with every score at 1. A missing key is empty.
+fn read_inputs(store: &mut Store, keys: &[Vec<u8>]) -> anyhow::Result<Vec<Entries>> {
+    let mut inputs = Vec::with_capacity(keys.len());
+    for key in keys {
+        let entries = match store.get(key) {
+            None => vec![],
+            Some(obj) => match &obj.value {
+                ObjectValue::Zset(zset) => zset.iter().map(|(m, s)| (m.to_vec(), s)).collect(),
+                ObjectValue::Set(set) => set.iter().map(|m| (m, 1.0)).collect(),
+                _ => {
+                    return Err(anyhow!(
+                        "WRONGTYPE Operation against a key holding the wrong kind of value"
+                    ))
+                }
+            },
+        };
+        inputs.push(entries);
+    }
+
+    return Ok(inputs);
+}
+
+/// ZUNIONSTORE and ZINTERSTORE: `dst numkeys key [key ...] [WEIGHTS weight
+/// [weight ...]] [AGGREGATE SUM|MIN|MAX]`.
+fn store_generic(args: Vec<Vec<u8>>, store: &mut Store, inter: bool, name: &str) -> Vec<u8> {
+    let numkeys = match parse_i64(&args[1]) {
+        Some(n) if n > 0 => n as usize,
+        Some(_) => {
+            return encode_error(anyhow!(
+                "ERR at least 1 input key is needed for '{}' command",
+                name
+            ))
+        }
+        None => return encode_error(anyhow!("ERR value is not an integer or out of range")),
+    };
+    if numkeys > args.len() - 2 {
+        return encode_error(anyhow!("ERR syntax error"));
+    }
+
+    let keys = &args[2..2 + numkeys];
+    let mut weights = vec![1.0; numkeys];
+    let mut aggregate = Aggregate::Sum;
+    let mut i = 2 + numkeys;
+    while i < args.len() {
+        match args[i].to_ascii_uppercase().as_slice() {
+            b"WEIGHTS" if i + numkeys < args.len() => {
+                for (w, arg) in weights.iter_mut().zip(&args[i + 1..]) {
+                    let Some(weight) = parse_f64(arg) else {
+                        return encode_error(anyhow!("ERR weight value is not a float"));
+                    };
+                    *w = weight;
+                }
+                i += numkeys;
+            }
+            b"AGGREGATE" if i + 1 < args.len() => {
+                aggregate = match args[i + 1].to_ascii_uppercase().as_slice() {
+                    b"SUM" => Aggregate::Sum,
+                    b"MIN" => Aggregate::Min,
+                    b"MAX" => Aggregate::Max,
+                    _ => return encode_error(anyhow!("ERR syntax error")),
+                };
+                i += 1;
+            }
+            _ => return encode_error(anyhow!("ERR syntax error")),
+        }
+        i += 1;
+    }
+
+    let inputs = match read_inputs(store, keys) {
+        Ok(inputs) => inputs,
+        Err(err) => return encode_error(err),
+    };
+
+    // Score of each member and the number of inputs it is in
+    let mut scores: HashMap<Vec<u8>, (f64, usize)> = HashMap::new();
+    for (entries, weight) in inputs.into_iter().zip(weights) {
+        for (member, score) in entries {
+            // 0 * inf is taken as 0, like in Redis
+            let score = match score * weight {
+                s if s.is_nan() => 0.0,
+                s => s,
+            };
+            scores
+                .entry(member)
+                .and_modify(|(total, seen)| {
+                    *total = aggregate.apply(*total, score);
+                    *seen += 1;
+                })
+                .or_insert((score, 1));
+        }
+    }
+
+    let entries = scores
+        .into_iter()
+        .filter(|(_, (_, seen))| !inter || *seen == numkeys)
+        .map(|(member, (score, _))| (member, score))
+        .collect();
+    let len = store_zset(store, &args[0], entries);
+
+    return encode(Value::Int64(len as i64), false);
+}
+
+pub fn zunionstore(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return store_generic(args, store, false, "zunionstore");
+}
+
+pub fn zinterstore(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    return store_generic(args, store, true, "zinterstore");
+}
+
+pub fn zscan(args: Vec<Vec<u8>>, _client: &mut Client, store: &mut Store) -> Vec<u8> {
+    let (cursor, opts) = match parse_scan_args(&args[1..], false, false) {
+        Ok(parsed) => parsed,
+        Err(err) => return encode_error(err),
+    };
+
+    let zset = match get_zset(store, &args[0]) {
+        Ok(Some(zset)) => zset,
+        Ok(None) => return scan_reply(0, vec![]),
+        Err(err) => return encode_error(err),
+    };
+
+    let mut items = Vec::new();
+    let cursor = scan_buckets(cursor, opts.count, |cursor| {
+        let next = zset.scan(cursor, |member, score| {
+            if opts.matches(member) {
+                items.push(member.to_vec());
+                items.push(format_double(score).into_bytes());
+            }
+        });
+        (next, items.len() / 2)
+    });
+
+    return scan_reply(cursor, items);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::eval::test_helpers::scan_with_huge_count;
+
+    #[test]
+    fn test_zscan_with_a_huge_count() {
+        let mut fill = vec!["ZADD".to_string(), "z".to_string()];
+        for i in 0..200 {
+            fill.extend([i.to_string(), format!("m{}", i)]);
+        }
+
+        assert_eq!(scan_with_huge_count(&fill, &["ZSCAN", "z", "0"]), (b"0".to_vec(), 400));
+    }
+}
@@ -1,5 +1,8 @@
+pub mod blocked;
+pub mod client;
 pub mod cmd;
 pub mod comm;
 pub mod eval;
 pub mod macros;
+pub mod multi;
 pub mod resp;
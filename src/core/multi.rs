@@ -0,0 +1,140 @@
+use crate::core::{client::Client, cmd::Command};
+use crate::data::store::Store;
+
+/// Commands a client queued between MULTI and EXEC.
+#[derive(Default)]
+pub struct MultiState {
+    pub cmds: Vec<Command>,
+    /// A command was refused while being queued, e.g. for a wrong number of
+    /// arguments, so EXEC must discard the transaction.
+    pub dirty_exec: bool,
+}
+
+/// Whether `cmd` is queued when the client is in a transaction rather than
+/// run right away.
+pub fn is_queued(name: &str) -> bool {
+    return !matches!(name, "exec" | "discard" | "multi" | "watch");
+}
+
+/// Makes the transaction of `client` fail at EXEC, if it is in one.
+pub fn flag_transaction(client: &mut Client) {
+    if let Some(multi) = client.multi.as_mut() {
+        multi.dirty_exec = true;
+    }
+}
+
+/// Has `client` watch `key` of its database, for the next EXEC to fail if
+/// the key is touched meanwhile.
+pub fn watch_key(client: &mut Client, store: &mut Store, key: &[u8]) {
+    let watched = (client.db, key.to_vec());
+    if client.watched_keys.contains(&watched) {
+        return;
+    }
+
+    store.watch_key(client.id, key);
+    client.watched_keys.push(watched);
+}
+
+/// Forgets every key `client` watches, e.g. once EXEC ran or when it
+/// disconnects.
+pub fn unwatch_all(client: &mut Client, store: &mut Store) {
+    let keys = std::mem::take(&mut client.watched_keys);
+    store.unwatch_keys(client.id, &keys);
+}
+
+/// Leaves the transaction `client` is in, if any, without running it.
+pub fn discard_transaction(client: &mut Client, store: &mut Store) {
+    client.multi = None;
+    unwatch_all(client, store);
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{config::Config, core::eval};
+
+    fn run(client: &mut Client, store: &mut Store, cmds: &[&str]) -> Vec<u8> {
+        let mut replies = Vec::new();
+        for cmd in cmds {
+            let mut args = cmd.split(' ').map(|a| a.as_bytes().to_vec());
+            let cmd = Command {
+                cmd: String::from_utf8(args.next().unwrap())
+                    .unwrap()
+                    .to_uppercase(),
+                args: args.collect(),
+            };
+            replies.extend(eval::call(cmd, client, store));
+        }
+        return replies;
+    }
+
+    #[test]
+    fn test_errors_while_queuing_abort_exec() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut client = Client::new();
+
+        assert_eq!(
+            run(
+                &mut client,
+                &mut store,
+                &["multi", "set k 1", "set k", "exec", "get k"]
+            ),
+            b"+OK\r\n+QUEUED\r\n-ERR wrong number of arguments for 'set' command\r\n\
+              -EXECABORT Transaction discarded because of previous errors.\r\n$-1\r\n"
+        );
+        assert!(client.multi.is_none());
+
+        // Errors while running don't stop the other commands
+        assert_eq!(
+            run(
+                &mut client,
+                &mut store,
+                &["multi", "set k a", "incr k", "blpop k 0", "exec"]
+            ),
+            b"+OK\r\n+QUEUED\r\n+QUEUED\r\n+QUEUED\r\n*3\r\n+OK\r\n\
+              -ERR value is not an integer or out of range\r\n\
+              -WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_exec_fails_once_a_watched_key_changed() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let mut watcher = Client::new();
+        let mut other = Client::new();
+        let transaction = ["multi", "sadd s b", "exec"];
+
+        run(&mut watcher, &mut store, &["sadd s a", "watch s"]);
+        // Neither of these changes the set
+        run(
+            &mut other,
+            &mut store,
+            &["srem s x", "sadd s a", "smembers s"],
+        );
+        assert_eq!(
+            run(&mut watcher, &mut store, &transaction),
+            b"+OK\r\n+QUEUED\r\n*1\r\n:1\r\n"
+        );
+
+        run(&mut watcher, &mut store, &["watch s"]);
+        run(
+            &mut other,
+            &mut store,
+            &["select 1", "del s", "select 0", "srem s a"],
+        );
+        assert_eq!(
+            run(&mut watcher, &mut store, &transaction),
+            b"+OK\r\n+QUEUED\r\n*-1\r\n"
+        );
+        assert!(watcher.watched_keys.is_empty());
+
+        run(&mut watcher, &mut store, &["watch s"]);
+        run(&mut other, &mut store, &["flushall"]);
+        assert_eq!(
+            run(&mut watcher, &mut store, &transaction),
+            b"+OK\r\n+QUEUED\r\n*-1\r\n"
+        );
+    }
+}
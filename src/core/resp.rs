@@ -1,45 +1,62 @@
 use anyhow::anyhow;
 
-use crate::common::Value;
+use crate::common::{format_double, Value};
 
 type PositionAndValue = (usize, Value);
 
-type Result = anyhow::Result<PositionAndValue>;
+/// `Ok(None)` means the buffer holds only a prefix of a frame and the caller
+/// should read more bytes before trying again.
+type Result = anyhow::Result<Option<PositionAndValue>>;
+
+pub const RESP2: u8 = 2;
+pub const RESP3: u8 = 3;
 
 pub const RESP_NIL: &[u8] = "$-1\r\n".as_bytes();
+pub const RESP3_NULL: &[u8] = "_\r\n".as_bytes();
 pub const RESP_OK: &[u8] = "+OK\r\n".as_bytes();
 pub const RESP_ZERO: &[u8] = ":0\r\n".as_bytes();
 pub const RESP_ONE: &[u8] = ":1\r\n".as_bytes();
 pub const RESP_MINUS_ONE: &[u8] = ":-1\r\n".as_bytes();
 pub const RESP_MINUS_TWO: &[u8] = ":-2\r\n".as_bytes();
 
-fn read_length(data: &[u8]) -> (usize, i32) {
-    let mut length = 0_i32;
+/// Largest bulk string accepted from a client, same as Redis' default
+/// `proto-max-bulk-len`.
+pub const PROTO_MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
 
-    for (pos, _) in data.into_iter().enumerate() {
-        let b = data[pos];
-        if !(b >= b'0' && b <= b'9') {
-            return (pos + 2, length);
-        }
+/// Largest number of elements accepted in a single array frame.
+pub const PROTO_MAX_MULTIBULK_LEN: i64 = 1024 * 1024;
 
-        length = length * 10 + (b - b'0') as i32;
-    }
+/// Longest `*<count>` or `$<len>` line accepted in a request, same as Redis'
+/// `PROTO_INLINE_MAX_SIZE`.
+const PROTO_INLINE_MAX_SIZE: usize = 64 * 1024;
 
-    return (0, 0);
+/// Returns the line following the type byte and the position right after its
+/// terminating CRLF, or `None` if the CRLF has not arrived yet.
+fn read_line(data: &[u8]) -> Option<(usize, &[u8])> {
+    let end = data.windows(2).skip(1).position(|w| w == b"\r\n")? + 1;
+
+    return Some((end + 2, &data[1..end]));
 }
 
-fn read_simple_string(data: &[u8]) -> Result {
-    let mut pos = 1_usize;
-    for d in data.iter().skip(pos) {
-        if *d == b'\r' {
-            break;
-        }
-        pos += 1;
+fn parse_length(line: &[u8], max: i64) -> anyhow::Result<i64> {
+    let len = std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or_else(|| anyhow!("ERR Protocol error: invalid length"))?;
+
+    if len < -1 || len > max {
+        return Err(anyhow!("ERR Protocol error: invalid length"));
     }
 
-    let simp_str = String::from_utf8(data[1..pos].to_vec())?;
+    return Ok(len);
+}
 
-    return Ok((pos + 2, Value::String(simp_str)));
+fn read_simple_string(data: &[u8]) -> Result {
+    let Some((pos, line)) = read_line(data) else {
+        return Ok(None);
+    };
+
+    return Ok(Some((pos, Value::String(line.to_vec()))));
 }
 
 fn read_error(data: &[u8]) -> Result {
@@ -47,48 +64,283 @@ fn read_error(data: &[u8]) -> Result {
 }
 
 fn read_i64(data: &[u8]) -> Result {
-    let mut pos = 1_usize;
-    let mut value = 0_i64;
+    let Some((pos, line)) = read_line(data) else {
+        return Ok(None);
+    };
 
-    while data[pos] != b'\r' {
-        value = value * 10 + (data[pos] - b'0') as i64;
-        pos += 1;
-    }
+    let value = std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .ok_or_else(|| anyhow!("ERR Protocol error: invalid integer"))?;
 
-    return Ok((pos + 2, Value::Int64(value)));
+    return Ok(Some((pos, Value::Int64(value))));
 }
 
 fn read_bulk_string(data: &[u8]) -> Result {
-    let mut pos = 1_usize;
-    let (delta, len) = read_length(&data[pos..]);
-    pos += delta;
+    let Some((pos, line)) = read_line(data) else {
+        return Ok(None);
+    };
+
+    // Null bulk string: $-1\r\n
+    let len = parse_length(line, PROTO_MAX_BULK_LEN)?;
+    if len == -1 {
+        return Ok(Some((pos, Value::Empty)));
+    }
 
     let len = len as usize;
-    let bulk_str = String::from_utf8(data[pos..(pos + len)].to_vec())?;
+    if data.len() < pos + len + 2 {
+        return Ok(None);
+    }
+    if &data[(pos + len)..(pos + len + 2)] != b"\r\n" {
+        return Err(anyhow!("ERR Protocol error: bulk string is not terminated by CRLF"));
+    }
+
+    // The payload is taken verbatim, so it can hold any byte sequence.
+    let bulk_str = data[pos..(pos + len)].to_vec();
 
-    return Ok((pos + len + 2, Value::String(bulk_str)));
+    return Ok(Some((pos + len + 2, Value::String(bulk_str))));
 }
 
 fn read_array(data: &[u8]) -> Result {
-    let mut pos = 1_usize;
-    let (delta, len) = read_length(&data[pos..]);
-    pos += delta;
+    let Some((mut pos, line)) = read_line(data) else {
+        return Ok(None);
+    };
+
+    let len = parse_length(line, PROTO_MAX_MULTIBULK_LEN)?;
+    if len == -1 {
+        return Ok(Some((pos, Value::Empty)));
+    }
 
-    let mut elems: Vec<Value> = Vec::with_capacity(len as usize);
+    // Don't trust the announced length for the allocation, the elements may
+    // never arrive.
+    let mut elems: Vec<Value> = Vec::with_capacity((len as usize).min(1024));
 
     for _ in 0..len {
-        let (delta, value) = decode_one(&data[pos..])?;
+        let Some((delta, value)) = decode_one(&data[pos..])? else {
+            return Ok(None);
+        };
 
         elems.push(value);
         pos += delta;
     }
 
-    return Ok((pos, Value::Vector(elems)));
+    return Ok(Some((pos, Value::Vector(elems))));
+}
+
+fn read_aggregate(data: &[u8], count_per_elem: usize) -> anyhow::Result<Option<(usize, Vec<Value>)>> {
+    let Some((mut pos, line)) = read_line(data) else {
+        return Ok(None);
+    };
+
+    let len = parse_length(line, PROTO_MAX_MULTIBULK_LEN)?.max(0) as usize * count_per_elem;
+    let mut elems: Vec<Value> = Vec::with_capacity(len.min(1024));
+
+    for _ in 0..len {
+        let Some((delta, value)) = decode_one(&data[pos..])? else {
+            return Ok(None);
+        };
+
+        elems.push(value);
+        pos += delta;
+    }
+
+    return Ok(Some((pos, elems)));
+}
+
+fn read_null(data: &[u8]) -> Result {
+    return Ok(read_line(data).map(|(pos, _)| (pos, Value::Empty)));
+}
+
+fn read_boolean(data: &[u8]) -> Result {
+    let Some((pos, line)) = read_line(data) else {
+        return Ok(None);
+    };
+
+    return match line {
+        b"t" => Ok(Some((pos, Value::Boolean(true)))),
+        b"f" => Ok(Some((pos, Value::Boolean(false)))),
+        _ => Err(anyhow!("ERR Protocol error: invalid boolean")),
+    };
+}
+
+fn read_double(data: &[u8]) -> Result {
+    let Some((pos, line)) = read_line(data) else {
+        return Ok(None);
+    };
+
+    let value = std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .ok_or_else(|| anyhow!("ERR Protocol error: invalid double"))?;
+
+    return Ok(Some((pos, Value::Double(value))));
+}
+
+fn read_big_number(data: &[u8]) -> Result {
+    return Ok(read_line(data).map(|(pos, line)| (pos, Value::BigNumber(line.to_vec()))));
+}
+
+fn read_verbatim_string(data: &[u8]) -> Result {
+    let Some((pos, Value::String(s))) = read_bulk_string(data)? else {
+        return Ok(None);
+    };
+    if s.len() < 4 || s[3] != b':' {
+        return Err(anyhow!("ERR Protocol error: invalid verbatim string"));
+    }
+
+    let format = String::from_utf8_lossy(&s[..3]).into_owned();
+    return Ok(Some((pos, Value::Verbatim(format, s[4..].to_vec()))));
+}
+
+fn read_map(data: &[u8]) -> Result {
+    let Some((pos, elems)) = read_aggregate(data, 2)? else {
+        return Ok(None);
+    };
+
+    let mut pairs = Vec::with_capacity(elems.len() / 2);
+    let mut iter = elems.into_iter();
+    while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+        pairs.push((k, v));
+    }
+
+    return Ok(Some((pos, Value::Map(pairs))));
+}
+
+fn read_set(data: &[u8]) -> Result {
+    return Ok(read_aggregate(data, 1)?.map(|(pos, elems)| (pos, Value::Set(elems))));
+}
+
+fn read_push(data: &[u8]) -> Result {
+    return Ok(read_aggregate(data, 1)?.map(|(pos, elems)| (pos, Value::Push(elems))));
+}
+
+/// Where parsing the request a client is sending stands, kept between reads
+/// so that a request split across many of them isn't parsed again from the
+/// start each time, like the multibulk state of a Redis client.
+#[derive(Default)]
+pub struct RequestParser {
+    /// Bulk strings of the current request still to come, 0 until its
+    /// `*<count>` line arrived.
+    multibulk_len: usize,
+    /// Length of the bulk string being read, once its `$<len>` line arrived.
+    bulk_len: Option<usize>,
+    /// Arguments of the current request read so far.
+    argv: Vec<Vec<u8>>,
+}
+
+impl RequestParser {
+    /// Parses the requests at the start of `buf` and drops the bytes they
+    /// span from it. Returns the argument vectors of the requests that are
+    /// complete, the rest of the last one is awaited on the next call.
+    ///
+    /// Requests must be arrays of bulk strings, as Redis' multibulk protocol
+    /// wants them; nothing else is accepted from a client.
+    pub fn parse(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<Vec<Vec<Vec<u8>>>> {
+        let mut requests = Vec::new();
+        let mut pos = 0;
+        let res = self.parse_from(buf, &mut pos, &mut requests);
+        buf.drain(..pos);
+        res?;
+
+        return Ok(requests);
+    }
+
+    fn parse_from(
+        &mut self,
+        buf: &[u8],
+        pos: &mut usize,
+        requests: &mut Vec<Vec<Vec<u8>>>,
+    ) -> anyhow::Result<()> {
+        loop {
+            if self.multibulk_len == 0 {
+                let Some((delta, line)) = read_request_line(&buf[*pos..], b'*', "mbulk count")?
+                else {
+                    return Ok(());
+                };
+                let len = parse_length(line, PROTO_MAX_MULTIBULK_LEN)
+                    .map_err(|_| anyhow!("ERR Protocol error: invalid multibulk length"))?;
+                *pos += delta;
+
+                // Empty requests are skipped
+                if len > 0 {
+                    self.multibulk_len = len as usize;
+                    // Don't trust the announced length for the allocation, the
+                    // arguments may never arrive.
+                    self.argv = Vec::with_capacity(self.multibulk_len.min(1024));
+                }
+                continue;
+            }
+
+            let len = match self.bulk_len {
+                Some(len) => len,
+                None => {
+                    let Some((delta, line)) =
+                        read_request_line(&buf[*pos..], b'$', "bulk count")?
+                    else {
+                        return Ok(());
+                    };
+                    let len = parse_length(line, PROTO_MAX_BULK_LEN)
+                        .ok()
+                        .filter(|&len| len >= 0)
+                        .ok_or_else(|| anyhow!("ERR Protocol error: invalid bulk length"))?;
+                    *pos += delta;
+                    self.bulk_len = Some(len as usize);
+                    len as usize
+                }
+            };
+
+            if buf.len() - *pos < len + 2 {
+                return Ok(());
+            }
+            if &buf[(*pos + len)..(*pos + len + 2)] != b"\r\n" {
+                return Err(anyhow!("ERR Protocol error: bulk string is not terminated by CRLF"));
+            }
+
+            // The payload is taken verbatim, so it can hold any byte sequence.
+            self.argv.push(buf[*pos..(*pos + len)].to_vec());
+            *pos += len + 2;
+            self.bulk_len = None;
+            self.multibulk_len -= 1;
+            if self.multibulk_len == 0 {
+                requests.push(std::mem::take(&mut self.argv));
+            }
+        }
+    }
+}
+
+/// Reads the `*<count>` or `$<len>` line at the start of `data`, whose type
+/// byte must be `prefix`. Returns `Ok(None)` if it is not complete yet.
+fn read_request_line<'a>(
+    data: &'a [u8],
+    prefix: u8,
+    what: &str,
+) -> anyhow::Result<Option<(usize, &'a [u8])>> {
+    let Some(&first) = data.first() else {
+        return Ok(None);
+    };
+    if first != prefix {
+        return Err(anyhow!(
+            "ERR Protocol error: expected '{}', got '{}'",
+            prefix as char,
+            first.escape_ascii()
+        ));
+    }
+
+    // Only look that far for the CRLF, or a line that never ends would be
+    // scanned again on every read
+    let line = read_line(&data[..data.len().min(PROTO_INLINE_MAX_SIZE)]);
+    if line.is_none() && data.len() >= PROTO_INLINE_MAX_SIZE {
+        return Err(anyhow!("ERR Protocol error: too big {} string", what));
+    }
+
+    return Ok(line);
 }
 
+/// Decodes the first frame in `data`, returning how many bytes it spans.
+/// Returns `Ok(None)` when `data` ends before the frame is complete.
 pub fn decode_one(data: &[u8]) -> Result {
-    if data.len() == 0 {
-        return Err(anyhow!("No data"));
+    if data.is_empty() {
+        return Ok(None);
     }
 
     return match data[0] {
@@ -97,6 +349,14 @@ pub fn decode_one(data: &[u8]) -> Result {
         b':' => read_i64(data),
         b'$' => read_bulk_string(data),
         b'*' => read_array(data),
+        b'_' => read_null(data),
+        b'#' => read_boolean(data),
+        b',' => read_double(data),
+        b'(' => read_big_number(data),
+        b'=' => read_verbatim_string(data),
+        b'%' => read_map(data),
+        b'~' => read_set(data),
+        b'>' => read_push(data),
         _ => {
             println!("possible cross protocol scripting attack detected");
             return Err(anyhow!("possible cross protocol scripting attack detected"));
@@ -104,17 +364,19 @@ pub fn decode_one(data: &[u8]) -> Result {
     };
 }
 
+/// Decodes every frame in `data`, which must end with a complete one.
+#[allow(dead_code)]
 pub fn decode(data: &[u8]) -> anyhow::Result<Vec<Value>> {
-    if data.len() == 0 {
+    if data.is_empty() {
         return Err(anyhow!("No data"));
     }
 
-    // Divided by 4 because a command represented by, at least, 4 bytes of data.
-    let mut values = Vec::<Value>::with_capacity(data.len() / 4);
-
+    let mut values = Vec::<Value>::new();
     let mut index = 0;
     while index < data.len() {
-        let (delta, value) = decode_one(&data[index..])?;
+        let Some((delta, value)) = decode_one(&data[index..])? else {
+            return Err(anyhow!("Incomplete frame"));
+        };
         index += delta;
         values.push(value);
     }
@@ -122,34 +384,162 @@ pub fn decode(data: &[u8]) -> anyhow::Result<Vec<Value>> {
     return Ok(values);
 }
 
-fn format_string(s: String) -> String {
-    return format!("${0}\r\n{1}\r\n", s.len(), s);
+fn format_string(s: &[u8]) -> Vec<u8> {
+    let mut buf = format!("${}\r\n", s.len()).into_bytes();
+    buf.extend_from_slice(s);
+    buf.extend_from_slice(b"\r\n");
+
+    return buf;
 }
 
+fn format_aggregate(prefix: u8, len: usize) -> Vec<u8> {
+    let mut buf = vec![prefix];
+    buf.extend_from_slice(format!("{}\r\n", len).as_bytes());
+
+    return buf;
+}
+
+/// Encodes a reply for a RESP2 connection.
 pub fn encode(value: Value, simple: bool) -> Vec<u8> {
+    return encode_proto(value, simple, RESP2);
+}
+
+/// Encodes a reply for a connection speaking `protover`. RESP3-only types
+/// are downgraded when `protover` is 2.
+pub fn encode_proto(value: Value, simple: bool, protover: u8) -> Vec<u8> {
+    let resp3 = protover >= RESP3;
+
     return match value {
         Value::String(s) => {
             if simple {
-                return format!("+{}\r\n", s).into_bytes();
+                let mut buf = Vec::with_capacity(s.len() + 3);
+                buf.push(b'+');
+                buf.extend_from_slice(&s);
+                buf.extend_from_slice(b"\r\n");
+                return buf;
             }
 
-            return format_string(s).into_bytes();
+            format_string(&s)
         }
         Value::Int64(i) => format!(":{}\r\n", i).into_bytes(),
         Value::Int32(i) => format!(":{}\r\n", i).into_bytes(),
-        Value::VectorString(vs) => format!(
-            "*{0}\r\n{1}",
-            vs.len(),
-            vs.iter()
-                .map(|s| format_string(s.to_string()))
-                .collect::<Vec<String>>()
-                .join("")
-        )
-        .into_bytes(),
-        _ => RESP_NIL.into(),
+        Value::VectorString(vs) => {
+            let mut buf = format_aggregate(b'*', vs.len());
+            for s in vs.iter() {
+                buf.extend(format_string(s));
+            }
+
+            buf
+        }
+        Value::Vector(vs) => {
+            let mut buf = format_aggregate(b'*', vs.len());
+            for v in vs {
+                buf.extend(encode_proto(v, false, protover));
+            }
+
+            buf
+        }
+        Value::Empty => nil(protover),
+        Value::Map(pairs) => {
+            let mut buf = if resp3 {
+                format_aggregate(b'%', pairs.len())
+            } else {
+                format_aggregate(b'*', pairs.len() * 2)
+            };
+            for (k, v) in pairs {
+                buf.extend(encode_proto(k, false, protover));
+                buf.extend(encode_proto(v, false, protover));
+            }
+
+            buf
+        }
+        Value::Set(vs) => {
+            let mut buf = format_aggregate(if resp3 { b'~' } else { b'*' }, vs.len());
+            for v in vs {
+                buf.extend(encode_proto(v, false, protover));
+            }
+
+            buf
+        }
+        Value::Push(vs) => {
+            let mut buf = format_aggregate(if resp3 { b'>' } else { b'*' }, vs.len());
+            for v in vs {
+                buf.extend(encode_proto(v, false, protover));
+            }
+
+            buf
+        }
+        Value::Double(d) => {
+            if resp3 {
+                format!(",{}\r\n", format_double(d)).into_bytes()
+            } else {
+                format_string(format_double(d).as_bytes())
+            }
+        }
+        Value::Boolean(b) => match (resp3, b) {
+            (true, true) => b"#t\r\n".to_vec(),
+            (true, false) => b"#f\r\n".to_vec(),
+            (false, true) => RESP_ONE.to_vec(),
+            (false, false) => RESP_ZERO.to_vec(),
+        },
+        Value::BigNumber(n) => {
+            if resp3 {
+                let mut buf = vec![b'('];
+                buf.extend_from_slice(&n);
+                buf.extend_from_slice(b"\r\n");
+                buf
+            } else {
+                format_string(&n)
+            }
+        }
+        Value::Verbatim(format, s) => {
+            if resp3 {
+                let mut buf = format!("={}\r\n{}:", s.len() + 4, format).into_bytes();
+                buf.extend_from_slice(&s);
+                buf.extend_from_slice(b"\r\n");
+                buf
+            } else {
+                format_string(&s)
+            }
+        }
+    };
+}
+
+/// Null reply in the encoding of `protover`.
+pub fn nil(protover: u8) -> Vec<u8> {
+    return if protover >= RESP3 {
+        RESP3_NULL.to_vec()
+    } else {
+        RESP_NIL.to_vec()
+    };
+}
+
+/// Null array reply in the encoding of `protover`.
+pub fn nil_array(protover: u8) -> Vec<u8> {
+    return if protover >= RESP3 {
+        RESP3_NULL.to_vec()
+    } else {
+        b"*-1\r\n".to_vec()
     };
 }
 
+/// Header of an array whose `len` elements are encoded separately, e.g. the
+/// replies of the commands of a transaction.
+pub fn array_header(len: usize) -> Vec<u8> {
+    return format_aggregate(b'*', len);
+}
+
+/// Reply to the HELP subcommand of container commands, one status line per
+/// entry.
+pub fn encode_help(lines: &[&str]) -> Vec<u8> {
+    let mut reply = format_aggregate(b'*', lines.len());
+    for line in lines {
+        reply.extend(encode(Value::String(line.as_bytes().to_vec()), true));
+    }
+
+    return reply;
+}
+
 pub fn encode_error(error: anyhow::Error) -> Vec<u8> {
     return format!("-{}\r\n", error).into_bytes();
 }
@@ -163,7 +553,7 @@ mod tests {
     #[test]
     fn test_simple_string_decode() {
         let cases: HashMap<String, Vec<Value>> =
-            HashMap::from([("+OK\r\n".to_owned(), vec![Value::String("OK".to_owned())])]);
+            HashMap::from([("+OK\r\n".to_owned(), vec![Value::String(b"OK".to_vec())])]);
 
         for (k, v) in cases.into_iter() {
             let data = decode(k.as_bytes()).unwrap();
@@ -175,7 +565,7 @@ mod tests {
     fn test_error() {
         let cases: HashMap<String, Vec<Value>> = HashMap::from([(
             "-Error message\r\n".to_owned(),
-            vec![Value::String("Error message".to_owned())],
+            vec![Value::String(b"Error message".to_vec())],
         )]);
 
         for (k, v) in cases.into_iter() {
@@ -204,8 +594,8 @@ mod tests {
             (
                 "*2\r\n$5\r\nhello\r\n$5\r\nworld\r\n".to_owned(),
                 vec![Value::Vector(vec![
-                    Value::String("hello".to_owned()),
-                    Value::String("world".to_owned()),
+                    Value::String(b"hello".to_vec()),
+                    Value::String(b"world".to_vec()),
                 ])],
             ),
             (
@@ -221,8 +611,8 @@ mod tests {
                 vec![Value::Vector(vec![
                     Value::Vector(vec![Value::Int64(1), Value::Int64(2), Value::Int64(3)]),
                     Value::Vector(vec![
-                        Value::String("Hello".to_owned()),
-                        Value::String("World".to_owned()),
+                        Value::String(b"Hello".to_vec()),
+                        Value::String(b"World".to_vec()),
                     ]),
                 ])],
             ),
@@ -233,4 +623,116 @@ mod tests {
             assert_eq!(data, v);
         }
     }
+
+    #[test]
+    fn test_binary_bulk_string() {
+        let data = b"$5\r\n\x00\xff\r\n\x80\r\n$-1\r\n";
+        let values = decode(data).unwrap();
+        assert_eq!(
+            values,
+            vec![Value::String(b"\x00\xff\r\n\x80".to_vec()), Value::Empty]
+        );
+
+        let encoded = encode(Value::String(b"\x00\xff\r\n\x80".to_vec()), false);
+        assert_eq!(encoded, b"$5\r\n\x00\xff\r\n\x80\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_negative_int_64() {
+        let data = decode(b":-42\r\n").unwrap();
+        assert_eq!(data, vec![Value::Int64(-42)]);
+    }
+
+    #[test]
+    fn test_split_requests() {
+        let frame = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n";
+        let get = vec![b"GET".to_vec(), b"key".to_vec()];
+
+        for cut in 0..frame.len() {
+            let mut parser = RequestParser::default();
+            let mut buf = frame[..cut].to_vec();
+            assert!(parser.parse(&mut buf).unwrap().is_empty());
+            buf.extend_from_slice(&frame[cut..]);
+            assert_eq!(parser.parse(&mut buf).unwrap(), vec![get.clone()]);
+            assert!(buf.is_empty());
+        }
+
+        // Bulk strings read so far are kept by the parser, not the buffer
+        let mut parser = RequestParser::default();
+        let mut buf = frame.to_vec();
+        buf.extend_from_slice(b"*0\r\n*2\r\n$4\r\nECHO\r\n$2\r\nh");
+        assert_eq!(parser.parse(&mut buf).unwrap(), vec![get]);
+        assert_eq!(buf, b"h");
+        buf.extend_from_slice(b"i\r\n");
+        assert_eq!(
+            parser.parse(&mut buf).unwrap(),
+            vec![vec![b"ECHO".to_vec(), b"hi".to_vec()]]
+        );
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let invalid: [&[u8]; 6] = [
+            b"$3\r\nfoo\r\n",
+            b"*1\r\n$abc\r\n",
+            b"*1\r\n$-1\r\n",
+            b"*1\r\n$3\r\nfooXX",
+            b"*1\r\n:1\r\n",
+            b"*1\r\n%1\r\n",
+        ];
+        for data in invalid {
+            assert!(RequestParser::default().parse(&mut data.to_vec()).is_err());
+        }
+
+        let mut line = b"*".to_vec();
+        line.resize(PROTO_INLINE_MAX_SIZE, b'1');
+        assert!(RequestParser::default().parse(&mut line).is_err());
+    }
+
+    #[test]
+    fn test_nested_requests() {
+        // Would recurse once per level with a frame decoder
+        let data = b"*1\r\n".repeat(200_000);
+        let err = RequestParser::default().parse(&mut data.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "ERR Protocol error: expected '$', got '*'");
+    }
+
+    #[test]
+    fn test_resp3_encode() {
+        let map = Value::Map(vec![(
+            Value::String(b"proto".to_vec()),
+            Value::Int64(3),
+        )]);
+        assert_eq!(
+            encode_proto(map.clone(), false, RESP3),
+            b"%1\r\n$5\r\nproto\r\n:3\r\n".to_vec()
+        );
+        assert_eq!(
+            encode_proto(map, false, RESP2),
+            b"*2\r\n$5\r\nproto\r\n:3\r\n".to_vec()
+        );
+
+        assert_eq!(encode_proto(Value::Empty, false, RESP3), b"_\r\n".to_vec());
+        assert_eq!(encode_proto(Value::Empty, false, RESP2), b"$-1\r\n".to_vec());
+        assert_eq!(encode_proto(Value::Double(1.5), false, RESP3), b",1.5\r\n".to_vec());
+        assert_eq!(encode_proto(Value::Double(1.5), false, RESP2), b"$3\r\n1.5\r\n".to_vec());
+        assert_eq!(encode_proto(Value::Boolean(true), false, RESP3), b"#t\r\n".to_vec());
+        assert_eq!(encode_proto(Value::Boolean(true), false, RESP2), b":1\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_resp3_round_trip() {
+        let values = vec![
+            Value::Map(vec![(Value::String(b"a".to_vec()), Value::Double(f64::INFINITY))]),
+            Value::Set(vec![Value::Int64(1), Value::Boolean(false)]),
+            Value::Push(vec![Value::String(b"message".to_vec()), Value::Empty]),
+            Value::BigNumber(b"3492890328409238509324850943850943825024385".to_vec()),
+            Value::Verbatim("txt".to_owned(), b"Some string".to_vec()),
+        ];
+
+        for v in values {
+            let data = decode(&encode_proto(v.clone(), false, RESP3)).unwrap();
+            assert_eq!(data, vec![v]);
+        }
+    }
 }
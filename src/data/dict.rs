@@ -0,0 +1,286 @@
+use std::{
+    borrow::Borrow,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    mem,
+};
+
+use crate::common::random_u64;
+
+const INITIAL_SIZE: usize = 4;
+
+/// Chained hash table with a power of two number of buckets, modeled after
+/// Redis' dict. Unlike `HashMap` it can hand out random entries cheaply,
+/// which approximated eviction and the like are built on.
+#[derive(Clone)]
+pub struct Dict<K, V> {
+    buckets: Vec<Vec<(K, V)>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> Dict<K, V> {
+    pub fn new() -> Dict<K, V> {
+        return Dict {
+            buckets: Vec::new(),
+            len: 0,
+            hasher: RandomState::new(),
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    fn bucket_of<Q>(&self, k: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        return self.hasher.hash_one(k) as usize & (self.buckets.len() - 1);
+    }
+
+    fn resize(&mut self, size: usize) {
+        let old = mem::replace(&mut self.buckets, (0..size).map(|_| Vec::new()).collect());
+        for (k, v) in old.into_iter().flatten() {
+            let i = self.bucket_of(&k);
+            self.buckets[i].push((k, v));
+        }
+    }
+
+    /// Grows when there are more entries than buckets, shrinks when the
+    /// table is less than 1/8 full.
+    fn resize_if_needed(&mut self) {
+        if self.buckets.is_empty() {
+            self.resize(INITIAL_SIZE);
+        } else if self.len > self.buckets.len() {
+            self.resize(self.buckets.len() * 2);
+        } else if self.buckets.len() > INITIAL_SIZE && self.len < self.buckets.len() / 8 {
+            self.resize(self.len.next_power_of_two().max(INITIAL_SIZE));
+        }
+    }
+
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+
+        return self.buckets[self.bucket_of(k)]
+            .iter()
+            .find(|(key, _)| key.borrow() == k)
+            .map(|(_, v)| v);
+    }
+
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+
+        let i = self.bucket_of(k);
+        return self.buckets[i]
+            .iter_mut()
+            .find(|(key, _)| key.borrow() == k)
+            .map(|(_, v)| v);
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        return self.get(k).is_some();
+    }
+
+    /// Inserts `v` under `k`, returning the value it replaced.
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(existing) = self.get_mut(&k) {
+            return Some(mem::replace(existing, v));
+        }
+
+        self.len += 1;
+        self.resize_if_needed();
+        let i = self.bucket_of(&k);
+        self.buckets[i].push((k, v));
+
+        return None;
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if self.len == 0 {
+            return None;
+        }
+
+        let i = self.bucket_of(k);
+        let pos = self.buckets[i]
+            .iter()
+            .position(|(key, _)| key.borrow() == k)?;
+        let (_, v) = self.buckets[i].swap_remove(pos);
+        self.len -= 1;
+        self.resize_if_needed();
+
+        return Some(v);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        return self.buckets.iter().flatten().map(|(k, v)| (k, v));
+    }
+
+    /// Calls `f` on the entries of one bucket and returns the cursor to pass
+    /// to get the next one, 0 once the whole table was visited. Every entry
+    /// present for the whole scan is seen at least once even if the table is
+    /// resized in between, because the cursor walks bucket indexes with
+    /// their bits reversed: the buckets a resize merges or splits are
+    /// visited together.
+    pub fn scan(&self, cursor: u64, mut f: impl FnMut(&K, &V)) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+
+        let mask = (self.buckets.len() - 1) as u64;
+        for (k, v) in self.buckets[(cursor & mask) as usize].iter() {
+            f(k, v);
+        }
+
+        // Increment the reversed cursor
+        let cursor = cursor | !mask;
+        return cursor.reverse_bits().wrapping_add(1).reverse_bits();
+    }
+
+    /// A random entry. Entries in crowded buckets are slightly less likely to
+    /// be picked, which is fine for sampling.
+    pub fn random_entry(&self) -> Option<(&K, &V)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mask = self.buckets.len() - 1;
+        loop {
+            let bucket = &self.buckets[random_u64() as usize & mask];
+            if !bucket.is_empty() {
+                let (k, v) = &bucket[random_u64() as usize % bucket.len()];
+                return Some((k, v));
+            }
+        }
+    }
+
+    /// Up to `count` distinct entries picked from a random spot in the table.
+    /// Much cheaper than `count` calls to `random_entry`, at the price of
+    /// entries close to each other being returned together.
+    pub fn sample(&self, count: usize) -> Vec<(&K, &V)> {
+        let count = count.min(self.len);
+        let mut entries = Vec::with_capacity(count);
+        if count == 0 {
+            return entries;
+        }
+
+        let mask = self.buckets.len() - 1;
+        let mut i = random_u64() as usize & mask;
+        while entries.len() < count {
+            for (k, v) in self.buckets[i].iter() {
+                if entries.len() == count {
+                    break;
+                }
+                entries.push((k, v));
+            }
+            i = (i + 1) & mask;
+        }
+
+        return entries;
+    }
+}
+
+impl<K: Hash + Eq, V> Default for Dict<K, V> {
+    fn default() -> Self {
+        return Dict::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_get_remove_across_resizes() {
+        let mut d = Dict::new();
+        for i in 0..1000 {
+            assert_eq!(d.insert(i, i * 2), None);
+        }
+        assert_eq!(d.insert(7, 0), Some(14));
+        assert_eq!(d.len(), 1000);
+        assert_eq!(d.get(&999), Some(&1998));
+
+        for i in 0..990 {
+            assert!(d.remove(&i).is_some());
+        }
+        assert_eq!(d.remove(&0), None);
+        assert_eq!(d.len(), 10);
+        assert!(d.buckets.len() <= 16);
+        assert_eq!(d.iter().count(), 10);
+        assert!(d.contains_key(&995));
+    }
+
+    #[test]
+    fn test_scan_survives_resizes() {
+        let mut d = Dict::new();
+        for i in 0..100 {
+            d.insert(i, ());
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        let mut steps = 0;
+        loop {
+            cursor = d.scan(cursor, |k, _| seen.push(*k));
+            steps += 1;
+            if steps == 10 {
+                for i in 100..1000 {
+                    d.insert(i, ());
+                }
+            }
+            if cursor == 0 {
+                break;
+            }
+        }
+
+        seen.sort();
+        seen.dedup();
+        assert!((0..100).all(|i| seen.binary_search(&i).is_ok()));
+    }
+
+    #[test]
+    fn test_sample_returns_distinct_entries() {
+        let mut d = Dict::new();
+        assert!(d.random_entry().is_none());
+        assert!(d.sample(5).is_empty());
+
+        for i in 0..100 {
+            d.insert(i, ());
+        }
+        let mut sampled = d
+            .sample(20)
+            .into_iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>();
+        sampled.sort();
+        sampled.dedup();
+        assert_eq!(sampled.len(), 20);
+        assert_eq!(d.sample(500).len(), 100);
+        assert!(d.random_entry().is_some());
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    common::{parse_i64, random_u64},
+    data::{dict::Dict, listpack::Listpack},
+};
+
+/// When a hash stops being a listpack, in the format of Redis'
+/// `hash-max-listpack-entries` and `hash-max-listpack-value`.
+#[derive(Clone, Copy, Debug)]
+pub struct HashLimits {
+    pub max_entries: usize,
+    pub max_value: usize,
+}
+
+/// Field expiries are absolute unix times in milliseconds, -1 when the field
+/// has none, like `StoreObject::expires_at`.
+#[derive(Clone)]
+pub enum Hash {
+    /// Fields and values one after the other, for small hashes.
+    Listpack(Listpack),
+    /// Like `Listpack`, with the expiry of each field after its value. A
+    /// small hash switches to it once one of its fields is given a TTL.
+    ListpackEx(Listpack),
+    Table {
+        /// Value and expiry of each field.
+        fields: Dict<Vec<u8>, (Vec<u8>, i64)>,
+        /// Fields that have an expiry, soonest first.
+        deadlines: BTreeSet<(i64, Vec<u8>)>,
+    },
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        return Hash::new();
+    }
+}
+
+impl Hash {
+    pub fn new() -> Hash {
+        return Hash::Listpack(Listpack::new());
+    }
+
+    /// Number of listpack entries per field.
+    fn stride(&self) -> usize {
+        return match self {
+            Hash::ListpackEx(_) => 3,
+            _ => 2,
+        };
+    }
+
+    /// Number of fields, including expired ones `remove_expired` didn't get
+    /// to yet.
+    pub fn len(&self) -> usize {
+        return match self {
+            Hash::Listpack(lp) | Hash::ListpackEx(lp) => lp.len() / self.stride(),
+            Hash::Table { fields, .. } => fields.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Position of the entry holding `field` in a listpack encoded hash.
+    fn position(lp: &Listpack, field: &[u8], stride: usize) -> Option<usize> {
+        return lp
+            .iter()
+            .step_by(stride)
+            .position(|f| f == field)
+            .map(|i| i * stride);
+    }
+
+    fn listpack_expiry(lp: &Listpack, i: usize) -> i64 {
+        return lp.get(i + 2).and_then(parse_i64).unwrap_or(-1);
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&[u8]> {
+        return match self {
+            Hash::Listpack(lp) | Hash::ListpackEx(lp) => {
+                Hash::position(lp, field, self.stride()).and_then(|i| lp.get(i + 1))
+            }
+            Hash::Table { fields, .. } => fields.get(field).map(|(v, _)| v.as_slice()),
+        };
+    }
+
+    pub fn contains(&self, field: &[u8]) -> bool {
+        return self.get(field).is_some();
+    }
+
+    /// Expiry of `field`, `None` if there is no such field.
+    pub fn expires_at(&self, field: &[u8]) -> Option<i64> {
+        return match self {
+            Hash::Listpack(lp) => Hash::position(lp, field, 2).map(|_| -1),
+            Hash::ListpackEx(lp) => {
+                Hash::position(lp, field, 3).map(|i| Hash::listpack_expiry(lp, i))
+            }
+            Hash::Table { fields, .. } => fields.get(field).map(|(_, at)| *at),
+        };
+    }
+
+    /// Sets `field` to `value` and drops its expiry, like HSET does. Returns
+    /// true if the field is new. Like in Redis, a hash converted to a table
+    /// never goes back to a listpack.
+    pub fn set(&mut self, field: &[u8], value: &[u8], limits: HashLimits) -> bool {
+        return self.set_generic(field, value, false, limits);
+    }
+
+    /// Like `set`, keeping the expiry of an existing field, for updates such
+    /// as HINCRBY.
+    pub fn set_keep_ttl(&mut self, field: &[u8], value: &[u8], limits: HashLimits) -> bool {
+        return self.set_generic(field, value, true, limits);
+    }
+
+    fn set_generic(
+        &mut self,
+        field: &[u8],
+        value: &[u8],
+        keep_ttl: bool,
+        limits: HashLimits,
+    ) -> bool {
+        if field.len() > limits.max_value || value.len() > limits.max_value {
+            self.convert();
+        }
+
+        let stride = self.stride();
+        let added = match self {
+            Hash::Listpack(lp) | Hash::ListpackEx(lp) => match Hash::position(lp, field, stride) {
+                Some(i) => {
+                    lp.replace(i + 1, value);
+                    if stride == 3 && !keep_ttl {
+                        lp.replace(i + 2, b"-1");
+                    }
+                    false
+                }
+                None => {
+                    lp.push_back(field);
+                    lp.push_back(value);
+                    if stride == 3 {
+                        lp.push_back(b"-1");
+                    }
+                    true
+                }
+            },
+            Hash::Table { fields, deadlines } => match fields.get_mut(field) {
+                Some((v, at)) => {
+                    *v = value.to_vec();
+                    if !keep_ttl && *at != -1 {
+                        deadlines.remove(&(*at, field.to_vec()));
+                        *at = -1;
+                    }
+                    false
+                }
+                None => {
+                    fields.insert(field.to_vec(), (value.to_vec(), -1));
+                    true
+                }
+            },
+        };
+
+        if self.len() > limits.max_entries {
+            self.convert();
+        }
+
+        return added;
+    }
+
+    /// Sets the expiry of an existing field, -1 removes it. Returns false if
+    /// there is no such field.
+    pub fn set_expiry(&mut self, field: &[u8], expires_at: i64) -> bool {
+        if let Hash::Listpack(lp) = self {
+            if expires_at == -1 {
+                return Hash::position(lp, field, 2).is_some();
+            }
+
+            // Make room for the expiries
+            let mut triplets = Listpack::new();
+            for (i, entry) in lp.iter().enumerate() {
+                triplets.push_back(entry);
+                if i % 2 == 1 {
+                    triplets.push_back(b"-1");
+                }
+            }
+            *self = Hash::ListpackEx(triplets);
+        }
+
+        return match self {
+            Hash::Listpack(_) => unreachable!("listpack converted above"),
+            Hash::ListpackEx(lp) => match Hash::position(lp, field, 3) {
+                Some(i) => lp.replace(i + 2, expires_at.to_string().as_bytes()),
+                None => false,
+            },
+            Hash::Table { fields, deadlines } => {
+                let Some((_, at)) = fields.get_mut(field) else {
+                    return false;
+                };
+                if *at != -1 {
+                    deadlines.remove(&(*at, field.to_vec()));
+                }
+                if expires_at != -1 {
+                    deadlines.insert((expires_at, field.to_vec()));
+                }
+                *at = expires_at;
+                true
+            }
+        };
+    }
+
+    /// Whether some fields have an expiry.
+    pub fn has_volatile_fields(&self) -> bool {
+        return match self {
+            Hash::Listpack(_) => false,
+            Hash::ListpackEx(lp) => (0..lp.len())
+                .step_by(3)
+                .any(|i| Hash::listpack_expiry(lp, i) != -1),
+            Hash::Table { deadlines, .. } => !deadlines.is_empty(),
+        };
+    }
+
+    /// Removes `field`, returns true if it was there.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        let stride = self.stride();
+        return match self {
+            Hash::Listpack(lp) | Hash::ListpackEx(lp) => match Hash::position(lp, field, stride) {
+                Some(i) => {
+                    for _ in 0..stride {
+                        lp.remove(i);
+                    }
+                    true
+                }
+                None => false,
+            },
+            Hash::Table { fields, deadlines } => match fields.remove(field) {
+                Some((_, at)) => {
+                    if at != -1 {
+                        deadlines.remove(&(at, field.to_vec()));
+                    }
+                    true
+                }
+                None => false,
+            },
+        };
+    }
+
+    /// Removes the fields that expired at `now`, returns how many.
+    pub fn remove_expired(&mut self, now: i64) -> usize {
+        return match self {
+            Hash::Listpack(_) => 0,
+            Hash::ListpackEx(lp) => {
+                let expired = (0..lp.len())
+                    .step_by(3)
+                    .filter(|&i| {
+                        let at = Hash::listpack_expiry(lp, i);
+                        at != -1 && at <= now
+                    })
+                    .collect::<Vec<_>>();
+                for &i in expired.iter().rev() {
+                    for _ in 0..3 {
+                        lp.remove(i);
+                    }
+                }
+                expired.len()
+            }
+            Hash::Table { fields, deadlines } => {
+                let mut removed = 0;
+                while deadlines.first().is_some_and(|(at, _)| *at <= now) {
+                    let (_, field) = deadlines.pop_first().unwrap();
+                    fields.remove(&field);
+                    removed += 1;
+                }
+                removed
+            }
+        };
+    }
+
+    fn convert(&mut self) {
+        if let Hash::Table { .. } = self {
+            return;
+        }
+
+        let mut fields = Dict::new();
+        let mut deadlines = BTreeSet::new();
+        for (field, value, at) in self.entries() {
+            fields.insert(field.to_vec(), (value.to_vec(), at));
+            if at != -1 {
+                deadlines.insert((at, field.to_vec()));
+            }
+        }
+        *self = Hash::Table { fields, deadlines };
+    }
+
+    /// Fields with their value and expiry.
+    pub fn entries(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8], i64)> + '_> {
+        return match self {
+            Hash::Listpack(lp) => {
+                let mut entries = lp.iter();
+                Box::new(std::iter::from_fn(move || {
+                    Some((entries.next()?, entries.next()?, -1))
+                }))
+            }
+            Hash::ListpackEx(lp) => {
+                let mut entries = lp.iter();
+                Box::new(std::iter::from_fn(move || {
+                    let (field, value) = (entries.next()?, entries.next()?);
+                    Some((field, value, parse_i64(entries.next()?).unwrap_or(-1)))
+                }))
+            }
+            Hash::Table { fields, .. } => Box::new(
+                fields
+                    .iter()
+                    .map(|(f, (v, at))| (f.as_slice(), v.as_slice(), *at)),
+            ),
+        };
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        return self.entries().map(|(field, value, _)| (field, value));
+    }
+
+    pub fn random_entry(&self) -> Option<(&[u8], &[u8])> {
+        return match self {
+            Hash::Listpack(lp) | Hash::ListpackEx(lp) => {
+                if lp.is_empty() {
+                    return None;
+                }
+                let i = (random_u64() as usize % self.len()) * self.stride();
+                Some((lp.get(i)?, lp.get(i + 1)?))
+            }
+            Hash::Table { fields, .. } => fields
+                .random_entry()
+                .map(|(f, (v, _))| (f.as_slice(), v.as_slice())),
+        };
+    }
+
+    /// Calls `f` on some of the entries and returns the cursor to resume
+    /// from, 0 when done. A listpack is small enough to be returned whole.
+    pub fn scan(&self, cursor: u64, mut f: impl FnMut(&[u8], &[u8])) -> u64 {
+        return match self {
+            Hash::Listpack(_) | Hash::ListpackEx(_) => {
+                for (field, value) in self.iter() {
+                    f(field, value);
+                }
+                0
+            }
+            Hash::Table { fields, .. } => fields.scan(cursor, |field, (value, _)| f(field, value)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: HashLimits = HashLimits {
+        max_entries: 2,
+        max_value: 8,
+    };
+
+    #[test]
+    fn test_converts_to_a_table_past_the_limits() {
+        let mut hash = Hash::new();
+        assert!(hash.set(b"a", b"1", LIMITS));
+        assert!(!hash.set(b"a", b"2", LIMITS));
+        assert!(hash.set(b"b", b"3", LIMITS));
+        assert!(matches!(hash, Hash::Listpack(_)));
+        assert_eq!(hash.get(b"a"), Some(&b"2"[..]));
+
+        assert!(hash.set(b"c", b"4", LIMITS));
+        assert!(matches!(hash, Hash::Table { .. }));
+        assert_eq!(hash.len(), 3);
+        assert_eq!(hash.get(b"b"), Some(&b"3"[..]));
+
+        let mut hash = Hash::new();
+        hash.set(b"a", b"a value too long", LIMITS);
+        assert!(matches!(hash, Hash::Table { .. }));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut hash = Hash::new();
+        hash.set(b"a", b"1", LIMITS);
+        hash.set(b"b", b"2", LIMITS);
+
+        assert!(hash.remove(b"a"));
+        assert!(!hash.remove(b"a"));
+        assert_eq!(
+            hash.iter().collect::<Vec<_>>(),
+            vec![(&b"b"[..], &b"2"[..])]
+        );
+    }
+
+    #[test]
+    fn test_field_expiry() {
+        let mut small = Hash::new();
+        small.set(b"a", b"1", LIMITS);
+        small.set(b"b", b"2", LIMITS);
+        let mut big = small.clone();
+        big.convert();
+
+        for hash in [&mut small, &mut big] {
+            assert!(!hash.set_expiry(b"x", 100));
+            assert!(hash.set_expiry(b"a", 100));
+            assert!(hash.set_expiry(b"b", 200));
+            assert!(hash.has_volatile_fields());
+            assert_eq!(hash.expires_at(b"a"), Some(100));
+
+            // HINCRBY keeps the TTL, HSET drops it
+            hash.set_keep_ttl(b"a", b"3", LIMITS);
+            assert_eq!(hash.expires_at(b"a"), Some(100));
+            hash.set(b"b", b"4", LIMITS);
+            assert_eq!(hash.expires_at(b"b"), Some(-1));
+
+            assert_eq!(hash.remove_expired(99), 0);
+            assert_eq!(hash.remove_expired(100), 1);
+            assert!(!hash.has_volatile_fields());
+            assert_eq!(
+                hash.iter().collect::<Vec<_>>(),
+                vec![(&b"b"[..], &b"4"[..])]
+            );
+        }
+        assert!(matches!(small, Hash::ListpackEx(_)));
+    }
+}
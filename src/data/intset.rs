@@ -0,0 +1,181 @@
+/// Sorted set of integers stored with the smallest width that fits all of
+/// them, like Redis' intset. Adding a wider integer upgrades every element.
+#[derive(Clone)]
+pub enum Intset {
+    I16(Vec<i16>),
+    I32(Vec<i32>),
+    I64(Vec<i64>),
+}
+
+impl Default for Intset {
+    fn default() -> Self {
+        return Intset::new();
+    }
+}
+
+/// Bytes needed to store `x`.
+fn width_of(x: i64) -> usize {
+    if i16::try_from(x).is_ok() {
+        return 2;
+    }
+    if i32::try_from(x).is_ok() {
+        return 4;
+    }
+    return 8;
+}
+
+impl Intset {
+    pub fn new() -> Intset {
+        return Intset::I16(Vec::new());
+    }
+
+    /// Bytes per element.
+    pub fn width(&self) -> usize {
+        return match self {
+            Intset::I16(_) => 2,
+            Intset::I32(_) => 4,
+            Intset::I64(_) => 8,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return match self {
+            Intset::I16(v) => v.len(),
+            Intset::I32(v) => v.len(),
+            Intset::I64(v) => v.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Bytes allocated for the elements.
+    pub fn capacity(&self) -> usize {
+        return match self {
+            Intset::I16(v) => v.capacity() * 2,
+            Intset::I32(v) => v.capacity() * 4,
+            Intset::I64(v) => v.capacity() * 8,
+        };
+    }
+
+    /// Position of `x`, or where it would be inserted. A value too wide for
+    /// the current encoding can't be there.
+    fn search(&self, x: i64) -> Result<usize, usize> {
+        return match self {
+            Intset::I16(v) => match i16::try_from(x) {
+                Ok(x) => v.binary_search(&x),
+                Err(_) => Err(if x < 0 { 0 } else { v.len() }),
+            },
+            Intset::I32(v) => match i32::try_from(x) {
+                Ok(x) => v.binary_search(&x),
+                Err(_) => Err(if x < 0 { 0 } else { v.len() }),
+            },
+            Intset::I64(v) => v.binary_search(&x),
+        };
+    }
+
+    pub fn contains(&self, x: i64) -> bool {
+        return self.search(x).is_ok();
+    }
+
+    pub fn get(&self, i: usize) -> Option<i64> {
+        return match self {
+            Intset::I16(v) => v.get(i).map(|x| *x as i64),
+            Intset::I32(v) => v.get(i).map(|x| *x as i64),
+            Intset::I64(v) => v.get(i).copied(),
+        };
+    }
+
+    fn upgrade_for(&mut self, x: i64) {
+        if width_of(x) <= self.width() {
+            return;
+        }
+
+        let all = self.iter().collect::<Vec<_>>();
+        *self = match width_of(x) {
+            4 => Intset::I32(all.into_iter().map(|x| x as i32).collect()),
+            _ => Intset::I64(all),
+        };
+    }
+
+    /// Adds `x`, returns false if it was already there.
+    pub fn insert(&mut self, x: i64) -> bool {
+        self.upgrade_for(x);
+        let Err(pos) = self.search(x) else {
+            return false;
+        };
+
+        // The encoding fits `x` after the upgrade
+        match self {
+            Intset::I16(v) => v.insert(pos, x as i16),
+            Intset::I32(v) => v.insert(pos, x as i32),
+            Intset::I64(v) => v.insert(pos, x),
+        }
+
+        return true;
+    }
+
+    /// Removes `x`, returns false if it wasn't there. Like in Redis, the
+    /// encoding is never downgraded.
+    pub fn remove(&mut self, x: i64) -> bool {
+        let Ok(pos) = self.search(x) else {
+            return false;
+        };
+
+        match self {
+            Intset::I16(v) => {
+                v.remove(pos);
+            }
+            Intset::I32(v) => {
+                v.remove(pos);
+            }
+            Intset::I64(v) => {
+                v.remove(pos);
+            }
+        }
+
+        return true;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = i64> + '_ {
+        return (0..self.len()).map(|i| self.get(i).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrades_to_fit_wider_integers() {
+        let mut set = Intset::new();
+        assert!(set.insert(5));
+        assert!(set.insert(-3));
+        assert!(!set.insert(5));
+        assert_eq!(set.width(), 2);
+
+        assert!(set.insert(100_000));
+        assert_eq!(set.width(), 4);
+        assert!(set.insert(i64::MIN));
+        assert_eq!(set.width(), 8);
+        assert_eq!(
+            set.iter().collect::<Vec<_>>(),
+            vec![i64::MIN, -3, 5, 100_000]
+        );
+
+        assert!(set.remove(100_000));
+        assert!(!set.remove(100_000));
+        assert!(!set.contains(100_000));
+        assert!(set.contains(-3));
+        assert_eq!(set.width(), 8);
+    }
+
+    #[test]
+    fn test_too_wide_values_are_not_found() {
+        let mut set = Intset::new();
+        set.insert(1);
+        assert!(!set.contains(1 << 40));
+        assert!(!set.remove(-(1 << 40)));
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::VecDeque;
+
+use crate::data::listpack::Listpack;
+
+/// Largest listpack allowed, in the format of Redis' `list-max-listpack-size`:
+/// a positive number of entries, or -1 to -5 for 4KB to 64KB.
+#[derive(Clone, Copy, Debug)]
+pub struct ListpackLimit(pub i64);
+
+impl ListpackLimit {
+    /// Whether `lp` may grow to `bytes` bytes and `len` entries. A single
+    /// entry is always allowed, however big.
+    fn allows(self, bytes: usize, len: usize) -> bool {
+        if len <= 1 {
+            return true;
+        }
+        if self.0 > 0 {
+            return len <= self.0 as usize;
+        }
+
+        let max_bytes = 4096 << (self.0.clamp(-5, -1).unsigned_abs() - 1);
+        return bytes <= max_bytes;
+    }
+
+    fn allows_push(self, lp: &Listpack, v: &[u8]) -> bool {
+        return self.allows(lp.bytes_with(v), lp.len() + 1);
+    }
+
+    /// A quicklist is turned back into a listpack once it fits in half the
+    /// limit, so that a list hovering around the limit isn't converted back
+    /// and forth.
+    fn allows_shrink(self, bytes: usize, len: usize) -> bool {
+        if self.0 > 0 {
+            return len <= self.0 as usize / 2;
+        }
+        return self.allows(bytes * 2, len);
+    }
+}
+
+/// Which end of a list to work on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ListEnd {
+    Head,
+    Tail,
+}
+
+/// Sequence of listpacks, for lists too big for a single one.
+#[derive(Clone, Default)]
+pub struct Quicklist {
+    nodes: VecDeque<Listpack>,
+    len: usize,
+}
+
+impl Quicklist {
+    /// Node holding the `i`th entry, and the index of the entry in it.
+    fn locate(&self, mut i: usize) -> Option<(usize, usize)> {
+        if i >= self.len {
+            return None;
+        }
+
+        if i < self.len / 2 {
+            for (n, node) in self.nodes.iter().enumerate() {
+                if i < node.len() {
+                    return Some((n, i));
+                }
+                i -= node.len();
+            }
+        } else {
+            let mut from_tail = self.len - 1 - i;
+            for (n, node) in self.nodes.iter().enumerate().rev() {
+                if from_tail < node.len() {
+                    return Some((n, node.len() - 1 - from_tail));
+                }
+                from_tail -= node.len();
+            }
+        }
+
+        return None;
+    }
+
+    fn push(&mut self, v: &[u8], end: ListEnd, limit: ListpackLimit) {
+        let node = match end {
+            ListEnd::Head => self.nodes.front_mut(),
+            ListEnd::Tail => self.nodes.back_mut(),
+        };
+
+        match node {
+            Some(node) if limit.allows_push(node, v) => match end {
+                ListEnd::Head => node.push_front(v),
+                ListEnd::Tail => node.push_back(v),
+            },
+            _ => {
+                let node = Listpack::from_iter([v]);
+                match end {
+                    ListEnd::Head => self.nodes.push_front(node),
+                    ListEnd::Tail => self.nodes.push_back(node),
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    fn remove(&mut self, i: usize) -> Option<Vec<u8>> {
+        let (n, i) = self.locate(i)?;
+        let v = self.nodes[n].remove(i);
+        if self.nodes[n].is_empty() {
+            self.nodes.remove(n);
+        }
+        self.len -= 1;
+
+        return v;
+    }
+
+    /// Splits node `n` in two if it grew past the limit.
+    fn split_if_needed(&mut self, n: usize, limit: ListpackLimit) {
+        let node = &mut self.nodes[n];
+        if limit.allows(node.bytes(), node.len()) {
+            return;
+        }
+
+        let tail = node.split_off(node.len() / 2);
+        self.nodes.insert(n + 1, tail);
+    }
+
+    fn insert(&mut self, i: usize, v: &[u8], limit: ListpackLimit) {
+        if i == self.len {
+            self.push(v, ListEnd::Tail, limit);
+            return;
+        }
+
+        let (n, i) = self.locate(i).expect("index is in range");
+        self.nodes[n].insert(i, v);
+        self.len += 1;
+        self.split_if_needed(n, limit);
+    }
+
+    fn replace(&mut self, i: usize, v: &[u8], limit: ListpackLimit) -> bool {
+        let Some((n, i)) = self.locate(i) else {
+            return false;
+        };
+
+        self.nodes[n].replace(i, v);
+        self.split_if_needed(n, limit);
+        return true;
+    }
+
+    fn bytes(&self) -> usize {
+        return self.nodes.iter().map(|n| n.bytes()).sum();
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Listpack> {
+        return self.nodes.iter();
+    }
+}
+
+/// List value: a single listpack while it is small, a quicklist once it
+/// outgrows `ListpackLimit`.
+#[derive(Clone)]
+pub enum List {
+    Listpack(Listpack),
+    Quicklist(Quicklist),
+}
+
+impl List {
+    pub fn new() -> List {
+        return List::Listpack(Listpack::new());
+    }
+
+    /// Builds a list out of `items`, in the smallest encoding that fits.
+    pub fn from_items<V: AsRef<[u8]>>(
+        items: impl IntoIterator<Item = V>,
+        limit: ListpackLimit,
+    ) -> List {
+        let mut list = List::new();
+        for v in items {
+            list.push(v.as_ref(), ListEnd::Tail, limit);
+        }
+        list.shrink_if_needed(limit);
+        return list;
+    }
+
+    pub fn len(&self) -> usize {
+        return match self {
+            List::Listpack(lp) => lp.len(),
+            List::Quicklist(ql) => ql.len,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    fn convert_to_quicklist(&mut self) {
+        if let List::Listpack(lp) = self {
+            let lp = std::mem::take(lp);
+            *self = List::Quicklist(Quicklist {
+                len: lp.len(),
+                nodes: VecDeque::from([lp]),
+            });
+        }
+    }
+
+    fn grow_if_needed(&mut self, v: &[u8], limit: ListpackLimit) {
+        if let List::Listpack(lp) = self {
+            if !limit.allows_push(lp, v) {
+                self.convert_to_quicklist();
+            }
+        }
+    }
+
+    fn shrink_if_needed(&mut self, limit: ListpackLimit) {
+        if let List::Quicklist(ql) = self {
+            if limit.allows_shrink(ql.bytes(), ql.len) {
+                *self = List::Listpack(Listpack::from_iter(ql.nodes.iter().flat_map(|n| n.iter())));
+            }
+        }
+    }
+
+    pub fn push(&mut self, v: &[u8], end: ListEnd, limit: ListpackLimit) {
+        self.grow_if_needed(v, limit);
+        match (self, end) {
+            (List::Listpack(lp), ListEnd::Head) => lp.push_front(v),
+            (List::Listpack(lp), ListEnd::Tail) => lp.push_back(v),
+            (List::Quicklist(ql), end) => ql.push(v, end, limit),
+        }
+    }
+
+    pub fn pop(&mut self, end: ListEnd, limit: ListpackLimit) -> Option<Vec<u8>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let i = match end {
+            ListEnd::Head => 0,
+            ListEnd::Tail => self.len() - 1,
+        };
+        return self.remove(i, limit);
+    }
+
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        return match self {
+            List::Listpack(lp) => lp.get(i),
+            List::Quicklist(ql) => {
+                let (n, i) = ql.locate(i)?;
+                ql.nodes[n].get(i)
+            }
+        };
+    }
+
+    /// Inserts `v` so that it becomes the `i`th entry, `i` may be `len()`.
+    pub fn insert(&mut self, i: usize, v: &[u8], limit: ListpackLimit) {
+        self.grow_if_needed(v, limit);
+        match self {
+            List::Listpack(lp) => lp.insert(i, v),
+            List::Quicklist(ql) => ql.insert(i, v, limit),
+        }
+    }
+
+    pub fn remove(&mut self, i: usize, limit: ListpackLimit) -> Option<Vec<u8>> {
+        let v = match self {
+            List::Listpack(lp) => lp.remove(i),
+            List::Quicklist(ql) => ql.remove(i),
+        };
+        self.shrink_if_needed(limit);
+        return v;
+    }
+
+    pub fn set(&mut self, i: usize, v: &[u8], limit: ListpackLimit) -> bool {
+        if let List::Listpack(lp) = self {
+            // Counting the old entry too overestimates a little, which only
+            // makes the conversion happen slightly early.
+            if !limit.allows(lp.bytes_with(v), lp.len()) {
+                self.convert_to_quicklist();
+            }
+        }
+
+        return match self {
+            List::Listpack(lp) => lp.replace(i, v),
+            List::Quicklist(ql) => ql.replace(i, v, limit),
+        };
+    }
+
+    /// Keeps only the entries for which `keep(index, entry)` is true.
+    pub fn retain(&mut self, mut keep: impl FnMut(usize, &[u8]) -> bool, limit: ListpackLimit) {
+        let kept = List::from_items(
+            self.iter()
+                .enumerate()
+                .filter(|(i, v)| keep(*i, v))
+                .map(|(_, v)| v),
+            limit,
+        );
+        *self = kept;
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        let inner: Box<dyn DoubleEndedIterator<Item = &[u8]>> = match self {
+            List::Listpack(lp) => Box::new(lp.iter()),
+            List::Quicklist(ql) => Box::new(ql.nodes.iter().flat_map(|n| n.iter())),
+        };
+
+        return Iter {
+            inner,
+            remaining: self.len(),
+        };
+    }
+}
+
+/// Iterator over the entries of a list, from head to tail.
+pub struct Iter<'a> {
+    inner: Box<dyn DoubleEndedIterator<Item = &'a [u8]> + 'a>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let v = self.inner.next()?;
+        self.remaining -= 1;
+        return Some(v);
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return (self.remaining, Some(self.remaining));
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let v = self.inner.next_back()?;
+        self.remaining -= 1;
+        return Some(v);
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(list: &List) -> Vec<Vec<u8>> {
+        return list.iter().map(|v| v.to_vec()).collect();
+    }
+
+    #[test]
+    fn test_converts_between_encodings() {
+        let limit = ListpackLimit(4);
+        let mut list = List::new();
+        for i in 0..10 {
+            list.push(i.to_string().as_bytes(), ListEnd::Tail, limit);
+        }
+        list.push(b"-1", ListEnd::Head, limit);
+        assert!(matches!(list, List::Quicklist(_)));
+        assert_eq!(list.len(), 11);
+        assert_eq!(list.get(0), Some(&b"-1"[..]));
+        assert_eq!(list.get(10), Some(&b"9"[..]));
+
+        list.insert(5, b"x", limit);
+        assert!(list.set(0, b"head", limit));
+        assert_eq!(list.iter().next_back(), Some(&b"9"[..]));
+        assert_eq!(
+            items(&list),
+            ["head", "0", "1", "2", "3", "x", "4", "5", "6", "7", "8", "9"]
+                .iter()
+                .map(|s| s.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        );
+
+        list.retain(|i, _| i < 3, limit);
+        assert!(matches!(list, List::Listpack(_)));
+        assert_eq!(list.pop(ListEnd::Tail, limit), Some(b"1".to_vec()));
+        assert_eq!(list.pop(ListEnd::Head, limit), Some(b"head".to_vec()));
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn test_byte_limit() {
+        let limit = ListpackLimit(-1);
+        let mut list = List::from_items([vec![b'x'; 3000]], limit);
+        assert!(matches!(list, List::Listpack(_)));
+
+        list.push(&[b'y'; 3000], ListEnd::Tail, limit);
+        assert!(matches!(list, List::Quicklist(_)));
+        list.pop(ListEnd::Tail, limit);
+        assert!(matches!(list, List::Listpack(_)));
+    }
+}
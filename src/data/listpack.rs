@@ -0,0 +1,294 @@
+/// Compact sequence of byte strings stored back to back in a single buffer,
+/// after Redis' listpack. Each entry is laid out as
+///
+/// ```text
+/// <len: LEB128> <payload> <backlen>
+/// ```
+///
+/// where `backlen` is the size of the first two parts written so that it can
+/// be read from its last byte, which makes the buffer walkable from both
+/// ends.
+#[derive(Clone, Default)]
+pub struct Listpack {
+    data: Vec<u8>,
+    len: usize,
+}
+
+fn leb128_len(mut n: usize) -> usize {
+    let mut size = 1;
+    while n >= 0x80 {
+        n >>= 7;
+        size += 1;
+    }
+    return size;
+}
+
+fn write_leb128(buf: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+/// Reads a LEB128 number at the start of `buf`, returns it along with its
+/// size.
+fn read_leb128(buf: &[u8]) -> (usize, usize) {
+    let mut n = 0;
+    for (i, &byte) in buf.iter().enumerate() {
+        n |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (n, i + 1);
+        }
+    }
+    unreachable!("listpack entry is corrupted");
+}
+
+/// Same as LEB128, but the first byte is the last one in memory.
+fn write_backlen(buf: &mut Vec<u8>, n: usize) {
+    let start = buf.len();
+    write_leb128(buf, n);
+    buf[start..].reverse();
+}
+
+/// Reads the backlen that ends right before `end`, returns it along with its
+/// size.
+fn read_backlen(buf: &[u8], end: usize) -> (usize, usize) {
+    let mut n = 0;
+    let mut i = 0;
+    loop {
+        let byte = buf[end - 1 - i];
+        n |= ((byte & 0x7f) as usize) << (7 * i);
+        i += 1;
+        if byte & 0x80 == 0 {
+            return (n, i);
+        }
+    }
+}
+
+fn encode_entry(v: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(entry_size(v.len()));
+    write_leb128(&mut entry, v.len());
+    entry.extend_from_slice(v);
+    let backlen = entry.len();
+    write_backlen(&mut entry, backlen);
+    return entry;
+}
+
+/// Bytes taken by an entry holding a payload of `len` bytes.
+fn entry_size(len: usize) -> usize {
+    let body = leb128_len(len) + len;
+    return body + leb128_len(body);
+}
+
+impl Listpack {
+    pub fn new() -> Listpack {
+        return Listpack::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+
+    /// Size of the encoded entries.
+    pub fn bytes(&self) -> usize {
+        return self.data.len();
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.data.capacity();
+    }
+
+    /// Bytes the listpack would take with `v` added.
+    pub fn bytes_with(&self, v: &[u8]) -> usize {
+        return self.data.len() + entry_size(v.len());
+    }
+
+    /// Payload of the entry starting at `offset`, and the offset of the next
+    /// one.
+    fn entry_at(&self, offset: usize) -> (&[u8], usize) {
+        let (len, header) = read_leb128(&self.data[offset..]);
+        let start = offset + header;
+        let backlen = leb128_len(header + len);
+        return (&self.data[start..start + len], start + len + backlen);
+    }
+
+    /// Offset of the entry ending at `end`.
+    fn entry_before(&self, end: usize) -> usize {
+        let (body, backlen) = read_backlen(&self.data, end);
+        return end - backlen - body;
+    }
+
+    /// Offset of the `i`th entry, walking from whichever end is closer.
+    fn offset_of(&self, i: usize) -> usize {
+        if i <= self.len / 2 {
+            let mut offset = 0;
+            for _ in 0..i {
+                offset = self.entry_at(offset).1;
+            }
+            return offset;
+        }
+
+        let mut offset = self.data.len();
+        for _ in i..self.len {
+            offset = self.entry_before(offset);
+        }
+        return offset;
+    }
+
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        if i >= self.len {
+            return None;
+        }
+
+        return Some(self.entry_at(self.offset_of(i)).0);
+    }
+
+    /// Inserts `v` so that it becomes the `i`th entry, `i` may be `len()`.
+    pub fn insert(&mut self, i: usize, v: &[u8]) {
+        let offset = if i == self.len {
+            self.data.len()
+        } else {
+            self.offset_of(i)
+        };
+        self.data.splice(offset..offset, encode_entry(v));
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, v: &[u8]) {
+        self.insert(self.len, v);
+    }
+
+    pub fn push_front(&mut self, v: &[u8]) {
+        self.insert(0, v);
+    }
+
+    pub fn remove(&mut self, i: usize) -> Option<Vec<u8>> {
+        if i >= self.len {
+            return None;
+        }
+
+        let offset = self.offset_of(i);
+        let (v, next) = self.entry_at(offset);
+        let v = v.to_vec();
+        self.data.drain(offset..next);
+        self.len -= 1;
+
+        return Some(v);
+    }
+
+    pub fn replace(&mut self, i: usize, v: &[u8]) -> bool {
+        if i >= self.len {
+            return false;
+        }
+
+        let offset = self.offset_of(i);
+        let next = self.entry_at(offset).1;
+        self.data.splice(offset..next, encode_entry(v));
+
+        return true;
+    }
+
+    /// Splits off the entries from `i` on into a new listpack.
+    pub fn split_off(&mut self, i: usize) -> Listpack {
+        let offset = if i >= self.len {
+            self.data.len()
+        } else {
+            self.offset_of(i)
+        };
+        let tail = Listpack {
+            data: self.data.split_off(offset),
+            len: self.len.saturating_sub(i),
+        };
+        self.len -= tail.len;
+
+        return tail;
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        return Iter {
+            lp: self,
+            front: 0,
+            back: self.data.len(),
+        };
+    }
+}
+
+pub struct Iter<'a> {
+    lp: &'a Listpack,
+    front: usize,
+    back: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.front >= self.back {
+            return None;
+        }
+
+        let (v, next) = self.lp.entry_at(self.front);
+        self.front = next;
+        return Some(v);
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            return None;
+        }
+
+        self.back = self.lp.entry_before(self.back);
+        return Some(self.lp.entry_at(self.back).0);
+    }
+}
+
+impl<V: AsRef<[u8]>> FromIterator<V> for Listpack {
+    fn from_iter<T: IntoIterator<Item = V>>(iter: T) -> Self {
+        let mut lp = Listpack::new();
+        for v in iter {
+            lp.push_back(v.as_ref());
+        }
+        return lp;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walks_both_ways() {
+        let big = vec![b'x'; 300];
+        let mut lp = Listpack::new();
+        lp.push_back(b"b");
+        lp.push_front(b"a");
+        lp.push_back(&big);
+        lp.push_back(b"");
+        lp.insert(2, b"c");
+
+        assert_eq!(lp.len(), 5);
+        let forward: Vec<&[u8]> = lp.iter().collect();
+        assert_eq!(forward, vec![&b"a"[..], b"b", b"c", &big, b""]);
+        let backward: Vec<&[u8]> = lp.iter().rev().collect();
+        assert_eq!(backward, forward.iter().rev().cloned().collect::<Vec<_>>());
+        assert_eq!(lp.get(3), Some(&big[..]));
+
+        assert!(lp.replace(3, b"short"));
+        assert_eq!(lp.remove(0), Some(b"a".to_vec()));
+        assert_eq!(lp.get(2), Some(&b"short"[..]));
+
+        let tail = lp.split_off(1);
+        assert_eq!(lp.iter().collect::<Vec<_>>(), vec![b"b"]);
+        assert_eq!(
+            tail.iter().collect::<Vec<_>>(),
+            vec![&b"c"[..], b"short", b""]
+        );
+    }
+}
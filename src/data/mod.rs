@@ -1 +1,10 @@
+pub mod dict;
+pub mod hash;
+pub mod intset;
+pub mod list;
+pub mod listpack;
+pub mod set;
+pub mod skiplist;
 pub mod store;
+pub mod stream;
+pub mod zset;
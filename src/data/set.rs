@@ -0,0 +1,172 @@
+use crate::{
+    common::{parse_i64, random_u64},
+    data::{dict::Dict, intset::Intset},
+};
+
+/// The integer `member` spells, if it spells it exactly like the integer
+/// would be formatted: "007" or "+7" must come back unchanged so they can't
+/// go in an intset.
+fn as_int(member: &[u8]) -> Option<i64> {
+    let i = parse_i64(member)?;
+    return (i.to_string().as_bytes() == member).then_some(i);
+}
+
+#[derive(Clone)]
+pub enum Set {
+    /// While every member is an integer and there aren't too many of them.
+    Intset(Intset),
+    Table(Dict<Vec<u8>, ()>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        return Set::new();
+    }
+}
+
+impl Set {
+    pub fn new() -> Set {
+        return Set::Intset(Intset::new());
+    }
+
+    /// Builds a set out of `members`, duplicates are ignored.
+    pub fn from_members<V: AsRef<[u8]>>(
+        members: impl IntoIterator<Item = V>,
+        max_intset_entries: usize,
+    ) -> Set {
+        let mut set = Set::new();
+        for member in members {
+            set.insert(member.as_ref(), max_intset_entries);
+        }
+
+        return set;
+    }
+
+    pub fn len(&self) -> usize {
+        return match self {
+            Set::Intset(is) => is.len(),
+            Set::Table(table) => table.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        return match self {
+            Set::Intset(is) => as_int(member).is_some_and(|i| is.contains(i)),
+            Set::Table(table) => table.contains_key(member),
+        };
+    }
+
+    /// Adds `member`, returns false if it was already there. Like in Redis,
+    /// a set converted to a table never goes back to an intset.
+    pub fn insert(&mut self, member: &[u8], max_intset_entries: usize) -> bool {
+        if let Set::Intset(is) = self {
+            if let Some(i) = as_int(member) {
+                let added = is.insert(i);
+                if is.len() > max_intset_entries {
+                    self.convert();
+                }
+                return added;
+            }
+            self.convert();
+        }
+
+        let Set::Table(table) = self else {
+            unreachable!("intset converted above");
+        };
+        if table.contains_key(member) {
+            return false;
+        }
+        table.insert(member.to_vec(), ());
+
+        return true;
+    }
+
+    /// Removes `member`, returns false if it wasn't there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        return match self {
+            Set::Intset(is) => as_int(member).is_some_and(|i| is.remove(i)),
+            Set::Table(table) => table.remove(member).is_some(),
+        };
+    }
+
+    fn convert(&mut self) {
+        let Set::Intset(is) = self else {
+            return;
+        };
+
+        let mut table = Dict::new();
+        for i in is.iter() {
+            table.insert(i.to_string().into_bytes(), ());
+        }
+        *self = Set::Table(table);
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Vec<u8>> + '_> {
+        return match self {
+            Set::Intset(is) => Box::new(is.iter().map(|i| i.to_string().into_bytes())),
+            Set::Table(table) => Box::new(table.iter().map(|(m, _)| m.clone())),
+        };
+    }
+
+    pub fn random_member(&self) -> Option<Vec<u8>> {
+        return match self {
+            Set::Intset(is) => {
+                if is.is_empty() {
+                    return None;
+                }
+                let i = is.get(random_u64() as usize % is.len())?;
+                Some(i.to_string().into_bytes())
+            }
+            Set::Table(table) => table.random_entry().map(|(m, _)| m.clone()),
+        };
+    }
+
+    /// Removes and returns a random member.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let member = self.random_member()?;
+        self.remove(&member);
+
+        return Some(member);
+    }
+
+    /// Calls `f` on some of the members and returns the cursor to resume
+    /// from, 0 when done. An intset is small enough to be returned whole.
+    pub fn scan(&self, cursor: u64, mut f: impl FnMut(&[u8])) -> u64 {
+        return match self {
+            Set::Intset(is) => {
+                for i in is.iter() {
+                    f(i.to_string().as_bytes());
+                }
+                0
+            }
+            Set::Table(table) => table.scan(cursor, |member, _| f(member)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_canonical_integers_stay_in_the_intset() {
+        let mut set = Set::from_members(["1", "2", "-3"], 3);
+        assert!(matches!(set, Set::Intset(_)));
+        assert!(set.contains(b"-3"));
+        assert!(!set.contains(b"+1"));
+
+        // Too many entries
+        set.insert(b"4", 3);
+        assert!(matches!(set, Set::Table(_)));
+        assert!(set.contains(b"4"));
+
+        let set = Set::from_members(["1", "007"], 3);
+        assert!(matches!(set, Set::Table(_)));
+        assert!(set.contains(b"007"));
+        assert!(!set.contains(b"7"));
+    }
+}
@@ -0,0 +1,348 @@
+use crate::common::random_u64;
+
+/// Same as Redis, enough for 2^64 elements with P = 1/4.
+const MAX_LEVEL: usize = 32;
+
+/// Index of the header node, which holds no element.
+const HEAD: usize = 0;
+
+/// Stands for a missing link.
+const NIL: usize = usize::MAX;
+
+#[derive(Clone)]
+struct Level {
+    forward: usize,
+    /// Number of elements between this node and `forward`, `forward`
+    /// included. That's what makes rank lookups logarithmic.
+    span: usize,
+}
+
+#[derive(Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: usize,
+    levels: Vec<Level>,
+}
+
+/// Whether `(score, member)` sorts before the element of `node`.
+fn sorts_before(node: &Node, score: f64, member: &[u8]) -> bool {
+    return node.score < score || (node.score == score && node.member.as_slice() < member);
+}
+
+fn random_level() -> usize {
+    let mut level = 1;
+    // Each level is a quarter as likely as the one below
+    while level < MAX_LEVEL && random_u64() & 0b11 == 0 {
+        level += 1;
+    }
+    return level;
+}
+
+/// Redis' skiplist: elements sorted by score then member, with spans so
+/// that ranks can be found in O(log n). Nodes live in an arena and link to
+/// each other by index.
+#[derive(Clone)]
+pub struct Skiplist {
+    nodes: Vec<Node>,
+    /// Slots of `nodes` freed by removals, reused by insertions.
+    free: Vec<usize>,
+    tail: usize,
+    len: usize,
+    level: usize,
+}
+
+impl Default for Skiplist {
+    fn default() -> Self {
+        return Skiplist::new();
+    }
+}
+
+impl Skiplist {
+    pub fn new() -> Skiplist {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: NIL,
+            levels: vec![
+                Level {
+                    forward: NIL,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+        };
+
+        return Skiplist {
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: NIL,
+            len: 0,
+            level: 1,
+        };
+    }
+
+    /// Bytes allocated for the nodes, members included.
+    pub fn capacity(&self) -> usize {
+        return self.nodes.capacity() * std::mem::size_of::<Node>()
+            + self
+                .nodes
+                .iter()
+                .map(|n| n.member.capacity() + n.levels.capacity() * std::mem::size_of::<Level>())
+                .sum::<usize>();
+    }
+
+    fn forward(&self, node: usize, level: usize) -> usize {
+        return self.nodes[node].levels[level].forward;
+    }
+
+    fn span(&self, node: usize, level: usize) -> usize {
+        return self.nodes[node].levels[level].span;
+    }
+
+    /// The last node on each level that sorts before `(score, member)`,
+    /// along with its rank.
+    fn predecessors(&self, score: f64, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !sorts_before(&self.nodes[next], score, member) {
+                    break;
+                }
+                rank[i] += self.span(x, i);
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        return (update, rank);
+    }
+
+    /// Adds an element, which must not be there already.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) {
+        let (mut update, mut rank) = self.predecessors(score, &member);
+
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: if update[0] == HEAD { NIL } else { update[0] },
+            levels: Vec::with_capacity(level),
+        };
+        let x = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = &mut self.nodes[update[i]].levels[i];
+            let link = Level {
+                forward: prev.forward,
+                span: prev.span - (rank[0] - rank[i]),
+            };
+            prev.forward = x;
+            prev.span = rank[0] - rank[i] + 1;
+            self.nodes[x].levels.push(link);
+        }
+        // Levels above the new node now span one more element
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+
+        match self.forward(x, 0) {
+            NIL => self.tail = x,
+            next => self.nodes[next].backward = x,
+        }
+        self.len += 1;
+    }
+
+    /// Removes an element, returns false if it wasn't there.
+    pub fn remove(&mut self, member: &[u8], score: f64) -> bool {
+        let (update, _) = self.predecessors(score, member);
+        let x = self.forward(update[0], 0);
+        if x == NIL || self.nodes[x].score != score || self.nodes[x].member != member {
+            return false;
+        }
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.forward(prev, i) == x {
+                let removed = self.nodes[x].levels[i].clone();
+                let prev = &mut self.nodes[prev].levels[i];
+                prev.span += removed.span;
+                prev.span -= 1;
+                prev.forward = removed.forward;
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+
+        let backward = self.nodes[x].backward;
+        match self.forward(x, 0) {
+            NIL => self.tail = backward,
+            next => self.nodes[next].backward = backward,
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1) == NIL {
+            self.level -= 1;
+        }
+
+        // Keep the slot but not what it held
+        self.nodes[x].member = Vec::new();
+        self.nodes[x].levels = Vec::new();
+        self.free.push(x);
+        self.len -= 1;
+
+        return true;
+    }
+
+    /// Number of elements for which `pred` holds, `pred` being true for a
+    /// prefix of the elements and false for the rest.
+    pub fn count_while(&self, pred: impl Fn(&[u8], f64) -> bool) -> usize {
+        let mut x = HEAD;
+        let mut rank = 0;
+
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || !pred(&self.nodes[next].member, self.nodes[next].score) {
+                    break;
+                }
+                rank += self.span(x, i);
+                x = next;
+            }
+        }
+
+        return rank;
+    }
+
+    /// Node at 0-based `rank`, which must be in range.
+    fn node_at(&self, rank: usize) -> usize {
+        // Ranks of the nodes are 1-based, the header being 0
+        let rank = rank + 1;
+        let mut x = HEAD;
+        let mut traversed = 0;
+
+        for i in (0..self.level).rev() {
+            loop {
+                let next = self.forward(x, i);
+                if next == NIL || traversed + self.span(x, i) > rank {
+                    break;
+                }
+                traversed += self.span(x, i);
+                x = next;
+            }
+            if traversed == rank {
+                return x;
+            }
+        }
+
+        unreachable!("rank {} out of range", rank - 1);
+    }
+
+    /// Elements from 0-based `rank` onwards, in order, or backwards down to
+    /// the first element if `reverse`.
+    pub fn iter_from(&self, rank: usize, reverse: bool) -> Iter<'_> {
+        let node = if rank >= self.len {
+            NIL
+        } else if rank == self.len - 1 {
+            // Reverse walks mostly start there
+            self.tail
+        } else {
+            self.node_at(rank)
+        };
+        return Iter {
+            list: self,
+            node,
+            reverse,
+        };
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        return Iter {
+            list: self,
+            node: self.forward(HEAD, 0),
+            reverse: false,
+        };
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a Skiplist,
+    node: usize,
+    reverse: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.node == NIL {
+            return None;
+        }
+
+        let node = &self.list.nodes[self.node];
+        self.node = if self.reverse {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+        return Some((&node.member, node.score));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranks_stay_right_across_changes() {
+        let mut list = Skiplist::new();
+        for i in (0..200).rev() {
+            list.insert(format!("m{:03}", i).into_bytes(), (i / 2) as f64);
+        }
+        for i in (0..200).step_by(3) {
+            assert!(list.remove(format!("m{:03}", i).as_bytes(), (i / 2) as f64));
+        }
+        assert!(!list.remove(b"m000", 0.0));
+        assert!(!list.remove(b"m001", 1.0));
+
+        let expected: Vec<_> = (0..200).filter(|i| i % 3 != 0).collect();
+        for (rank, i) in expected.iter().enumerate() {
+            let member = format!("m{:03}", i).into_bytes();
+            let (m, score) = list.iter_from(rank, false).next().unwrap();
+            assert_eq!((m, score), (member.as_slice(), (i / 2) as f64));
+            let below = list.count_while(|m, s| sorts_before_pair(s, m, (i / 2) as f64, &member));
+            assert_eq!(below, rank);
+        }
+
+        let backwards: Vec<_> = list.iter_from(expected.len() - 1, true).collect();
+        assert_eq!(backwards.len(), expected.len());
+        assert_eq!(backwards[0], (&b"m199"[..], 99.0));
+        assert!(backwards.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert_eq!(list.iter().count(), expected.len());
+        assert!(list.iter_from(expected.len(), false).next().is_none());
+    }
+
+    fn sorts_before_pair(score: f64, member: &[u8], than_score: f64, than: &[u8]) -> bool {
+        return score < than_score || (score == than_score && member < than);
+    }
+}
@@ -1,38 +1,726 @@
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::Instant,
 };
 
-use crate::{common::Value, core::resp::encode};
+use anyhow::anyhow;
+use chrono::Utc;
 
-use super::{Store, StoreObject};
+use crate::{
+    common::{format_double, Value},
+    config::AppendFsync,
+    core::{
+        client::Client,
+        cmd::Command,
+        eval,
+        resp::{decode_one, encode},
+    },
+    data::stream::Stream,
+};
+
+use super::{
+    child::{Child, ChildKind},
+    ObjectValue, Store, StoreObject,
+};
+
+/// Open append-only file along with the commands not yet written to it.
+pub struct Aof {
+    file: File,
+    /// Commands encoded in RESP, written out at the end of each event loop
+    /// iteration.
+    buf: Vec<u8>,
+    /// Data was written since the last fsync.
+    fsync_pending: bool,
+    last_fsync_ms: i64,
+    /// Background fsync started by the `everysec` policy.
+    bg_fsync: Option<JoinHandle<()>>,
+    /// Why the last write to the file failed, write commands are refused
+    /// until one succeeds.
+    write_error: Option<String>,
+}
+
+impl Aof {
+    fn open(path: &str) -> io::Result<Aof> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        return Ok(Aof {
+            file,
+            buf: Vec::new(),
+            fsync_pending: false,
+            last_fsync_ms: Utc::now().timestamp_millis(),
+            bg_fsync: None,
+            write_error: None,
+        });
+    }
+
+    /// Writes out `buf`. What was written is removed from it even if the
+    /// rest fails, so that a retry doesn't log anything twice.
+    fn write_buf(&mut self) -> io::Result<()> {
+        while !self.buf.is_empty() {
+            match self.file.write(&self.buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.buf.drain(..n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn bg_fsync_in_progress(&self) -> bool {
+        return self.bg_fsync.as_ref().is_some_and(|h| !h.is_finished());
+    }
+
+    fn fsync(&mut self, policy: AppendFsync) -> io::Result<()> {
+        match policy {
+            AppendFsync::Always => self.file.sync_data()?,
+            AppendFsync::Everysec => {
+                let now = Utc::now().timestamp_millis();
+                if now - self.last_fsync_ms < 1_000 || self.bg_fsync_in_progress() {
+                    return Ok(());
+                }
+
+                // Don't stall the event loop on the disk, fsync from another
+                // thread on a duplicate of the descriptor.
+                let file = self.file.try_clone()?;
+                self.bg_fsync = Some(thread::spawn(move || {
+                    if let Err(err) = file.sync_data() {
+                        println!("AOF background fsync failed: {:?}", err);
+                    }
+                }));
+                self.last_fsync_ms = now;
+            }
+            AppendFsync::No => (),
+        }
+
+        self.fsync_pending = false;
+        return Ok(());
+    }
+}
 
 impl Store {
-    fn dump_key(&mut self, file: &mut File, key: String, store_value: StoreObject) {
-        let cmd = format!("SET {0} {1}", key, store_value.value);
-        let tokens = cmd.split_whitespace().map(str::to_string).collect();
+    /// Opens the append-only file so write commands start being logged.
+    pub fn open_aof(&mut self) -> io::Result<()> {
+        if self.config.appendonly {
+            self.aof = Some(Aof::open(&self.config.aof_file)?);
+        }
 
-        let _ = file.write_all(&encode(Value::VectorString(tokens), false));
+        return Ok(());
     }
 
-    pub fn dump_all_aof(&mut self) {
-        let mut f = match File::create(self.config.aof_file.clone()) {
+    /// Runs every complete command in `data`, returning how many bytes they
+    /// span and how many there were. A transaction cut short by the end of
+    /// `data` doesn't count, it was never applied.
+    fn replay_aof(&mut self, data: &[u8]) -> anyhow::Result<(usize, u64)> {
+        let mut client = Client::new();
+        let mut loaded = 0_u64;
+        let mut pos = 0;
+        let mut multi_start = None;
+
+        while pos < data.len() {
+            let Some((delta, value)) = decode_one(&data[pos..]).map_err(|err| {
+                anyhow!("Bad file format reading the append only file at offset {}: {}", pos, err)
+            })?
+            else {
+                break;
+            };
+            let cmd = Command::try_from(value)
+                .map_err(|err| anyhow!("Bad file format reading the append only file: {}", err))?;
+
+            match cmd.cmd.as_str() {
+                "MULTI" => multi_start = Some((pos, loaded)),
+                "EXEC" | "DISCARD" => multi_start = None,
+                _ => {}
+            }
+
+            let reply = eval::call(cmd, &mut client, self);
+            if reply.first() == Some(&b'-') {
+                println!(
+                    "AOF command at offset {} failed: {}",
+                    pos,
+                    String::from_utf8_lossy(&reply[1..]).trim_end()
+                );
+            }
+
+            pos += delta;
+            loaded += 1;
+        }
+
+        return Ok(multi_start.unwrap_or((pos, loaded)));
+    }
+
+    /// Rebuilds the dataset by replaying the AOF through the regular command
+    /// path. Must run before `open_aof` so the replayed commands aren't
+    /// logged a second time.
+    pub fn load_aof(&mut self) -> anyhow::Result<()> {
+        if !self.config.appendonly {
+            return Ok(());
+        }
+
+        let path = self.config.aof_file.clone();
+        let data = match fs::read(&path) {
             Ok(res) => res,
-            Err(err) => {
-                println!("error {:?}", err);
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let start = Instant::now();
+        self.loading = true;
+        let replayed = self.replay_aof(&data);
+        self.loading = false;
+        let (pos, loaded) = replayed?;
+
+        // A crash in the middle of a write leaves a partial command, or
+        // transaction, at the end
+        if pos < data.len() {
+            if !self.config.aof_load_truncated {
+                return Err(anyhow!(
+                    "Unexpected end of file reading the append only file {}, start the server \
+                     with --aof-load-truncated true to truncate it and continue",
+                    path
+                ));
+            }
+
+            println!(
+                "!!! Warning: short read while loading the AOF file {}, truncating it to {} bytes",
+                path, pos
+            );
+            OpenOptions::new().write(true).open(&path)?.set_len(pos as u64)?;
+        }
+
+        println!(
+            "DB loaded from append only file: {:.3} seconds, {} commands",
+            start.elapsed().as_secs_f64(),
+            loaded
+        );
+        return Ok(());
+    }
+
+    /// Queues a command that ran against database `db` to be appended to
+    /// the AOF, after a SELECT if the previous one ran against another.
+    pub fn feed_aof(&mut self, db: usize, argv: Vec<Vec<u8>>) {
+        if self.aof.is_none() && self.aof_rewrite.buf.is_none() {
+            return;
+        }
+
+        let mut data = Vec::new();
+        if self.aof_db != Some(db) {
+            data = encode(Value::VectorString(select_command(db)), false);
+            self.aof_db = Some(db);
+        }
+        data.extend(encode(Value::VectorString(argv), false));
+        if let Some(buf) = self.aof_rewrite.buf.as_mut() {
+            buf.extend_from_slice(&data);
+        }
+        if let Some(aof) = self.aof.as_mut() {
+            aof.buf.extend(data);
+        }
+    }
+
+    /// Writes the queued commands to the AOF and fsyncs it according to
+    /// `appendfsync`. Called before replies are sent, so with `always` a
+    /// client never sees an acknowledgement for a write that isn't on disk.
+    pub fn flush_aof(&mut self) {
+        let policy = self.config.appendfsync;
+        let Some(aof) = self.aof.as_mut() else {
+            return;
+        };
+
+        if !aof.buf.is_empty() {
+            if let Err(err) = aof.write_buf() {
+                // The rest of the buffer is retried on the next flush
+                if aof.write_error.is_none() {
+                    println!("error writing to the AOF: {:?}", err);
+                }
+                aof.write_error = Some(err.to_string());
                 return;
             }
+            if aof.write_error.take().is_some() {
+                println!("AOF write error looks solved, the server can write again.");
+            }
+            aof.fsync_pending = true;
+        }
+
+        if aof.fsync_pending {
+            if let Err(err) = aof.fsync(policy) {
+                println!("error during AOF fsync: {:?}", err);
+            }
+        }
+    }
+
+    /// Writes commands that rebuild the current dataset to `w`. Keys that
+    /// already expired are left out and expiry times are absolute, so
+    /// replaying the output at any time yields the same dataset.
+    /// `progress` is called after each key.
+    pub fn rewrite_aof(&self, w: &mut impl Write, mut progress: impl FnMut()) -> anyhow::Result<()> {
+        let now = Utc::now().timestamp_millis();
+
+        for (i, db) in self.dbs.iter().enumerate() {
+            if db.is_empty() {
+                continue;
+            }
+
+            w.write_all(&encode(Value::VectorString(select_command(i)), false))?;
+            for (key, obj) in db.iter(now) {
+                for argv in rewrite_object(key, obj)? {
+                    w.write_all(&encode(Value::VectorString(argv), false))?;
+                }
+                progress();
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Why the last write to the AOF failed, `None` once one succeeded
+    /// again.
+    pub fn aof_write_error(&self) -> Option<&str> {
+        return self.aof.as_ref().and_then(|aof| aof.write_error.as_deref());
+    }
+
+    pub fn aof_rewrite_in_progress(&self) -> bool {
+        return self.aof_rewrite.buf.is_some();
+    }
+
+    /// Where the child of `pid` writes the new AOF. It sits next to the AOF
+    /// so it can be renamed over it atomically.
+    fn temp_rewrite_path(&self, pid: u32) -> PathBuf {
+        return Path::new(&self.config.aof_file)
+            .with_file_name(format!("temp-rewriteaof-bg-{}.aof", pid));
+    }
+
+    /// Rewrites the AOF in a forked child from a snapshot of the dataset.
+    /// Writes that happen meanwhile are buffered and appended to the new
+    /// file when the child is done, then it atomically replaces the old one.
+    pub fn bg_rewrite_aof(&mut self) -> anyhow::Result<()> {
+        if self.aof_rewrite.buf.is_some() {
+            return Err(anyhow!("Background append only file rewriting already in progress"));
+        }
+
+        self.start_child(ChildKind::AofRewrite, |store, reporter| {
+            let path = store.temp_rewrite_path(std::process::id());
+            let mut w = BufWriter::new(File::create(&path)?);
+            store.rewrite_aof(&mut w, || reporter.key_done())?;
+
+            let f = w.into_inner().map_err(|err| err.into_error())?;
+            f.sync_all()?;
+            return Ok(());
+        })?;
+
+        self.aof_rewrite.buf = Some(Vec::new());
+        // The child's output may end in any database
+        self.aof_db = None;
+        self.aof_rewrite.last_started_ms = Utc::now().timestamp_millis();
+        println!("Background append only file rewriting started");
+
+        return Ok(());
+    }
+
+    /// Appends the writes buffered during the rewrite to the child's output
+    /// and moves it over the AOF.
+    fn install_rewritten_aof(&mut self, tmp: &Path, buf: &[u8]) -> io::Result<()> {
+        let mut f = OpenOptions::new().append(true).open(tmp)?;
+        f.write_all(buf)?;
+        f.sync_all()?;
+        fs::rename(tmp, &self.config.aof_file)?;
+
+        if self.aof.is_some() {
+            // Everything still queued for the old file is part of `buf`
+            self.aof = Some(Aof::open(&self.config.aof_file)?);
+        }
+
+        return Ok(());
+    }
+
+    pub(super) fn aof_rewrite_done(&mut self, child: &Child, success: bool) {
+        let tmp = self.temp_rewrite_path(child.pid as u32);
+        let buf = self.aof_rewrite.buf.take().unwrap_or_default();
+        let elapsed_ms = Utc::now().timestamp_millis() - child.started_ms;
+        self.aof_rewrite.last_time_sec = elapsed_ms / 1_000;
+
+        let installed = if success {
+            self.install_rewritten_aof(&tmp, &buf)
+        } else {
+            Err(io::Error::other("rewrite child failed"))
+        };
+
+        match installed {
+            Ok(_) => {
+                self.aof_rewrite.last_ok = true;
+                println!("Background AOF rewrite finished successfully");
+            }
+            Err(err) => {
+                self.aof_rewrite.last_ok = false;
+                let _ = fs::remove_file(&tmp);
+                println!("Background AOF rewrite failed: {}", err);
+            }
+        }
+    }
+
+    /// `INFO persistence` fields about the AOF.
+    pub fn aof_info(&self) -> Vec<(&'static str, String)> {
+        let rewriting = self.aof_rewrite.buf.is_some();
+        let current_time_sec = if rewriting {
+            (Utc::now().timestamp_millis() - self.aof_rewrite.last_started_ms) / 1_000
+        } else {
+            -1
         };
-        println!("rewriting AOF file at {0}", self.config.aof_file);
 
-        let mut tuples: Vec<(String, StoreObject)> = Vec::new();
-        for (k, sv) in self.inner.iter() {
-            tuples.push((k.clone(), sv.clone()));
+        return vec![
+            ("aof_enabled", (self.aof.is_some() as u8).to_string()),
+            ("aof_rewrite_in_progress", (rewriting as u8).to_string()),
+            ("aof_rewrite_scheduled", (self.aof_rewrite_scheduled() as u8).to_string()),
+            ("aof_last_rewrite_time_sec", self.aof_rewrite.last_time_sec.to_string()),
+            ("aof_current_rewrite_time_sec", current_time_sec.to_string()),
+            (
+                "aof_last_bgrewrite_status",
+                if self.aof_rewrite.last_ok { "ok" } else { "err" }.to_string(),
+            ),
+            (
+                "aof_rewrite_buffer_length",
+                self.aof_rewrite.buf.as_ref().map_or(0, |b| b.len()).to_string(),
+            ),
+        ];
+    }
+}
+
+/// Bookkeeping for background AOF rewrites.
+pub struct AofRewrite {
+    /// Writes made since the rewrite child was forked, `Some` while it runs.
+    buf: Option<Vec<u8>>,
+    last_started_ms: i64,
+    last_time_sec: i64,
+    last_ok: bool,
+}
+
+impl Default for AofRewrite {
+    fn default() -> Self {
+        return AofRewrite {
+            buf: None,
+            last_started_ms: 0,
+            last_time_sec: -1,
+            last_ok: true,
+        };
+    }
+}
+
+/// Elements per command when rewriting aggregates, so that huge values don't
+/// turn into a single huge command.
+const AOF_REWRITE_ITEMS_PER_CMD: usize = 64;
+
+/// `<cmd> <key> <items...>` split in chunks of `AOF_REWRITE_ITEMS_PER_CMD`
+/// items, each item made of `args_per_item` arguments.
+fn variadic(
+    cmd: &[u8],
+    key: &[u8],
+    items: Vec<Vec<u8>>,
+    args_per_item: usize,
+) -> Vec<Vec<Vec<u8>>> {
+    return items
+        .chunks(AOF_REWRITE_ITEMS_PER_CMD * args_per_item)
+        .map(|chunk| {
+            let mut argv = vec![cmd.to_vec(), key.to_vec()];
+            argv.extend(chunk.iter().cloned());
+            argv
+        })
+        .collect();
+}
+
+/// Same as Redis: XADD each entry, then XSETID to restore the counters, and
+/// recreate the groups with their pending entries through forced XCLAIMs.
+fn rewrite_stream(key: &[u8], stream: &Stream) -> Vec<Vec<Vec<u8>>> {
+    let arg = |s: &str| s.as_bytes().to_vec();
+    let mut cmds = Vec::new();
+
+    for (id, fields) in stream.entries.iter() {
+        let mut argv = vec![b"XADD".to_vec(), key.to_vec(), id.to_bytes()];
+        argv.extend(fields.iter().cloned());
+        cmds.push(argv);
+    }
+    if stream.is_empty() {
+        // An empty stream is created by adding an entry and trimming it away
+        cmds.push(vec![
+            b"XADD".to_vec(),
+            key.to_vec(),
+            arg("MAXLEN"),
+            arg("0"),
+            arg("0-1"),
+            arg("x"),
+            arg("y"),
+        ]);
+    }
+    cmds.push(vec![
+        b"XSETID".to_vec(),
+        key.to_vec(),
+        stream.last_id.to_bytes(),
+        arg("ENTRIESADDED"),
+        stream.entries_added.to_string().into_bytes(),
+        arg("MAXDELETEDID"),
+        stream.max_deleted_id.to_bytes(),
+    ]);
+
+    for (name, group) in stream.groups.iter() {
+        let entries_read = group.entries_read.map_or(-1, |n| n as i64);
+        cmds.push(vec![
+            b"XGROUP".to_vec(),
+            arg("CREATE"),
+            key.to_vec(),
+            name.clone(),
+            group.last_id.to_bytes(),
+            arg("ENTRIESREAD"),
+            entries_read.to_string().into_bytes(),
+        ]);
+        for (consumer_name, consumer) in group.consumers.iter() {
+            if consumer.pending.is_empty() {
+                cmds.push(vec![
+                    b"XGROUP".to_vec(),
+                    arg("CREATECONSUMER"),
+                    key.to_vec(),
+                    name.clone(),
+                    consumer_name.clone(),
+                ]);
+            }
+            for id in consumer.pending.iter() {
+                let entry = &group.pel[id];
+                cmds.push(vec![
+                    b"XCLAIM".to_vec(),
+                    key.to_vec(),
+                    name.clone(),
+                    consumer_name.clone(),
+                    arg("0"),
+                    id.to_bytes(),
+                    arg("TIME"),
+                    entry.delivery_time.to_string().into_bytes(),
+                    arg("RETRYCOUNT"),
+                    entry.delivery_count.to_string().into_bytes(),
+                    arg("JUSTID"),
+                    arg("FORCE"),
+                ]);
+            }
+        }
+    }
+
+    return cmds;
+}
+
+/// Switches the database the commands that follow run against.
+fn select_command(db: usize) -> Vec<Vec<u8>> {
+    return vec![b"SELECT".to_vec(), db.to_string().into_bytes()];
+}
+
+/// Commands that recreate `obj` under `key`, including its expiry.
+fn rewrite_object(key: &[u8], obj: &StoreObject) -> anyhow::Result<Vec<Vec<Vec<u8>>>> {
+    let mut cmds = match &obj.value {
+        ObjectValue::String(s) => vec![vec![b"SET".to_vec(), key.to_vec(), s.clone()]],
+        ObjectValue::List(list) => {
+            let items = list.iter().map(|v| v.to_vec()).collect::<Vec<_>>();
+            variadic(b"RPUSH", key, items, 1)
+        }
+        ObjectValue::Set(set) => variadic(b"SADD", key, set.iter().collect(), 1),
+        ObjectValue::Zset(zset) => {
+            let items = zset
+                .iter()
+                .flat_map(|(m, s)| [format_double(s).into_bytes(), m.to_vec()])
+                .collect::<Vec<_>>();
+            variadic(b"ZADD", key, items, 2)
+        }
+        ObjectValue::Hash(hash) => {
+            let items = hash
+                .iter()
+                .flat_map(|(f, v)| [f.to_vec(), v.to_vec()])
+                .collect::<Vec<_>>();
+            let mut cmds = variadic(b"HSET", key, items, 2);
+            for (field, _, at) in hash.entries().filter(|(_, _, at)| *at != -1) {
+                cmds.push(vec![
+                    b"HPEXPIREAT".to_vec(),
+                    key.to_vec(),
+                    at.to_string().into_bytes(),
+                    b"FIELDS".to_vec(),
+                    b"1".to_vec(),
+                    field.to_vec(),
+                ]);
+            }
+            cmds
+        }
+        ObjectValue::Stream(stream) => rewrite_stream(key, stream),
+    };
+
+    if obj.expires_at != -1 {
+        cmds.push(vec![
+            b"PEXPIREAT".to_vec(),
+            key.to_vec(),
+            obj.expires_at.to_string().into_bytes(),
+        ]);
+    }
+
+    return Ok(cmds);
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        core::resp::decode,
+        data::{
+            list::List,
+            store::{ENCODING_LISTPACK, TYPE_LIST, TYPE_STRING},
+        },
+    };
+
+    #[test]
+    fn test_rewrite_preserves_values_and_ttls() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let now = Utc::now().timestamp_millis();
+
+        let mut with_ttl = StoreObject::new(ObjectValue::String(b"a b\r\n".to_vec()), -1, TYPE_STRING, 0);
+        with_ttl.expires_at = now + 60_000;
+        store.put(b"with ttl".to_vec(), with_ttl);
+
+        let mut expired = StoreObject::new(ObjectValue::String(b"gone".to_vec()), -1, TYPE_STRING, 0);
+        expired.expires_at = now - 1;
+        store.put(b"expired".to_vec(), expired);
+
+        store.select(2);
+        store.put(
+            b"elsewhere".to_vec(),
+            StoreObject::new(ObjectValue::String(b"c".to_vec()), -1, TYPE_STRING, 0),
+        );
+
+        let mut out = Vec::new();
+        store.rewrite_aof(&mut out, || ()).unwrap();
+
+        let bulk = |s: &[u8]| Value::String(s.to_vec());
+        assert_eq!(
+            decode(&out).unwrap(),
+            vec![
+                Value::Vector(vec![bulk(b"SELECT"), bulk(b"0")]),
+                Value::Vector(vec![bulk(b"SET"), bulk(b"with ttl"), bulk(b"a b\r\n")]),
+                Value::Vector(vec![
+                    bulk(b"PEXPIREAT"),
+                    bulk(b"with ttl"),
+                    bulk((now + 60_000).to_string().as_bytes()),
+                ]),
+                Value::Vector(vec![bulk(b"SELECT"), bulk(b"2")]),
+                Value::Vector(vec![bulk(b"SET"), bulk(b"elsewhere"), bulk(b"c")]),
+            ]
+        );
+    }
+
+    /// Replays `cmds` the way `load_aof` does.
+    fn replay(store: &mut Store, cmds: &[&[&[u8]]]) {
+        let mut data = Vec::new();
+        for argv in cmds {
+            let argv = argv.iter().map(|a| a.to_vec()).collect();
+            data.extend(encode(Value::VectorString(argv), false));
         }
-        for (k, sv) in tuples {
-            self.dump_key(&mut f, k, sv);
+
+        store.loading = true;
+        store.replay_aof(&data).unwrap();
+        store.loading = false;
+    }
+
+    #[test]
+    fn test_replay_doesnt_expire_keys() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let past = (Utc::now().timestamp_millis() - 1000).to_string();
+
+        // The key was alive when APPEND ran, so it modified it rather than
+        // created a new one without a TTL
+        replay(
+            &mut store,
+            &[
+                &[b"SET", b"k", b"v"],
+                &[b"PEXPIREAT", b"k", past.as_bytes()],
+                &[b"APPEND", b"k", b"x"],
+            ],
+        );
+        let obj = store.db().inner.get(b"k".as_slice()).unwrap();
+        assert!(matches!(&obj.value, ObjectValue::String(s) if s == b"vx"));
+        assert_eq!(obj.expires_at.to_string(), past);
+
+        assert!(store.get(b"k").is_none());
+    }
+
+    #[test]
+    fn test_rewrite_splits_big_lists() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        let items = (0..100).map(|i| i.to_string()).collect::<Vec<_>>();
+        let list = List::from_items(&items, store.list_limit());
+        store.put(
+            b"list".to_vec(),
+            StoreObject::new(ObjectValue::List(list), -1, TYPE_LIST, ENCODING_LISTPACK),
+        );
+
+        let mut out = Vec::new();
+        store.rewrite_aof(&mut out, || ()).unwrap();
+
+        let mut cmds = decode(&out).unwrap();
+        assert_eq!(cmds.len(), 3);
+        assert_eq!(
+            cmds.remove(0),
+            Value::Vector(vec![Value::String(b"SELECT".to_vec()), Value::String(b"0".to_vec())])
+        );
+        let mut replayed = Vec::new();
+        for cmd in cmds {
+            let Value::Vector(argv) = cmd else {
+                panic!("not a command");
+            };
+            assert_eq!(argv[..2], [Value::String(b"RPUSH".to_vec()), Value::String(b"list".to_vec())]);
+            replayed.extend(argv[2..].iter().map(|v| v.to_string()));
         }
+        assert_eq!(replayed, items);
+    }
+
+    #[test]
+    fn test_writes_are_refused_until_the_aof_is_written() {
+        // Every write to /dev/full fails as if the disk was full
+        let mut store = Store::new(Config::parse_from([
+            "redrust",
+            "--appendonly",
+            "--aof-file",
+            "/dev/full",
+        ]));
+        store.open_aof().unwrap();
+        let mut client = Client::new();
+        let mut call = |store: &mut Store, args: &[&str]| {
+            let argv = args.iter().map(|a| a.as_bytes().to_vec()).collect::<Vec<_>>();
+            return eval::call(Command::from(argv), &mut client, store);
+        };
+
+        call(&mut store, &["SET", "k", "v"]);
+        store.flush_aof();
+        assert!(store.aof_write_error().is_some());
+        assert!(call(&mut store, &["SET", "k", "w"])
+            .starts_with(b"-MISCONF Errors writing to the AOF file: "));
+        assert!(call(&mut store, &["PING"]).starts_with(b"-MISCONF"));
+        assert_eq!(call(&mut store, &["GET", "k"]), b"$1\r\nv\r\n");
+
+        // Once there is room again, the write that failed goes through
+        let path =
+            std::env::temp_dir().join(format!("redrust-misconf-{}.aof", std::process::id()));
+        store.aof.as_mut().unwrap().file = File::create(&path).unwrap();
+        store.flush_aof();
+        assert_eq!(store.aof_write_error(), None);
+        call(&mut store, &["SET", "k", "w"]);
+        store.flush_aof();
 
-        println!("AOF File rewrite complete");
+        let bulk = |s: &str| Value::String(s.as_bytes().to_vec());
+        assert_eq!(
+            decode(&fs::read(&path).unwrap()).unwrap(),
+            vec![
+                Value::Vector(vec![bulk("SELECT"), bulk("0")]),
+                Value::Vector(vec![bulk("SET"), bulk("k"), bulk("v")]),
+                Value::Vector(vec![bulk("SET"), bulk("k"), bulk("w")]),
+            ]
+        );
     }
 }
@@ -0,0 +1,61 @@
+use std::collections::VecDeque;
+
+use super::Store;
+
+impl Store {
+    /// Queues client `id` on each of `keys`, behind the clients already
+    /// waiting there. It is only served once they hold a `key_type`.
+    pub fn block_on_keys(&mut self, id: u64, keys: &[Vec<u8>], key_type: u8) {
+        for key in keys {
+            let queue = self.blocking_keys[self.db].entry(key.clone()).or_default();
+            // The same key may be given twice, e.g. BLPOP k k 0
+            if !queue.iter().any(|&(c, _)| c == id) {
+                queue.push_back((id, key_type));
+            }
+        }
+    }
+
+    /// Takes client `id` off the queues of `keys`, which it blocked on from
+    /// database `db`.
+    pub fn unblock_from_keys(&mut self, id: u64, db: usize, keys: &[Vec<u8>]) {
+        let blocking_keys = &mut self.blocking_keys[db];
+        for key in keys {
+            let Some(queue) = blocking_keys.get_mut(key) else {
+                continue;
+            };
+            queue.retain(|&(c, _)| c != id);
+            if queue.is_empty() {
+                blocking_keys.remove(key);
+            }
+        }
+    }
+
+    /// Ids of the clients blocked on `key` of the selected database and the
+    /// type they wait for, first come first.
+    pub fn clients_blocked_on(&self, key: &[u8]) -> Vec<(u64, u8)> {
+        return self.blocking_keys[self.db]
+            .get(key)
+            .map_or(vec![], |q| q.iter().copied().collect());
+    }
+
+    /// Remembers that clients blocked on `key` of the selected database may
+    /// now be served. Called whenever a key is created, and by XADD on
+    /// existing streams.
+    pub fn signal_key_as_ready(&mut self, key: &[u8]) {
+        let db = self.db;
+        if self.blocking_keys[db].contains_key(key)
+            && !self.ready_keys.iter().any(|(d, k)| *d == db && k == key)
+        {
+            self.ready_keys.push((db, key.to_vec()));
+        }
+    }
+
+    /// Keys signaled as ready since the last call, with their database.
+    pub fn take_ready_keys(&mut self) -> Vec<(usize, Vec<u8>)> {
+        return std::mem::take(&mut self.ready_keys);
+    }
+}
+
+/// Clients waiting on a key, in the order they blocked, along with the type
+/// of key they wait for.
+pub(super) type BlockedQueue = VecDeque<(u64, u8)>;
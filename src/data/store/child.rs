@@ -0,0 +1,172 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    mem::size_of,
+    os::fd::{FromRawFd, RawFd},
+};
+
+use anyhow::anyhow;
+use chrono::Utc;
+
+use crate::syscall;
+
+use super::Store;
+
+/// What a forked child is producing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildKind {
+    AofRewrite,
+    Rdb,
+}
+
+/// A forked child working on a point-in-time copy of the dataset.
+pub struct Child {
+    pub pid: libc::pid_t,
+    pub kind: ChildKind,
+    pub started_ms: i64,
+    /// Keys written by the child so far, as reported through the pipe.
+    pub keys_processed: u64,
+    /// Keys in the dataset when the child was forked.
+    pub keys_total: u64,
+    progress: File,
+}
+
+/// Write end of the pipe a child uses to report its progress.
+pub struct ProgressReporter {
+    fd: RawFd,
+    keys_processed: u64,
+}
+
+impl ProgressReporter {
+    /// Reports every 1024 keys, a pipe write per key would dominate the work.
+    pub fn key_done(&mut self) {
+        self.keys_processed += 1;
+        if self.keys_processed.is_multiple_of(1024) {
+            let buf = self.keys_processed.to_ne_bytes();
+            // Best effort, the parent only uses it for INFO
+            let _ = syscall!(write(self.fd, buf.as_ptr() as *const libc::c_void, buf.len()));
+        }
+    }
+}
+
+impl Store {
+    /// Forks a child that runs `job` on a snapshot of the dataset: the
+    /// child's copy of memory is frozen at fork time while the parent keeps
+    /// serving clients. Only one child may run at a time.
+    pub(super) fn start_child<F>(&mut self, kind: ChildKind, job: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Store, &mut ProgressReporter) -> anyhow::Result<()>,
+    {
+        if self.child.is_some() {
+            return Err(anyhow!("a background child is already running"));
+        }
+
+        let mut fds = [0 as RawFd; 2];
+        syscall!(pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC))?;
+        let (read_fd, write_fd) = (fds[0], fds[1]);
+
+        let pid = match syscall!(fork()) {
+            Ok(pid) => pid,
+            Err(err) => {
+                let _ = syscall!(close(read_fd));
+                let _ = syscall!(close(write_fd));
+                return Err(err.into());
+            }
+        };
+
+        if pid == 0 {
+            // Child: do the work and leave without running destructors or
+            // touching anything shared with the parent.
+            let _ = syscall!(close(read_fd));
+            let mut reporter = ProgressReporter {
+                fd: write_fd,
+                keys_processed: 0,
+            };
+            let code = match job(self, &mut reporter) {
+                Ok(_) => 0,
+                Err(err) => {
+                    println!("Background {:?} failed: {:#}", kind, err);
+                    1
+                }
+            };
+            unsafe { libc::_exit(code) };
+        }
+
+        let _ = syscall!(close(write_fd));
+        self.child = Some(Child {
+            pid,
+            kind,
+            started_ms: Utc::now().timestamp_millis(),
+            keys_processed: 0,
+            keys_total: self.total_keys() as u64,
+            progress: unsafe { File::from_raw_fd(read_fd) },
+        });
+
+        return Ok(());
+    }
+
+    pub fn child_in_progress(&self) -> bool {
+        return self.child.is_some();
+    }
+
+    /// `INFO persistence` fields about the running child, if any.
+    pub fn child_info(&self) -> Vec<(&'static str, String)> {
+        let (processed, total) = self
+            .child
+            .as_ref()
+            .map_or((0, 0), |c| (c.keys_processed, c.keys_total));
+        let perc = if total > 0 {
+            processed as f64 * 100.0 / total as f64
+        } else {
+            0.0
+        };
+
+        return vec![
+            ("current_fork_perc", format!("{:.2}", perc)),
+            ("current_save_keys_processed", processed.to_string()),
+            ("current_save_keys_total", total.to_string()),
+        ];
+    }
+
+    /// Collects progress from the running child and handles its exit. Called
+    /// from the event loop.
+    pub fn check_child(&mut self) {
+        let Some(child) = self.child.as_mut() else {
+            return;
+        };
+
+        let mut buf = [0u8; size_of::<u64>() * 64];
+        loop {
+            // Reports are written whole and the buffer holds a whole number
+            // of them, so only the last one needs to be looked at.
+            let n = match child.progress.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if n >= size_of::<u64>() {
+                let last = n / size_of::<u64>() * size_of::<u64>();
+                let mut report = [0u8; size_of::<u64>()];
+                report.copy_from_slice(&buf[last - size_of::<u64>()..last]);
+                child.keys_processed = u64::from_ne_bytes(report);
+            }
+        }
+
+        let mut status = 0;
+        let pid = unsafe { libc::waitpid(child.pid, &mut status, libc::WNOHANG) };
+        if pid == 0 {
+            return;
+        }
+
+        let success = pid == child.pid && libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0;
+        let Some(child) = self.child.take() else {
+            return;
+        };
+
+        match child.kind {
+            ChildKind::AofRewrite => self.aof_rewrite_done(&child, success),
+            ChildKind::Rdb => self.rdb_bgsave_done(&child, success),
+        }
+    }
+}
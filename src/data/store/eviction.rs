@@ -1,17 +1,310 @@
-use super::Store;
+use chrono::Utc;
 
-impl Store {
-    fn evict_first(&mut self) {
-        for (k, _) in self.inner.iter() {
-            self.inner.remove(&k.to_string());
+use crate::{
+    common::{random_f64, random_u64},
+    config::EvictionStrategy,
+};
+
+use super::{Store, StoreObject};
+
+/// Number of best candidates remembered between evictions. Keeping them
+/// around makes the approximation much closer to a true LRU/LFU than looking
+/// at a single sample each time.
+const EVPOOL_SIZE: usize = 16;
+
+/// Counter given to new keys, so they get a chance to be accessed before
+/// being evicted.
+pub(super) const LFU_INIT_VAL: u8 = 5;
+/// The higher the factor, the more accesses it takes to saturate the counter.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Minutes without access it takes for the counter to be decremented.
+const LFU_DECAY_TIME: u64 = 1;
+
+/// Clock used for `StoreObject::lru`, in seconds.
+pub(super) fn lru_clock() -> u32 {
+    return Utc::now().timestamp() as u32;
+}
+
+/// Clock used for `StoreObject::lfu_decay_time`, in minutes. Wraps around
+/// every ~45 days, which only makes old keys look younger than they are.
+pub(super) fn lfu_minutes() -> u16 {
+    return (Utc::now().timestamp() / 60) as u16;
+}
+
+/// Probabilistic increment: the higher the counter already is, the less
+/// likely it is to grow, so 8 bits are enough for millions of accesses.
+fn lfu_log_incr(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    if random_f64() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+        return counter + 1;
+    }
+
+    return counter;
+}
+
+impl StoreObject {
+    /// Seconds since the key was last accessed.
+    pub fn idle_time(&self) -> u64 {
+        return lru_clock().saturating_sub(self.lru) as u64;
+    }
+
+    /// Access frequency, decayed by the time elapsed since it was last
+    /// updated.
+    pub fn lfu_freq(&self) -> u8 {
+        let elapsed = lfu_minutes().wrapping_sub(self.lfu_decay_time) as u64;
+        let periods = (elapsed / LFU_DECAY_TIME).min(u8::MAX as u64) as u8;
+        return self.lfu_counter.saturating_sub(periods);
+    }
+
+    /// Records an access. Only the clock the eviction strategy looks at is
+    /// maintained.
+    pub(super) fn touch(&mut self, lfu: bool) {
+        if lfu {
+            self.lfu_counter = lfu_log_incr(self.lfu_freq());
+            self.lfu_decay_time = lfu_minutes();
+        } else {
+            self.lru = lru_clock();
+        }
+    }
+}
+
+/// Eviction candidates sorted by ascending score, the best one is last,
+/// along with their database.
+#[derive(Default)]
+pub(super) struct EvictionPool(Vec<(u64, usize, Vec<u8>)>);
+
+impl EvictionPool {
+    fn insert(&mut self, score: u64, db: usize, key: Vec<u8>) {
+        if self.0.iter().any(|(_, d, k)| *d == db && *k == key) {
             return;
         }
+        if self.0.len() == EVPOOL_SIZE && score <= self.0[0].0 {
+            return;
+        }
+
+        let pos = self.0.partition_point(|(s, _, _)| *s < score);
+        self.0.insert(pos, (score, db, key));
+        if self.0.len() > EVPOOL_SIZE {
+            self.0.remove(0);
+        }
+    }
+}
+
+impl Store {
+    /// The higher, the better a candidate the key is.
+    fn eviction_score(&self, obj: &StoreObject) -> u64 {
+        return match self.config.eviction_strategy {
+            EvictionStrategy::AllkeysLfu | EvictionStrategy::VolatileLfu => {
+                (u8::MAX - obj.lfu_freq()) as u64
+            }
+            EvictionStrategy::VolatileTtl => u64::MAX - obj.expires_at as u64,
+            _ => obj.idle_time(),
+        };
+    }
+
+    /// Samples the keys of each database, like Redis does, so that the
+    /// best candidates of all of them end up in the pool.
+    fn populate_eviction_pool(&mut self) {
+        let samples = self.config.maxmemory_samples as usize;
+        let volatile = self.config.eviction_strategy.is_volatile();
+
+        let mut candidates = Vec::new();
+        for (i, db) in self.dbs.iter().enumerate() {
+            let keys: Vec<&Vec<u8>> = if volatile {
+                db.expires.sample(samples).into_iter().map(|(k, _)| k).collect()
+            } else {
+                db.inner.sample(samples).into_iter().map(|(k, _)| k).collect()
+            };
+            for k in keys {
+                if let Some(obj) = db.inner.get(k) {
+                    candidates.push((self.eviction_score(obj), i, k.clone()));
+                }
+            }
+        }
+        for (score, db, key) in candidates {
+            self.eviction_pool.insert(score, db, key);
+        }
+    }
+
+    /// A random key of a random database that has any, with a TTL if
+    /// `volatile`.
+    fn random_eviction_candidate(&self, volatile: bool) -> Option<(usize, Vec<u8>)> {
+        let start = random_u64() as usize % self.dbs.len();
+        return (0..self.dbs.len())
+            .map(|i| (start + i) % self.dbs.len())
+            .find_map(|i| {
+                let key = if volatile {
+                    self.dbs[i].expires.random_entry().map(|(k, _)| k)
+                } else {
+                    self.dbs[i].inner.random_entry().map(|(k, _)| k)
+                };
+                key.map(|k| (i, k.clone()))
+            });
+    }
+
+    fn eviction_candidate(&mut self) -> Option<(usize, Vec<u8>)> {
+        return match self.config.eviction_strategy {
+            EvictionStrategy::Noeviction => None,
+            EvictionStrategy::AllkeysRandom => self.random_eviction_candidate(false),
+            EvictionStrategy::VolatileRandom => self.random_eviction_candidate(true),
+            strategy => {
+                self.populate_eviction_pool();
+
+                // Pooled keys may have been deleted, or lost their TTL,
+                // since they were sampled.
+                while let Some((_, db, key)) = self.eviction_pool.0.pop() {
+                    let exists = if strategy.is_volatile() {
+                        self.dbs[db].expires.contains_key(&key)
+                    } else {
+                        self.dbs[db].inner.contains_key(&key)
+                    };
+                    if exists {
+                        return Some((db, key));
+                    }
+                }
+
+                None
+            }
+        };
+    }
+
+    /// Evicts a single key picked by the eviction strategy. Returns false
+    /// if there is nothing left to evict.
+    fn evict_one(&mut self) -> bool {
+        let Some((db, key)) = self.eviction_candidate() else {
+            return false;
+        };
+
+        self.dbs[db].unlink(&key);
+        self.touch_watched_key(db, &key);
+        self.evicted_keys += 1;
+        // The key is gone for good, the AOF must not bring it back.
+        self.feed_aof(db, vec![b"DEL".to_vec(), key]);
+
+        return true;
+    }
+
+    /// Whether used memory is over `maxmemory`, or the number of keys over
+    /// the deprecated `keys_limit`.
+    fn over_limits(&self) -> bool {
+        let maxmemory = self.config.maxmemory as usize;
+        if maxmemory != 0 && self.used_memory() > maxmemory {
+            return true;
+        }
+
+        return self.config.keys_limit.is_some_and(|limit| self.total_keys() > limit);
+    }
+
+    /// Evicts keys until used memory is back under `maxmemory`. Returns
+    /// false when that isn't possible, e.g. with `noeviction` or when a
+    /// volatile strategy runs out of keys with a TTL.
+    pub fn perform_evictions(&mut self) -> bool {
+        self.measure_resized_keys();
+        while self.over_limits() {
+            if !self.evict_one() {
+                return false;
+            }
+        }
+
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        data::store::{ObjectValue, TYPE_STRING},
+    };
+
+    fn store_with(strategy: &str) -> Store {
+        return Store::new(Config::parse_from([
+            "redrust",
+            "--eviction-strategy",
+            strategy,
+            "--maxmemory-samples",
+            "10",
+        ]));
+    }
+
+    fn string(s: &str) -> StoreObject {
+        return StoreObject::new(
+            ObjectValue::String(s.as_bytes().to_vec()),
+            -1,
+            TYPE_STRING,
+            0,
+        );
+    }
+
+    #[test]
+    fn test_allkeys_lru_evicts_the_idlest_key() {
+        let mut store = store_with("allkeys-lru");
+        for (i, key) in ["a", "b", "c", "d"].iter().enumerate() {
+            let mut obj = string(key);
+            obj.lru -= 100 - i as u32;
+            store.put(key.as_bytes().to_vec(), obj);
+        }
+
+        assert!(store.evict_one());
+        assert!(store.get(b"a").is_none());
+        assert!(store.get(b"b").is_some());
     }
 
-    pub(super) fn evict(&mut self) {
-        match self.config.eviction_strategy.as_str() {
-            "simple-first" => self.evict_first(),
-            _ => (),
+    #[test]
+    fn test_volatile_ttl_only_evicts_keys_with_a_ttl() {
+        let mut store = store_with("volatile-ttl");
+        let now = Utc::now().timestamp_millis();
+        store.put(b"far".to_vec(), string("x"));
+        store.set_expiry(b"far", now + 60_000);
+        store.put(b"near".to_vec(), string("x"));
+        store.set_expiry(b"near", now + 1_000);
+        store.put(b"persistent1".to_vec(), string("x"));
+        store.put(b"persistent2".to_vec(), string("x"));
+
+        assert!(store.evict_one());
+        assert!(store.get(b"near").is_none());
+        assert!(store.get(b"far").is_some());
+
+        assert!(store.evict_one());
+        assert!(store.get(b"far").is_none());
+
+        // Nothing left with a TTL
+        assert!(!store.evict_one());
+        assert!(store.get(b"persistent1").is_some());
+    }
+
+    #[test]
+    fn test_noeviction_never_evicts() {
+        let mut store = store_with("noeviction");
+        for key in ["a", "b", "c", "d"] {
+            store.put(key.as_bytes().to_vec(), string(key));
         }
+
+        assert!(!store.evict_one());
+        assert!(store.get(b"a").is_some());
+    }
+
+    #[test]
+    fn test_deprecated_keys_limit_still_evicts() {
+        let mut store = Store::new(Config::parse_from([
+            "redrust",
+            "--keys-limit",
+            "2",
+            "--eviction-strategy",
+            "allkeys-random",
+        ]));
+        for key in ["a", "b", "c", "d"] {
+            store.put(key.as_bytes().to_vec(), string(key));
+        }
+
+        assert!(store.perform_evictions());
+        assert_eq!(store.total_keys(), 2);
     }
 }
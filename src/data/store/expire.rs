@@ -1,48 +1,94 @@
 use chrono::Utc;
 
-use super::Store;
+use super::{ObjectValue, Store};
+
+/// Keys with a TTL looked at per round of active expiry.
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
 
 impl Store {
-    // TODO: Optimize
-    //   - Sampling
-    //   - Unnecessary iteration
-    fn expire_sample(&mut self) -> f32 {
-        let mut limit = 20;
+    /// Deletes the expired keys among a random sample of keys with a TTL of
+    /// database `db` and returns the fraction of the sample that was expired.
+    fn expire_sample(&mut self, index: usize) -> f32 {
+        let now = Utc::now().timestamp_millis();
+        let db = &mut self.dbs[index];
+        let sampled = db
+            .expires
+            .sample(ACTIVE_EXPIRE_SAMPLE)
+            .into_iter()
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        if sampled.is_empty() {
+            return 0.0;
+        }
+
         let mut expired_count = 0;
-        let mut keys_to_remove = Vec::<String>::new();
+        for k in sampled.iter() {
+            if db.inner.get(k).is_some_and(|obj| obj.is_expired_at(now)) {
+                db.unlink(k);
+                self.watched.touch(index, k, db);
+                expired_count += 1;
+            }
+        }
 
-        // Iteration of Hashmap is not in order
-        for (key, val) in self.inner.iter() {
-            if val.expires_at != -1 {
-                limit -= 1;
+        return expired_count as f32 / sampled.len() as f32;
+    }
 
-                if val.expires_at <= Utc::now().timestamp_millis() {
-                    keys_to_remove.push(key.to_string());
-                }
-            }
+    /// Deletes the expired fields of a random sample of hashes of database
+    /// `db` that have fields with a TTL, and returns the fraction of the
+    /// sample that had some.
+    fn expire_fields_sample(&mut self, index: usize) -> f32 {
+        let now = Utc::now().timestamp_millis();
+        let db = &mut self.dbs[index];
+        let sampled = db
+            .volatile_hashes
+            .sample(ACTIVE_EXPIRE_SAMPLE)
+            .into_iter()
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        if sampled.is_empty() {
+            return 0.0;
+        }
 
-            if limit == 0 {
-                break;
+        let mut expired_count = 0;
+        for k in sampled.iter() {
+            let Some(obj) = db.inner.get_mut(k) else {
+                continue;
+            };
+            let ObjectValue::Hash(hash) = &mut obj.value else {
+                continue;
+            };
+            if hash.remove_expired(now) == 0 {
+                continue;
             }
-        }
 
-        for k in keys_to_remove {
-            self.inner.remove(&k);
             expired_count += 1;
+            if hash.is_empty() {
+                db.unlink(k);
+            } else {
+                obj.refresh_encoding();
+                db.track_field_expiries(k);
+                db.measure(k);
+            }
+            self.watched.touch(index, k, db);
         }
 
-        return expired_count as f32 / 20.0;
+        return expired_count as f32 / sampled.len() as f32;
     }
 
     // Delete expired keys active mode
     // Sampling approach: https://redis.io/commands/expire/
     pub fn delete_expired_keys(&mut self) {
-        loop {
-            let frac = self.expire_sample();
+        for db in 0..self.dbs.len() {
+            loop {
+                let frac = self.expire_sample(db);
 
-            if frac < 0.25 {
-                break;
+                if frac < 0.25 {
+                    break;
+                }
             }
+
+            // Same for hash fields
+            while self.expire_fields_sample(db) >= 0.25 {}
         }
     }
 }
@@ -0,0 +1,215 @@
+use std::mem::size_of;
+
+use crate::data::{
+    hash::Hash,
+    list::List,
+    listpack::Listpack,
+    set::Set,
+    stream::{Fields, PendingEntry, StreamId},
+    zset::Zset,
+};
+
+use super::{Db, ObjectValue, Store, StoreObject};
+
+/// Elements looked at to estimate the size of an aggregate value, like
+/// Redis' `MEMORY USAGE` default.
+pub const MEMORY_USAGE_SAMPLES: usize = 5;
+
+/// Formats a number of bytes the way INFO does, e.g. `1.50M`.
+fn bytes_to_human(n: u64) -> String {
+    const UNITS: [(&str, f64); 5] = [
+        ("P", (1_u64 << 50) as f64),
+        ("T", (1_u64 << 40) as f64),
+        ("G", (1_u64 << 30) as f64),
+        ("M", (1_u64 << 20) as f64),
+        ("K", (1_u64 << 10) as f64),
+    ];
+
+    for (unit, size) in UNITS {
+        if n as f64 >= size {
+            return format!("{:.2}{}", n as f64 / size, unit);
+        }
+    }
+
+    return format!("{}B", n);
+}
+
+/// Sums the first `samples` sizes and extrapolates to `len` items, 0 samples
+/// every item.
+fn sampled_size(sizes: impl Iterator<Item = usize>, len: usize, samples: usize) -> usize {
+    let take = if samples == 0 { len } else { samples.min(len) };
+    if take == 0 {
+        return 0;
+    }
+
+    return sizes.take(take).sum::<usize>() * len / take;
+}
+
+/// Estimated heap usage of a value. Only `samples` elements of an aggregate
+/// are measured and the average is extrapolated to the rest.
+fn value_size(value: &ObjectValue, samples: usize) -> usize {
+    return match value {
+        ObjectValue::String(s) => s.capacity(),
+        ObjectValue::List(List::Listpack(lp)) => lp.capacity(),
+        ObjectValue::List(List::Quicklist(ql)) => {
+            let nodes = ql.nodes().count();
+            nodes * size_of::<Listpack>()
+                + sampled_size(ql.nodes().map(|n| n.capacity()), nodes, samples)
+        }
+        ObjectValue::Hash(Hash::Listpack(lp) | Hash::ListpackEx(lp)) => lp.capacity(),
+        ObjectValue::Hash(Hash::Table { fields, deadlines }) => {
+            let sizes = fields.iter().map(|(f, (v, _))| f.capacity() + v.capacity());
+            let deadline_sizes = deadlines.iter().map(|(_, f)| f.capacity());
+            fields.len() * size_of::<(Vec<u8>, (Vec<u8>, i64))>()
+                + sampled_size(sizes, fields.len(), samples)
+                + deadlines.len() * size_of::<(i64, Vec<u8>)>()
+                + sampled_size(deadline_sizes, deadlines.len(), samples)
+        }
+        ObjectValue::Set(Set::Intset(is)) => is.capacity(),
+        ObjectValue::Set(Set::Table(table)) => {
+            let sizes = table.iter().map(|(m, _)| m.capacity());
+            table.len() * size_of::<(Vec<u8>, ())>() + sampled_size(sizes, table.len(), samples)
+        }
+        ObjectValue::Zset(Zset::Listpack(lp)) => lp.capacity(),
+        ObjectValue::Zset(Zset::Skiplist { scores, index }) => {
+            let sizes = scores.iter().map(|(m, _)| m.capacity());
+            scores.len() * size_of::<(Vec<u8>, f64)>()
+                + sampled_size(sizes, scores.len(), samples)
+                + index.capacity()
+        }
+        ObjectValue::Stream(stream) => {
+            let sizes = stream
+                .entries
+                .values()
+                .map(|fields| fields.iter().map(|f| f.capacity()).sum::<usize>());
+            let pending = stream.groups.values().map(|g| g.pel.len()).sum::<usize>();
+            stream.len() * size_of::<(StreamId, Fields)>()
+                + sampled_size(sizes, stream.len(), samples)
+                + pending * (size_of::<(StreamId, PendingEntry)>() + size_of::<StreamId>())
+        }
+    };
+}
+
+impl StoreObject {
+    /// Estimated memory used by the key and this object in the keyspace.
+    pub fn memory_usage(&self, key: &[u8], samples: usize) -> usize {
+        return size_of::<(Vec<u8>, StoreObject)>() + key.len() + value_size(&self.value, samples);
+    }
+}
+
+impl Db {
+    /// Accounts for the memory of `k` again after it was modified in place.
+    pub(super) fn measure(&mut self, k: &[u8]) {
+        let Some(obj) = self.inner.get_mut(k) else {
+            return;
+        };
+
+        let memory = obj.memory_usage(k, MEMORY_USAGE_SAMPLES);
+        self.used_memory = self.used_memory - obj.memory + memory;
+        obj.memory = memory;
+    }
+}
+
+impl Store {
+    /// Estimated memory used by the keys of every database, as accounted
+    /// for each object when it's linked or modified.
+    pub fn used_memory(&self) -> usize {
+        return self.dbs.iter().map(|db| db.used_memory).sum();
+    }
+
+    /// Accounts for the keys the running command modified through
+    /// `get_mut`, they may have grown or shrunk.
+    pub(super) fn measure_resized_keys(&mut self) {
+        for (db, k) in std::mem::take(&mut self.resized) {
+            self.dbs[db].measure(&k);
+        }
+        self.peak_memory = self.peak_memory.max(self.used_memory());
+    }
+
+    /// Estimated memory used by a key and its value, `None` if it doesn't
+    /// exist.
+    pub fn memory_usage(&mut self, key: &[u8], samples: usize) -> Option<usize> {
+        return self.get(key).map(|obj| obj.memory_usage(key, samples));
+    }
+
+    pub fn memory_info(&self) -> Vec<(&'static str, String)> {
+        let used = self.used_memory() as u64;
+        let peak = self.peak_memory.max(self.used_memory()) as u64;
+        let maxmemory = self.config.maxmemory;
+
+        return vec![
+            ("used_memory", used.to_string()),
+            ("used_memory_human", bytes_to_human(used)),
+            ("used_memory_peak", peak.to_string()),
+            ("used_memory_peak_human", bytes_to_human(peak)),
+            (
+                "used_memory_peak_perc",
+                format!("{:.2}%", used as f64 * 100.0 / peak.max(1) as f64),
+            ),
+            ("maxmemory", maxmemory.to_string()),
+            ("maxmemory_human", bytes_to_human(maxmemory)),
+            ("maxmemory_policy", self.config.eviction_strategy.name()),
+        ];
+    }
+
+    pub fn stats_info(&self) -> Vec<(&'static str, String)> {
+        return vec![("evicted_keys", self.evicted_keys.to_string())];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{config::Config, data::store::TYPE_STRING};
+
+    fn string(s: &[u8]) -> StoreObject {
+        return StoreObject::new(ObjectValue::String(s.to_vec()), -1, TYPE_STRING, 0);
+    }
+
+    #[test]
+    fn test_bytes_to_human() {
+        assert_eq!(bytes_to_human(1000), "1000B");
+        assert_eq!(bytes_to_human(1536), "1.50K");
+        assert_eq!(bytes_to_human(100 << 20), "100.00M");
+    }
+
+    #[test]
+    fn test_aggregate_size_is_extrapolated_from_samples() {
+        let sizes = [10, 10, 1000];
+        assert_eq!(sampled_size(sizes.into_iter(), 3, 2), 30);
+        assert_eq!(sampled_size(sizes.into_iter(), 3, 0), 1020);
+        assert_eq!(sampled_size(sizes.into_iter(), 0, 5), 0);
+    }
+
+    #[test]
+    fn test_used_memory_follows_the_objects() {
+        let mut store = Store::new(Config::parse_from(["redrust"]));
+        assert_eq!(store.used_memory(), 0);
+
+        store.put(b"a".to_vec(), string(b"x"));
+        let one = store.used_memory();
+        assert_eq!(Some(one), store.memory_usage(b"a", MEMORY_USAGE_SAMPLES));
+
+        // Grown in place, accounted for once the command is done with it
+        let Some(ObjectValue::String(s)) = store.get_mut(b"a").map(|obj| &mut obj.value) else {
+            unreachable!();
+        };
+        s.extend_from_slice(&[b'x'; 1000]);
+        store.discard_propagation();
+        assert!(store.used_memory() >= one + 1000);
+        assert_eq!(
+            Some(store.used_memory()),
+            store.memory_usage(b"a", MEMORY_USAGE_SAMPLES)
+        );
+
+        store.put(b"b".to_vec(), string(b"x"));
+        store.del(b"a");
+        assert_eq!(store.used_memory(), one);
+        assert!(store.peak_memory >= one + 1000);
+
+        store.flush(false);
+        assert_eq!(store.used_memory(), 0);
+    }
+}
@@ -1,34 +1,342 @@
+use std::collections::HashMap;
+
 use anyhow::anyhow;
 use chrono::Utc;
 
-use crate::{common::Value, config::Config};
-use std::collections::HashMap;
+use crate::{
+    common::parse_i64,
+    config::Config,
+    data::{
+        dict::Dict,
+        hash::{Hash, HashLimits},
+        list::{List, ListpackLimit},
+        set::Set,
+        stream::Stream,
+        zset::{Zset, ZsetLimits},
+    },
+};
 
 pub const TYPE_STRING: u8 = 0 << 4;
+pub const TYPE_LIST: u8 = 1 << 4;
+pub const TYPE_SET: u8 = 2 << 4;
+pub const TYPE_ZSET: u8 = 3 << 4;
+pub const TYPE_HASH: u8 = 4 << 4;
+pub const TYPE_STREAM: u8 = 6 << 4;
 
 pub const ENCODING_RAW: u8 = 0;
 pub const ENCODING_INT: u8 = 1;
+pub const ENCODING_HT: u8 = 2;
+pub const ENCODING_INTSET: u8 = 6;
+pub const ENCODING_SKIPLIST: u8 = 7;
 pub const ENCODING_EMBSTR: u8 = 8;
+pub const ENCODING_QUICKLIST: u8 = 9;
+pub const ENCODING_STREAM: u8 = 10;
+pub const ENCODING_LISTPACK: u8 = 11;
+pub const ENCODING_LISTPACK_EX: u8 = 12;
 
 pub const EMBED_STRING_MAX_LENGTH: usize = 44;
 
+/// Keys with a TTL looked at to estimate their average TTL for INFO.
+const AVG_TTL_SAMPLES: usize = 64;
+
+/// One of the logical databases picked with SELECT.
+#[derive(Default)]
+pub struct Db {
+    inner: Dict<Vec<u8>, StoreObject>,
+    /// Keys of `inner` that have a TTL, kept in sync by `link` and `unlink`
+    /// so they can be sampled on their own.
+    expires: Dict<Vec<u8>, ()>,
+    /// Keys of hashes with fields that have a TTL, for active expiry.
+    volatile_hashes: Dict<Vec<u8>, ()>,
+    /// Sum of the memory accounted for each object of `inner`.
+    used_memory: usize,
+}
+
+impl Db {
+    /// Adds or replaces a key, keeping `expires` and `used_memory` in sync.
+    fn link(&mut self, k: Vec<u8>, mut obj: StoreObject) -> Option<StoreObject> {
+        if obj.expires_at != -1 {
+            self.expires.insert(k.clone(), ());
+        } else {
+            self.expires.remove(&k);
+        }
+        if obj.has_volatile_fields() {
+            self.volatile_hashes.insert(k.clone(), ());
+        } else {
+            self.volatile_hashes.remove(&k);
+        }
+        obj.memory = obj.memory_usage(&k, MEMORY_USAGE_SAMPLES);
+        self.used_memory += obj.memory;
+
+        let old = self.inner.insert(k, obj);
+        if let Some(old) = &old {
+            self.used_memory -= old.memory;
+        }
+        return old;
+    }
+
+    /// Removes a key, keeping `expires` and `used_memory` in sync.
+    fn unlink(&mut self, k: &[u8]) -> Option<StoreObject> {
+        let obj = self.inner.remove(k)?;
+        self.used_memory -= obj.memory;
+        if obj.expires_at != -1 {
+            self.expires.remove(k);
+        }
+        if obj.has_volatile_fields() {
+            self.volatile_hashes.remove(k);
+        }
+
+        return Some(obj);
+    }
+
+    fn track_field_expiries(&mut self, k: &[u8]) {
+        let volatile = self.inner.get(k).is_some_and(|obj| obj.has_volatile_fields());
+        if volatile {
+            if !self.volatile_hashes.contains_key(k) {
+                self.volatile_hashes.insert(k.to_vec(), ());
+            }
+        } else {
+            self.volatile_hashes.remove(k);
+        }
+    }
+
+    fn len(&self) -> usize {
+        return self.inner.len();
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.inner.is_empty();
+    }
+
+    /// Every key that isn't expired at `now`, with its object.
+    fn iter(&self, now: i64) -> impl Iterator<Item = (&[u8], &StoreObject)> {
+        return self
+            .inner
+            .iter()
+            .filter(move |(_, obj)| !obj.is_expired_at(now))
+            .map(|(k, obj)| (k.as_slice(), obj));
+    }
+}
+
 pub struct Store {
-    inner: HashMap<String, StoreObject>,
+    /// The databases, commands run against the one at `db`.
+    dbs: Vec<Db>,
+    /// Database selected by the client whose command is running, see
+    /// `select`.
+    db: usize,
     config: Config,
+    aof: Option<aof::Aof>,
+    /// Database the last command fed to the AOF ran against, `None` to log a
+    /// SELECT before the next one whatever its database.
+    aof_db: Option<usize>,
+    aof_rewrite: aof::AofRewrite,
+    rdb: rdb::RdbState,
+    /// Background process working on a snapshot, see `child.rs`.
+    child: Option<child::Child>,
+    /// Number of changes made to the dataset, commands that don't move it
+    /// are not propagated to the AOF.
+    dirty: u64,
+    /// Commands to log instead of the one being executed, see
+    /// `rewrite_propagation`.
+    propagate_as: Vec<Vec<Vec<u8>>>,
+    /// Set while EXEC runs, to whether the MULTI that opens its writes in
+    /// the AOF was logged yet.
+    exec_propagation: Option<bool>,
+    /// The dataset is being rebuilt from disk.
+    loading: bool,
+    eviction_pool: eviction::EvictionPool,
+    evicted_keys: u64,
+    /// Clients blocked on each key by BLPOP and friends, per database. They
+    /// stay with the database index when SWAPDB moves the data around.
+    blocking_keys: Vec<HashMap<Vec<u8>, blocking::BlockedQueue>>,
+    /// Keys created or, for streams, added to since blocked clients were
+    /// last served, along with their database.
+    ready_keys: Vec<(usize, Vec<u8>)>,
+    watched: watch::WatchedKeys,
+    /// Keys the running command looked up through `get_mut`, along with
+    /// their database, whose memory must be accounted for again once it's
+    /// done with them.
+    resized: Vec<(usize, Vec<u8>)>,
+    /// Highest memory the dataset ever used.
+    peak_memory: usize,
 }
 
 impl Store {
     pub fn new(config: Config) -> Store {
+        let databases = config.databases as usize;
         return Store {
-            inner: HashMap::new(),
-            config: config,
+            dbs: (0..databases).map(|_| Db::default()).collect(),
+            db: 0,
+            config,
+            aof: None,
+            aof_db: None,
+            aof_rewrite: aof::AofRewrite::default(),
+            rdb: rdb::RdbState::default(),
+            child: None,
+            dirty: 0,
+            propagate_as: Vec::new(),
+            exec_propagation: None,
+            loading: false,
+            eviction_pool: eviction::EvictionPool::default(),
+            evicted_keys: 0,
+            blocking_keys: (0..databases).map(|_| HashMap::new()).collect(),
+            ready_keys: Vec::new(),
+            watched: watch::WatchedKeys::new(databases),
+            resized: Vec::new(),
+            peak_memory: 0,
         };
     }
 
-    fn may_remove(&mut self, k: &String) -> Option<()> {
-        if let Some(i) = self.inner.get(k) {
-            if i.expires_at != -1 && i.expires_at <= Utc::now().timestamp_millis() {
-                self.inner.remove(k);
+    pub fn is_loading(&self) -> bool {
+        return self.loading;
+    }
+
+    pub fn dirty(&self) -> u64 {
+        return self.dirty;
+    }
+
+    /// Records `n` changes made to the dataset in place, e.g. through
+    /// `get_mut`. `put` and `del` account for themselves.
+    pub fn add_dirty(&mut self, n: u64) {
+        self.dirty += n;
+    }
+
+    /// Makes the current command propagate as `argv` instead of itself. May
+    /// be called several times to log a sequence of commands, e.g. to turn a
+    /// relative expiry into an absolute one that replays identically.
+    pub fn rewrite_propagation(&mut self, argv: Vec<Vec<u8>>) {
+        self.propagate_as.push(argv);
+    }
+
+    /// Logs a write command that changed the dataset, honoring any rewrite
+    /// requested while it ran, and invalidates the transactions watching the
+    /// keys it modified.
+    pub fn propagate(&mut self, argv: Vec<Vec<u8>>) {
+        self.touch_pending_watched_keys();
+        self.measure_resized_keys();
+        if self.exec_propagation == Some(false) {
+            self.feed_aof(self.db, vec![b"MULTI".to_vec()]);
+            self.exec_propagation = Some(true);
+        }
+        if self.propagate_as.is_empty() {
+            self.feed_aof(self.db, argv);
+            return;
+        }
+
+        for argv in std::mem::take(&mut self.propagate_as) {
+            self.feed_aof(self.db, argv);
+        }
+    }
+
+    /// Drops rewrites requested by a command that turned out not to change
+    /// anything, and forgets the keys it looked up through `get_mut`.
+    pub fn discard_propagation(&mut self) {
+        self.propagate_as.clear();
+        self.discard_pending_watched_keys();
+        // Looking a key up may still resize it, e.g. by dropping expired
+        // hash fields
+        self.measure_resized_keys();
+    }
+
+    /// Makes the writes of the commands that follow, up to `end_exec`,
+    /// propagate as a transaction so that they replay atomically.
+    pub fn begin_exec(&mut self) {
+        self.exec_propagation = Some(false);
+    }
+
+    pub fn end_exec(&mut self) {
+        if self.exec_propagation.take() == Some(true) {
+            // Whatever the database, no need for a SELECT
+            let db = self.aof_db.unwrap_or(self.db);
+            self.feed_aof(db, vec![b"EXEC".to_vec()]);
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        return &self.config;
+    }
+
+    pub fn list_limit(&self) -> ListpackLimit {
+        return ListpackLimit(self.config.list_max_listpack_size);
+    }
+
+    pub fn set_max_intset_entries(&self) -> usize {
+        return self.config.set_max_intset_entries;
+    }
+
+    pub fn zset_limits(&self) -> ZsetLimits {
+        return ZsetLimits {
+            max_entries: self.config.zset_max_listpack_entries,
+            max_value: self.config.zset_max_listpack_value,
+        };
+    }
+
+    pub fn hash_limits(&self) -> HashLimits {
+        return HashLimits {
+            max_entries: self.config.hash_max_listpack_entries,
+            max_value: self.config.hash_max_listpack_value,
+        };
+    }
+
+    /// Makes the following calls operate on database `db`, which must be in
+    /// range. Set from the client before each of its commands runs.
+    pub fn select(&mut self, db: usize) {
+        self.db = db;
+    }
+
+    pub fn databases(&self) -> usize {
+        return self.dbs.len();
+    }
+
+    /// The selected database.
+    fn db(&self) -> &Db {
+        return &self.dbs[self.db];
+    }
+
+    fn db_mut(&mut self) -> &mut Db {
+        return &mut self.dbs[self.db];
+    }
+
+    /// Adds or replaces a key, waking up the clients blocked on it and
+    /// invalidating the transactions watching it.
+    fn link(&mut self, k: Vec<u8>, obj: StoreObject) -> Option<StoreObject> {
+        if !self.blocking_keys[self.db].is_empty() && !self.db().inner.contains_key(&k) {
+            self.signal_key_as_ready(&k);
+        }
+
+        let watched = self.watched.is_watched(self.db, &k).then(|| k.clone());
+        let old = self.db_mut().link(k, obj);
+        if let Some(k) = watched {
+            self.touch_watched_key(self.db, &k);
+        }
+
+        return old;
+    }
+
+    fn unlink(&mut self, k: &[u8]) -> Option<StoreObject> {
+        let obj = self.db_mut().unlink(k)?;
+        self.touch_watched_key(self.db, k);
+
+        return Some(obj);
+    }
+
+    /// Must be called after the fields of the hash at `k` were changed
+    /// through `get_mut`, so that active expiry knows about their TTLs.
+    pub fn track_field_expiries(&mut self, k: &[u8]) {
+        self.db_mut().track_field_expiries(k);
+    }
+
+    /// Whether `obj` is past its TTL at `now`. Nothing expires while the AOF
+    /// is loaded: its commands ran against keys that were alive back then,
+    /// same as Redis' `keyIsExpired`.
+    fn is_expired(&self, obj: &StoreObject, now: i64) -> bool {
+        return !self.loading && obj.is_expired_at(now);
+    }
+
+    fn may_remove(&mut self, k: &[u8]) -> Option<()> {
+        if let Some(i) = self.db().inner.get(k) {
+            if self.is_expired(i, Utc::now().timestamp_millis()) {
+                self.unlink(k);
                 return None;
             }
 
@@ -38,45 +346,284 @@ impl Store {
         return None;
     }
 
-    pub fn get(&mut self, k: &String) -> Option<&StoreObject> {
-        return self.may_remove(k).and_then(|_| self.inner.get(k));
+    pub fn exists(&mut self, k: &[u8]) -> bool {
+        return self.may_remove(k).is_some();
+    }
+
+    pub fn get(&mut self, k: &[u8]) -> Option<&StoreObject> {
+        self.may_remove(k)?;
+        let lfu = self.config.eviction_strategy.is_lfu();
+        let obj = self.db_mut().inner.get_mut(k)?;
+        obj.touch(lfu);
+        return Some(obj);
+    }
+
+    /// Read-only lookup for commands that need several keys at once: expired
+    /// keys are hidden but not removed, and it doesn't count as an access.
+    /// Go through `get` first for those.
+    pub fn get_ref(&self, k: &[u8]) -> Option<&StoreObject> {
+        let now = Utc::now().timestamp_millis();
+        return self.db().inner.get(k).filter(|obj| !self.is_expired(obj, now));
+    }
+
+    /// Like `get`, without counting as an access for eviction.
+    pub fn peek(&mut self, k: &[u8]) -> Option<&StoreObject> {
+        self.may_remove(k)?;
+        return self.db().inner.get(k);
+    }
+
+    pub fn get_mut(&mut self, k: &[u8]) -> Option<&mut StoreObject> {
+        self.may_remove(k)?;
+        self.may_touch_watched_key(k);
+        self.resized.push((self.db, k.to_vec()));
+        let lfu = self.config.eviction_strategy.is_lfu();
+        let obj = self.db_mut().inner.get_mut(k)?;
+        obj.touch(lfu);
+        return Some(obj);
     }
 
-    pub fn get_mut(&mut self, k: &String) -> Option<&mut StoreObject> {
-        return self.may_remove(k).and_then(|_| self.inner.get_mut(k));
+    pub fn get_or_insert(&mut self, k: &[u8], default: StoreObject) -> &mut StoreObject {
+        self.may_remove(k);
+        if self.db().inner.contains_key(k) {
+            self.may_touch_watched_key(k);
+        } else {
+            self.link(k.to_vec(), default);
+        }
+        self.resized.push((self.db, k.to_vec()));
+
+        let lfu = self.config.eviction_strategy.is_lfu();
+        let obj = self.db_mut().inner.get_mut(k).unwrap();
+        obj.touch(lfu);
+        return obj;
     }
 
-    pub fn get_or_insert(&mut self, k: &String, default: StoreObject) -> &mut StoreObject {
-        return match self.may_remove(k) {
-            _ => self.inner.entry(k.to_string()).or_insert(default),
+    /// Sets the absolute expiry of an existing key, -1 removes it. Expiries
+    /// must be changed through here rather than `get_mut` so that the key
+    /// can be found by active expiry and the volatile eviction policies.
+    pub fn set_expiry(&mut self, k: &[u8], expires_at: i64) -> bool {
+        let Some(obj) = self.get_mut(k) else {
+            return false;
         };
+        obj.expires_at = expires_at;
+
+        let expires = &mut self.db_mut().expires;
+        if expires_at != -1 {
+            expires.insert(k.to_vec(), ());
+        } else {
+            expires.remove(k);
+        }
+
+        return true;
+    }
+
+    pub fn put(&mut self, k: Vec<u8>, obj: StoreObject) -> Option<StoreObject> {
+        self.dirty += 1;
+        return self.link(k, obj);
     }
 
-    pub fn put(&mut self, k: String, obj: StoreObject) -> Option<StoreObject> {
-        if self.inner.len() >= self.config.keys_limit as usize {
-            self.evict();
+    pub fn del(&mut self, k: &[u8]) -> bool {
+        let deleted = self.unlink(k).is_some();
+        if deleted {
+            self.dirty += 1;
         }
-        return self.inner.insert(k, obj);
+
+        return deleted;
     }
 
-    pub fn del(&mut self, k: String) -> bool {
-        return self.inner.remove(&k).map_or(false, |_| true);
+    /// Removes a key and hands its object over, e.g. to move it under
+    /// another name.
+    pub fn take(&mut self, k: &[u8]) -> Option<StoreObject> {
+        self.may_remove(k)?;
+        let obj = self.unlink(k)?;
+        self.dirty += 1;
+
+        return Some(obj);
+    }
+
+    /// Moves `k` from the selected database to `db`. Returns false if it
+    /// isn't there, or if `db` has a key by that name already.
+    pub fn move_key(&mut self, k: &[u8], db: usize) -> bool {
+        let from = self.db;
+        self.db = db;
+        let taken = self.exists(k);
+        self.db = from;
+        if taken {
+            return false;
+        }
+        let Some(obj) = self.take(k) else {
+            return false;
+        };
+
+        self.db = db;
+        self.put(k.to_vec(), obj);
+        self.db = from;
+        return true;
+    }
+
+    /// Exchanges the keys of two databases. Clients blocked on keys stay
+    /// with their database index, and are woken up by the keys they find
+    /// there now.
+    pub fn swap_dbs(&mut self, a: usize, b: usize) {
+        self.dbs.swap(a, b);
+        self.watched.touch_all(a, &self.dbs[b], &self.dbs[a]);
+        self.watched.touch_all(b, &self.dbs[a], &self.dbs[b]);
+        for db in [a, b] {
+            let ready = self.blocking_keys[db]
+                .keys()
+                .filter(|k| self.dbs[db].inner.contains_key(k.as_slice()))
+                .map(|k| (db, k.clone()))
+                .collect::<Vec<_>>();
+            for key in ready {
+                if !self.ready_keys.contains(&key) {
+                    self.ready_keys.push(key);
+                }
+            }
+        }
+        self.dirty += 1;
+    }
+
+    /// Number of keys of the selected database, including expired ones that
+    /// weren't removed yet.
+    pub fn len(&self) -> usize {
+        return self.db().len();
+    }
+
+    /// Every key of the selected database that isn't expired, with its
+    /// object.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &StoreObject)> {
+        return self.db().iter(Utc::now().timestamp_millis());
+    }
+
+    /// Calls `f` on the keys of one bucket of the selected database, leaving
+    /// out the expired ones, and returns the cursor of the next, see
+    /// `Dict::scan`.
+    pub fn scan(&self, cursor: u64, mut f: impl FnMut(&[u8], &StoreObject)) -> u64 {
+        let now = Utc::now().timestamp_millis();
+        return self.db().inner.scan(cursor, |k, obj| {
+            if !obj.is_expired_at(now) {
+                f(k, obj);
+            }
+        });
+    }
+
+    /// A random key that isn't expired, removing the expired ones it runs
+    /// into.
+    pub fn random_key(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let (k, _) = self.db().inner.random_entry()?;
+            let k = k.clone();
+            if self.may_remove(&k).is_some() {
+                return Some(k);
+            }
+        }
+    }
+
+    /// Removes every key of the selected database and returns how many there
+    /// were. With `lazy`, the memory is given back by a background thread
+    /// rather than right away.
+    pub fn flush(&mut self, lazy: bool) -> usize {
+        let old = std::mem::take(self.db_mut());
+        self.watched.touch_all(self.db, &old, &self.dbs[self.db]);
+        let removed = old.len();
+        if lazy {
+            std::thread::spawn(move || drop(old));
+        }
+        self.dirty += removed as u64;
+
+        return removed;
+    }
+
+    /// Same as `flush` for every database.
+    pub fn flush_all(&mut self, lazy: bool) -> usize {
+        let old = self.dbs.iter_mut().map(std::mem::take).collect::<Vec<_>>();
+        for (i, db) in old.iter().enumerate() {
+            self.watched.touch_all(i, db, &self.dbs[i]);
+        }
+        let removed = old.iter().map(|db| db.len()).sum::<usize>();
+        if lazy {
+            std::thread::spawn(move || drop(old));
+        }
+        self.dirty += removed as u64;
+
+        return removed;
+    }
+
+    /// Total number of keys, for progress reports and `keys_limit`.
+    pub(super) fn total_keys(&self) -> usize {
+        return self.dbs.iter().map(|db| db.len()).sum();
+    }
+
+    /// `INFO keyspace` fields: the keys and keys with a TTL of each database
+    /// that has any, along with the average TTL of a sample of the latter.
+    pub fn keyspace_info(&self) -> Vec<(String, String)> {
+        let now = Utc::now().timestamp_millis();
+
+        return self
+            .dbs
+            .iter()
+            .enumerate()
+            .filter(|(_, db)| !db.is_empty())
+            .map(|(i, db)| {
+                let ttls = db
+                    .expires
+                    .sample(AVG_TTL_SAMPLES)
+                    .into_iter()
+                    .filter_map(|(k, _)| db.inner.get(k))
+                    .map(|obj| (obj.expires_at - now).max(0))
+                    .collect::<Vec<_>>();
+                let avg_ttl = ttls.iter().sum::<i64>() / (ttls.len().max(1) as i64);
+                let fields = format!(
+                    "keys={},expires={},avg_ttl={}",
+                    db.len(),
+                    db.expires.len(),
+                    avg_ttl
+                );
+                (format!("db{}", i), fields)
+            })
+            .collect();
     }
 }
 
 mod aof;
+mod blocking;
+mod child;
 mod eviction;
 mod expire;
+mod memory;
+mod rdb;
+mod watch;
+
+pub use memory::MEMORY_USAGE_SAMPLES;
+
+/// Value of a key, one variant per type.
+#[derive(Clone)]
+pub enum ObjectValue {
+    String(Vec<u8>),
+    List(List),
+    Hash(Hash),
+    Set(Set),
+    Zset(Zset),
+    Stream(Stream),
+}
 
 #[derive(Clone)]
 pub struct StoreObject {
     pub type_encoding: u8,
-    pub value: Value,
+    pub value: ObjectValue,
     pub expires_at: i64,
+    /// Last access, in seconds, for the LRU policies.
+    pub lru: u32,
+    /// Logarithmic access frequency for the LFU policies, see `eviction.rs`.
+    pub lfu_counter: u8,
+    /// Minutes clock of the last time `lfu_counter` was decayed.
+    pub lfu_decay_time: u16,
+    /// Memory accounted for this object in its database, see
+    /// `memory_usage`.
+    memory: usize,
 }
 
 impl StoreObject {
-    pub fn new(value: Value, duration_ms: i64, obj_type: u8, obj_encoding: u8) -> StoreObject {
+    pub fn new(value: ObjectValue, duration_ms: i64, obj_type: u8, obj_encoding: u8) -> StoreObject {
         let mut expires_at = -1_i64;
 
         if duration_ms > 0 {
@@ -87,12 +634,27 @@ impl StoreObject {
             type_encoding: obj_type | obj_encoding,
             value,
             expires_at,
+            lru: eviction::lru_clock(),
+            lfu_counter: eviction::LFU_INIT_VAL,
+            lfu_decay_time: eviction::lfu_minutes(),
+            memory: 0,
         };
     }
 
+    pub fn is_expired_at(&self, now_ms: i64) -> bool {
+        return self.expires_at != -1 && self.expires_at <= now_ms;
+    }
+
+    /// Whether this is a hash with fields that have a TTL.
+    pub fn has_volatile_fields(&self) -> bool {
+        return matches!(&self.value, ObjectValue::Hash(hash) if hash.has_volatile_fields());
+    }
+
     pub fn assert_type(&self, t: u8) -> anyhow::Result<()> {
         if self.get_type() != t {
-            return Err(anyhow!("the operation is not permitted on this type"));
+            return Err(anyhow!(
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ));
         }
 
         return Ok(());
@@ -106,20 +668,77 @@ impl StoreObject {
         return Ok(());
     }
 
-    fn get_type(&self) -> u8 {
+    pub(crate) fn get_type(&self) -> u8 {
         return self.type_encoding & 0b11110000;
     }
 
-    fn get_encoding(&self) -> u8 {
+    pub(crate) fn get_encoding(&self) -> u8 {
         return self.type_encoding & 0b00001111;
     }
+
+    /// Updates the encoding bits after the value changed, aggregates switch
+    /// representation on their own as they grow and shrink.
+    pub fn refresh_encoding(&mut self) {
+        let encoding = match &self.value {
+            ObjectValue::String(_) => return,
+            ObjectValue::List(List::Listpack(_)) => ENCODING_LISTPACK,
+            ObjectValue::List(List::Quicklist(_)) => ENCODING_QUICKLIST,
+            ObjectValue::Hash(Hash::Listpack(_)) => ENCODING_LISTPACK,
+            ObjectValue::Hash(Hash::ListpackEx(_)) => ENCODING_LISTPACK_EX,
+            ObjectValue::Hash(Hash::Table { .. }) => ENCODING_HT,
+            ObjectValue::Set(Set::Intset(_)) => ENCODING_INTSET,
+            ObjectValue::Set(Set::Table(_)) => ENCODING_HT,
+            ObjectValue::Zset(Zset::Listpack(_)) => ENCODING_LISTPACK,
+            ObjectValue::Zset(Zset::Skiplist { .. }) => ENCODING_SKIPLIST,
+            ObjectValue::Stream(_) => ENCODING_STREAM,
+        };
+        self.type_encoding = self.get_type() | encoding;
+    }
 }
 
-pub fn deduce_type_encoding(value: &String) -> (u8, u8) {
+/// Name of a type as reported by `TYPE`.
+pub fn type_name(obj_type: u8) -> &'static str {
+    return match obj_type {
+        TYPE_STRING => "string",
+        TYPE_LIST => "list",
+        TYPE_SET => "set",
+        TYPE_ZSET => "zset",
+        TYPE_HASH => "hash",
+        TYPE_STREAM => "stream",
+        _ => "unknown",
+    };
+}
+
+/// The type called `name` by `TYPE`, in any case.
+pub fn type_by_name(name: &[u8]) -> Option<u8> {
+    return [TYPE_STRING, TYPE_LIST, TYPE_SET, TYPE_ZSET, TYPE_HASH, TYPE_STREAM]
+        .into_iter()
+        .find(|&t| type_name(t).as_bytes().eq_ignore_ascii_case(name));
+}
+
+/// Name of an encoding as reported by `OBJECT ENCODING`.
+pub fn encoding_name(encoding: u8) -> &'static str {
+    return match encoding {
+        ENCODING_RAW => "raw",
+        ENCODING_INT => "int",
+        ENCODING_HT => "hashtable",
+        ENCODING_INTSET => "intset",
+        ENCODING_SKIPLIST => "skiplist",
+        ENCODING_EMBSTR => "embstr",
+        ENCODING_QUICKLIST => "quicklist",
+        ENCODING_STREAM => "stream",
+        ENCODING_LISTPACK => "listpack",
+        ENCODING_LISTPACK_EX => "listpackex",
+        _ => "unknown",
+    };
+}
+
+pub fn deduce_type_encoding(value: &[u8]) -> (u8, u8) {
     let obj_type = TYPE_STRING;
-    let Err(_) = value.parse::<i64>() else {
+    // Only integers that print back the same, "007" or "+1" must stay as is
+    if parse_i64(value).is_some_and(|i| i.to_string().as_bytes() == value) {
         return (obj_type, ENCODING_INT);
-    };
+    }
 
     if value.len() <= EMBED_STRING_MAX_LENGTH {
         return (obj_type, ENCODING_EMBSTR);
@@ -0,0 +1,823 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::anyhow;
+use chrono::Utc;
+
+use crate::{
+    common::parse_i64,
+    data::{
+        hash::{Hash, HashLimits},
+        list::{List, ListpackLimit},
+        set::Set,
+        stream::{Consumer, ConsumerGroup, Stream, StreamId},
+        zset::{Zset, ZsetLimits},
+    },
+};
+
+use super::{
+    child::{Child, ChildKind},
+    ObjectValue, Store, StoreObject, ENCODING_INT, TYPE_HASH, TYPE_LIST, TYPE_SET, TYPE_STREAM,
+    TYPE_STRING, TYPE_ZSET,
+};
+
+// Snapshot layout:
+//
+//   "REDRUST" <4 digit version>
+//   ( OPCODE_SELECTDB <db>
+//     ( [OPCODE_EXPIRETIME_MS <i64>] <type_encoding> <key> <value> )* )*
+//   OPCODE_EOF <crc64 of everything before, little endian>
+//
+// Only non-empty databases are saved. Lengths, database indexes included,
+// are LEB128 varints, strings are a length followed by their bytes.
+// The value layout depends on the type and encoding bits of the object: a
+// string is a string or an i64 when integer encoded, a list is its length
+// followed by its elements, and so is a set. A sorted set is its length
+// followed by each member and its score as the bits of an f64, a hash its
+// number of fields followed by each field, its value and its expiry as an
+// i64 (-1 for none).
+//
+// A stream is its number of entries followed by each id, as two lengths, and
+// its fields and values as a list. Then come its last id, entries added and
+// max deleted id, and its groups: the name, last id and entries read (an i64,
+// -1 if unknown) of each, followed by its consumers with their seen and
+// active times, then its pending entries with their consumer, delivery time
+// and delivery count.
+
+const RDB_MAGIC: &[u8] = b"REDRUST";
+const RDB_VERSION: &[u8] = b"0001";
+
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+/// Time to wait before retrying an automatic snapshot that failed.
+const BGSAVE_RETRY_DELAY_MS: i64 = 5_000;
+
+/// CRC-64/Jones, the checksum Redis uses for its own snapshots.
+fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac9329ac4bc9b5;
+    const TABLE: [u64; 256] = {
+        let mut table = [0u64; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u64;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    for &b in data {
+        crc = TABLE[((crc ^ b as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    return crc;
+}
+
+/// Writer that keeps a running checksum of everything going through it.
+struct CrcWriter<W: Write> {
+    inner: W,
+    crc: u64,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc = crc64(self.crc, &buf[..n]);
+        return Ok(n);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+fn write_len(w: &mut impl Write, mut len: u64) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(10);
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+
+    return w.write_all(&buf);
+}
+
+fn write_string(w: &mut impl Write, s: &[u8]) -> io::Result<()> {
+    write_len(w, s.len() as u64)?;
+    return w.write_all(s);
+}
+
+fn write_id(w: &mut impl Write, id: StreamId) -> io::Result<()> {
+    write_len(w, id.ms)?;
+    return write_len(w, id.seq);
+}
+
+fn write_stream(w: &mut impl Write, stream: &Stream) -> io::Result<()> {
+    write_len(w, stream.len() as u64)?;
+    for (&id, fields) in stream.entries.iter() {
+        write_id(w, id)?;
+        write_len(w, fields.len() as u64)?;
+        for field in fields {
+            write_string(w, field)?;
+        }
+    }
+    write_id(w, stream.last_id)?;
+    write_len(w, stream.entries_added)?;
+    write_id(w, stream.max_deleted_id)?;
+
+    write_len(w, stream.groups.len() as u64)?;
+    for (name, group) in stream.groups.iter() {
+        write_string(w, name)?;
+        write_id(w, group.last_id)?;
+        w.write_all(&group.entries_read.map_or(-1, |n| n as i64).to_le_bytes())?;
+        write_len(w, group.consumers.len() as u64)?;
+        for (name, consumer) in group.consumers.iter() {
+            write_string(w, name)?;
+            w.write_all(&consumer.seen_time.to_le_bytes())?;
+            w.write_all(&consumer.active_time.to_le_bytes())?;
+        }
+        write_len(w, group.pel.len() as u64)?;
+        for (&id, entry) in group.pel.iter() {
+            write_id(w, id)?;
+            write_string(w, &entry.consumer)?;
+            w.write_all(&entry.delivery_time.to_le_bytes())?;
+            write_len(w, entry.delivery_count)?;
+        }
+    }
+
+    return Ok(());
+}
+
+fn write_object(w: &mut impl Write, obj: &StoreObject) -> anyhow::Result<()> {
+    match &obj.value {
+        ObjectValue::String(bytes) => match obj.get_encoding() {
+            ENCODING_INT => {
+                let i = parse_i64(bytes)
+                    .ok_or_else(|| anyhow!("integer encoded string is not an integer"))?;
+                w.write_all(&i.to_le_bytes())?;
+            }
+            _ => write_string(w, bytes)?,
+        },
+        ObjectValue::List(list) => {
+            write_len(w, list.len() as u64)?;
+            for v in list.iter() {
+                write_string(w, v)?;
+            }
+        }
+        ObjectValue::Set(set) => {
+            write_len(w, set.len() as u64)?;
+            for member in set.iter() {
+                write_string(w, &member)?;
+            }
+        }
+        ObjectValue::Zset(zset) => {
+            write_len(w, zset.len() as u64)?;
+            for (member, score) in zset.iter() {
+                write_string(w, member)?;
+                w.write_all(&score.to_bits().to_le_bytes())?;
+            }
+        }
+        ObjectValue::Hash(hash) => {
+            write_len(w, hash.len() as u64)?;
+            for (field, value, expires_at) in hash.entries() {
+                write_string(w, field)?;
+                write_string(w, value)?;
+                w.write_all(&expires_at.to_le_bytes())?;
+            }
+        }
+        ObjectValue::Stream(stream) => write_stream(w, stream)?,
+    }
+
+    return Ok(());
+}
+
+/// Cursor over a snapshot being loaded.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    list_limit: ListpackLimit,
+    hash_limits: HashLimits,
+    set_max_intset_entries: usize,
+    zset_limits: ZsetLimits,
+}
+
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.data.len() - self.pos < n {
+            return Err(anyhow!("unexpected end of snapshot"));
+        }
+
+        let bytes = &self.data[self.pos..(self.pos + n)];
+        self.pos += n;
+        return Ok(bytes);
+    }
+
+    fn read_u8(&mut self) -> anyhow::Result<u8> {
+        return Ok(self.read_bytes(1)?[0]);
+    }
+
+    fn read_i64(&mut self) -> anyhow::Result<i64> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.read_bytes(8)?);
+        return Ok(i64::from_le_bytes(buf));
+    }
+
+    fn read_len(&mut self) -> anyhow::Result<u64> {
+        let mut len = 0_u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if shift >= 64 {
+                return Err(anyhow!("invalid length in snapshot"));
+            }
+            len |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(len);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_string(&mut self) -> anyhow::Result<Vec<u8>> {
+        let len = self.read_len()? as usize;
+        return Ok(self.read_bytes(len)?.to_vec());
+    }
+
+    fn read_id(&mut self) -> anyhow::Result<StreamId> {
+        return Ok(StreamId {
+            ms: self.read_len()?,
+            seq: self.read_len()?,
+        });
+    }
+
+    fn read_stream(&mut self) -> anyhow::Result<Stream> {
+        let mut stream = Stream::new();
+        let len = self.read_len()?;
+        for _ in 0..len {
+            let id = self.read_id()?;
+            let mut fields = Vec::new();
+            for _ in 0..self.read_len()? {
+                fields.push(self.read_string()?);
+            }
+            stream.entries.insert(id, fields);
+        }
+        stream.last_id = self.read_id()?;
+        stream.entries_added = self.read_len()?;
+        stream.max_deleted_id = self.read_id()?;
+
+        for _ in 0..self.read_len()? {
+            let name = self.read_string()?;
+            let last_id = self.read_id()?;
+            let entries_read = u64::try_from(self.read_i64()?).ok();
+            let mut group = ConsumerGroup::new(last_id, entries_read);
+            for _ in 0..self.read_len()? {
+                let name = self.read_string()?;
+                let mut consumer = Consumer::new(self.read_i64()?);
+                consumer.active_time = self.read_i64()?;
+                group.consumers.insert(name, consumer);
+            }
+            for _ in 0..self.read_len()? {
+                let id = self.read_id()?;
+                let consumer = self.read_string()?;
+                if !group.consumers.contains_key(&consumer) {
+                    return Err(anyhow!("pending entry of an unknown consumer in snapshot"));
+                }
+                let delivery_time = self.read_i64()?;
+                group.assign(id, &consumer, delivery_time, self.read_len()?);
+            }
+            stream.groups.insert(name, group);
+        }
+
+        return Ok(stream);
+    }
+
+    fn read_object(&mut self, type_encoding: u8) -> anyhow::Result<StoreObject> {
+        let value = match type_encoding & 0b11110000 {
+            TYPE_STRING => match type_encoding & 0b00001111 {
+                ENCODING_INT => ObjectValue::String(self.read_i64()?.to_string().into_bytes()),
+                _ => ObjectValue::String(self.read_string()?),
+            },
+            TYPE_LIST => {
+                let len = self.read_len()?;
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(self.read_string()?);
+                }
+                ObjectValue::List(List::from_items(items, self.list_limit))
+            }
+            TYPE_SET => {
+                let len = self.read_len()?;
+                let mut set = Set::new();
+                for _ in 0..len {
+                    set.insert(&self.read_string()?, self.set_max_intset_entries);
+                }
+                ObjectValue::Set(set)
+            }
+            TYPE_ZSET => {
+                let len = self.read_len()?;
+                let mut zset = Zset::new();
+                for _ in 0..len {
+                    let member = self.read_string()?;
+                    let score = f64::from_bits(self.read_i64()? as u64);
+                    zset.insert(&member, score, self.zset_limits);
+                }
+                ObjectValue::Zset(zset)
+            }
+            TYPE_HASH => {
+                let len = self.read_len()?;
+                let mut hash = Hash::new();
+                for _ in 0..len {
+                    let field = self.read_string()?;
+                    hash.set(&field, &self.read_string()?, self.hash_limits);
+                    hash.set_expiry(&field, self.read_i64()?);
+                }
+                ObjectValue::Hash(hash)
+            }
+            TYPE_STREAM => ObjectValue::Stream(self.read_stream()?),
+            t => return Err(anyhow!("unknown object type {} in snapshot", t)),
+        };
+
+        let mut obj = StoreObject::new(value, -1, type_encoding, 0);
+        // The encoding picked when saving may not suit the current limits.
+        obj.refresh_encoding();
+        return Ok(obj);
+    }
+}
+
+impl Store {
+    /// Writes a snapshot of the dataset to `w`. `progress` is called after
+    /// each key.
+    pub fn rdb_save_to(&self, w: impl Write, mut progress: impl FnMut()) -> anyhow::Result<()> {
+        let mut w = CrcWriter { inner: w, crc: 0 };
+        let now = Utc::now().timestamp_millis();
+
+        w.write_all(RDB_MAGIC)?;
+        w.write_all(RDB_VERSION)?;
+
+        for (i, db) in self.dbs.iter().enumerate() {
+            if db.is_empty() {
+                continue;
+            }
+
+            w.write_all(&[OPCODE_SELECTDB])?;
+            write_len(&mut w, i as u64)?;
+            for (key, obj) in db.iter(now) {
+                if obj.expires_at != -1 {
+                    w.write_all(&[OPCODE_EXPIRETIME_MS])?;
+                    w.write_all(&obj.expires_at.to_le_bytes())?;
+                }
+                w.write_all(&[obj.type_encoding])?;
+                write_string(&mut w, key)?;
+                write_object(&mut w, obj)?;
+                progress();
+            }
+        }
+
+        w.write_all(&[OPCODE_EOF])?;
+        let crc = w.crc;
+        w.write_all(&crc.to_le_bytes())?;
+        w.flush()?;
+
+        return Ok(());
+    }
+
+    /// Saves a snapshot to a temporary file that is then renamed over
+    /// `dbfilename`, so a crash never leaves a half written snapshot behind.
+    fn rdb_save_file(&self, mut progress: impl FnMut()) -> anyhow::Result<()> {
+        let tmp = self.temp_rdb_path(std::process::id());
+        let result = (|| {
+            let mut w = BufWriter::new(File::create(&tmp)?);
+            self.rdb_save_to(&mut w, &mut progress)?;
+            let f = w.into_inner().map_err(|err| err.into_error())?;
+            f.sync_all()?;
+            fs::rename(&tmp, &self.config.dbfilename)?;
+            return anyhow::Ok(());
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        return result;
+    }
+
+    fn temp_rdb_path(&self, pid: u32) -> PathBuf {
+        return Path::new(&self.config.dbfilename).with_file_name(format!("temp-{}.rdb", pid));
+    }
+
+    /// Saves a snapshot synchronously, blocking every client meanwhile.
+    pub fn rdb_save(&mut self) -> anyhow::Result<()> {
+        if self.child.is_some() {
+            return Err(anyhow!("Background save already in progress"));
+        }
+
+        self.rdb_save_file(|| ())?;
+        self.rdb.last_save = Utc::now().timestamp();
+        self.rdb.last_bgsave_ok = true;
+        self.dirty = 0;
+        println!("DB saved on disk");
+
+        return Ok(());
+    }
+
+    /// Saves a snapshot from a forked child.
+    pub fn rdb_bgsave(&mut self) -> anyhow::Result<()> {
+        if self.child.as_ref().is_some_and(|c| c.kind == ChildKind::Rdb) {
+            return Err(anyhow!("Background save already in progress"));
+        }
+
+        let now = Utc::now().timestamp_millis();
+        self.rdb.last_bgsave_try_ms = now;
+        if let Err(err) = self.start_child(ChildKind::Rdb, |store, reporter| {
+            return store.rdb_save_file(|| reporter.key_done());
+        }) {
+            self.rdb.last_bgsave_ok = false;
+            return Err(err);
+        }
+
+        self.rdb.dirty_before_bgsave = self.dirty;
+        self.rdb.bgsave_started_ms = now;
+        println!("Background saving started");
+
+        return Ok(());
+    }
+
+    pub(super) fn rdb_bgsave_done(&mut self, child: &Child, success: bool) {
+        let elapsed_ms = Utc::now().timestamp_millis() - child.started_ms;
+        self.rdb.last_bgsave_time_sec = elapsed_ms / 1_000;
+        self.rdb.last_bgsave_ok = success;
+
+        if success {
+            // Writes that happened while the child ran are not in the
+            // snapshot and still count towards the next one.
+            self.dirty -= self.rdb.dirty_before_bgsave.min(self.dirty);
+            self.rdb.last_save = Utc::now().timestamp();
+            println!("Background saving terminated with success");
+        } else {
+            let _ = fs::remove_file(self.temp_rdb_path(child.pid as u32));
+            println!("Background saving error");
+        }
+    }
+
+    /// Runs an AOF rewrite as soon as the running child is done.
+    pub fn schedule_aof_rewrite(&mut self) {
+        self.rdb.aof_rewrite_scheduled = true;
+    }
+
+    /// Runs a background save as soon as the running child is done.
+    pub fn schedule_bgsave(&mut self) {
+        self.rdb.bgsave_scheduled = true;
+    }
+
+    /// Starts the background jobs that are due: a scheduled AOF rewrite or a
+    /// snapshot whose `save` rule is met. Called from the event loop.
+    pub fn persistence_cron(&mut self) {
+        if self.child.is_some() {
+            return;
+        }
+
+        if self.rdb.aof_rewrite_scheduled {
+            self.rdb.aof_rewrite_scheduled = false;
+            if let Err(err) = self.bg_rewrite_aof() {
+                println!("Scheduled AOF rewrite failed to start: {}", err);
+            }
+            return;
+        }
+
+        if self.rdb.bgsave_scheduled {
+            self.rdb.bgsave_scheduled = false;
+            if let Err(err) = self.rdb_bgsave() {
+                println!("Scheduled background save failed to start: {}", err);
+            }
+            return;
+        }
+
+        let now_ms = Utc::now().timestamp_millis();
+        if !self.rdb.last_bgsave_ok && now_ms - self.rdb.last_bgsave_try_ms < BGSAVE_RETRY_DELAY_MS {
+            return;
+        }
+
+        let since_save_sec = (now_ms / 1_000 - self.rdb.last_save).max(0) as u64;
+        let due = self.config.save.0.iter().find(|(seconds, changes)| {
+            self.dirty >= *changes && since_save_sec >= *seconds
+        });
+        if let Some((seconds, changes)) = due {
+            println!("{} changes in {} seconds. Saving...", changes, seconds);
+            if let Err(err) = self.rdb_bgsave() {
+                println!("Automatic snapshot failed to start: {}", err);
+            }
+        }
+    }
+
+    /// Restores the dataset from `dbfilename`, verifying its checksum.
+    pub fn load_rdb(&mut self) -> anyhow::Result<()> {
+        let path = self.config.dbfilename.clone();
+        let data = match fs::read(&path) {
+            Ok(res) => res,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let start = Instant::now();
+        let loaded = self
+            .rdb_load_from(&data)
+            .map_err(|err| anyhow!("Bad snapshot file {}: {}", path, err))?;
+
+        println!(
+            "DB loaded from disk: {:.3} seconds, {} keys",
+            start.elapsed().as_secs_f64(),
+            loaded
+        );
+        return Ok(());
+    }
+
+    /// Loads every key of the snapshot in `data`, returning how many there
+    /// were.
+    pub fn rdb_load_from(&mut self, data: &[u8]) -> anyhow::Result<u64> {
+        let header_len = RDB_MAGIC.len() + RDB_VERSION.len();
+        if data.len() < header_len + 9 || !data.starts_with(RDB_MAGIC) {
+            return Err(anyhow!("wrong signature"));
+        }
+        if &data[RDB_MAGIC.len()..header_len] != RDB_VERSION {
+            return Err(anyhow!("unsupported version"));
+        }
+
+        let (body, checksum) = data.split_at(data.len() - 8);
+        let mut expected = [0u8; 8];
+        expected.copy_from_slice(checksum);
+        if crc64(0, body) != u64::from_le_bytes(expected) {
+            return Err(anyhow!("checksum mismatch"));
+        }
+
+        let now = Utc::now().timestamp_millis();
+        let mut r = Reader {
+            data: body,
+            pos: header_len,
+            list_limit: self.list_limit(),
+            hash_limits: self.hash_limits(),
+            set_max_intset_entries: self.set_max_intset_entries(),
+            zset_limits: self.zset_limits(),
+        };
+        let mut loaded = 0;
+        let mut db = 0;
+        loop {
+            let mut opcode = r.read_u8()?;
+            if opcode == OPCODE_EOF {
+                break;
+            }
+            if opcode == OPCODE_SELECTDB {
+                db = r.read_len()? as usize;
+                if db >= self.dbs.len() {
+                    return Err(anyhow!(
+                        "database {} out of range, the server has {} databases",
+                        db,
+                        self.dbs.len()
+                    ));
+                }
+                continue;
+            }
+
+            let mut expires_at = -1;
+            if opcode == OPCODE_EXPIRETIME_MS {
+                expires_at = r.read_i64()?;
+                opcode = r.read_u8()?;
+            }
+
+            let key = r.read_string()?;
+            let mut obj = r.read_object(opcode)?;
+            obj.expires_at = expires_at;
+
+            // No point in loading keys that are already gone
+            if obj.is_expired_at(now) {
+                continue;
+            }
+            if let ObjectValue::Hash(hash) = &mut obj.value {
+                hash.remove_expired(now);
+                if hash.is_empty() {
+                    continue;
+                }
+                obj.refresh_encoding();
+            }
+            self.dbs[db].link(key, obj);
+            loaded += 1;
+        }
+
+        return Ok(loaded);
+    }
+
+    /// `INFO persistence` fields about snapshots.
+    pub fn rdb_info(&self) -> Vec<(&'static str, String)> {
+        let bgsave_in_progress = self.child.as_ref().is_some_and(|c| c.kind == ChildKind::Rdb);
+        let current_time_sec = if bgsave_in_progress {
+            (Utc::now().timestamp_millis() - self.rdb.bgsave_started_ms) / 1_000
+        } else {
+            -1
+        };
+
+        return vec![
+            ("rdb_changes_since_last_save", self.dirty.to_string()),
+            ("rdb_bgsave_scheduled", (self.rdb.bgsave_scheduled as u8).to_string()),
+            ("rdb_bgsave_in_progress", (bgsave_in_progress as u8).to_string()),
+            ("rdb_last_save_time", self.rdb.last_save.to_string()),
+            (
+                "rdb_last_bgsave_status",
+                if self.rdb.last_bgsave_ok { "ok" } else { "err" }.to_string(),
+            ),
+            ("rdb_last_bgsave_time_sec", self.rdb.last_bgsave_time_sec.to_string()),
+            ("rdb_current_bgsave_time_sec", current_time_sec.to_string()),
+        ];
+    }
+
+    pub fn last_save(&self) -> i64 {
+        return self.rdb.last_save;
+    }
+
+    pub fn aof_rewrite_scheduled(&self) -> bool {
+        return self.rdb.aof_rewrite_scheduled;
+    }
+}
+
+/// Bookkeeping for snapshots.
+pub struct RdbState {
+    /// Unix time of the last successful save.
+    last_save: i64,
+    last_bgsave_ok: bool,
+    last_bgsave_try_ms: i64,
+    last_bgsave_time_sec: i64,
+    bgsave_started_ms: i64,
+    /// `dirty` when the running child was forked.
+    dirty_before_bgsave: u64,
+    /// BGREWRITEAOF arrived while a snapshot was being saved.
+    aof_rewrite_scheduled: bool,
+    /// BGSAVE SCHEDULE arrived while the AOF was being rewritten.
+    bgsave_scheduled: bool,
+}
+
+impl Default for RdbState {
+    fn default() -> Self {
+        return RdbState {
+            last_save: Utc::now().timestamp(),
+            last_bgsave_ok: true,
+            last_bgsave_try_ms: 0,
+            last_bgsave_time_sec: -1,
+            bgsave_started_ms: 0,
+            dirty_before_bgsave: 0,
+            aof_rewrite_scheduled: false,
+            bgsave_scheduled: false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        data::store::{
+            ENCODING_EMBSTR, ENCODING_HT, ENCODING_INTSET, ENCODING_LISTPACK, ENCODING_QUICKLIST,
+            ENCODING_STREAM,
+        },
+    };
+
+    fn string_value(obj: &StoreObject) -> Vec<u8> {
+        let ObjectValue::String(s) = &obj.value else {
+            panic!("not a string");
+        };
+        return s.clone();
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let config = Config::parse_from([
+            "redrust",
+            "--list-max-listpack-size",
+            "2",
+            "--hash-max-listpack-entries",
+            "1",
+        ]);
+        let mut store = Store::new(config.clone());
+        let expires_at = Utc::now().timestamp_millis() + 60_000;
+
+        store.put(
+            b"int".to_vec(),
+            StoreObject::new(ObjectValue::String(b"-42".to_vec()), -1, TYPE_STRING, ENCODING_INT),
+        );
+        let mut bin = StoreObject::new(
+            ObjectValue::String(b"\x00\xff\r\n".to_vec()),
+            -1,
+            TYPE_STRING,
+            ENCODING_EMBSTR,
+        );
+        bin.expires_at = expires_at;
+        store.put(b"bin\x00".to_vec(), bin);
+        let list = List::from_items(["a", "", "c"], store.list_limit());
+        store.put(
+            b"list".to_vec(),
+            StoreObject::new(ObjectValue::List(list), -1, TYPE_LIST, ENCODING_QUICKLIST),
+        );
+        let mut hash = Hash::new();
+        hash.set(b"f1", b"v1", store.hash_limits());
+        hash.set(b"f2", b"", store.hash_limits());
+        hash.set(b"gone", b"", store.hash_limits());
+        hash.set_expiry(b"f1", expires_at);
+        hash.set_expiry(b"gone", 1);
+        store.put(
+            b"hash".to_vec(),
+            StoreObject::new(ObjectValue::Hash(hash), -1, TYPE_HASH, ENCODING_HT),
+        );
+        let zset = Zset::from_entries([("b", -0.5), ("a", f64::INFINITY)], store.zset_limits());
+        store.put(
+            b"zset".to_vec(),
+            StoreObject::new(ObjectValue::Zset(zset), -1, TYPE_ZSET, ENCODING_LISTPACK),
+        );
+        let set = Set::from_members(["7", "-1"], store.set_max_intset_entries());
+        store.put(
+            b"set".to_vec(),
+            StoreObject::new(ObjectValue::Set(set), -1, TYPE_SET, ENCODING_INTSET),
+        );
+        let mut stream = Stream::new();
+        let id = StreamId { ms: 5, seq: 1 };
+        stream.add(id, vec![b"f".to_vec(), b"".to_vec()]);
+        stream.last_id = StreamId { ms: 7, seq: 0 };
+        let mut group = ConsumerGroup::new(id, None);
+        group.consumer(b"alice", 10);
+        group.consumer(b"bob", 11);
+        group.assign(id, b"bob", 12, 3);
+        stream.groups.insert(b"g".to_vec(), group);
+        store.put(
+            b"stream".to_vec(),
+            StoreObject::new(ObjectValue::Stream(stream), -1, TYPE_STREAM, ENCODING_STREAM),
+        );
+
+        let mut data = Vec::new();
+        store.rdb_save_to(&mut data, || ()).unwrap();
+
+        let mut loaded = Store::new(config);
+        assert_eq!(loaded.rdb_load_from(&data).unwrap(), 7);
+        assert_eq!(string_value(loaded.get(b"int").unwrap()), b"-42");
+        let bin = loaded.get(b"bin\x00").unwrap();
+        assert_eq!(string_value(bin), b"\x00\xff\r\n");
+        assert_eq!(bin.expires_at, expires_at);
+        let list = loaded.get(b"list").unwrap();
+        assert_eq!(list.get_encoding(), ENCODING_QUICKLIST);
+        let ObjectValue::List(list) = &list.value else {
+            panic!("not a list");
+        };
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![&b"a"[..], b"", b"c"]);
+        let hash = loaded.get(b"hash").unwrap();
+        assert_eq!(hash.get_encoding(), ENCODING_HT);
+        let ObjectValue::Hash(hash) = &hash.value else {
+            panic!("not a hash");
+        };
+        assert_eq!(hash.get(b"f2"), Some(&b""[..]));
+        assert_eq!(hash.expires_at(b"f1"), Some(expires_at));
+        assert!(!hash.contains(b"gone"));
+        let ObjectValue::Zset(zset) = &loaded.get(b"zset").unwrap().value else {
+            panic!("not a sorted set");
+        };
+        let entries: Vec<_> = zset.iter().collect();
+        assert_eq!(entries, vec![(&b"b"[..], -0.5), (&b"a"[..], f64::INFINITY)]);
+        let set = loaded.get(b"set").unwrap();
+        assert_eq!(set.get_encoding(), ENCODING_INTSET);
+        let ObjectValue::Set(set) = &set.value else {
+            panic!("not a set");
+        };
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![b"-1".to_vec(), b"7".to_vec()]);
+        let ObjectValue::Stream(stream) = &loaded.get(b"stream").unwrap().value else {
+            panic!("not a stream");
+        };
+        assert_eq!(stream.entries[&id], vec![b"f".to_vec(), b"".to_vec()]);
+        assert_eq!((stream.last_id.ms, stream.entries_added), (7, 1));
+        let group = &stream.groups[&b"g".to_vec()];
+        assert_eq!((group.last_id, group.entries_read), (id, None));
+        assert_eq!(group.consumers.len(), 2);
+        assert_eq!(group.pel[&id].consumer, b"bob");
+        assert_eq!((group.pel[&id].delivery_time, group.pel[&id].delivery_count), (12, 3));
+        assert!(group.consumers[&b"bob".to_vec()].pending.contains(&id));
+
+        // Any flipped bit must be caught by the checksum
+        data[RDB_MAGIC.len() + RDB_VERSION.len() + 2] ^= 1;
+        assert!(loaded.rdb_load_from(&data).is_err());
+    }
+}
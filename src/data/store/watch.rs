@@ -0,0 +1,169 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+
+use super::{Db, Store};
+
+/// Keys watched by clients with WATCH, and the clients whose transactions
+/// must fail because one of them was touched since.
+#[derive(Default)]
+pub(super) struct WatchedKeys {
+    /// Clients watching each key, per database.
+    keys: Vec<HashMap<Vec<u8>, Watchers>>,
+    /// Clients whose EXEC is going to fail.
+    dirty_cas: HashSet<u64>,
+    /// Keys of the selected database the running command looked up through
+    /// `get_mut`. They only count as touched if the command changed
+    /// anything, see `Store::propagate`.
+    pending: Vec<(usize, Vec<u8>)>,
+}
+
+impl WatchedKeys {
+    pub(super) fn new(databases: usize) -> WatchedKeys {
+        return WatchedKeys {
+            keys: (0..databases).map(|_| HashMap::new()).collect(),
+            ..Default::default()
+        };
+    }
+
+    pub(super) fn is_watched(&self, db: usize, key: &[u8]) -> bool {
+        return self.keys[db].contains_key(key);
+    }
+
+    /// Flags the clients watching `key` of database `db`, which now holds
+    /// `after`. Deleting a key that was already expired when it was watched
+    /// changes nothing as far as the watcher can tell.
+    pub(super) fn touch(&mut self, db: usize, key: &[u8], after: &Db) {
+        let Some(watchers) = self.keys[db].get_mut(key) else {
+            return;
+        };
+
+        for (id, expired) in watchers {
+            if *expired && !after.inner.contains_key(key) {
+                *expired = false;
+                continue;
+            }
+            self.dirty_cas.insert(*id);
+        }
+    }
+
+    /// Same as `touch` for every key watched in database `db`, whose content
+    /// was replaced at once, by FLUSHDB or SWAPDB, from `before` to `after`.
+    pub(super) fn touch_all(&mut self, db: usize, before: &Db, after: &Db) {
+        let now = Utc::now().timestamp_millis();
+        let is_expired = |key: &[u8]| after.inner.get(key).is_some_and(|o| o.is_expired_at(now));
+
+        for (key, watchers) in self.keys[db].iter_mut() {
+            let existed = before.inner.contains_key(key);
+            if !existed && !after.inner.contains_key(key) {
+                continue;
+            }
+
+            for (id, expired) in watchers {
+                if *expired {
+                    if !after.inner.contains_key(key) {
+                        *expired = false;
+                        continue;
+                    }
+                    if is_expired(key) {
+                        continue;
+                    }
+                } else if !existed && is_expired(key) {
+                    *expired = true;
+                    continue;
+                }
+                self.dirty_cas.insert(*id);
+            }
+        }
+    }
+}
+
+impl Store {
+    /// Has client `id` watch `key` of the selected database.
+    pub fn watch_key(&mut self, id: u64, key: &[u8]) {
+        let expired = self.key_is_expired(key);
+        let watchers = self.watched.keys[self.db].entry(key.to_vec()).or_default();
+        if !watchers.iter().any(|&(c, _)| c == id) {
+            watchers.push((id, expired));
+        }
+    }
+
+    /// Stops client `id` from watching `keys`, given with their database,
+    /// and forgets whether any of them was touched.
+    pub fn unwatch_keys(&mut self, id: u64, keys: &[(usize, Vec<u8>)]) {
+        for (db, key) in keys {
+            let watched = &mut self.watched.keys[*db];
+            let Some(watchers) = watched.get_mut(key) else {
+                continue;
+            };
+            watchers.retain(|&(c, _)| c != id);
+            if watchers.is_empty() {
+                watched.remove(key);
+            }
+        }
+        self.watched.dirty_cas.remove(&id);
+    }
+
+    /// Whether a key watched by client `id` was touched since, or expired
+    /// while it wasn't when it was watched.
+    pub fn watched_keys_touched(&self, id: u64, keys: &[(usize, Vec<u8>)]) -> bool {
+        if self.watched.dirty_cas.contains(&id) {
+            return true;
+        }
+
+        let now = Utc::now().timestamp_millis();
+        return keys.iter().any(|(db, key)| {
+            let expired_when_watched = self.watched.keys[*db]
+                .get(key)
+                .and_then(|w| w.iter().find(|&&(c, _)| c == id))
+                .is_some_and(|&(_, expired)| expired);
+            let expired = self.dbs[*db]
+                .inner
+                .get(key)
+                .is_some_and(|o| o.is_expired_at(now));
+            !expired_when_watched && expired
+        });
+    }
+
+    /// Whether `key` of the selected database is still there but its TTL
+    /// passed.
+    fn key_is_expired(&self, key: &[u8]) -> bool {
+        let now = Utc::now().timestamp_millis();
+        return self
+            .db()
+            .inner
+            .get(key)
+            .is_some_and(|obj| obj.is_expired_at(now));
+    }
+
+    /// Flags the clients watching `key` of database `db`, which was just
+    /// created, replaced or deleted.
+    pub(super) fn touch_watched_key(&mut self, db: usize, key: &[u8]) {
+        if self.watched.is_watched(db, key) {
+            self.watched.touch(db, key, &self.dbs[db]);
+        }
+    }
+
+    /// Touches the watched keys the running command modified in place.
+    pub(super) fn touch_pending_watched_keys(&mut self) {
+        for (db, key) in std::mem::take(&mut self.watched.pending) {
+            self.watched.touch(db, &key, &self.dbs[db]);
+        }
+    }
+
+    /// Remembers that the running command may modify `key` of the selected
+    /// database in place.
+    pub(super) fn may_touch_watched_key(&mut self, key: &[u8]) {
+        if self.watched.is_watched(self.db, key) {
+            self.watched.pending.push((self.db, key.to_vec()));
+        }
+    }
+
+    pub(super) fn discard_pending_watched_keys(&mut self) {
+        self.watched.pending.clear();
+    }
+}
+
+/// Clients watching a key, along with whether the key was already expired
+/// when they started to.
+type Watchers = Vec<(u64, bool)>;
@@ -0,0 +1,561 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// Id of a stream entry: a unix time in milliseconds and a sequence number
+/// for the entries added within the same millisecond.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parses `ms-seq`, or `ms` alone which gets `missing_seq`. `-` and `+`
+    /// are the smallest and largest ids.
+    pub fn parse(arg: &[u8], missing_seq: u64) -> Option<StreamId> {
+        match arg {
+            b"-" => return Some(StreamId::MIN),
+            b"+" => return Some(StreamId::MAX),
+            _ => {}
+        }
+
+        let s = std::str::from_utf8(arg).ok()?;
+        let parse = |part: &str| part.parse::<u64>().ok().filter(|_| !part.starts_with('+'));
+        return match s.split_once('-') {
+            Some((ms, seq)) => Some(StreamId {
+                ms: parse(ms)?,
+                seq: parse(seq)?,
+            }),
+            None => Some(StreamId {
+                ms: parse(s)?,
+                seq: missing_seq,
+            }),
+        };
+    }
+
+    /// The id right after this one, `None` for the largest id.
+    pub fn next(self) -> Option<StreamId> {
+        if self.seq < u64::MAX {
+            return Some(StreamId {
+                ms: self.ms,
+                seq: self.seq + 1,
+            });
+        }
+        return Some(StreamId {
+            ms: self.ms.checked_add(1)?,
+            seq: 0,
+        });
+    }
+
+    /// The id right before this one, `None` for the smallest id.
+    pub fn prev(self) -> Option<StreamId> {
+        if self.seq > 0 {
+            return Some(StreamId {
+                ms: self.ms,
+                seq: self.seq - 1,
+            });
+        }
+        return Some(StreamId {
+            ms: self.ms.checked_sub(1)?,
+            seq: u64::MAX,
+        });
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        return self.to_string().into_bytes();
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}-{}", self.ms, self.seq);
+    }
+}
+
+/// Fields and values of an entry, one after the other.
+pub type Fields = Vec<Vec<u8>>;
+
+/// Entry delivered to a consumer of a group and not acknowledged yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    /// Unix time in ms of the last delivery.
+    pub delivery_time: i64,
+    pub delivery_count: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Consumer {
+    /// Unix time in ms of the last time the consumer was seen, whether it
+    /// got anything or not.
+    pub seen_time: i64,
+    /// Unix time in ms of the last time the consumer read or claimed
+    /// something, -1 if it never did.
+    pub active_time: i64,
+    /// Ids of its entries in the group's pending entries list.
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    pub fn new(now: i64) -> Consumer {
+        return Consumer {
+            seen_time: now,
+            active_time: -1,
+            pending: BTreeSet::new(),
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConsumerGroup {
+    /// Last entry delivered to the group.
+    pub last_id: StreamId,
+    /// Number of entries of the stream the group read, `None` when it can't
+    /// be known, e.g. after its last id was set arbitrarily.
+    pub entries_read: Option<u64>,
+    /// Pending entries list, shared by all the consumers of the group.
+    pub pel: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_id: StreamId, entries_read: Option<u64>) -> ConsumerGroup {
+        return ConsumerGroup {
+            last_id,
+            entries_read,
+            pel: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        };
+    }
+
+    /// The consumer called `name`, created if missing. Either way, it is
+    /// seen at `now`.
+    pub fn consumer(&mut self, name: &[u8], now: i64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_vec())
+            .or_insert_with(|| Consumer::new(now));
+        consumer.seen_time = now;
+        return consumer;
+    }
+
+    /// Makes `consumer`, which must exist, the owner of the pending entry
+    /// `id`, taking it from its previous owner if any.
+    pub fn assign(&mut self, id: StreamId, consumer: &[u8], delivery_time: i64, count: u64) {
+        let entry = PendingEntry {
+            consumer: consumer.to_vec(),
+            delivery_time,
+            delivery_count: count,
+        };
+        if let Some(previous) = self.pel.insert(id, entry) {
+            if let Some(owner) = self.consumers.get_mut(&previous.consumer) {
+                owner.pending.remove(&id);
+            }
+        }
+        if let Some(owner) = self.consumers.get_mut(consumer) {
+            owner.pending.insert(id);
+        }
+    }
+
+    /// Removes `id` from the pending entries list, returns false if it
+    /// wasn't there.
+    pub fn ack(&mut self, id: StreamId) -> bool {
+        let Some(entry) = self.pel.remove(&id) else {
+            return false;
+        };
+        if let Some(owner) = self.consumers.get_mut(&entry.consumer) {
+            owner.pending.remove(&id);
+        }
+
+        return true;
+    }
+
+    /// Deletes a consumer along with its pending entries, returns how many
+    /// it had or `None` if there is no such consumer.
+    pub fn remove_consumer(&mut self, name: &[u8]) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in consumer.pending.iter() {
+            self.pel.remove(id);
+        }
+
+        return Some(consumer.pending.len());
+    }
+}
+
+/// How XADD and XTRIM shorten a stream.
+#[derive(Clone, Copy, Debug)]
+pub enum Trim {
+    /// Keep at most that many entries.
+    MaxLen(u64),
+    /// Drop the entries with a smaller id.
+    MinId(StreamId),
+}
+
+/// Redis' stream: entries sorted by id, along with the consumer groups
+/// reading them.
+#[derive(Clone, Debug, Default)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, Fields>,
+    /// Largest id ever added, even if it was deleted since.
+    pub last_id: StreamId,
+    /// Largest id removed by XDEL.
+    pub max_deleted_id: StreamId,
+    /// Number of entries ever added.
+    pub entries_added: u64,
+    pub groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        return Stream::default();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    /// Id of the first entry, 0-0 when there is none.
+    pub fn first_id(&self) -> StreamId {
+        return self.entries.keys().next().copied().unwrap_or_default();
+    }
+
+    /// Id to give to an entry added at `now_ms` when none was specified,
+    /// `None` if the stream went through every possible id.
+    pub fn next_id(&self, now_ms: u64) -> Option<StreamId> {
+        if now_ms > self.last_id.ms {
+            return Some(StreamId { ms: now_ms, seq: 0 });
+        }
+        return self.last_id.next();
+    }
+
+    /// Adds an entry, `id` must be greater than `last_id`.
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    /// Deletes an entry, returns false if there is no such entry.
+    pub fn delete(&mut self, id: StreamId) -> bool {
+        if self.entries.remove(&id).is_none() {
+            return false;
+        }
+        self.max_deleted_id = self.max_deleted_id.max(id);
+
+        return true;
+    }
+
+    /// Removes entries from the start of the stream according to `trim`,
+    /// and no more than `limit` of them if not 0. Returns how many were
+    /// removed.
+    pub fn trim(&mut self, trim: Trim, limit: usize) -> usize {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let mut removed = 0;
+        while removed < limit {
+            let Some(&first) = self.entries.keys().next() else {
+                break;
+            };
+            let keep = match trim {
+                Trim::MaxLen(max) => self.entries.len() as u64 <= max,
+                Trim::MinId(min) => first >= min,
+            };
+            if keep {
+                break;
+            }
+
+            self.entries.remove(&first);
+            removed += 1;
+        }
+
+        return removed;
+    }
+
+    /// Entries with ids in `start..=end`, at most `count` of them, from the
+    /// last one if `reverse`.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        count: usize,
+        reverse: bool,
+    ) -> Vec<(StreamId, Fields)> {
+        if start > end {
+            return vec![];
+        }
+
+        let range = self.entries.range(start..=end);
+        let owned = |(id, fields): (&StreamId, &Fields)| (*id, fields.clone());
+        if reverse {
+            return range.rev().take(count).map(owned).collect();
+        }
+        return range.take(count).map(owned).collect();
+    }
+
+    /// Whether entries were deleted at or after `start`, which makes the
+    /// number of entries read by a group impossible to deduce from ids.
+    fn has_tombstones_from(&self, start: StreamId) -> bool {
+        return !self.is_empty()
+            && self.max_deleted_id != StreamId::MIN
+            && start <= self.max_deleted_id;
+    }
+
+    /// Number of entries added up to `id` included, if it can be told from
+    /// the counters without walking the stream.
+    fn estimate_distance(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.is_empty() && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        if id == self.last_id {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+
+        // Without deletions, the entries before the first one were trimmed
+        let first = self.first_id();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first {
+            if id < first {
+                return Some(self.entries_added - self.len() as u64);
+            }
+            if id == first {
+                return Some(self.entries_added - self.len() as u64 + 1);
+            }
+        }
+
+        return None;
+    }
+
+    /// Number of entries the group `name` has yet to read, `None` when it
+    /// can't be known.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if let Some(read) = group.entries_read {
+            if !self.has_tombstones_from(group.last_id) {
+                return Some(self.entries_added - read);
+            }
+        }
+
+        return self
+            .estimate_distance(group.last_id)
+            .map(|read| self.entries_added - read);
+    }
+
+    /// Moves the last id of the group `name` to `id` as its entries get
+    /// delivered, counting them along the way.
+    pub fn advance_group(&mut self, name: &[u8], id: StreamId) {
+        let Some(group) = self.groups.get(name) else {
+            return;
+        };
+        if id <= group.last_id {
+            return;
+        }
+
+        let entries_read = match group.entries_read {
+            Some(read) if !self.has_tombstones_from(id) => Some(read + 1),
+            _ if self.entries_added > 0 => self.estimate_distance(id),
+            read => read,
+        };
+        let group = self.groups.get_mut(name).unwrap();
+        group.entries_read = entries_read;
+        group.last_id = id;
+    }
+
+    /// Delivers to `consumer`, which must exist in the group `name`, at most
+    /// `count` entries the group hasn't seen yet. They become pending unless
+    /// `noack`.
+    pub fn deliver(
+        &mut self,
+        name: &[u8],
+        consumer: &[u8],
+        count: usize,
+        noack: bool,
+        now: i64,
+    ) -> Vec<(StreamId, Fields)> {
+        let Some(from) = self.groups.get(name).and_then(|g| g.last_id.next()) else {
+            return vec![];
+        };
+
+        let entries = self.range(from, StreamId::MAX, count, false);
+        for (id, _) in entries.iter() {
+            self.advance_group(name, *id);
+        }
+
+        let group = self.groups.get_mut(name).unwrap();
+        if !noack {
+            for (id, _) in entries.iter() {
+                group.assign(*id, consumer, now, 1);
+            }
+        }
+        if !entries.is_empty() {
+            group.consumer(consumer, now).active_time = now;
+        }
+
+        return entries;
+    }
+
+    /// Pending entries of `consumer` in the group `name` with ids greater
+    /// than `after`, at most `count` of them, along with their fields or
+    /// `None` when they were deleted since. Those that still exist count as
+    /// delivered once more.
+    pub fn redeliver(
+        &mut self,
+        name: &[u8],
+        consumer: &[u8],
+        after: StreamId,
+        count: usize,
+        now: i64,
+    ) -> Vec<(StreamId, Option<Fields>)> {
+        let Some(group) = self.groups.get_mut(name) else {
+            return vec![];
+        };
+        let Some(pending) = group.consumers.get(consumer).map(|c| &c.pending) else {
+            return vec![];
+        };
+
+        let ids: Vec<StreamId> = pending
+            .iter()
+            .filter(|&&id| id > after)
+            .take(count)
+            .copied()
+            .collect();
+        let mut entries = Vec::with_capacity(ids.len());
+        for id in ids {
+            let fields = self.entries.get(&id).cloned();
+            if fields.is_some() {
+                let entry = group.pel.get_mut(&id).unwrap();
+                entry.delivery_time = now;
+                entry.delivery_count += 1;
+            }
+            entries.push((id, fields));
+        }
+
+        return entries;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        return StreamId { ms, seq };
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        assert_eq!(StreamId::parse(b"5-3", 0), Some(id(5, 3)));
+        assert_eq!(StreamId::parse(b"5", u64::MAX), Some(id(5, u64::MAX)));
+        assert_eq!(StreamId::parse(b"-", 0), Some(StreamId::MIN));
+        assert_eq!(StreamId::parse(b"+", 0), Some(StreamId::MAX));
+        assert_eq!(StreamId::parse(b"5-", 0), None);
+        assert_eq!(StreamId::parse(b"+5", 0), None);
+        assert_eq!(StreamId::parse(b"a-1", 0), None);
+        assert_eq!(id(5, u64::MAX).next(), Some(id(6, 0)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(id(6, 0).prev(), Some(id(5, u64::MAX)));
+        assert_eq!(StreamId::MIN.prev(), None);
+    }
+
+    #[test]
+    fn test_lag_of_groups() {
+        let mut stream = Stream::new();
+        for ms in 1..=5 {
+            stream.add(id(ms, 0), vec![b"f".to_vec(), b"v".to_vec()]);
+        }
+        stream
+            .groups
+            .insert(b"g".to_vec(), ConsumerGroup::new(StreamId::MIN, Some(0)));
+
+        stream.advance_group(b"g", id(1, 0));
+        stream.advance_group(b"g", id(2, 0));
+        let group = &stream.groups[&b"g".to_vec()];
+        assert_eq!(group.entries_read, Some(2));
+        assert_eq!(stream.lag(group), Some(3));
+
+        // A deleted entry ahead of the group makes the lag unknown
+        assert!(stream.delete(id(4, 0)));
+        assert_eq!(stream.lag(&stream.groups[&b"g".to_vec()]), None);
+
+        let group = ConsumerGroup::new(id(3, 0), None);
+        assert_eq!(stream.lag(&group), None);
+
+        // Without deletions, trimmed entries are still counted
+        let mut stream = Stream::new();
+        for ms in 1..=5 {
+            stream.add(id(ms, 0), vec![]);
+        }
+        assert_eq!(stream.trim(Trim::MinId(id(3, 0)), 0), 2);
+        assert_eq!(stream.lag(&group), Some(2));
+        assert_eq!(stream.trim(Trim::MaxLen(1), 1), 1);
+        assert_eq!(stream.first_id(), id(4, 0));
+    }
+
+    #[test]
+    fn test_delivery_to_consumers() {
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(id(ms, 0), vec![b"f".to_vec(), ms.to_string().into_bytes()]);
+        }
+        let mut group = ConsumerGroup::new(StreamId::MIN, Some(0));
+        group.consumer(b"alice", 0);
+        group.consumer(b"bob", 0);
+        stream.groups.insert(b"g".to_vec(), group);
+
+        let ids = |entries: Vec<(StreamId, Fields)>| {
+            entries.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(stream.deliver(b"g", b"alice", 2, false, 10)),
+            vec![id(1, 0), id(2, 0)]
+        );
+        assert_eq!(
+            ids(stream.deliver(b"g", b"bob", 5, true, 20)),
+            vec![id(3, 0)]
+        );
+        assert!(stream.deliver(b"g", b"bob", 5, false, 20).is_empty());
+
+        let group = &stream.groups[&b"g".to_vec()];
+        assert_eq!((group.last_id, group.entries_read), (id(3, 0), Some(3)));
+        assert_eq!(group.pel.len(), 2);
+        assert_eq!(group.consumers[&b"alice".to_vec()].active_time, 10);
+
+        // Deleted entries are still pending, without their fields
+        assert!(stream.delete(id(1, 0)));
+        let history = stream.redeliver(b"g", b"alice", StreamId::MIN, 10, 30);
+        assert_eq!(history[0], (id(1, 0), None));
+        assert_eq!(history[1].0, id(2, 0));
+        let group = stream.groups.get_mut(b"g".as_slice()).unwrap();
+        assert_eq!(group.pel[&id(2, 0)].delivery_count, 2);
+        assert_eq!(group.pel[&id(1, 0)].delivery_count, 1);
+
+        // Claiming moves an entry between consumers
+        group.assign(id(2, 0), b"bob", 40, 5);
+        assert!(group.consumers[&b"bob".to_vec()]
+            .pending
+            .contains(&id(2, 0)));
+        assert!(!group.consumers[&b"alice".to_vec()]
+            .pending
+            .contains(&id(2, 0)));
+        assert_eq!(group.remove_consumer(b"bob"), Some(1));
+        assert!(group.ack(id(1, 0)));
+        assert!(group.pel.is_empty());
+    }
+}
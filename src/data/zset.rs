@@ -0,0 +1,281 @@
+use crate::{
+    common::{format_double, parse_f64},
+    data::{dict::Dict, listpack::Listpack, skiplist::Skiplist},
+};
+
+/// When a sorted set stops being a listpack, in the format of Redis'
+/// `zset-max-listpack-entries` and `zset-max-listpack-value`.
+#[derive(Clone, Copy, Debug)]
+pub struct ZsetLimits {
+    pub max_entries: usize,
+    pub max_value: usize,
+}
+
+/// Whether `(score, member)` sorts before `(than_score, than)`.
+pub fn sorts_before(score: f64, member: &[u8], than_score: f64, than: &[u8]) -> bool {
+    return score < than_score || (score == than_score && member < than);
+}
+
+#[derive(Clone)]
+pub enum Zset {
+    /// Members and scores one after the other, ordered by score then
+    /// member, for small sorted sets.
+    Listpack(Listpack),
+    Skiplist {
+        scores: Dict<Vec<u8>, f64>,
+        /// Same elements as `scores`, in order.
+        index: Skiplist,
+    },
+}
+
+impl Default for Zset {
+    fn default() -> Self {
+        return Zset::new();
+    }
+}
+
+/// Member and score pairs of a listpack encoded sorted set.
+fn pairs(lp: &Listpack) -> std::vec::IntoIter<(&[u8], f64)> {
+    let members = lp.iter().step_by(2);
+    let scores = lp.iter().skip(1).step_by(2);
+    return members
+        .zip(scores)
+        .map(|(m, s)| (m, parse_f64(s).expect("listpack score is corrupted")))
+        .collect::<Vec<_>>()
+        .into_iter();
+}
+
+impl Zset {
+    pub fn new() -> Zset {
+        return Zset::Listpack(Listpack::new());
+    }
+
+    /// Builds a sorted set out of `entries`, a member given twice keeps its
+    /// last score.
+    pub fn from_entries<V: AsRef<[u8]>>(
+        entries: impl IntoIterator<Item = (V, f64)>,
+        limits: ZsetLimits,
+    ) -> Zset {
+        let mut zset = Zset::new();
+        for (member, score) in entries {
+            zset.insert(member.as_ref(), score, limits);
+        }
+
+        return zset;
+    }
+
+    pub fn len(&self) -> usize {
+        return match self {
+            Zset::Listpack(lp) => lp.len() / 2,
+            Zset::Skiplist { scores, .. } => scores.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        return match self {
+            Zset::Listpack(lp) => pairs(lp).find(|(m, _)| *m == member).map(|(_, s)| s),
+            Zset::Skiplist { scores, .. } => scores.get(member).copied(),
+        };
+    }
+
+    /// Sets the score of `member`, returns its previous score if it was
+    /// already there. Like in Redis, a sorted set converted to a skiplist
+    /// never goes back to a listpack.
+    pub fn insert(&mut self, member: &[u8], score: f64, limits: ZsetLimits) -> Option<f64> {
+        let old = self.score(member);
+        if old == Some(score) {
+            return old;
+        }
+        if old.is_some() {
+            self.remove(member);
+        }
+
+        if member.len() > limits.max_value || self.len() + 1 > limits.max_entries {
+            self.convert();
+        }
+
+        if let Zset::Skiplist { scores, index } = self {
+            scores.insert(member.to_vec(), score);
+            index.insert(member.to_vec(), score);
+            return old;
+        }
+
+        let i = self.count_while(|m, s| sorts_before(s, m, score, member));
+        let Zset::Listpack(lp) = self else {
+            unreachable!("skiplist handled above");
+        };
+        lp.insert(i * 2, member);
+        lp.insert(i * 2 + 1, format_double(score).as_bytes());
+
+        return old;
+    }
+
+    /// Removes `member`, returns false if it wasn't there.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        return match self {
+            Zset::Listpack(lp) => {
+                let Some(i) = lp.iter().step_by(2).position(|m| m == member) else {
+                    return false;
+                };
+                lp.remove(i * 2);
+                lp.remove(i * 2);
+                true
+            }
+            Zset::Skiplist { scores, index } => match scores.remove(member) {
+                Some(score) => index.remove(member, score),
+                None => false,
+            },
+        };
+    }
+
+    /// Number of elements for which `pred` holds, `pred` being true for a
+    /// prefix of the elements in order and false for the rest. That's how
+    /// score and lex ranges are turned into ranks.
+    pub fn count_while(&self, pred: impl Fn(&[u8], f64) -> bool) -> usize {
+        return match self {
+            Zset::Listpack(lp) => pairs(lp).take_while(|(m, s)| pred(m, *s)).count(),
+            Zset::Skiplist { index, .. } => index.count_while(pred),
+        };
+    }
+
+    /// 0-based rank of `member` in ascending order.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        return Some(self.count_while(|m, s| sorts_before(s, m, score, member)));
+    }
+
+    /// Elements of ranks `start..end`, in order or from `end - 1` down to
+    /// `start` if `reverse`.
+    pub fn range(&self, start: usize, end: usize, reverse: bool) -> Vec<(Vec<u8>, f64)> {
+        let end = end.min(self.len());
+        if start >= end {
+            return vec![];
+        }
+
+        let owned = |(m, s): (&[u8], f64)| (m.to_vec(), s);
+        return match (self, reverse) {
+            (Zset::Listpack(lp), false) => {
+                pairs(lp).skip(start).take(end - start).map(owned).collect()
+            }
+            (Zset::Listpack(lp), true) => pairs(lp)
+                .skip(start)
+                .take(end - start)
+                .rev()
+                .map(owned)
+                .collect(),
+            (Zset::Skiplist { index, .. }, _) => {
+                let first = if reverse { end - 1 } else { start };
+                index
+                    .iter_from(first, reverse)
+                    .take(end - start)
+                    .map(owned)
+                    .collect()
+            }
+        };
+    }
+
+    /// Removes the elements of ranks `start..end`, returns how many there
+    /// were.
+    pub fn remove_range(&mut self, start: usize, end: usize) -> usize {
+        let removed = self.range(start, end, false);
+        for (member, _) in removed.iter() {
+            self.remove(member);
+        }
+
+        return removed.len();
+    }
+
+    /// Removes and returns the element with the lowest score, or the highest
+    /// one if `max`.
+    pub fn pop(&mut self, max: bool) -> Option<(Vec<u8>, f64)> {
+        let rank = if max { self.len().checked_sub(1)? } else { 0 };
+        let (member, score) = self.range(rank, rank + 1, false).pop()?;
+        self.remove(&member);
+
+        return Some((member, score));
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+        return match self {
+            Zset::Listpack(lp) => Box::new(pairs(lp)),
+            Zset::Skiplist { index, .. } => Box::new(index.iter()),
+        };
+    }
+
+    /// Calls `f` on some of the elements and returns the cursor to resume
+    /// from, 0 when done. A listpack is small enough to be returned whole.
+    pub fn scan(&self, cursor: u64, mut f: impl FnMut(&[u8], f64)) -> u64 {
+        return match self {
+            Zset::Listpack(lp) => {
+                for (member, score) in pairs(lp) {
+                    f(member, score);
+                }
+                0
+            }
+            Zset::Skiplist { scores, .. } => scores.scan(cursor, |m, s| f(m, *s)),
+        };
+    }
+
+    fn convert(&mut self) {
+        let Zset::Listpack(lp) = self else {
+            return;
+        };
+
+        let mut scores = Dict::new();
+        let mut index = Skiplist::new();
+        for (member, score) in pairs(lp) {
+            scores.insert(member.to_vec(), score);
+            index.insert(member.to_vec(), score);
+        }
+        *self = Zset::Skiplist { scores, index };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ZsetLimits = ZsetLimits {
+        max_entries: 3,
+        max_value: 8,
+    };
+
+    #[test]
+    fn test_both_encodings_agree() {
+        let entries = [("c", 2.0), ("a", 2.0), ("b", 1.0)];
+        let small = Zset::from_entries(entries, LIMITS);
+        let mut big = Zset::from_entries(entries, LIMITS);
+        big.insert(b"z", f64::INFINITY, LIMITS);
+        assert!(big.remove(b"z"));
+        assert!(matches!(small, Zset::Listpack(_)));
+        assert!(matches!(big, Zset::Skiplist { .. }));
+
+        for zset in [&small, &big] {
+            let members: Vec<_> = zset.iter().map(|(m, _)| m.to_vec()).collect();
+            assert_eq!(members, vec![b"b".to_vec(), b"a".to_vec(), b"c".to_vec()]);
+            assert_eq!(zset.rank(b"c"), Some(2));
+            assert_eq!(zset.rank(b"x"), None);
+            assert_eq!(zset.count_while(|_, s| s < 2.0), 1);
+            assert_eq!(
+                zset.range(1, 3, true),
+                vec![(b"c".to_vec(), 2.0), (b"a".to_vec(), 2.0)]
+            );
+        }
+
+        // Updating a score moves the member
+        let mut zset = small;
+        assert_eq!(zset.insert(b"b", 3.0, LIMITS), Some(1.0));
+        assert_eq!(zset.rank(b"b"), Some(2));
+        assert_eq!(zset.pop(false), Some((b"a".to_vec(), 2.0)));
+        assert_eq!(zset.remove_range(0, 5), 2);
+        assert!(zset.is_empty());
+
+        // Long members don't fit in the listpack
+        let zset = Zset::from_entries([("a long member", 1.0)], LIMITS);
+        assert!(matches!(zset, Zset::Skiplist { .. }));
+    }
+}
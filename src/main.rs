@@ -1,3 +1,6 @@
+// Explicit `return` statements are the house style of this crate.
+#![allow(clippy::needless_return)]
+
 use clap::Parser;
 
 mod common;
@@ -11,6 +14,12 @@ fn main() {
     let conf = config::Config::parse();
 
     println!("Starting the server!");
+    if conf.keys_limit.is_some() {
+        println!("--keys-limit is deprecated and will be removed, use --maxmemory instead");
+    }
 
-    server::async_tcp::run(conf).expect("Something's wrong!");
+    if let Err(err) = server::async_tcp::run(conf) {
+        println!("Fatal error: {:#}", err);
+        std::process::exit(1);
+    }
 }
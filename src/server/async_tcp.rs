@@ -1,5 +1,6 @@
 use std::{
-    io,
+    collections::HashMap,
+    io::{self, Write},
     mem::{size_of, MaybeUninit},
     net::SocketAddrV4,
     os::fd::RawFd,
@@ -10,19 +11,24 @@ use libc;
 
 use crate::{
     config::Config,
-    core::comm::FdComm,
+    core::{blocked, client::Client, comm::FdComm, eval, multi, resp::encode_error},
     data::store::Store,
-    server::sync_tcp::{read_command, respond},
+    error::EOFError,
+    server::sync_tcp::read_command,
     syscall,
 };
 
+/// Largest amount of replies kept for a client that doesn't read them, past
+/// which it is disconnected.
+const REPLY_BUF_MAX_LEN: usize = 256 * 1024 * 1024;
+
 fn set_nonblocking(fd: RawFd, nonblocking: bool) -> io::Result<()> {
     let flag = syscall!(fcntl(fd, libc::F_GETFL))?;
 
     let new_flag = if nonblocking {
         flag | libc::O_NONBLOCK
     } else {
-        flag | &!libc::O_NONBLOCK
+        flag & !libc::O_NONBLOCK
     };
 
     if flag != new_flag {
@@ -44,13 +50,170 @@ fn accept(fd: RawFd) -> io::Result<i32> {
     ));
 }
 
+/// Writes as much of `buf` as the socket takes without blocking, returns
+/// how much that was.
+fn write_nonblocking(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    let mut comm = FdComm { fd };
+    let mut written = 0;
+    while written < buf.len() {
+        match comm.write(&buf[written..]) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => written += n,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    return Ok(written);
+}
+
+/// Has epoll report `fd` once it is writable rather than readable while its
+/// client has replies waiting, so that no more commands are read from a
+/// client that doesn't read their replies, and back once they were sent.
+fn watch_backlog(epoll_fd: RawFd, fd: RawFd, backlog: bool) -> io::Result<()> {
+    let events = if backlog {
+        libc::EPOLLOUT
+    } else {
+        libc::EPOLLIN
+    };
+    let mut event = libc::epoll_event {
+        events: events as u32,
+        u64: fd as u64,
+    };
+
+    syscall!(epoll_ctl(epoll_fd, libc::EPOLL_CTL_MOD, fd, &mut event))?;
+    return Ok(());
+}
+
+/// Sends `replies` to `client`. What the socket doesn't take right away is
+/// kept in the client and written once the socket is writable, so a big
+/// reply or a slow reader never blocks the server. Fails if the client went
+/// away or let too many replies pile up.
+fn send_replies(
+    epoll_fd: RawFd,
+    fd: RawFd,
+    client: &mut Client,
+    replies: &[u8],
+) -> io::Result<()> {
+    // Replies already waiting for the socket go first
+    if !client.reply_buf.is_empty() {
+        client.reply_buf.extend_from_slice(replies);
+    } else {
+        let written = write_nonblocking(fd, replies)?;
+        if written == replies.len() {
+            return Ok(());
+        }
+        client.reply_buf = replies[written..].to_vec();
+        watch_backlog(epoll_fd, fd, true)?;
+    }
+
+    if client.reply_buf.len() - client.reply_sent > REPLY_BUF_MAX_LEN {
+        return Err(io::Error::other("Output buffer limit reached"));
+    }
+    return Ok(());
+}
+
+/// Writes the replies `client` is owed now that its socket is writable.
+fn write_pending_replies(epoll_fd: RawFd, fd: RawFd, client: &mut Client) -> io::Result<()> {
+    client.reply_sent += write_nonblocking(fd, &client.reply_buf[client.reply_sent..])?;
+    if client.reply_sent == client.reply_buf.len() {
+        // Don't hold on to the memory of a big reply
+        client.reply_buf = Vec::new();
+        client.reply_sent = 0;
+        watch_backlog(epoll_fd, fd, false)?;
+    }
+
+    return Ok(());
+}
+
+/// Sends `client` replies it is owed outside of the request/response cycle,
+/// e.g. once it is unblocked.
+fn send_deferred(epoll_fd: RawFd, fd: RawFd, client: &mut Client, store: &mut Store, replies: &[u8]) {
+    // Writes must be in the AOF before the client hears about them.
+    store.flush_aof();
+    // The client can't be closed from here, hanging up has epoll report it
+    // and it is cleaned up then.
+    if send_replies(epoll_fd, fd, client, replies).is_err() {
+        let _ = syscall!(shutdown(fd, libc::SHUT_RDWR));
+    }
+}
+
+/// Forgets about the client of `fd` and closes its connection.
+fn close_client(
+    fd: RawFd,
+    store: &mut Store,
+    clients: &mut HashMap<RawFd, Client>,
+    client_fds: &mut HashMap<u64, RawFd>,
+) -> io::Result<()> {
+    if let Some(mut client) = clients.remove(&fd) {
+        blocked::unblock(&mut client, store);
+        multi::unwatch_all(&mut client, store);
+        client_fds.remove(&client.id);
+    }
+    syscall!(close(fd))?;
+
+    return Ok(());
+}
+
+fn serve_blocked_clients(
+    epoll_fd: RawFd,
+    store: &mut Store,
+    clients: &mut HashMap<RawFd, Client>,
+    client_fds: &HashMap<u64, RawFd>,
+) {
+    blocked::handle_clients_blocked_on_keys(store, |id, store| {
+        let Some(&fd) = client_fds.get(&id) else {
+            return;
+        };
+        let Some(client) = clients.get_mut(&fd) else {
+            return;
+        };
+
+        let replies = blocked::reprocess(client, store);
+        send_deferred(epoll_fd, fd, client, store, &replies);
+    });
+}
+
+/// Replies to the blocked clients whose timeout expired. Returns how long
+/// until the next one expires, if any.
+fn handle_blocked_timeouts(
+    epoll_fd: RawFd,
+    store: &mut Store,
+    clients: &mut HashMap<RawFd, Client>,
+) -> Option<i64> {
+    let now = Utc::now().timestamp_millis();
+    let mut next_timeout = None;
+
+    for (&fd, client) in clients.iter_mut() {
+        if let Some(replies) = blocked::expire_timeout(client, store, now) {
+            send_deferred(epoll_fd, fd, client, store, &replies);
+        }
+
+        if let Some(state) = client.blocked.as_ref().filter(|b| b.timeout_at != 0) {
+            let remaining = state.timeout_at - now;
+            next_timeout = Some(next_timeout.map_or(remaining, |t: i64| t.min(remaining)));
+        }
+    }
+
+    return next_timeout;
+}
+
 pub fn run(conf: Config) -> anyhow::Result<()> {
     println!(
         "Starting an asynchronous TCP Server on {0}:{1}",
         conf.host, conf.port
     );
     let mut store = Store::new(conf.clone());
-    let mut con_clients = 0;
+    if conf.appendonly {
+        store.load_aof()?;
+    } else {
+        store.load_rdb()?;
+    }
+    store.open_aof()?;
+    let mut clients = HashMap::<RawFd, Client>::new();
+    // Blocked clients are tracked by id, see `serve_blocked_clients`.
+    let mut client_fds = HashMap::<u64, RawFd>::new();
 
     let max_clients = 20000;
     let mut events = Vec::<libc::epoll_event>::with_capacity(max_clients);
@@ -58,6 +221,10 @@ pub fn run(conf: Config) -> anyhow::Result<()> {
     let cron_frequency = TimeDelta::seconds(1);
     let mut last_cron_exec_time = Utc::now();
 
+    // Longest time the event loop sleeps waiting for I/O, so that periodic
+    // tasks like the `everysec` AOF fsync still run on an idle server.
+    let event_loop_timeout_ms = 100;
+
     let server_fd = syscall!(socket(
         libc::AF_INET,
         libc::O_NONBLOCK | libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
@@ -108,13 +275,21 @@ pub fn run(conf: Config) -> anyhow::Result<()> {
             store.delete_expired_keys();
             last_cron_exec_time = Utc::now();
         }
+        store.check_child();
+        store.persistence_cron();
+        let next_timeout = handle_blocked_timeouts(epoll_fd, &mut store, &mut clients);
+        serve_blocked_clients(epoll_fd, &mut store, &mut clients, &client_fds);
+        store.flush_aof();
 
+        let timeout_ms = next_timeout.map_or(event_loop_timeout_ms, |t| {
+            t.clamp(0, event_loop_timeout_ms as i64) as i32
+        });
         events.clear();
         let n_events = match syscall!(epoll_wait(
             epoll_fd,
             events.as_mut_ptr(),
             max_clients as i32,
-            -1
+            timeout_ms
         )) {
             Ok(res) => res,
             Err(_) => continue,
@@ -133,8 +308,9 @@ pub fn run(conf: Config) -> anyhow::Result<()> {
                     }
                 };
                 set_nonblocking(fd, true)?;
-
-                con_clients += 1;
+                let client = Client::new();
+                client_fds.insert(client.id, fd);
+                clients.insert(fd, client);
 
                 // Add this new TCP connection to be monitored
                 let mut socket_client_event = libc::epoll_event {
@@ -154,16 +330,47 @@ pub fn run(conf: Config) -> anyhow::Result<()> {
                     }
                 };
             } else {
-                let mut comm = FdComm { fd: ev.u64 as i32 };
-                let cmds = match read_command(&mut comm) {
+                let fd = ev.u64 as RawFd;
+                let mut comm = FdComm { fd };
+                let Some(client) = clients.get_mut(&fd) else {
+                    continue;
+                };
+
+                if ev.events & libc::EPOLLOUT as u32 != 0 {
+                    if write_pending_replies(epoll_fd, fd, client).is_err() {
+                        close_client(fd, &mut store, &mut clients, &mut client_fds)?;
+                        continue;
+                    }
+                    let readable = (libc::EPOLLIN | libc::EPOLLERR | libc::EPOLLHUP) as u32;
+                    if ev.events & readable == 0 {
+                        continue;
+                    }
+                }
+
+                let cmds = match read_command(&mut comm, client) {
                     Ok(res) => res,
-                    Err(_) => {
-                        syscall!(close(ev.u64 as i32))?;
-                        con_clients -= 1;
+                    Err(err) => {
+                        if let Some(io_err) = err.downcast_ref::<io::Error>() {
+                            if io_err.kind() == io::ErrorKind::WouldBlock {
+                                continue;
+                            }
+                        } else if !err.is::<EOFError>() {
+                            // Protocol error, tell the client why before hanging up
+                            let _ = comm.write_all(&encode_error(err));
+                        }
+
+                        close_client(fd, &mut store, &mut clients, &mut client_fds)?;
                         continue;
                     }
                 };
-                respond(cmds, &mut store, &mut comm)?;
+
+                let replies = eval::replies(cmds, client, &mut store);
+                // A client that can't be written to is gone, like one that
+                // can't be read from
+                if send_replies(epoll_fd, fd, client, &replies).is_err() {
+                    close_client(fd, &mut store, &mut clients, &mut client_fds)?;
+                }
+                serve_blocked_clients(epoll_fd, &mut store, &mut clients, &client_fds);
             }
         }
     }
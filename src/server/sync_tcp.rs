@@ -1,73 +1,69 @@
 use anyhow::anyhow;
 
-use std::io;
+use std::io::{self, Write};
 use std::net::{Shutdown, TcpListener};
 
-use crate::common::Value;
 use crate::core::cmd::Commands;
 use crate::data::store::Store;
 use crate::{
     config::Config,
-    core::{cmd::Command, eval, resp::decode},
+    core::{client::Client, cmd::Command, eval, resp::encode_error},
     error::EOFError,
 };
 
 pub trait Stream: io::Write + io::Read {}
 impl<T> Stream for T where T: io::Write + io::Read {}
 
-fn to_array_string(values: Vec<Value>) -> anyhow::Result<Vec<String>> {
-    let mut arrs = Vec::<String>::with_capacity(values.len());
-    for v in values {
-        arrs.push(v.to_string());
-    }
+/// Size of a single read from the socket, same as Redis' `PROTO_IOBUF_LEN`.
+const IO_BUF_LEN: usize = 16 * 1024;
 
-    return Ok(arrs);
-}
+/// Largest amount of unparsed input kept for a single client.
+const QUERY_BUF_MAX_LEN: usize = 1024 * 1024 * 1024;
 
-pub fn read_command(stream: &mut impl Stream) -> anyhow::Result<Commands> {
-    let mut buf: [u8; 512] = [0u8; 512];
+/// Reads once from `stream` into the client's query buffer and returns the
+/// commands that are now complete. An empty result means the buffer only
+/// holds part of a command and the caller should wait for more data.
+pub fn read_command(stream: &mut impl Stream, client: &mut Client) -> anyhow::Result<Commands> {
+    let mut buf = [0u8; IO_BUF_LEN];
 
     let bytes = stream.read(&mut buf)?;
     if bytes == 0 {
         return Err(EOFError.into());
     }
 
-    let values = decode(&buf[..bytes])?;
-    let mut cmds = Commands::with_capacity(values.len());
-
-    for val in values {
-        if let Value::Vector(v) = val {
-            let tokens = to_array_string(v)?;
-
-            cmds.push(Command {
-                cmd: tokens[0].to_uppercase(),
-                args: tokens[1..].to_vec(),
-            })
-        } else {
-            return Err(anyhow!("Value is not a Vec type"));
-        }
+    client.query_buf.extend_from_slice(&buf[..bytes]);
+    if client.query_buf.len() > QUERY_BUF_MAX_LEN {
+        return Err(anyhow!("Query buffer limit reached"));
     }
 
-    return Ok(cmds);
-}
+    let requests = client.request.parse(&mut client.query_buf)?;
 
-fn respond_error(err: anyhow::Error, stream: &mut impl Stream) -> io::Result<()> {
-    return stream.write(format!("-{}\r\n", err).as_bytes()).and(Ok(()));
+    return Ok(requests.into_iter().map(Command::from).collect());
 }
 
-pub fn respond(cmds: Commands, store: &mut Store, stream: &mut impl Stream) -> io::Result<()> {
-    return eval::respond(cmds, store, stream);
+pub fn respond(
+    cmds: Commands,
+    client: &mut Client,
+    store: &mut Store,
+    stream: &mut impl Stream,
+) -> io::Result<()> {
+    return eval::respond(cmds, client, store, stream);
 }
 
-pub fn run(conf: Config) -> io::Result<()> {
+#[allow(dead_code)]
+pub fn run(conf: Config) -> anyhow::Result<()> {
     println!(
         "Starting a synchronous TCP Server on {0}:{1}",
         conf.host, conf.port
     );
 
     let mut store = Store::new(conf.clone());
-
-    let mut con_clients = 0u8;
+    if conf.appendonly {
+        store.load_aof()?;
+    } else {
+        store.load_rdb()?;
+    }
+    store.open_aof()?;
 
     let listener = TcpListener::bind(format!("{0}:{1}", conf.host, conf.port))?;
 
@@ -80,26 +76,25 @@ pub fn run(conf: Config) -> io::Result<()> {
             }
         };
 
-        con_clients += 1;
+        let mut client = Client::new();
 
         loop {
-            let cmds = match read_command(&mut stream) {
+            let cmds = match read_command(&mut stream, &mut client) {
                 Ok(res) => res,
                 Err(err) => {
-                    stream.shutdown(Shutdown::Both)?;
-
-                    con_clients -= 1;
-
-                    if err.is::<EOFError>() {
-                        break;
+                    if !err.is::<EOFError>() {
+                        println!("Err {}", err);
+                        let _ = stream.write_all(&encode_error(err));
                     }
 
-                    println!("Err {}", err);
-                    return Ok(());
+                    stream.shutdown(Shutdown::Both)?;
+                    break;
                 }
             };
 
-            respond(cmds, &mut store, &mut stream)?;
+            respond(cmds, &mut client, &mut store, &mut stream)?;
+            store.check_child();
+            store.persistence_cron();
         }
     }
 }